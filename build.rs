@@ -0,0 +1,30 @@
+use std::{env, fs, path::Path};
+
+include!("src/gf256_mult.rs");
+
+/// Writes the multiplicative inverses of the elements of GF(2^8) to `inverse_table.rs` in
+/// `OUT_DIR` as `const INVERSE: [u8; 256]`, included by `src/gf256.rs`, so that the table
+/// costs nothing at run time and lives in read-only memory.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/gf256_mult.rs");
+
+    // a^254 is the inverse of a since the multiplicative group has 255 elements; it is 0
+    // for 0, which has no inverse.
+    let inverses: Vec<String> = (0..=255u8)
+        .map(|a| {
+            (0..253)
+                .fold(a, |power, _| russian_peasant_mult(power, a))
+                .to_string()
+        })
+        .collect();
+    let table = format!(
+        "/// Multiplicative inverses of the elements of GF(2^8), generated by `build.rs`; the\n\
+         /// entry for 0, which has no inverse, is 0.\n\
+         const INVERSE: [u8; 256] = [{}];\n",
+        inverses.join(", ")
+    );
+    let out_dir = env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo");
+    fs::write(Path::new(&out_dir).join("inverse_table.rs"), table)
+        .expect("cannot write the inverse table");
+}
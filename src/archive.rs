@@ -0,0 +1,266 @@
+use flate2::read::GzDecoder;
+use std::{
+    fs::File,
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+use crate::error::Result;
+
+/// Magic bytes at the start of a zip archive.
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+/// Magic bytes at the start of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Magic bytes of a tar archive, found at `TAR_MAGIC_OFFSET`.
+const TAR_MAGIC: [u8; 5] = *b"ustar";
+
+/// Offset of the magic bytes within the first header of a tar archive.
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// Bounds on what is read from an archive, so that a small compressed archive, such as a zip
+/// bomb, cannot expand to exhaust memory.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// The maximum number of entries, files or not.
+    entries: usize,
+    /// The maximum total size of the files, once decompressed.
+    unpacked_len: u64,
+}
+
+/// The limits of `read_entries`: 10000 entries and 1 GiB of files. Every shard is as long as
+/// the secret, so this also bounds the secrets which can be combined from an archive.
+const LIMITS: Limits = Limits {
+    entries: 10_000,
+    unpacked_len: 1 << 30,
+};
+
+/// Formats of shard archives which can be read without extracting them to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// An uncompressed tar archive
+    Tar,
+    /// A gzip-compressed tar archive
+    TarGz,
+    /// A zip archive
+    Zip,
+}
+
+/// Detects the format of an archive from its first bytes.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to inspect.
+///
+/// # Returns
+///
+/// The `ArchiveFormat` of the file, or `None` if it is not a supported archive.
+pub fn detect_format(path: &Path) -> Result<Option<ArchiveFormat>> {
+    let mut start = Vec::new();
+    File::open(path)?
+        .take((TAR_MAGIC_OFFSET + TAR_MAGIC.len()) as u64)
+        .read_to_end(&mut start)?;
+
+    let format = if start.starts_with(&ZIP_MAGIC) {
+        Some(ArchiveFormat::Zip)
+    } else if start.starts_with(&GZIP_MAGIC) {
+        Some(ArchiveFormat::TarGz)
+    } else if start.get(TAR_MAGIC_OFFSET..) == Some(&TAR_MAGIC[..]) {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    };
+
+    Ok(format)
+}
+
+/// Reads every file stored in an archive into memory.
+///
+/// # Arguments
+///
+/// * `path` - The path of the archive.
+/// * `format` - The format of the archive, as returned by `detect_format`.
+///
+/// # Returns
+///
+/// The name and content of every regular file of the archive, in archive order, or an
+/// `io::Error` of kind `io::ErrorKind::InvalidData` if the archive goes past `LIMITS`.
+pub fn read_entries(path: &Path, format: ArchiveFormat) -> Result<Vec<(String, Vec<u8>)>> {
+    let file = File::open(path)?;
+    let entries = match format {
+        ArchiveFormat::Tar => read_tar(file, LIMITS)?,
+        ArchiveFormat::TarGz => read_tar(GzDecoder::new(file), LIMITS)?,
+        ArchiveFormat::Zip => read_zip(file, LIMITS)?,
+    };
+    Ok(entries)
+}
+
+/// Checks whether `bytes` start with the gzip magic.
+pub fn has_gzip_magic(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+/// Decompresses a file stored gzip-compressed on its own, such as a shard `shards_0.sss.gz`.
+///
+/// # Returns
+///
+/// The decompressed data, `None` if `data` does not start with the gzip magic, or an
+/// `io::Error` if the gzip stream is corrupted or truncated or expands past the size limit
+/// of `LIMITS`.
+pub fn gunzip(data: &[u8]) -> Option<io::Result<Vec<u8>>> {
+    if !has_gzip_magic(data) {
+        return None;
+    }
+    Some(Budget::new(LIMITS).read(GzDecoder::new(data)))
+}
+
+/// Reads the regular files of a tar stream, within `limits`.
+fn read_tar<R: Read>(reader: R, limits: Limits) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut budget = Budget::new(limits);
+    let mut entries = Vec::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        budget.count_entry()?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.display().to_string();
+        entries.push((name, budget.read(entry)?));
+    }
+
+    Ok(entries)
+}
+
+/// Reads the regular files of a zip archive, within `limits`.
+fn read_zip<R: Read + Seek>(reader: R, limits: Limits) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut budget = Budget::new(limits);
+    let mut entries = Vec::new();
+
+    for index in 0..archive.len() {
+        budget.count_entry()?;
+        let entry = archive.by_index(index)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        entries.push((name, budget.read(entry)?));
+    }
+
+    Ok(entries)
+}
+
+/// What is left of the `Limits` of an archive as its entries are read.
+struct Budget {
+    limits: Limits,
+    entries: usize,
+    unpacked_len: u64,
+}
+
+impl Budget {
+    fn new(limits: Limits) -> Self {
+        Budget {
+            limits,
+            entries: limits.entries,
+            unpacked_len: limits.unpacked_len,
+        }
+    }
+
+    /// Counts one more entry, failing once there are more than `Limits::entries`.
+    fn count_entry(&mut self) -> io::Result<()> {
+        self.entries = self.entries.checked_sub(1).ok_or_else(|| {
+            too_large(format!(
+                "archive holds more than {} entries",
+                self.limits.entries
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Reads an entry to its end, failing as soon as the files read so far exceed
+    /// `Limits::unpacked_len`, without reading the rest of the entry.
+    fn read(&mut self, entry: impl Read) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        entry
+            .take(self.unpacked_len.saturating_add(1))
+            .read_to_end(&mut data)?;
+        self.unpacked_len = self
+            .unpacked_len
+            .checked_sub(data.len() as u64)
+            .ok_or_else(|| {
+                too_large(format!(
+                    "archive expands to more than {} bytes",
+                    self.limits.unpacked_len
+                ))
+            })?;
+        Ok(data)
+    }
+}
+
+/// Builds the error returned for an archive exceeding its `Limits`.
+fn too_large(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// Builds a tar archive holding a file of `len` zeros for every entry of `lens`.
+    fn tar_of_zeros(lens: &[usize]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (index, &len) in lens.iter().enumerate() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(len as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("shard_{}", index), &vec![0; len][..])
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_read_tar_within_limits() {
+        let archive = tar_of_zeros(&[100, 100, 100]);
+        let limits = |entries, unpacked_len| Limits {
+            entries,
+            unpacked_len,
+        };
+
+        let entries = read_tar(&archive[..], limits(3, 300)).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        for limits in [limits(2, 300), limits(3, 299)] {
+            let err = read_tar(&archive[..], limits).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", limits);
+        }
+    }
+
+    #[test]
+    fn test_read_zip_bomb() {
+        // 8 MiB of zeros compress to a few kilobytes.
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("bomb", options).unwrap();
+        let zeros = vec![0; 1 << 20];
+        for _ in 0..8 {
+            writer.write_all(&zeros).unwrap();
+        }
+        let archive = writer.finish().unwrap().into_inner();
+        assert!(archive.len() < 1 << 16);
+
+        let limits = Limits {
+            entries: 10,
+            unpacked_len: 1 << 20,
+        };
+        let err = read_zip(Cursor::new(&archive), limits).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("more than 1048576 bytes"));
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    header,
+};
+
+/// Magic bytes identifying a bundle of shard sets.
+pub const MAGIC: [u8; 4] = *b"SHMB";
+
+/// Current version of the bundle format.
+const VERSION: u8 = 1;
+
+/// Length in bytes of the magic and version starting a bundle.
+const BUNDLE_HEADER_LEN: usize = 5;
+
+/// Length in bytes of the big-endian length preceding every record.
+const RECORD_LEN_LEN: usize = 8;
+
+/// Checks whether the bytes start with the bundle magic.
+pub fn has_magic(bytes: &[u8]) -> bool {
+    bytes.len() >= MAGIC.len() && header::ct_eq(&bytes[..MAGIC.len()], &MAGIC)
+}
+
+/// Splits a bundle into its records.
+///
+/// A bundle is `MAGIC` and a version byte, followed by records which are each a big-endian
+/// `u64` length and as many bytes. Every record is a column layout file holding one shard
+/// set, so new sets are appended without rewriting the bundle.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of the bundle.
+///
+/// # Returns
+///
+/// The records in the order they were appended, or `Error::InvalidHeader` if the bundle does
+/// not start with the magic, uses an unsupported version or ends with a truncated record.
+pub fn read_records(bytes: &[u8]) -> Result<Vec<&[u8]>> {
+    if !has_magic(bytes) || bytes.len() < BUNDLE_HEADER_LEN {
+        return Err(invalid_header("file is not a shard bundle"));
+    }
+    if bytes[MAGIC.len()] != VERSION {
+        return Err(invalid_header(&format!(
+            "unsupported bundle version {}, only version {} can be read",
+            bytes[MAGIC.len()],
+            VERSION
+        )));
+    }
+
+    let mut records = Vec::new();
+    let mut rest = &bytes[BUNDLE_HEADER_LEN..];
+    while !rest.is_empty() {
+        let truncated = || invalid_header("bundle ends with a truncated record");
+        let len = rest.get(..RECORD_LEN_LEN).ok_or_else(truncated)?;
+        let len = u64::from_be_bytes(len.try_into().expect("length is 8 bytes"));
+        let record = usize::try_from(len)
+            .ok()
+            .and_then(|len| rest.get(RECORD_LEN_LEN..RECORD_LEN_LEN.checked_add(len)?))
+            .ok_or_else(truncated)?;
+        records.push(record);
+        rest = &rest[RECORD_LEN_LEN + record.len()..];
+    }
+    Ok(records)
+}
+
+/// Picks one record of a bundle.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of the bundle.
+/// * `record` - The index of the record, from 0, or `None` if the bundle must hold a single
+///   record.
+///
+/// # Returns
+///
+/// The record, the errors of `read_records`, or `Error::InvalidParameters` if the record does
+/// not exist or none was chosen among several.
+pub fn select_record(bytes: &[u8], record: Option<usize>) -> Result<&[u8]> {
+    let records = read_records(bytes)?;
+    match (record, records.len()) {
+        (Some(index), len) if index < len => Ok(records[index]),
+        (None, 1) => Ok(records[0]),
+        (Some(index), len) => Err(Error::InvalidParameters(format!(
+            "the bundle holds {} records, numbered from 0, so record {} does not exist",
+            len, index
+        ))),
+        (None, len) => Err(Error::InvalidParameters(format!(
+            "the bundle holds {} records, choose one with --record",
+            len
+        ))),
+    }
+}
+
+/// Writes a record to a bundle file, creating the bundle or appending to it.
+///
+/// An existing bundle is checked before anything is appended, and truncated back to its
+/// previous length if the append fails, so a failed append never corrupts it.
+///
+/// # Arguments
+///
+/// * `path` - The path of the bundle.
+/// * `record` - The record to add, a column layout file.
+/// * `append` - Whether to add the record to an existing bundle. An empty file is treated as
+///   an empty bundle.
+///
+/// # Returns
+///
+/// The index of the new record, an `Error::Io` of kind `io::ErrorKind::AlreadyExists` if
+/// `path` exists and `append` is `false`, or the errors of `read_records` if the existing
+/// bundle is corrupt.
+pub fn write_record(path: &Path, record: &[u8], append: bool) -> Result<usize> {
+    let existing = if append && path.exists() {
+        fs::read(path)?
+    } else {
+        Vec::new()
+    };
+    let index = if existing.is_empty() {
+        0
+    } else {
+        read_records(&existing)
+            .map_err(|e| e.in_shard(&path.display().to_string()))?
+            .len()
+    };
+
+    let mut file = if append {
+        OpenOptions::new().create(true).append(true).open(path)?
+    } else {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => io::Error::new(
+                    e.kind(),
+                    format!(
+                        "{} already exists, use --append to add a record to it",
+                        path.display()
+                    ),
+                ),
+                _ => e,
+            })?
+    };
+    let mut bytes = Vec::with_capacity(BUNDLE_HEADER_LEN + RECORD_LEN_LEN + record.len());
+    if existing.is_empty() {
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+    }
+    bytes.extend_from_slice(&(record.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(record);
+
+    if let Err(e) = file.write_all(&bytes).and_then(|()| file.sync_all()) {
+        let _ = file.set_len(existing.len() as u64);
+        return Err(e.into());
+    }
+    Ok(index)
+}
+
+/// Builds an `Error::InvalidHeader` with the given message.
+fn invalid_header(message: &str) -> Error {
+    Error::InvalidHeader(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::Verbosity,
+        columnar,
+        combine::{combine_secret, unpack_bundle, CombineOptions},
+        gf256::GfBackend,
+        header::Header,
+        shamir,
+        sharding::{shard_into_bundle, SharingConfig},
+    };
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_records_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.sss");
+
+        assert_eq!(write_record(&path, b"first", false).unwrap(), 0);
+        let err = write_record(&path, b"second", false).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(write_record(&path, b"second", true).unwrap(), 1);
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(read_records(&bytes).unwrap(), [&b"first"[..], b"second"]);
+        assert_eq!(select_record(&bytes, Some(1)).unwrap(), b"second");
+        for record in [None, Some(2)] {
+            assert!(matches!(
+                select_record(&bytes, record),
+                Err(Error::InvalidParameters(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_append_to_empty_or_corrupt_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let empty = dir.path().join("empty.sss");
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(write_record(&empty, b"first", true).unwrap(), 0);
+        assert_eq!(
+            select_record(&fs::read(&empty).unwrap(), None).unwrap(),
+            b"first"
+        );
+
+        let truncated = dir.path().join("truncated.sss");
+        let mut bytes = fs::read(&empty).unwrap();
+        bytes.pop();
+        fs::write(&truncated, &bytes).unwrap();
+        let foreign = dir.path().join("foreign.sss");
+        fs::write(&foreign, b"not a bundle").unwrap();
+        for path in [truncated, foreign] {
+            let before = fs::read(&path).unwrap();
+            assert!(write_record(&path, b"second", true).is_err());
+            assert_eq!(fs::read(&path).unwrap(), before);
+        }
+    }
+
+    #[test]
+    fn test_combine_appended_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_path = dir.path().join("bundle.sss");
+        let options = SharingConfig::builder()
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let cancel = AtomicBool::new(false);
+        for (index, secret) in [&b"first"[..], b"second"].into_iter().enumerate() {
+            let secret_path = dir.path().join(format!("secret_{}", index));
+            fs::write(&secret_path, secret).unwrap();
+            let record =
+                shard_into_bundle(&secret_path, &bundle_path, true, &options, &cancel).unwrap();
+            assert_eq!(record, index);
+        }
+
+        for (record, secret) in [&b"first"[..], b"second"].into_iter().enumerate() {
+            let recovered_path = dir.path().join(format!("recovered_{}", record));
+            let options = CombineOptions {
+                record: Some(record),
+                verbosity: Verbosity::Quiet,
+                ..Default::default()
+            };
+            combine_secret(&bundle_path, &recovered_path, &options, &cancel).unwrap();
+            assert_eq!(fs::read(recovered_path).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_unpack_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_path = dir.path().join("bundle.sss");
+        let options = SharingConfig::builder()
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let cancel = AtomicBool::new(false);
+        // Two secret files with the same name, from different directories.
+        for (index, secret) in [&b"first"[..], b"second"].into_iter().enumerate() {
+            let secret_dir = dir.path().join(index.to_string());
+            fs::create_dir(&secret_dir).unwrap();
+            fs::write(secret_dir.join("notes.txt"), secret).unwrap();
+            shard_into_bundle(
+                &secret_dir.join("notes.txt"),
+                &bundle_path,
+                true,
+                &options,
+                &cancel,
+            )
+            .unwrap();
+        }
+
+        let out_dir = dir.path().join("unpacked");
+        let options = CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        };
+        let paths = unpack_bundle(&bundle_path, &out_dir, &options, &cancel).unwrap();
+        assert_eq!(
+            paths,
+            [out_dir.join("notes.txt"), out_dir.join("notes.txt.1")]
+        );
+        assert_eq!(fs::read(&paths[0]).unwrap(), b"first");
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"second");
+    }
+
+    #[test]
+    fn test_unpack_rejects_path_traversal() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_path = dir.path().join("bundle.sss");
+        let shares =
+            shamir::split(b"evil", 3, 2, false, GfBackend::default(), None, false).unwrap();
+        let set_header = Header {
+            threshold: 2,
+            parts: 3,
+            secret_len: 4,
+            file_name: Some("../evil".to_string()),
+            ..Default::default()
+        };
+        let mut record = Vec::new();
+        header::write_shard(
+            &mut record,
+            &columnar::header_bytes(&set_header, false),
+            &columnar::transpose(&shares),
+        )
+        .unwrap();
+        write_record(&bundle_path, &record, false).unwrap();
+
+        let out_dir = dir.path().join("unpacked");
+        let options = CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        };
+        let err =
+            unpack_bundle(&bundle_path, &out_dir, &options, &AtomicBool::new(false)).unwrap_err();
+        assert!(err.to_string().contains("unsafe file name"));
+        assert!(!out_dir.exists());
+        assert!(!dir.path().join("evil").exists());
+    }
+}
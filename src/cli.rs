@@ -1,42 +1,1009 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    num::NonZeroUsize,
+    str::FromStr,
+};
+
+use crate::{
+    gf256::{GfBackend, TableFormat},
+    sharding::{Encoding, Layout, OutputFormat, OverwritePolicy},
+    timing,
+};
 
 /// Rust-based command-line application that implements Shamir's Secret Sharing algorithm
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Suppress all output except errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print shard paths, x-coordinates and timings
+    #[clap(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Print to stderr how long each phase took, such as reading the secret, splitting it and
+    /// writing the shards, to tell whether a run is CPU-bound or I/O-bound
+    #[clap(long, global = true)]
+    pub time: bool,
+
+    /// Cancel the operation if it takes longer than this many seconds
+    #[clap(long, global = true)]
+    pub timeout: Option<u64>,
+
+    /// Number of threads splitting and combining in parallel, at least 1; defaults to one per
+    /// available core
+    #[clap(long, global = true)]
+    pub num_threads: Option<NonZeroUsize>,
+
+    /// GF(2^8) arithmetic to use; `auto` only builds lookup tables for larger secrets
+    #[clap(long, global = true, arg_enum, default_value = "auto")]
+    pub gf_backend: GfBackend,
+
+    /// When to color the output; `auto` colors it when stdout is a terminal, unless
+    /// `NO_COLOR` is set or `CLICOLOR` is `0`
+    #[clap(long, global = true, arg_enum, default_value = "auto")]
+    pub color: ColorChoice,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+impl Cli {
+    /// Returns the verbosity selected by the `--quiet` and `--verbose` flags.
+    pub fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (_, true) => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        }
+    }
+
+    /// Generates the completion script of the CLI for `shell`, from its current definition.
+    pub fn completions(shell: clap_complete::Shell) -> Vec<u8> {
+        let mut command = Cli::command();
+        let name = command.get_name().to_string();
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut command, name, &mut script);
+        script
+    }
+}
+
+/// Amount of output printed while running a command
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors are printed
+    Quiet,
+    /// Progress bars and a summary are printed
+    #[default]
+    Normal,
+    /// Details about every shard are printed as well
+    Verbose,
+}
+
+/// When the output is colored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum ColorChoice {
+    /// Color only when stdout is a terminal and the environment does not disable it
+    #[default]
+    Auto,
+    /// Always color, even when the output is redirected
+    Always,
+    /// Never color
+    Never,
+}
+
+impl ColorChoice {
+    /// Returns whether the output should be colored, following the `NO_COLOR` and `CLICOLOR`
+    /// conventions for `Auto`.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                let clicolor_off = env::var_os("CLICOLOR").is_some_and(|value| value == "0");
+                !no_color && !clicolor_off && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+/// Threshold and number of parts of a sharing scheme, written `3-of-5` or `3/5`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scheme {
+    /// Minimum number of parts required to recombine the secret
+    pub threshold: usize,
+    /// Number of parts to split the secret into
+    pub parts: usize,
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    /// Parses `N-of-M` or `N/M`, where the threshold `N` cannot exceed the parts `M`.
+    fn from_str(scheme: &str) -> Result<Self, Self::Err> {
+        let (threshold, parts) = scheme
+            .split_once("-of-")
+            .or_else(|| scheme.split_once('/'))
+            .ok_or_else(|| format!("expected N-of-M or N/M, such as 3-of-5, got `{}`", scheme))?;
+        let parse = |count: &str| {
+            count
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("`{}` is not a number of parts", count))
+        };
+        let (threshold, parts) = (parse(threshold)?, parse(parts)?);
+        if threshold > parts {
+            return Err(format!(
+                "threshold {} cannot exceed the {} parts",
+                threshold, parts
+            ));
+        }
+        Ok(Scheme { threshold, parts })
+    }
+}
+
+/// A point in time in seconds since the Unix epoch, written as a number or `now`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp(pub u64);
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    /// Parses `now` as the current time, or a number of seconds since the Unix epoch.
+    fn from_str(time: &str) -> Result<Self, Self::Err> {
+        match time.trim() {
+            "now" => Ok(Timestamp(timing::unix_now())),
+            seconds => seconds.parse().map(Timestamp).map_err(|_| {
+                format!(
+                    "expected seconds since the Unix epoch or `now`, got `{}`",
+                    time
+                )
+            }),
+        }
+    }
+}
+
+/// A length of time in seconds, written as a number followed by a unit, such as `90d`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lifetime(pub u64);
+
+impl FromStr for Lifetime {
+    type Err = String;
+
+    /// Parses a positive number followed by `s`, `m`, `h`, `d` or `w` for seconds, minutes,
+    /// hours, days or weeks; a bare number counts seconds.
+    fn from_str(lifetime: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected a positive number followed by s, m, h, d or w, such as 90d, got `{}`",
+                lifetime
+            )
+        };
+        let lifetime = lifetime.trim();
+        let (count, unit) = match lifetime.char_indices().last() {
+            Some((index, unit)) if unit.is_ascii_alphabetic() => (&lifetime[..index], unit),
+            _ => (lifetime, 's'),
+        };
+        let unit_seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        count
+            .parse::<u64>()
+            .ok()
+            .filter(|&count| count > 0)
+            .and_then(|count| count.checked_mul(unit_seconds))
+            .map(Lifetime)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Parses Unix file permissions written in octal, such as `600`, `0640` or `0o644`. The
+/// setuid, setgid and sticky bits are refused.
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o777)
+        .ok_or_else(|| format!("expected octal permissions such as 600, got `{}`", mode))
+}
+
+/// Parses 32 bytes, such as a SHA-256 hash or a seed, written as 64 hexadecimal digits, in
+/// either case.
+fn parse_hex_32(hash: &str) -> Result<[u8; 32], String> {
+    let hash = hash.trim();
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("expected 64 hexadecimal digits, got `{}`", hash));
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hash[2 * i..2 * i + 2], 16).map_err(|e| e.to_string())?;
+    }
+    Ok(bytes)
+}
+
+/// The flags of `shard` whose value is as sensitive as the secret itself.
+const SECRET_FLAGS: [&str; 2] = ["--secret-string", "--seed"];
+
+/// Stands for the value of a flag of `SECRET_FLAGS` in a suggested command line.
+const REDACTED: &str = "<redacted>";
+
+/// Rewrites a `shard` command line whose parts and threshold look swapped, such as
+/// `--parts 3 --threshold 5` meant as 3-of-5, into the command line splitting into `threshold`
+/// parts with a threshold of `parts`, for the error refusing it to suggest.
+///
+/// The values of `-p`, `--parts`, `-t` and `--threshold` are swapped where they are given, in
+/// any of their spellings; those taken from `SSS_PARTS` or `SSS_THRESHOLD` are appended as
+/// flags after the subcommand. The values of the flags in `SECRET_FLAGS` are replaced with
+/// `<redacted>`, so that the secret or seed does not end up in terminal scrollback or logs,
+/// and the other arguments which the shell would split or expand are single-quoted.
+///
+/// # Arguments
+///
+/// * `args` - The command line, starting with the program.
+/// * `parts` - The number of parts given, smaller than `threshold`.
+/// * `threshold` - The threshold given.
+///
+/// # Examples
+///
+/// ```
+/// let args = ["shamir-encryption", "shard", "my key", "out", "-p", "3", "--threshold=5"];
+/// assert_eq!(
+///     swapped_scheme_command(args.map(String::from), 3, 5),
+///     "shamir-encryption shard 'my key' out -p 5 --threshold=3"
+/// );
+/// ```
+pub fn swapped_scheme_command(
+    args: impl IntoIterator<Item = String>,
+    parts: usize,
+    threshold: usize,
+) -> String {
+    let (mut parts_given, mut threshold_given) = (false, false);
+    let mut swapped = Vec::new();
+    let mut args = args.into_iter();
+    if let Some(program) = args.next() {
+        let name = std::path::Path::new(&program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        swapped.push(shell_quote(&name.unwrap_or(program)));
+    }
+    // The value replacing the next argument, after a flag given on its own.
+    let mut pending: Option<String> = None;
+    let mut positional_only = false;
+    for arg in args {
+        if let Some(value) = pending.take() {
+            swapped.push(value);
+            continue;
+        }
+        if positional_only || arg == "--" {
+            positional_only = true;
+            swapped.push(shell_quote(&arg));
+            continue;
+        }
+        if SECRET_FLAGS.contains(&arg.as_str()) {
+            pending = Some(REDACTED.to_string());
+            swapped.push(arg);
+            continue;
+        }
+        let secret_flag = SECRET_FLAGS
+            .iter()
+            .find(|flag| arg.starts_with(&format!("{}=", flag)));
+        if let Some(flag) = secret_flag {
+            swapped.push(format!("{}={}", flag, REDACTED));
+            continue;
+        }
+        let flag = match arg.as_str() {
+            "-p" | "--parts" => Some((arg.clone(), Some(threshold), true)),
+            "-t" | "--threshold" => Some((arg.clone(), Some(parts), false)),
+            _ => [
+                ("--parts=", threshold, true),
+                ("--threshold=", parts, false),
+                ("-p", threshold, true),
+                ("-t", parts, false),
+            ]
+            .into_iter()
+            .find(|(prefix, _, _)| arg.starts_with(prefix))
+            .map(|(prefix, value, is_parts)| (format!("{}{}", prefix, value), None, is_parts)),
+        };
+        let Some((swapped_arg, next_value, is_parts)) = flag else {
+            swapped.push(shell_quote(&arg));
+            continue;
+        };
+        if is_parts {
+            parts_given = true;
+        } else {
+            threshold_given = true;
+        }
+        pending = next_value.map(|value| value.to_string());
+        swapped.push(swapped_arg);
+    }
+    // Flags for the values of the environment go right after the subcommand, before any `--`.
+    let mut at = swapped
+        .iter()
+        .position(|arg| arg == "shard")
+        .map_or(swapped.len(), |index| index + 1);
+    for (given, flag, value) in [
+        (parts_given, "--parts", threshold),
+        (threshold_given, "--threshold", parts),
+    ] {
+        if !given {
+            swapped.splice(at..at, [flag.to_string(), value.to_string()]);
+            at += 2;
+        }
+    }
+    swapped.join(" ")
+}
+
+/// Single-quotes an argument for a POSIX shell, unless it is made of characters the shell
+/// leaves alone.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
 /// Commands supported by the CLI
 #[derive(Subcommand)]
 pub enum Commands {
     /// Shard a secret into shards
+    // With `--secret-string`, the first path given is the shards path, hence `multiple`.
+    #[clap(group(ArgGroup::new("secret").required(true).multiple(true).args(&["secret-path", "secret-string"])))]
     Shard {
-        /// Path to the secret file
+        /// Path to the secret file, or an http:// or https:// URL to fetch it from when built
+        /// with the `net` feature. Left out with `--secret-string`
         #[clap(parse(from_os_str))]
-        secret_path: std::path::PathBuf,
+        secret_path: Option<std::path::PathBuf>,
 
-        /// Path to store the shards
-        #[clap(parse(from_os_str))]
-        shards_path: std::path::PathBuf,
+        /// Path to store the shards: a directory, or the file to create with another
+        /// `--output-format`. Left out when the shards are added to a bundle with `--bundle`
+        #[clap(
+            parse(from_os_str),
+            required_unless_present_any = &["bundle", "secret-string", "export-env", "s3"]
+        )]
+        shards_path: Option<std::path::PathBuf>,
+
+        /// Shard this UTF-8 string instead of a secret file, for quick experiments with tiny
+        /// secrets; the only path given is then the shards directory. The string may be saved
+        /// in the shell history and is visible to other users in the process list
+        #[clap(long, conflicts_with = "bundle")]
+        secret_string: Option<String>,
+
+        /// Print the shards to stdout as shell `export` statements, one variable per shard
+        /// named after `--prefix` and its index, instead of writing them to a shards path.
+        /// Anything able to read the environment of the shell can then read the shards
+        #[clap(
+            long,
+            conflicts_with_all = &[
+                "bundle", "output-format", "layout", "no-header", "sidecar", "verify-all",
+                "label-file", "qr", "print0"
+            ]
+        )]
+        export_env: bool,
+
+        /// Prefix of the environment variables printed by `--export-env`
+        #[clap(long, default_value = "SHARD_", requires = "export-env")]
+        prefix: String,
+
+        /// Upload every shard to its own object of an S3-compatible store instead of writing
+        /// them to a shards path, such as `--s3 backups/vault` for the keys
+        /// `vault/shards_0.sss` and so on of the bucket `backups` (requires the `cloud`
+        /// feature). The store is configured by the `AWS_ACCESS_KEY_ID`,
+        /// `AWS_SECRET_ACCESS_KEY`, `AWS_REGION` and `AWS_ENDPOINT_URL` variables. The keys
+        /// take `--extension`, and the shards already under the prefix are handled by
+        /// `--overwrite-policy`, backups being copied to a `backup-<timestamp>` prefix below
+        /// it. If an upload fails, the others are still tried and the shards uploaded are
+        /// reported
+        #[clap(
+            long,
+            value_name = "BUCKET/PREFIX",
+            conflicts_with_all = &[
+                "export-env", "bundle", "output-format", "layout", "no-header", "sidecar",
+                "verify-all", "label-file", "qr", "print0"
+            ]
+        )]
+        s3: Option<String>,
 
         /// Number of parts to split the secret into
-        #[clap(short, long, default_value_t = 5)]
+        #[clap(short, long, env = "SSS_PARTS", default_value_t = 5)]
         parts: usize,
 
         /// Threshold number of parts required to recombine the secret
-        #[clap(short, long, default_value_t = 3)]
+        #[clap(short, long, env = "SSS_THRESHOLD", default_value_t = 3)]
         threshold: usize,
+
+        /// Threshold and parts together, such as `3-of-5` or `3/5`. Cannot be combined with
+        /// `--parts`, `--threshold` or their environment variables
+        #[clap(long, conflicts_with_all = &["parts", "threshold"])]
+        scheme: Option<Scheme>,
+
+        /// Give the shards the x-coordinates 1 to parts in order instead of random ones, so that
+        /// a lost shard can be recomputed for the same x-coordinate. This reveals the number
+        /// of shards and the index of each, but not the secret
+        #[clap(long)]
+        sequential_x: bool,
+
+        /// Store a SHA-256 hash of the secret in the shards to verify it when combining
+        #[clap(long)]
+        embed_hash: bool,
+
+        /// Pad shard headers to a fixed width so that all shard files have the same size
+        #[clap(long)]
+        uniform_size: bool,
+
+        /// Store a checksum of every block of 64 KiB or more of each shard in its header, so
+        /// that `combine --stream` rejects a corrupted block as soon as it reads it; not
+        /// available with --bundle
+        #[clap(long, conflicts_with = "no-header")]
+        block_checksums: bool,
+
+        /// Content-type of the secret, such as `text/plain`, stored in cleartext in the shard
+        /// headers
+        #[clap(long)]
+        content_type: Option<String>,
+
+        /// Record in the shard headers when the shards were created, in seconds since the Unix
+        /// epoch; `--created` alone records the current time, which is also the default with
+        /// `--expires`. It is stored in cleartext
+        #[clap(
+            long,
+            min_values = 0,
+            require_equals = true,
+            default_missing_value = "now",
+            value_name = "SECONDS"
+        )]
+        created: Option<Timestamp>,
+
+        /// Record in the shard headers that the shards expire this long after their creation,
+        /// such as `90d` or `12h`. Expired shards still combine, but `combine` and `info` warn
+        /// about them
+        #[clap(long, value_name = "DURATION")]
+        expires: Option<Lifetime>,
+
+        /// Write the shards without any header, as bare y-coordinates in files named after their
+        /// x-coordinate, and the parameters of the set to a single `scheme.json` next to them.
+        /// The shards are then indistinguishable from random data but no longer
+        /// self-describing: without the sidecar, `combine` cannot check them
+        #[clap(long, conflicts_with_all = &["passphrase", "uniform-size", "qr", "bundle"])]
+        no_header: bool,
+
+        /// Also write the parameters of the set and the file name and x-coordinate of every
+        /// shard to `scheme.json`, for `combine --manifest` to report missing or unexpected
+        /// shards. Headerless shards always have one
+        #[clap(long, conflicts_with_all = &["no-header", "bundle"])]
+        sidecar: bool,
+
+        /// Record the name, Unix permissions and modification time of the secret file in
+        /// cleartext in every shard, and in the sidecar, for `combine --restore-metadata` to
+        /// recreate the file as it was
+        #[clap(long, conflicts_with = "secret-string")]
+        store_metadata: bool,
+
+        /// After writing, read the shards back and check that every quorum of threshold shards
+        /// reconstructs the secret. This combines the secret C(parts, threshold) times, 6 for
+        /// 2-of-4 but 184756 for 10-of-20, so schemes of more than 10000 quorums are refused
+        #[clap(long, alias = "recombine-check-all")]
+        verify_all: bool,
+
+        /// Text file holding a cleartext description of the secret, such as recovery
+        /// instructions, stored once next to the shards and shown by `info` and `combine`
+        #[clap(long, parse(from_os_str), conflicts_with = "bundle")]
+        label_file: Option<std::path::PathBuf>,
+
+        /// Derive one more share from a passphrase, read from `SSS_PASSPHRASE` or prompted for,
+        /// so that the passphrase can stand in for one shard when combining. Anyone holding
+        /// threshold - 1 shards can then try to guess the passphrase offline
+        #[clap(long)]
+        passphrase: bool,
+
+        /// Derive all the randomness of the split, the x-coordinates, coefficients and set id,
+        /// from this master seed of 64 hexadecimal digits, so that the same seed, secret and
+        /// options reproduce the same shards byte for byte for an audit. With `--expires`,
+        /// the creation time defaults to now, so they differ unless it is fixed with
+        /// `--created=SECONDS`. Only the SHA-256 hash of the seed is stored with the shards;
+        /// anyone holding the seed itself and a single shard can recover the secret, so it
+        /// must be random and kept like the secret
+        #[clap(
+            long,
+            env = "SSS_SEED",
+            hide_env_values = true,
+            parse(try_from_str = parse_hex_32),
+            value_name = "HEX"
+        )]
+        seed: Option<[u8; 32]>,
+
+        /// Refuse to shard a secret larger than this many bytes; a secret file is checked
+        /// before it is read
+        #[clap(long)]
+        max_secret_size: Option<u64>,
+
+        /// Extension of the shard files, empty for none
+        #[clap(long, default_value = crate::header::SHARD_EXTENSION)]
+        extension: String,
+
+        /// What to do with shards already in the shards directory, or with an existing bundle
+        /// or text file: fail, overwrite them, or back them up by renaming them with a
+        /// timestamp suffix
+        #[clap(long, arg_enum, default_value = "overwrite")]
+        overwrite_policy: OverwritePolicy,
+
+        /// `files` writes the shards to a shards directory; `bundle` writes a new bundle file;
+        /// `json` and `base64-lines` write a single text file holding the base64 of every
+        /// shard, as a JSON object or one per line. `combine` reads any of them
+        #[clap(long, arg_enum, default_value = "files")]
+        output_format: OutputFormat,
+
+        /// `row` writes one file per shard; `column` writes a single file holding byte `i` of
+        /// every shard together, for storage layers which stripe data. `combine` reads either
+        #[clap(long, arg_enum, default_value = "row", conflicts_with = "qr")]
+        layout: Layout,
+
+        /// `binary` writes the raw bytes of every shard; `mnemonic` writes them as words of the
+        /// BIP39 English wordlist followed by a checksum word, to read aloud or copy on paper.
+        /// `combine` decodes mnemonic shard files, whatever their whitespace and case
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "binary",
+            conflicts_with_all = &["qr", "no-header", "bundle", "export-env", "s3"]
+        )]
+        encoding: Encoding,
+
+        /// Write the shards as a record of this bundle file, a single file holding several
+        /// shard sets, instead of a shards directory
+        #[clap(long, parse(from_os_str), conflicts_with_all = &["shards-path", "layout", "qr"])]
+        bundle: Option<std::path::PathBuf>,
+
+        /// Append the shards to the existing bundle as a new record instead of creating it
+        #[clap(long)]
+        append: bool,
+
+        /// Also write every shard as QR-code PNG images for a paper backup (requires the `qr`
+        /// feature). Large shards span several images, numbered from 1, to scan in order
+        #[clap(long)]
+        qr: bool,
+
+        /// Print only the paths of the created shards, each followed by a NUL byte, for
+        /// `xargs -0` and similar tools
+        #[clap(long)]
+        print0: bool,
+
+        /// Do not warn when the threshold equals the parts, so that losing any shard makes
+        /// the secret unrecoverable, or when the secret is given with `--secret-string`
+        #[clap(long)]
+        no_warn: bool,
     },
     /// Combine shards into a secret
     Combine {
+        /// Directory path containing the shards, a tar, tar.gz or zip archive of the shards, or
+        /// a column layout file. Left out when the shards are given with `--hex` or
+        /// `--from-env`, or read from an object store with `--s3`
+        #[clap(parse(from_os_str), required_unless_present_any = &["hex", "from-env", "s3"])]
+        shards_dir: Option<std::path::PathBuf>,
+
+        /// Path to store the recovered secret. Left out when the secret is piped to a command
+        /// with `--exec` or a bundle is unpacked with `--unpack`
+        #[clap(
+            parse(from_os_str),
+            required_unless_present_any = &["hex", "from-env", "s3", "exec", "unpack"]
+        )]
+        recovered_secret_path: Option<std::path::PathBuf>,
+
+        /// A shard as a hexadecimal string, instead of a shards directory. Repeat it for
+        /// every shard
+        #[clap(long, multiple_occurrences = true)]
+        hex: Vec<String>,
+
+        /// Read the shards from the environment variables named `--prefix` followed by a
+        /// number, as printed by `shard --export-env`, instead of a shards directory. Gaps in
+        /// the numbering are allowed
+        #[clap(long, conflicts_with_all = &["hex", "unpack", "stream"])]
+        from_env: bool,
+
+        /// Prefix of the environment variables read by `--from-env`
+        #[clap(long, default_value = "SHARD_", requires = "from-env")]
+        prefix: String,
+
+        /// Download the shards uploaded by `shard --s3` from this bucket and key prefix of an
+        /// S3-compatible store, instead of a shards directory (requires the `cloud` feature)
+        #[clap(
+            long,
+            value_name = "BUCKET/PREFIX",
+            conflicts_with_all = &["hex", "from-env", "unpack", "manifest"]
+        )]
+        s3: Option<String>,
+
+        /// Overwrite the recovered secret file if it already exists
+        #[clap(short, long)]
+        force: bool,
+
+        /// Search subdirectories of the shards directory for shards
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// Reconstruct even if shards fail validation (set id, checksum or threshold checks).
+        /// The recovered secret may then be wrong without any other warning
+        #[clap(long)]
+        force_combine: bool,
+
+        /// Read files without the shard magic as version 0 shards, written before shards had
+        /// a header. They carry no checksum, so they are combined without any check
+        #[clap(long)]
+        legacy: bool,
+
+        /// Reconstruct the secret from every disjoint quorum of threshold shards and fail if
+        /// they disagree, which detects a corrupted shard
+        #[clap(long, conflicts_with = "subset")]
+        redundancy_check: bool,
+
+        /// SHA-256 hash the secret is known to have, as 64 hexadecimal digits. If the shards
+        /// reconstruct another secret, their subsets of threshold shards are searched for one
+        /// which matches it and the shards left out are reported as corrupted. Each subset
+        /// tried combines the whole secret, and more than 10000 subsets are refused
+        #[clap(
+            long,
+            parse(try_from_str = parse_hex_32),
+            value_name = "SHA256",
+            conflicts_with_all = &["redundancy-check", "subset", "partial", "unpack"]
+        )]
+        expect_hash: Option<[u8; 32]>,
+
+        /// Ignore shards of other sets, such as those left by an earlier split in the same
+        /// directory, instead of refusing them: the largest set of shards which reaches its
+        /// threshold is combined, and the shards ignored are reported
+        #[clap(long, conflicts_with_all = &["manifest", "partial"])]
+        allow_extra: bool,
+
+        /// The record to combine when the shards are a bundle, numbered from 0 in the order
+        /// they were appended; required if the bundle holds several records
+        #[clap(long)]
+        record: Option<usize>,
+
+        /// Reconstruct from exactly the shards with these comma-separated x-coordinates, as
+        /// shown by `info`, such as `--subset 12,87,203`. Fails if one of them is missing
+        #[clap(long, use_value_delimiter = true)]
+        subset: Vec<u8>,
+
+        /// Run this shell command with the recovered secret on its standard input instead of
+        /// writing it to a file, and exit with the command's exit code
+        #[clap(long, conflicts_with_all = &["recovered-secret-path", "force", "infer-extension"])]
+        exec: Option<String>,
+
+        /// Best-effort forensic recovery from damaged or truncated shards: every byte with at
+        /// least threshold surviving shards is reconstructed, the others are set to 0 and
+        /// listed in a warning. Checksums are not verified, so bytes may be wrong silently
+        #[clap(
+            long,
+            alias = "recovery-threshold-override",
+            conflicts_with_all = &["redundancy-check", "subset", "passphrase", "exec"]
+        )]
+        partial: bool,
+
+        /// Encrypt the recovered secret to this age public key before writing it, so that the
+        /// plaintext never lands on disk (requires the `age` feature). Repeat it for several
+        /// recipients, any of which can decrypt the secret
+        #[clap(
+            long,
+            multiple_occurrences = true,
+            conflicts_with_all = &["exec", "unpack", "infer-extension"]
+        )]
+        recipient: Vec<String>,
+
+        /// Check the shards against this `scheme.json`, as written by `shard --sidecar`,
+        /// naming the listed shards which are missing and failing on unlisted ones before
+        /// combining
+        #[clap(long, parse(from_os_str), conflicts_with = "hex")]
+        manifest: Option<std::path::PathBuf>,
+
+        /// Derive the share of the passphrase the secret was split with, read from
+        /// `SSS_PASSPHRASE` or prompted for, so that one shard fewer is needed
+        #[clap(long)]
+        passphrase: bool,
+
+        /// Refuse to combine shards past the expiry time recorded in their headers, instead
+        /// of only warning about them
+        #[clap(long)]
+        enforce_expiry: bool,
+
+        /// Octal Unix permissions of the recovered secret file, such as `640`; by default it is
+        /// readable and writable by its owner only (`600`). Ignored outside Unix, where the
+        /// file inherits the permissions of its directory
+        #[clap(long, parse(try_from_str = parse_mode), value_name = "OCTAL", conflicts_with = "exec")]
+        mode: Option<u32>,
+
+        /// Recreate the secret file under the name, permissions and modification time recorded
+        /// by `shard --store-metadata`, in the directory given as recovered secret path. Names
+        /// holding a directory are refused, and `--mode` still overrides the permissions
+        #[clap(
+            long,
+            conflicts_with_all = &["exec", "unpack", "infer-extension", "recipient", "stream"]
+        )]
+        restore_metadata: bool,
+
+        /// Reconstruct every record of a bundle and write each secret to this directory under
+        /// the name of the file it was sharded from
+        #[clap(
+            long,
+            parse(from_os_str),
+            conflicts_with_all = &["recovered-secret-path", "hex", "exec", "record", "subset", "infer-extension", "qr-text"]
+        )]
+        unpack: Option<std::path::PathBuf>,
+
+        /// Add an extension matching the content-type declared in the shards, such as `.pem`,
+        /// to a recovered secret path which has none
+        #[clap(long)]
+        infer_extension: bool,
+
+        /// Read the shards from the texts of their scanned QR codes: the shards directory
+        /// holds one file per shard with one QR text per line, in any order (requires the
+        /// `qr` feature)
+        #[clap(long)]
+        qr_text: bool,
+
+        /// Read the shards block by block, checking every block against the checksum written
+        /// by `shard --block-checksums` before combining it, and write the secret as it is
+        /// reconstructed, so that neither the shards nor the secret are held in memory
+        #[clap(
+            long,
+            conflicts_with_all = &["hex", "exec", "unpack", "record", "subset", "partial", "recipient", "manifest", "passphrase", "redundancy-check", "force-combine", "legacy", "qr-text", "infer-extension"]
+        )]
+        stream: bool,
+    },
+    /// Show the headers of the shards in a directory and whether they can reconstruct the
+    /// secret. Exits with status 1 if they cannot
+    Info {
         /// Directory path containing the shards
         #[clap(parse(from_os_str))]
         shards_dir: std::path::PathBuf,
+    },
+    /// Print the x-coordinate and the first y-coordinates of every share in a table, to
+    /// teach the scheme or debug a reconstruction. This reveals share data: a quorum of
+    /// shares reveals the bytes of the secret shown
+    DumpShares {
+        /// Directory path containing the shards, or any other input accepted by `combine`
+        #[clap(parse(from_os_str))]
+        shards_dir: std::path::PathBuf,
 
-        /// Path to store the recovered secret
+        /// Maximum number of y-coordinates printed per share
+        #[clap(long, default_value = "16")]
+        limit: usize,
+    },
+    /// Re-split the secret of a set of shards into a new set, without writing the secret to
+    /// disk. The new shards keep the parts and threshold of the old ones unless overridden
+    Reshard {
+        /// Directory path containing the existing shards
         #[clap(parse(from_os_str))]
-        recovered_secret_path: std::path::PathBuf,
+        shards_dir: std::path::PathBuf,
+
+        /// Directory to store the new shards, which must not already hold shards
+        #[clap(parse(from_os_str))]
+        new_shards_dir: std::path::PathBuf,
+
+        /// Number of new shards, instead of the number of existing ones. Unlike `shard`, it
+        /// is not read from `SSS_PARTS`, so the scheme only changes when asked to
+        #[clap(short, long)]
+        parts: Option<usize>,
+
+        /// Threshold of the new shards, instead of the existing threshold
+        #[clap(short, long)]
+        threshold: Option<usize>,
+    },
+    /// Regenerate a lost shard, identical to the original, from a quorum of the surviving
+    /// shards of its set
+    Repair {
+        /// Directory path containing the surviving shards
+        #[clap(parse(from_os_str))]
+        shards_dir: std::path::PathBuf,
+
+        /// X-coordinate of the lost shard, as printed by `info` or listed in the sidecar
+        #[clap(long, value_name = "X")]
+        missing_x: u8,
+
+        /// File to write the regenerated shard to, which must not exist
+        #[clap(parse(from_os_str))]
+        out_path: std::path::PathBuf,
+    },
+    /// Check that two shard sets hold the same secret, such as a set and its reshare, by
+    /// comparing hashes of their secrets reconstructed in memory. Exits with 1 if they differ
+    Compare {
+        /// Directory path containing the shards of the first set
+        #[clap(parse(from_os_str))]
+        dir_a: std::path::PathBuf,
+
+        /// Directory path containing the shards of the second set
+        #[clap(parse(from_os_str))]
+        dir_b: std::path::PathBuf,
+    },
+    /// Check that the field arithmetic and the secret sharing work on this machine
+    Selftest {
+        /// Check the field axioms on all 65536 pairs of elements instead of a sample, which
+        /// runs about 250 times more checks
+        #[clap(long)]
+        exhaustive: bool,
+    },
+    /// Print a completion script for a shell, generated from the current flags, to stdout.
+    /// For bash: `shamir-encryption completions bash > /etc/bash_completion.d/shamir-encryption`
+    Completions {
+        /// Shell to generate the completion script for
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the logarithm, antilogarithm, inverse and multiplication tables of GF(2^8), to
+    /// diff them against a reference implementation
+    #[clap(hide = true)]
+    DumpTables {
+        /// Output format of the tables
+        #[clap(long, arg_enum, default_value = "csv")]
+        format: TableFormat,
+    },
+    /// Collect the valid shards of several directories into one directory that can be
+    /// combined, keeping a single copy of each shard
+    Merge {
+        /// Directories containing some of the shards, in order of preference
+        #[clap(parse(from_os_str), required = true)]
+        dirs: Vec<std::path::PathBuf>,
+
+        /// Directory to store the merged shards
+        #[clap(short, long, parse(from_os_str))]
+        out_dir: std::path::PathBuf,
+    },
+    /// Rewrite shards of an older version of the format in the current one with a new
+    /// checksum, in place, after checking their current checksum. The share data is kept, so
+    /// the set does not need to be re-split
+    Rehash {
+        /// Directory path containing the shards
+        #[clap(parse(from_os_str))]
+        shards_dir: std::path::PathBuf,
     },
+    /// Serve shard and combine requests on a Unix domain socket, avoiding the startup cost
+    /// of a process per secret. See the README for the protocol
+    #[cfg(unix)]
+    Serve {
+        /// Path of the socket to create
+        #[clap(parse(from_os_str))]
+        socket: std::path::PathBuf,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheme_parse() {
+        let scheme = Scheme {
+            threshold: 3,
+            parts: 5,
+        };
+        assert_eq!("3-of-5".parse(), Ok(scheme));
+        assert_eq!("3/5".parse(), Ok(scheme));
+        assert_eq!("5-of-5".parse::<Scheme>().map(|scheme| scheme.parts), Ok(5));
+
+        for invalid in ["5-of-3", "abc", "3-of-", "3 of 5", "-1/5", "3/5/7"] {
+            assert!(
+                invalid.parse::<Scheme>().is_err(),
+                "{} was accepted",
+                invalid
+            );
+        }
+        assert_eq!(
+            "5-of-3".parse::<Scheme>(),
+            Err("threshold 5 cannot exceed the 3 parts".to_string())
+        );
+    }
+
+    #[test]
+    fn test_timestamp_and_lifetime_parse() {
+        assert_eq!("1700000000".parse(), Ok(Timestamp(1_700_000_000)));
+        let Ok(Timestamp(now)) = "now".parse() else {
+            panic!("`now` was refused");
+        };
+        assert!(now > 1_700_000_000);
+        assert!("yesterday".parse::<Timestamp>().is_err());
+
+        assert_eq!("90".parse(), Ok(Lifetime(90)));
+        assert_eq!("15m".parse(), Ok(Lifetime(900)));
+        assert_eq!("12h".parse(), Ok(Lifetime(43_200)));
+        assert_eq!("90d".parse(), Ok(Lifetime(7_776_000)));
+        assert_eq!("2w".parse(), Ok(Lifetime(1_209_600)));
+        for invalid in ["", "0d", "d", "-1d", "3y", "1.5h", "99999999999999999w"] {
+            assert!(
+                invalid.parse::<Lifetime>().is_err(),
+                "{} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_hex_32() {
+        let hash = "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824";
+        let bytes = parse_hex_32(hash).unwrap();
+        assert_eq!(bytes[..2], [0x2c, 0xf2]);
+        assert_eq!(parse_hex_32(&hash.to_lowercase()), Ok(bytes));
+        for invalid in ["", &hash[1..], &hash.replace('2', "g")] {
+            assert!(parse_hex_32(invalid).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("600"), Ok(0o600));
+        assert_eq!(parse_mode("0640"), Ok(0o640));
+        assert_eq!(parse_mode("0o644"), Ok(0o644));
+        for invalid in ["", "8", "rw", "17777", "4755", "1777"] {
+            assert!(parse_mode(invalid).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_swapped_scheme_command() {
+        let command = |args: &[&str]| {
+            let args = ["/usr/bin/shamir-encryption", "shard"]
+                .iter()
+                .chain(args)
+                .map(|arg| arg.to_string());
+            swapped_scheme_command(args, 3, 5)
+        };
+        assert_eq!(
+            command(&["secret", "out", "--parts", "3", "--threshold", "5"]),
+            "shamir-encryption shard secret out --parts 5 --threshold 3"
+        );
+        assert_eq!(
+            command(&["-p3", "-t", "5", "it's", "out"]),
+            "shamir-encryption shard -p5 -t 3 'it'\\''s' out"
+        );
+        // Values from the environment are given as flags.
+        assert_eq!(
+            command(&["secret", "out", "--threshold=5"]),
+            "shamir-encryption shard --parts 5 secret out --threshold=3"
+        );
+        // Paths after `--` are left alone, even if they look like flags.
+        assert_eq!(
+            command(&["-p", "3", "-t", "5", "--", "-p9", "out"]),
+            "shamir-encryption shard -p 5 -t 3 -- -p9 out"
+        );
+        // The secret and the seed are never repeated.
+        assert_eq!(
+            command(&["--secret-string", "hunter 2", "out", "-p", "3", "-t", "5"]),
+            "shamir-encryption shard --secret-string <redacted> out -p 5 -t 3"
+        );
+        assert_eq!(
+            command(&[
+                "--seed=00ff",
+                "--secret-string=hunter2",
+                "out",
+                "-p3",
+                "-t5"
+            ]),
+            "shamir-encryption shard --seed=<redacted> --secret-string=<redacted> out -p5 -t3"
+        );
+    }
+
+    #[test]
+    fn test_completions() {
+        let script = String::from_utf8(Cli::completions(clap_complete::Shell::Bash)).unwrap();
+        assert!(script.contains("shard") && script.contains("combine"));
+        assert!(script.contains("--verify-all"));
+        for shell in [
+            clap_complete::Shell::Zsh,
+            clap_complete::Shell::Fish,
+            clap_complete::Shell::PowerShell,
+        ] {
+            assert!(!Cli::completions(shell).is_empty());
+        }
+    }
 }
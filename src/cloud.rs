@@ -0,0 +1,400 @@
+use std::{
+    env, fmt,
+    io::{self, Write},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use s3::{creds::Credentials, error::S3Error, Region};
+
+use crate::{
+    error::{Error, Result},
+    sink::ShardSink,
+};
+
+/// Region signed for when neither `AWS_REGION` nor `AWS_DEFAULT_REGION` is set.
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// Largest object downloaded, so that a bogus object cannot exhaust the memory.
+const MAX_OBJECT_LEN: u64 = 1 << 30;
+
+/// Most objects downloaded from a prefix, since a set has at most 255 shards.
+const MAX_OBJECTS: usize = 255;
+
+/// A bucket and a key prefix, such as `backups/vault` for the keys starting with `vault/` in
+/// the bucket `backups`, where the shards of a set are stored one per object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// The prefix, without leading or trailing `/`, or empty for the root of the bucket.
+    pub prefix: String,
+}
+
+impl S3Location {
+    /// Returns the key of the shard of index `index`, named like the files of
+    /// `sink::DirectorySink` with the extension `extension`, such as `vault/shards_0.sss`.
+    pub fn shard_key(&self, index: usize, extension: &str) -> String {
+        if extension.is_empty() {
+            self.key(&format!("shards_{}", index))
+        } else {
+            self.key(&format!("shards_{}.{}", index, extension))
+        }
+    }
+
+    /// Returns the key of `name` under the prefix.
+    fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.prefix, name)
+        }
+    }
+
+    /// Returns the name of `key` if it is directly under the prefix, not in a deeper one.
+    fn name_of<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(&self.key(""))
+            .filter(|name| !name.is_empty() && !name.contains('/'))
+    }
+}
+
+impl FromStr for S3Location {
+    type Err = Error;
+
+    /// Parses `bucket/prefix`, optionally starting with `s3://`; the prefix may be left out.
+    fn from_str(location: &str) -> Result<Self> {
+        let path = location.strip_prefix("s3://").unwrap_or(location);
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        let valid_bucket = bucket
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.');
+        if bucket.is_empty() || !valid_bucket {
+            return Err(Error::InvalidParameters(format!(
+                "`{}` is not a bucket name followed by an optional prefix, such as backups/vault",
+                location
+            )));
+        }
+        Ok(S3Location {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+}
+
+impl fmt::Display for S3Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "s3://{}/{}", self.bucket, self.prefix)
+    }
+}
+
+/// A bucket of an S3-compatible object store, reached through the `rust-s3` client.
+///
+/// The store is configured like the AWS command line: `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and the optional `AWS_SESSION_TOKEN` hold the credentials,
+/// `AWS_REGION` or `AWS_DEFAULT_REGION` the region, and `AWS_ENDPOINT_URL` the endpoint of
+/// another store, such as `http://localhost:9000` for MinIO or
+/// `https://storage.googleapis.com` for Google Cloud Storage with HMAC keys. Objects are
+/// addressed in the path style, `{endpoint}/{bucket}/{key}`, which every store supports.
+#[derive(Debug)]
+pub struct Bucket {
+    bucket: Box<s3::Bucket>,
+}
+
+impl Bucket {
+    /// Opens the bucket `name` with the configuration of the environment.
+    ///
+    /// # Returns
+    ///
+    /// The bucket, or `Error::InvalidParameters` if the credentials are not set.
+    pub fn from_env(name: &str) -> Result<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let (Some(access_key), Some(secret_key)) =
+            (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY"))
+        else {
+            return Err(Error::InvalidParameters(
+                "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set to reach the object store"
+                    .to_string(),
+            ));
+        };
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| DEFAULT_REGION.to_string());
+        let endpoint = var("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let credentials = Credentials::new(
+            Some(&access_key),
+            Some(&secret_key),
+            None,
+            var("AWS_SESSION_TOKEN").as_deref(),
+            None,
+        )
+        .map_err(|e| Error::InvalidParameters(e.to_string()))?;
+        let region = Region::Custom {
+            region,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        };
+        let bucket = s3::Bucket::new(name, region, credentials)
+            .map_err(|e| Error::InvalidParameters(e.to_string()))?;
+        Ok(Bucket {
+            bucket: bucket.with_path_style(),
+        })
+    }
+
+    /// Stores `body` as the object `key`, replacing any object of that key.
+    pub fn put_object(&self, key: &str, body: &[u8]) -> Result<()> {
+        self.bucket
+            .put_object(key, body)
+            .map(|_| ())
+            .map_err(|e| failed("PUT", key, e))
+    }
+
+    /// Returns the content of the object `key`, or an `Error::Io` if it is larger than
+    /// `MAX_OBJECT_LEN`.
+    pub fn get_object(&self, key: &str) -> Result<Vec<u8>> {
+        let mut body = CappedBuffer(Vec::new());
+        self.bucket
+            .get_object_to_writer(key, &mut body)
+            .map_err(|e| failed("GET", key, e))?;
+        Ok(body.0)
+    }
+
+    /// Removes the object `key`.
+    pub fn delete_object(&self, key: &str) -> Result<()> {
+        self.bucket
+            .delete_object(key)
+            .map(|_| ())
+            .map_err(|e| failed("DELETE", key, e))
+    }
+
+    /// Returns the keys of the objects starting with `prefix`, in lexicographic order, going
+    /// through every page of the listing.
+    pub fn list_objects(&self, prefix: &str) -> Result<Vec<String>> {
+        let target = format!("the bucket {}", self.bucket.name());
+        let pages = self
+            .bucket
+            .list(prefix.to_string(), None)
+            .map_err(|e| failed("GET", &target, e))?;
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| object.key)
+            .collect())
+    }
+}
+
+/// Returns the `Error::Io` of a failed request for `target`, giving only the status of an
+/// error response rather than its XML body.
+fn failed(method: &str, target: &str, error: S3Error) -> Error {
+    let reason = match error {
+        S3Error::HttpFailWithBody(status, _) => format!("status {}", status),
+        e => e.to_string(),
+    };
+    Error::Io(io::Error::other(format!(
+        "{} {} failed: {}",
+        method, target, reason
+    )))
+}
+
+/// The content of a downloaded object, which fails to grow past `MAX_OBJECT_LEN`.
+struct CappedBuffer(Vec<u8>);
+
+impl Write for CappedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if (self.0.len() + buf.len()) as u64 > MAX_OBJECT_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the object is larger than the maximum of {} bytes",
+                    MAX_OBJECT_LEN
+                ),
+            ));
+        }
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Uploads every shard of a set to its own object, keyed by `S3Location::shard_key`.
+///
+/// A failed upload does not stop the others: every shard is tried, and `finish_upload`
+/// reports which ones were stored, so that the operator can retry the others.
+#[derive(Debug)]
+pub struct S3Sink {
+    bucket: Bucket,
+    location: S3Location,
+    extension: String,
+    /// The keys of the shards stored so far.
+    uploaded: Vec<String>,
+    /// Why each shard which could not be stored failed, naming its key.
+    failed: Vec<String>,
+}
+
+impl S3Sink {
+    /// Creates a sink uploading to `location` objects with the extension `extension`, which
+    /// may be empty, with the configuration of `Bucket::from_env`.
+    pub fn new(location: S3Location, extension: &str) -> Result<Self> {
+        Ok(S3Sink {
+            bucket: Bucket::from_env(&location.bucket)?,
+            location,
+            extension: extension.to_string(),
+            uploaded: Vec::new(),
+            failed: Vec::new(),
+        })
+    }
+
+    /// Returns the keys of the shards stored so far.
+    pub fn uploaded(&self) -> &[String] {
+        &self.uploaded
+    }
+
+    /// Returns the keys of the objects directly under the prefix named like the shards the
+    /// sink uploads, such as those of a previous set: `shards_<index>` with its extension.
+    pub fn existing_shards(&self) -> Result<Vec<String>> {
+        let is_shard_name = |name: &str| {
+            let index = if self.extension.is_empty() {
+                name.strip_prefix("shards_")
+            } else {
+                name.strip_prefix("shards_")
+                    .and_then(|name| name.strip_suffix(&self.extension))
+                    .and_then(|name| name.strip_suffix('.'))
+            };
+            index
+                .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+        };
+        Ok(self
+            .bucket
+            .list_objects(&self.location.key(""))?
+            .into_iter()
+            .filter(|key| self.location.name_of(key).is_some_and(is_shard_name))
+            .collect())
+    }
+
+    /// Copies the objects `keys` under the prefix to a `backup-<timestamp>` prefix below it,
+    /// such as `vault/backup-1700000000/shards_0.sss`, which `download_shards` ignores.
+    ///
+    /// # Returns
+    ///
+    /// The keys of the copies, in the order of `keys`, or the errors of `Bucket`.
+    pub fn back_up(&self, keys: &[String]) -> Result<Vec<String>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        keys.iter()
+            .map(|key| {
+                let name = self.location.name_of(key).unwrap_or(key);
+                let backup_key = self.location.key(&format!("backup-{}/{}", timestamp, name));
+                self.bucket
+                    .put_object(&backup_key, &self.bucket.get_object(key)?)?;
+                Ok(backup_key)
+            })
+            .collect()
+    }
+
+    /// Completes the upload, like `finish`, then removes the objects of `replaced` which no
+    /// shard overwrote, such as the extra shards of a previous, larger set, so that the two
+    /// sets are never mixed.
+    ///
+    /// # Returns
+    ///
+    /// The keys of the shards, the errors of `Bucket` if an object cannot be removed, or an
+    /// `Error::Io` listing the shards which were uploaded and those which were not if any
+    /// upload failed, in which case nothing is removed.
+    pub fn finish_upload(self, replaced: &[String]) -> Result<Vec<String>> {
+        if self.failed.is_empty() {
+            for stale_key in replaced.iter().filter(|key| !self.uploaded.contains(key)) {
+                self.bucket.delete_object(stale_key)?;
+            }
+            return Ok(self.uploaded);
+        }
+        let uploaded = if self.uploaded.is_empty() {
+            "no shard was uploaded".to_string()
+        } else {
+            format!("uploaded {}", self.uploaded.join(", "))
+        };
+        Err(Error::Io(io::Error::other(format!(
+            "{} to s3://{}, but {}",
+            uploaded,
+            self.location.bucket,
+            self.failed.join("; ")
+        ))))
+    }
+}
+
+impl ShardSink for S3Sink {
+    fn write_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        let key = self.location.shard_key(index, &self.extension);
+        match self.bucket.put_object(&key, bytes) {
+            Ok(()) => self.uploaded.push(key),
+            Err(e) => self.failed.push(e.to_string()),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.finish_upload(&[]).map(|_| ()).map_err(|e| match e {
+            Error::Io(e) => e,
+            e => io::Error::other(e),
+        })
+    }
+}
+
+/// Downloads the shards stored at `location` by `S3Sink`: the objects directly under the
+/// prefix, ignoring those in deeper prefixes such as backups.
+///
+/// # Returns
+///
+/// The name, such as `s3://backups/vault/shards_0.sss`, and content of every shard, the
+/// errors of `Bucket`, `Error::NoShardsFound` if there is no object under the prefix, or
+/// `Error::InvalidParameters` if there are more than `MAX_OBJECTS`.
+pub fn download_shards(location: &S3Location) -> Result<Vec<(String, Vec<u8>)>> {
+    let bucket = Bucket::from_env(&location.bucket)?;
+    let keys: Vec<String> = bucket
+        .list_objects(&location.key(""))?
+        .into_iter()
+        .filter(|key| location.name_of(key).is_some())
+        .collect();
+    if keys.is_empty() {
+        return Err(Error::NoShardsFound {
+            dir: location.to_string(),
+        });
+    }
+    if keys.len() > MAX_OBJECTS {
+        return Err(Error::InvalidParameters(format!(
+            "{} holds {} objects, more than the {} shards a set can have",
+            location,
+            keys.len(),
+            MAX_OBJECTS
+        )));
+    }
+    keys.into_iter()
+        .map(|key| {
+            let shard = bucket.get_object(&key)?;
+            Ok((format!("s3://{}/{}", location.bucket, key), shard))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_location() {
+        let location: S3Location = "s3://backups/vault/keys/".parse().unwrap();
+        assert_eq!(location.bucket, "backups");
+        assert_eq!(location.shard_key(2, "sss"), "vault/keys/shards_2.sss");
+        assert_eq!(location.shard_key(2, ""), "vault/keys/shards_2");
+        assert_eq!(location.name_of("vault/keys/shards_2"), Some("shards_2"));
+        assert_eq!(location.name_of("vault/keys/backup-1/shards_2"), None);
+        assert_eq!(location.to_string(), "s3://backups/vault/keys");
+
+        let location: S3Location = "backups".parse().unwrap();
+        assert_eq!(location.shard_key(0, "sss"), "shards_0.sss");
+        for invalid in ["", "/vault", "Backups/vault", "s3://"] {
+            assert!(invalid.parse::<S3Location>().is_err(), "{}", invalid);
+        }
+    }
+}
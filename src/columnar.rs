@@ -0,0 +1,192 @@
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{Error, Result},
+    header::{self, Header},
+};
+
+/// Magic bytes identifying a file holding a whole shard set in the column layout.
+pub const MAGIC: [u8; 4] = *b"SHMC";
+
+/// Name of the file written by the column layout, before its extension.
+pub const FILE_STEM: &str = "shards_columns";
+
+/// Serializes the header of a column layout file.
+///
+/// The file starts with `MAGIC`, followed by a shard header describing the whole set: its
+/// `parts` give the number of rows of the share matrix and its `secret_len` plus one the
+/// number of columns. The x-coordinate of this header is 0, since the x-coordinates of the
+/// shares form the last column of the matrix. The matrix follows, stored column by column so
+/// that byte `i` of every share is contiguous, then a SHA-256 checksum of everything before
+/// it, as written by `header::write_shard`.
+///
+/// # Arguments
+///
+/// * `set_header` - The header shared by the shards of the set; its x-coordinate is ignored.
+/// * `padded` - Whether to pad the shard header like `Header::to_padded_bytes`.
+///
+/// # Returns
+///
+/// The bytes preceding the share matrix.
+pub fn header_bytes(set_header: &Header, padded: bool) -> Vec<u8> {
+    let set_header = Header {
+        x: 0,
+        ..set_header.clone()
+    };
+    let shard_header = if padded {
+        set_header.to_padded_bytes()
+    } else {
+        set_header.to_bytes()
+    };
+    [&MAGIC[..], &shard_header].concat()
+}
+
+/// Transposes shares into the column-major share matrix of a column layout file.
+///
+/// # Arguments
+///
+/// * `shares` - The shares produced by `shamir::split`, each ending with its x-coordinate.
+///
+/// # Returns
+///
+/// Byte `i` of every share, in share order, for every `i` in turn.
+pub fn transpose(shares: &[impl AsRef<[u8]>]) -> Vec<u8> {
+    let share_len = shares.first().map_or(0, |share| share.as_ref().len());
+    (0..share_len)
+        .flat_map(|i| shares.iter().map(move |share| share.as_ref()[i]))
+        .collect()
+}
+
+/// Checks whether the bytes start with the column layout magic.
+pub fn has_magic(bytes: &[u8]) -> bool {
+    bytes.len() >= MAGIC.len() && header::ct_eq(&bytes[..MAGIC.len()], &MAGIC)
+}
+
+/// Splits a column layout file back into regular shard files, one per row of the matrix.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of the column layout file.
+///
+/// # Returns
+///
+/// The content of every shard file, as written by the row layout, `Error::ChecksumMismatch`
+/// if the checksum does not match, or `Error::InvalidHeader` if the header is invalid or the
+/// size of the matrix does not match it.
+pub fn decode_columns(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if !has_magic(bytes) {
+        return Err(invalid_header(
+            "file does not start with the column layout magic",
+        ));
+    }
+    let content_len = bytes
+        .len()
+        .checked_sub(header::CHECKSUM_LEN)
+        .ok_or_else(|| invalid_header("file is too short to contain a checksum"))?;
+    let (content, checksum) = bytes.split_at(content_len);
+    if !header::ct_eq(&Sha256::digest(content), checksum) {
+        return Err(Error::ChecksumMismatch);
+    }
+
+    let (set_header, header_len) = Header::from_bytes(&content[MAGIC.len()..])?;
+    let matrix = &content[MAGIC.len() + header_len..];
+    let rows = set_header.parts as usize;
+    let matrix_len = set_header
+        .secret_len
+        .checked_add(1)
+        .and_then(|columns| columns.checked_mul(rows as u64));
+    if rows == 0 || matrix_len != Some(matrix.len() as u64) {
+        return Err(invalid_header(
+            "share matrix size does not match the column layout header",
+        ));
+    }
+
+    Ok((0..rows)
+        .map(|row| {
+            let share: Vec<u8> = matrix.iter().skip(row).step_by(rows).copied().collect();
+            let (&x, body) = share.split_last().expect("share is non-empty");
+            let shard_header = Header {
+                x,
+                ..set_header.clone()
+            };
+            header::encode_shard(&shard_header, body)
+        })
+        .collect())
+}
+
+/// Builds an `Error::InvalidHeader` with the given message.
+fn invalid_header(message: &str) -> Error {
+    Error::InvalidHeader(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gf256::GfBackend, shamir};
+
+    /// Splits `secret` into a column layout file, returning it with the shares.
+    fn encode(secret: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let shares = shamir::split(secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
+        let set_header = Header {
+            threshold: 3,
+            parts: 5,
+            set_id: 7,
+            secret_len: secret.len() as u64,
+            ..Default::default()
+        };
+        let mut file = Vec::new();
+        header::write_shard(
+            &mut file,
+            &header_bytes(&set_header, false),
+            &transpose(&shares),
+        )
+        .unwrap();
+        (file, shares)
+    }
+
+    #[test]
+    fn test_columns_round_trip() {
+        let (file, shares) = encode(b"test");
+        // Byte 0 of every share comes first.
+        let matrix_start = MAGIC.len() + header::FIXED_HEADER_LEN;
+        let first_column: Vec<u8> = shares.iter().map(|share| share[0]).collect();
+        assert_eq!(file[matrix_start..matrix_start + 5], first_column[..]);
+
+        let shards = decode_columns(&file).unwrap();
+        assert_eq!(shards.len(), 5);
+        for (shard, share) in shards.iter().zip(&shares) {
+            let (shard_header, body) = header::decode_shard(shard).unwrap();
+            assert_eq!(shard_header.x, *share.last().unwrap());
+            assert_eq!(shard_header.set_id, 7);
+            assert_eq!(body, &share[..4]);
+        }
+    }
+
+    #[test]
+    fn test_decode_columns_invalid() {
+        let (mut file, _) = encode(b"test");
+        *file.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decode_columns(&file),
+            Err(Error::ChecksumMismatch)
+        ));
+
+        let (file, _) = encode(b"test");
+        let header_end = MAGIC.len() + header::FIXED_HEADER_LEN;
+        let mut truncated = Vec::new();
+        header::write_shard(
+            &mut truncated,
+            &file[..header_end],
+            &file[header_end..header_end + 9],
+        )
+        .unwrap();
+        assert!(matches!(
+            decode_columns(&truncated),
+            Err(Error::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            decode_columns(&file[MAGIC.len()..]),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+}
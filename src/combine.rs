@@ -1,79 +1,3546 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use colored::*;
+use sha2::{Digest, Sha256};
 use std::{
-    fs::File,
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
     io::{self, Read, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, UNIX_EPOCH},
 };
 
-use crate::shamir;
+use crate::{
+    archive, bundle,
+    cli::Verbosity,
+    columnar,
+    error::{Error, Result},
+    export,
+    gf256::GfBackend,
+    header::{self, Header},
+    json, mnemonic,
+    passphrase::Passphrase,
+    shamir, sharding,
+    sidecar::{self, Manifest},
+    stream,
+    timing::{self, Timings},
+};
+use zeroize::Zeroizing;
+
+/// The name and raw content of every shard read.
+type NamedShards = Vec<(String, Vec<u8>)>;
+
+/// Largest number of quorums `verify_quorums` may combine, so that verifying a large scheme
+/// is refused rather than running for hours: 2-of-255 already has 32385 quorums.
+pub const MAX_VERIFIED_QUORUMS: u128 = 10_000;
+
+/// Permissions of the recovered secret files unless `CombineOptions::mode` is set: readable
+/// and writable by their owner only, since a secret is often a private key.
+pub const SECRET_FILE_MODE: u32 = 0o600;
+
+/// UTF-8 byte order mark, which some editors add at the start of text files.
+const UTF8_BOM: char = '\u{feff}';
+
+/// Options controlling how shards are combined.
+#[derive(Debug, Clone, Default)]
+pub struct CombineOptions {
+    /// Whether an existing recovered secret file may be overwritten.
+    pub force: bool,
+    /// Whether to search subdirectories of the shards directory, up to `MAX_DEPTH` levels,
+    /// ignoring files that are not shards.
+    pub recursive: bool,
+    /// Whether failed set id, checksum and threshold checks are reported as warnings instead
+    /// of errors. The recovered secret may then be wrong.
+    pub force_combine: bool,
+    /// Whether data without the shard magic, which is neither a text nor a bare shard, is
+    /// read as a version 0 share instead of refused. Such shares carry no checksum, so any
+    /// file would combine: each one read is warned about.
+    pub legacy: bool,
+    /// Whether to reconstruct the secret from every disjoint quorum of threshold shards and
+    /// check that they agree, which detects a corrupted shard when enough shards are present.
+    pub redundancy_check: bool,
+    /// Whether to add an extension matching the content-type declared in the shards to a
+    /// recovered secret path which has none.
+    pub infer_extension: bool,
+    /// Whether the shards directory holds the texts of scanned QR codes, one file per
+    /// shard, instead of shard files. Requires the `qr` feature.
+    pub qr_text: bool,
+    /// Whether to combine the shards block by block with `stream::combine_stream`, checking
+    /// their block checksums, instead of reading them whole.
+    pub stream: bool,
+    /// The index of the record to combine when the shards are a bundle, from 0; it may be
+    /// left out for a bundle holding a single record.
+    pub record: Option<usize>,
+    /// The x-coordinates of the only shards to reconstruct the secret from, or empty to use
+    /// every shard read.
+    pub subset: Vec<u8>,
+    /// Whether to recover what can be recovered from damaged or truncated shards, setting
+    /// the bytes which cannot be to 0, instead of failing. See `recover_partial`.
+    pub partial: bool,
+    /// The age public keys to encrypt the recovered secret to before it is written, or empty
+    /// to write it in plaintext. Requires the `age` feature.
+    pub recipients: Vec<String>,
+    /// A sidecar listing the shards of the set, such as the `sidecar::FILE_NAME` written by
+    /// `shard --sidecar`, to check the shards read against before combining them. See
+    /// `check_manifest`.
+    pub manifest: Option<PathBuf>,
+    /// The passphrase the secret was split with, from which one more share is derived.
+    pub passphrase: Option<Passphrase>,
+    /// Whether shards past the expiry time recorded in their headers are refused, instead
+    /// of only warned about.
+    pub enforce_expiry: bool,
+    /// The SHA-256 hash the secret is known to have. If the shards reconstruct another
+    /// secret, their subsets are searched for one matching it, see `combine_expecting`.
+    pub expect_hash: Option<[u8; 32]>,
+    /// Whether shards of other sets are ignored instead of refused, keeping the largest
+    /// group of consistent shards which reaches its threshold. See `select_set`.
+    pub allow_extra: bool,
+    /// The Unix permissions of the recovered secret files, or `None` for `SECRET_FILE_MODE`.
+    /// They are not changed outside Unix, where files keep the permissions inherited from
+    /// their directory.
+    pub mode: Option<u32>,
+    /// Whether the recovered secret path is a directory in which the secret file is
+    /// recreated with the name, permissions and modification time recorded by
+    /// `shard --store-metadata`. An explicit `mode` still takes precedence.
+    pub restore_metadata: bool,
+    /// The GF(2^8) backend used to interpolate the secret.
+    pub gf_backend: GfBackend,
+    /// Controls the progress bar and whether each shard read is reported.
+    pub verbosity: Verbosity,
+    /// Collects the durations of reading the shards, combining them and writing the secret,
+    /// or `None` not to measure them.
+    pub timings: Option<Arc<Timings>>,
+}
+
+/// Warns that shards expired at `expires`, or fails with `Error::Expired` if
+/// `enforce_expiry` is set. The expiry is metadata only: expired shares still combine.
+///
+/// # Arguments
+///
+/// * `set_header` - The header common to the shards.
+/// * `now` - The current time, in seconds since the Unix epoch.
+/// * `enforce_expiry` - Whether expired shards are refused.
+pub(crate) fn check_expiry(set_header: &Header, now: u64, enforce_expiry: bool) -> Result<()> {
+    let Some(expires) = set_header.expires.filter(|_| set_header.is_expired(now)) else {
+        return Ok(());
+    };
+    if enforce_expiry {
+        return Err(Error::Expired { expires });
+    }
+    eprintln!(
+        "{} the shards expired at {} (seconds since the Unix epoch), {} seconds ago \
+         (--enforce-expiry to refuse them)",
+        "WARNING:".yellow().bold(),
+        expires,
+        now - expires
+    );
+    Ok(())
+}
+
+/// Fails with `error`, or only prints it as a warning when `force_combine` is set.
+fn validation_failure(error: Error, force_combine: bool) -> Result<()> {
+    if !force_combine {
+        return Err(error);
+    }
+    eprintln!("{} {}", "WARNING:".yellow().bold(), error);
+    Ok(())
+}
+
+/// Parses the content of a shard and verifies its header and checksum.
+///
+/// Data without the shard magic is decoded first if it is a shard stored as text, see
+/// `decode_text_shard`. Otherwise, it is accepted as a bare share when its name, without
+/// extension, is an x-coordinate between 1 and 255 (e.g. `3.bin`): the whole data is then
+/// used as the y-coordinates. Any other data is refused, unless `legacy` is set: it is then
+/// read as a version 0 shard, written before the header was introduced, whose last byte is
+/// the x-coordinate. Since the y-coordinates of these shares may start with the magic by
+/// chance, data with the magic but an invalid header falls back to them the same way.
+///
+/// # Arguments
+///
+/// * `name` - The name of the shard, typically its path.
+/// * `shard_data` - The raw content of the shard.
+/// * `force_combine` - Whether a checksum mismatch is only reported as a warning.
+/// * `legacy` - Whether data without the shard magic may be a version 0 shard.
+///
+/// # Returns
+///
+/// The shard's `Header`, if it has one, and its share, made of the y-coordinates followed by
+/// the x-coordinate as expected by `shamir::combine`. Invalid shards yield an `InvalidData`
+/// error naming the shard.
+fn parse_shard(
+    name: &str,
+    mut shard_data: Vec<u8>,
+    force_combine: bool,
+    legacy: bool,
+) -> Result<(Option<Header>, Vec<u8>)> {
+    let bare_x = bare_share_x(Path::new(name));
+    let starts_with_magic = header::has_magic(&shard_data);
+    if !starts_with_magic {
+        if let Some(decoded) = decode_text_shard(&shard_data) {
+            shard_data = decoded;
+        } else if let Some(x) = bare_x {
+            shard_data.push(x);
+            return Ok((None, shard_data));
+        } else if legacy {
+            return legacy_share(name, shard_data).map(|share| (None, share));
+        } else {
+            let error = Error::InvalidHeader(
+                "shard does not start with the shard magic (use --legacy to read it as a \
+                 version 0 shard)"
+                    .to_string(),
+            );
+            return Err(error.in_shard(name));
+        }
+    }
+
+    let decoded = if force_combine {
+        header::decode_shard_unverified(&shard_data)
+    } else {
+        header::decode_shard(&shard_data).map(|(shard_header, body)| (shard_header, body, true))
+    };
+    let (shard_header, body, checksum_matches) = match decoded {
+        Ok(decoded) => decoded,
+        // The y-coordinates of a bare or version 0 share may start with the magic by chance.
+        Err(Error::InvalidHeader(_)) if starts_with_magic && bare_x.is_some() => {
+            shard_data.extend(bare_x);
+            return Ok((None, shard_data));
+        }
+        Err(Error::InvalidHeader(_)) if starts_with_magic && legacy => {
+            return legacy_share(name, shard_data).map(|share| (None, share));
+        }
+        Err(e) => return Err(e.in_shard(name)),
+    };
+    if !checksum_matches {
+        validation_failure(Error::ChecksumMismatch.in_shard(name), true)?;
+    }
+    let mut share = body.to_vec();
+    share.push(shard_header.x);
+
+    Ok((Some(shard_header), share))
+}
+
+/// Checks a version 0 shard, made of the share alone as expected by `shamir::combine`: the
+/// y-coordinates followed by the x-coordinate, without magic or checksum, and warns that it
+/// is unverified.
+///
+/// # Returns
+///
+/// The share, or `Error::InvalidHeader` naming the shard if it has no y-coordinate or its
+/// last byte is not a valid x-coordinate, so that it is neither a legacy nor a current shard.
+fn legacy_share(name: &str, share: Vec<u8>) -> Result<Vec<u8>> {
+    match share.split_last() {
+        Some((&x, ys)) if x != 0 && !ys.is_empty() => {
+            eprintln!(
+                "{} {} has no shard magic and is read as a version 0 shard, without any check",
+                "WARNING:".yellow().bold(),
+                name
+            );
+            Ok(share)
+        }
+        _ => Err(
+            Error::InvalidHeader("shard does not start with the shard magic".to_string())
+                .in_shard(name),
+        ),
+    }
+}
+
+/// Decodes a shard stored as hexadecimal or base64 text, as copied or edited by hand.
+///
+/// A UTF-8 byte order mark is skipped and all whitespace is removed before decoding, so
+/// CRLF line endings, surrounding blank lines and base64 wrapped over several lines are
+/// accepted.
+///
+/// # Returns
+///
+/// The raw content of the shard, or `None` if the data is not text in either encoding or
+/// does not decode to data starting with the shard magic.
+fn decode_text_shard(data: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(data).ok()?;
+    let text: String = text
+        .trim_start_matches(UTF8_BOM)
+        .split_whitespace()
+        .collect();
+    let shard = decode_hex(&text)
+        .ok()
+        .or_else(|| STANDARD.decode(&text).ok())?;
+    header::has_magic(&shard).then_some(shard)
+}
+
+/// Picks a file extension for a content-type, ignoring its parameters.
+///
+/// # Arguments
+///
+/// * `content_type` - The content-type declared in the shard headers, such as
+///   `text/plain; charset=utf-8`.
+///
+/// # Returns
+///
+/// The extension, without the leading dot, or `None` for an unknown content-type.
+fn extension_for(content_type: &str) -> Option<&'static str> {
+    let essence = content_type.split(';').next()?.trim();
+    let extension = match essence.to_ascii_lowercase().as_str() {
+        "text/plain" => "txt",
+        "text/csv" => "csv",
+        "application/json" => "json",
+        "application/x-pem-file" => "pem",
+        "application/pgp-keys" => "asc",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/octet-stream" => "bin",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Extracts the x-coordinate of a bare share from its file name.
+///
+/// # Arguments
+///
+/// * `path` - The path of the bare share, such as `shards/3.bin`.
+///
+/// # Returns
+///
+/// The x-coordinate if the file stem parses as a nonzero `u8`, `None` otherwise.
+pub fn bare_share_x(path: &Path) -> Option<u8> {
+    path.file_stem()?
+        .to_str()?
+        .parse::<u8>()
+        .ok()
+        .filter(|&x| x != 0)
+}
+
+/// Reads shard files, decompressing those stored gzip-compressed, decoding those written as
+/// words by `shard --encoding mnemonic` and splitting column layout files into the shards
+/// they hold.
+///
+/// # Arguments
+///
+/// * `shard_paths` - The paths of the shard files.
+///
+/// # Returns
+///
+/// The name and raw content of every shard, or the error of an unreadable file or an invalid
+/// column layout file. The files are opened by their paths, so non-UTF-8 paths are read
+/// correctly; only the names, used in messages, may be lossy.
+fn read_shards<P: AsRef<Path>>(shard_paths: &[P]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut shards = Vec::new();
+    for shard_path in shard_paths {
+        let mut shard_data = fs::read(shard_path)?;
+        let name = shard_path.as_ref().display().to_string();
+        // Bare shares are random data, which may start with the gzip magic by chance.
+        match archive::gunzip(&shard_data) {
+            Some(Ok(decompressed)) => shard_data = decompressed,
+            Some(Err(e)) if bare_share_x(shard_path.as_ref()).is_none() => {
+                return Err(Error::from(e).in_shard(&name));
+            }
+            _ => (),
+        }
+        if bare_share_x(shard_path.as_ref()).is_none() {
+            if let Some(text) = mnemonic::as_mnemonic(&shard_data) {
+                shard_data = mnemonic::decode(text).map_err(|e| e.in_shard(&name))?;
+            }
+        }
+        if columnar::has_magic(&shard_data) {
+            let rows = columnar::decode_columns(&shard_data).map_err(|e| e.in_shard(&name))?;
+            shards.extend(
+                rows.into_iter()
+                    .enumerate()
+                    .map(|(row, shard)| (format!("{} row {}", name, row), shard)),
+            );
+        } else {
+            shards.push((name, shard_data));
+        }
+    }
+    Ok(shards)
+}
+
+/// Reads the shards scanned from QR codes.
+///
+/// # Arguments
+///
+/// * `texts_dir` - The directory holding one file per shard, with the texts of the shard's
+///   QR codes one per line in any order, as accepted by `qr::decode_qr_text`.
+///
+/// # Returns
+///
+/// The name and raw content of every shard, or the error of an unreadable or invalid text.
+#[cfg(feature = "qr")]
+fn read_qr_texts(texts_dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut text_paths = fs::read_dir(texts_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    text_paths.retain(|path| path.is_file());
+    text_paths.sort();
+
+    text_paths
+        .iter()
+        .map(|text_path| {
+            let name = text_path.display().to_string();
+            let shard = crate::qr::decode_qr_text(&fs::read_to_string(text_path)?)
+                .map_err(|e| e.in_shard(&name))?;
+            Ok((name, shard))
+        })
+        .collect()
+}
+
+/// QR texts cannot be decoded when the `qr` feature is disabled.
+#[cfg(not(feature = "qr"))]
+fn read_qr_texts(_: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    Err(Error::InvalidParameters(
+        "QR texts require building with the `qr` feature".to_string(),
+    ))
+}
+
+/// Encrypts the recovered secret to age recipients, see `encrypt::encrypt_to`.
+#[cfg(feature = "age")]
+fn encrypt_secret(recipients: &[String], secret: &[u8]) -> Result<Vec<u8>> {
+    crate::encrypt::encrypt_to(recipients, secret)
+}
+
+/// The secret cannot be encrypted when the `age` feature is disabled.
+#[cfg(not(feature = "age"))]
+fn encrypt_secret(_: &[String], _: &[u8]) -> Result<Vec<u8>> {
+    Err(Error::InvalidParameters(
+        "encrypting the secret requires building with the `age` feature".to_string(),
+    ))
+}
+
+/// Decodes a shard given as a hexadecimal string.
+///
+/// # Arguments
+///
+/// * `hex` - The hexadecimal digits of the shard, in either case, with a UTF-8 byte order mark
+///   and surrounding whitespace ignored.
+///
+/// # Returns
+///
+/// The raw content of the shard, or `Error::InvalidParameters` if the string has an odd
+/// number of digits or characters which are not hexadecimal digits.
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim().trim_start_matches(UTF8_BOM).trim_start();
+    if !hex.len().is_multiple_of(2) {
+        return Err(Error::InvalidParameters(
+            "hex shard has an odd number of digits".to_string(),
+        ));
+    }
+    if let Some(c) = hex.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidParameters(format!(
+            "hex shard contains `{}`, which is not a hexadecimal digit",
+            c
+        )));
+    }
+
+    Ok(json::decode_hex(hex).expect("digits were checked"))
+}
 
-/// Combines data from multiple "shard" files into a single secret file.
+/// Combines shards given as hexadecimal strings into a single secret file, without reading
+/// anything from the filesystem.
 ///
 /// # Arguments
 ///
-/// * `shard_paths` - A slice of `String` containing the paths to the shards to be combined.
+/// * `hex_shards` - The content of every shard, hex-encoded.
 /// * `output_path` - The path where the combined result file will be saved.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination before anything is written.
 ///
 /// # Returns
 ///
-/// This function returns an `io::Result<()>`. On success, it returns `Ok(())`.
-/// On failure, it returns an `io::Error` that can occur during the reading of shard files
-/// or writing of the secret file.
+/// This function returns a `Result<PathBuf>`, with the same errors as `combine_shards`, or
+/// `Error::InvalidParameters` naming the shard if a string is not valid hexadecimal.
 ///
 /// # Examples
 ///
 /// ```
-/// let shard_paths = vec!["./shard1.txt".to_string(), "./shard2.txt".to_string()];
-/// let output_path = Path::new("./secret_combined.txt");
-/// let result = combine_files(&shard_paths, &output_path);
-///
-/// assert!(result.is_ok());
+/// let hex_shards = vec!["53484d52...".to_string(), "53484d52...".to_string()];
+/// let cancel = AtomicBool::new(false);
+/// combine_hex(&hex_shards, Path::new("secret"), &CombineOptions::default(), &cancel)?;
 /// ```
-fn combine_files(shard_paths: &[String], output_path: &Path) -> io::Result<()> {
-    let mut parts = Vec::new();
+pub fn combine_hex(
+    hex_shards: &[String],
+    output_path: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    check_output_path(output_path, options)?;
 
-    for shard_path in shard_paths {
-        let mut file = File::open(shard_path)?;
-        let mut shard_data = Vec::new();
-        file.read_to_end(&mut shard_data)?;
-        parts.push(shard_data);
-    }
+    combine_shards(read_hex(hex_shards)?, output_path, options, cancel)
+}
 
-    let secret = shamir::combine(parts);
-    let mut output_file = File::create(output_path)?;
-    output_file.write_all(&secret)?;
+/// Reconstructs a secret from shards given as hexadecimal strings without writing it to disk.
+///
+/// # Arguments
+///
+/// * `hex_shards` - The content of every shard, hex-encoded.
+/// * `options` - The `CombineOptions` to apply; the options about the recovered secret file
+///   are ignored.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The secret and the header of the shard set, as returned by `recover_secret`, with the
+/// errors of `combine_hex`.
+pub fn recover_hex(
+    hex_shards: &[String],
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    recover_shards(read_hex(hex_shards)?, options, cancel)
+}
 
-    Ok(())
+/// Reconstructs a secret from shards as they arrive, such as shards streamed from the
+/// network, without holding their raw contents at the same time.
+///
+/// The shards are read in order, giving up on the first one which fails to arrive or to
+/// parse or which does not belong to the set, as `recover_shards` does. Only the shares are
+/// kept until they are combined. Checking a manifest and `options.allow_extra` need every
+/// shard at once, so the shards are buffered with either of them.
+///
+/// # Arguments
+///
+/// * `shards` - The raw content of every shard, or the error which stopped it from
+///   arriving. The shards are named by their position in the errors of this function.
+/// * `options` - The `CombineOptions` to apply; the options about the recovered secret file
+///   are ignored.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The secret, the first error of `shards` as it is, or the errors of `recover_secret`.
+///
+/// # Examples
+///
+/// ```
+/// let shards = shard_paths.iter().map(|path| Ok(fs::read(path)?));
+/// let secret = combine_from_iter(shards, &CombineOptions::default(), &cancel)?;
+/// ```
+pub fn combine_from_iter<I: Iterator<Item = Result<Vec<u8>>>>(
+    shards: I,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<Vec<u8>> {
+    let shards = shards
+        .enumerate()
+        .map(|(index, shard)| Ok((format!("shard {}", index + 1), shard?)));
+    let (secret, _) = if options.manifest.is_some() || options.allow_extra {
+        recover_shards(shards.collect::<Result<_>>()?, options, cancel)?
+    } else {
+        let shards = shards.map(|shard| parse_named_shard(shard?, options));
+        recover_stream(shards, options, cancel)?
+    };
+    Ok(secret)
 }
 
-/// Orchestrates the combination of shard files found within a specified directory
-/// and writes the result to the given file path.
+/// Reconstructs a secret from shards exported to environment variables by
+/// `shard --export-env`.
 ///
 /// # Arguments
 ///
-/// * `shards_dir` - A `PathBuf` pointing to the directory containing the shards.
-/// * `recovered_secret_path` - A `PathBuf` specifying the path where the recovered secret will be written.
+/// * `prefix` - The prefix of the variables, followed by the number of each shard. Gaps in
+///   the numbering are allowed.
+/// * `output_path` - The path where the recovered secret is written.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination.
 ///
 /// # Returns
 ///
-/// This function returns an `io::Result<()>`. On success, it returns `Ok(())`.
-/// On failure, it returns an `io::Error`.
+/// The path of the recovered secret, with the errors of `combine_shards` and
+/// `export::from_env_vars`.
 ///
 /// # Examples
 ///
 /// ```
-/// use std::path::PathBuf;
+/// let cancel = AtomicBool::new(false);
+/// combine_env("SHARD_", Path::new("secret"), &CombineOptions::default(), &cancel)?;
+/// ```
+pub fn combine_env(
+    prefix: &str,
+    output_path: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    check_output_path(output_path, options)?;
+
+    let shards = export::from_env_vars(std::env::vars_os(), prefix)?;
+    combine_shards(shards, output_path, options, cancel)
+}
+
+/// Reconstructs a secret from shards exported to environment variables without writing it
+/// to disk, like `combine_env`.
+pub fn recover_env(
+    prefix: &str,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    recover_shards(
+        export::from_env_vars(std::env::vars_os(), prefix)?,
+        options,
+        cancel,
+    )
+}
+
+/// Reconstructs a secret from shards stored in an S3-compatible object store by
+/// `shard --s3`.
 ///
-/// let shards_dir = PathBuf::from("./shards");
-/// let recovered_secret_path = PathBuf::from("./recovered_secret.txt");
-/// let result = combine_secret(&shards_dir, &recovered_secret_path);
+/// # Arguments
+///
+/// * `location` - The bucket and key prefix of the shards, such as `backups/vault`. Every
+///   object directly under the prefix is read as a shard.
+/// * `output_path` - The path where the recovered secret is written.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The path of the recovered secret, with the errors of `combine_shards` and
+/// `cloud::download_shards`, or `Error::InvalidParameters` if the `cloud` feature is
+/// disabled.
+///
+/// # Examples
 ///
-/// assert!(result.is_ok());
 /// ```
-pub fn combine_secret(shards_dir: &PathBuf, recovered_secret_path: &Path) -> io::Result<()> {
-    let shard_paths: Vec<String> = std::fs::read_dir(shards_dir)?
-        .filter_map(Result::ok)
-        .map(|entry| entry.path().display().to_string())
+/// let cancel = AtomicBool::new(false);
+/// combine_s3("backups/vault", Path::new("secret"), &CombineOptions::default(), &cancel)?;
+/// ```
+pub fn combine_s3(
+    location: &str,
+    output_path: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    check_output_path(output_path, options)?;
+
+    let shards = timing::measure(options.timings.as_deref(), "read shards", || {
+        read_s3_shards(location)
+    })?;
+    combine_shards(shards, output_path, options, cancel)
+}
+
+/// Reconstructs a secret from shards stored in an object store without writing it to disk,
+/// like `combine_s3`.
+pub fn recover_s3(
+    location: &str,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    let shards = timing::measure(options.timings.as_deref(), "read shards", || {
+        read_s3_shards(location)
+    })?;
+    recover_shards(shards, options, cancel)
+}
+
+#[cfg(feature = "cloud")]
+fn read_s3_shards(location: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    crate::cloud::download_shards(&location.parse()?)
+}
+
+/// Object stores are rejected when the `cloud` feature is disabled.
+#[cfg(not(feature = "cloud"))]
+fn read_s3_shards(_: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    Err(Error::InvalidParameters(
+        "reading the shards from an object store requires building with the `cloud` feature"
+            .to_string(),
+    ))
+}
+
+/// Decodes shards given as hexadecimal strings, naming them by their position.
+fn read_hex(hex_shards: &[String]) -> Result<Vec<(String, Vec<u8>)>> {
+    hex_shards
+        .iter()
+        .enumerate()
+        .map(|(index, hex)| {
+            let name = format!("hex shard {}", index + 1);
+            let shard = decode_hex(hex).map_err(|e| e.in_shard(&name))?;
+            Ok((name, shard))
+        })
+        .collect()
+}
+
+/// Reconstructs a secret in memory from shards held in memory.
+///
+/// All shards must carry the same set id, compared in constant time, and at least as many
+/// shards as the threshold recorded in their headers must be provided, unless
+/// `options.force_combine` is set. At least two distinct shares are always required. If the
+/// shards carry a hash of the secret, a warning is printed when the recovered secret does
+/// not match it.
+///
+/// # Arguments
+///
+/// * `shards` - The name and raw content of every shard.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The secret and the header of the first shard which has one, or `None` if all the shards
+/// are bare shares. Invalid or insufficient shards yield the same errors as `combine_shards`.
+fn recover_shards(
+    shards: Vec<(String, Vec<u8>)>,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    check_manifest(&shards, options)?;
+    if options.allow_extra {
+        let shards = shards
+            .into_iter()
+            .map(|shard| parse_named_shard(shard, options))
+            .collect::<Result<_>>()?;
+        let shards = select_set(shards, options)?;
+        recover_stream(shards.into_iter().map(Ok), options, cancel)
+    } else {
+        let shards = shards
+            .into_iter()
+            .map(|shard| parse_named_shard(shard, options));
+        recover_stream(shards, options, cancel)
+    }
+}
+
+/// A shard converted into its share by `parse_shard`: its name, its header unless it is a
+/// bare share, and the share.
+type ParsedShard = (String, Option<Header>, Vec<u8>);
+
+/// Converts a named shard into its share with `parse_shard`, keeping its name.
+fn parse_named_shard(
+    (name, data): (String, Vec<u8>),
+    options: &CombineOptions,
+) -> Result<ParsedShard> {
+    let (shard_header, share) = parse_shard(&name, data, options.force_combine, options.legacy)?;
+    Ok((name, shard_header, share))
+}
+
+/// Reconstructs a secret from shards read and parsed one at a time, for `recover_shards`
+/// and `combine_from_iter`.
+fn recover_stream(
+    shards: impl Iterator<Item = Result<ParsedShard>>,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    let mut parts = Vec::new();
+    // The name of the shard of every part, to report corrupted ones.
+    let mut names = Vec::new();
+    let mut set_header: Option<Header> = None;
+    // The name of the shard `set_header` was taken from.
+    let mut first_path = String::new();
+
+    for shard in shards {
+        let (shard_path, shard_header, share) = shard?;
+        if options.verbosity == Verbosity::Verbose {
+            let x = share.last().expect("share is non-empty");
+            println!("Read shard {} (x = {})", shard_path, x);
+        }
+
+        match (&set_header, shard_header) {
+            (_, None) => (),
+            (Some(first), Some(shard_header))
+                if !header::ct_eq(
+                    &first.set_id.to_be_bytes(),
+                    &shard_header.set_id.to_be_bytes(),
+                ) =>
+            {
+                let error =
+                    Error::InconsistentShards("shard belongs to a different share set".to_string());
+                validation_failure(error.in_shard(&shard_path), options.force_combine)?;
+            }
+            (Some(first), Some(shard_header)) => {
+                // Shards written by different versions of the format still combine as long
+                // as these agree.
+                if let Err(error) =
+                    check_same_scheme(&first_path, first, &shard_path, &shard_header)
+                {
+                    validation_failure(error, options.force_combine)?;
+                }
+            }
+            (None, shard_header) => {
+                set_header = shard_header;
+                first_path.clone_from(&shard_path);
+            }
+        }
+        parts.push(share);
+        names.push(shard_path);
+    }
+
+    if let Some(set_header) = &set_header {
+        check_expiry(set_header, timing::unix_now(), options.enforce_expiry)?;
+    }
+
+    if let Some(passphrase) = &options.passphrase {
+        let description = set_header
+            .as_ref()
+            .and_then(|first| first.passphrase.as_ref())
+            .ok_or_else(|| {
+                Error::InvalidParameters("the shards were not split with a passphrase".to_string())
+            })?;
+        let len = set_header
+            .as_ref()
+            .map_or(0, |first| first.secret_len as usize);
+        let mut share = passphrase.share(description, len)?;
+        parts.push(std::mem::take(&mut *share));
+        names.push("the passphrase share".to_string());
+    }
+
+    // A subset is checked against the threshold by `shamir::combine_subset` instead.
+    if let (Some(first), true) = (&set_header, options.subset.is_empty()) {
+        if parts.len() < first.threshold as usize {
+            let error = Error::InsufficientShares {
+                have: parts.len(),
+                need: first.threshold as usize,
+            };
+            validation_failure(error, options.force_combine)?;
+        }
+    }
+
+    // Interpolation is impossible without two distinct points, whatever the options.
+    let distinct_x: HashSet<u8> = parts
+        .iter()
+        .filter_map(|part| part.last().copied())
+        .collect();
+    if distinct_x.len() < 2 {
+        return Err(Error::InsufficientShares {
+            have: distinct_x.len(),
+            need: 2,
+        });
+    }
+
+    let threshold = set_header.as_ref().map(|first| first.threshold as usize);
+    let timings = options.timings.as_deref();
+    let secret = timing::measure(timings, "combine", || -> Result<_> {
+        Ok(match threshold {
+            _ if !options.subset.is_empty() => {
+                // Bare shares do not record their threshold, so only the minimum is enforced.
+                let need = threshold.filter(|_| !options.force_combine).unwrap_or(2);
+                let progress = options.verbosity > Verbosity::Quiet;
+                shamir::combine_subset(
+                    &parts,
+                    &options.subset,
+                    need,
+                    options.gf_backend,
+                    Some(cancel),
+                    progress,
+                )?
+            }
+            _ if options.expect_hash.is_some() => {
+                combine_expecting(parts, &names, threshold, options, cancel)?
+            }
+            Some(threshold) if options.redundancy_check => {
+                combine_quorums(parts, threshold, options, cancel)?
+            }
+            _ => {
+                if options.redundancy_check && options.verbosity > Verbosity::Quiet {
+                    eprintln!(
+                        "{} the threshold of bare shares is unknown, skipping the redundancy check",
+                        "WARNING:".yellow().bold()
+                    );
+                }
+                let progress = options.verbosity > Verbosity::Quiet;
+                shamir::combine(parts, options.gf_backend, Some(cancel), progress)?
+            }
+        })
+    })?;
+    check_recovered_len(&secret, set_header.as_ref())?;
+    if set_header
+        .as_ref()
+        .and_then(|first| first.verify_secret(&secret))
+        == Some(false)
+    {
+        eprintln!(
+            "{}",
+            "WARNING: the recovered secret does not match the hash stored in the shards, \
+             at least one shard is corrupted"
+                .red()
+                .bold()
+        );
+    }
+
+    Ok((secret, set_header))
+}
+
+/// Keeps the shards of a single set among shards gathered from several operations, such as
+/// a directory holding the shards of an old and a new split, for `combine --allow-extra`.
+///
+/// The shards are grouped by set id, threshold, parts and secret length, and the group with
+/// the most distinct shares among those reaching their threshold is kept; ties go to the
+/// group read first. Bare shares cannot be told apart, so they are kept only when no shard
+/// has a header. The choice is reported unless `options.verbosity` is quiet.
+///
+/// # Arguments
+///
+/// * `shards` - Every shard, as parsed by `parse_named_shard`.
+/// * `options` - The `CombineOptions` to apply; a passphrase counts as one more share.
+///
+/// # Returns
+///
+/// The shards of the chosen group, in the order they were read, or
+/// `Error::InsufficientShares` for the largest group if none reaches its threshold.
+fn select_set(shards: Vec<ParsedShard>, options: &CombineOptions) -> Result<Vec<ParsedShard>> {
+    // Shards of one set agree on more than their set id, see `check_same_scheme`.
+    let set_key = |header: &Header| {
+        (
+            header.set_id,
+            header.threshold,
+            header.parts,
+            header.secret_len,
+        )
+    };
+    // The groups in the order they were first read.
+    let mut groups: Vec<ShardGroup> = Vec::new();
+    for (index, (_, shard_header, share)) in shards.iter().enumerate() {
+        let Some(shard_header) = shard_header else {
+            continue;
+        };
+        let x = *share.last().expect("share is non-empty");
+        match groups
+            .iter_mut()
+            .find(|group| set_key(&group.header) == set_key(shard_header))
+        {
+            Some(group) => {
+                group.xs.insert(x);
+                group.indices.push(index);
+            }
+            None => groups.push(ShardGroup {
+                header: shard_header.clone(),
+                xs: HashSet::from([x]),
+                indices: vec![index],
+            }),
+        }
+    }
+    if groups.is_empty() {
+        return Ok(shards);
+    }
+
+    let extra_shares = usize::from(options.passphrase.is_some());
+    let have = |group: &ShardGroup| group.xs.len() + extra_shares;
+    let need = |group: &ShardGroup| group.header.threshold as usize;
+    // `max_by_key` returns the last of equal groups, hence the reversal.
+    let largest = |reaching_threshold: bool| {
+        groups
+            .iter()
+            .filter(|group| !reaching_threshold || have(group) >= need(group))
+            .rev()
+            .max_by_key(|group| group.xs.len())
+    };
+    let Some(chosen) = largest(true) else {
+        let group = largest(false).expect("groups is non-empty");
+        return Err(Error::InsufficientShares {
+            have: have(group),
+            need: need(group),
+        });
+    };
+
+    let (kept, ignored): (Vec<_>, Vec<_>) = shards
+        .into_iter()
+        .enumerate()
+        .partition(|(index, _)| chosen.indices.contains(index));
+    if options.verbosity > Verbosity::Quiet && !ignored.is_empty() {
+        let names: Vec<&str> = ignored
+            .iter()
+            .map(|(_, (name, ..))| name.as_str())
+            .collect();
+        println!(
+            "Combining the {} shards of set {:016x}, ignoring {}",
+            chosen.indices.len(),
+            chosen.header.set_id,
+            names.join(", ")
+        );
+    }
+    Ok(kept.into_iter().map(|(_, shard)| shard).collect())
+}
+
+/// The shards of one set found by `select_set`.
+struct ShardGroup {
+    /// The header of the first shard of the set.
+    header: Header,
+    /// The distinct x-coordinates of the shares.
+    xs: HashSet<u8>,
+    /// The positions of the shards among those read.
+    indices: Vec<usize>,
+}
+
+/// Checks that a shard declares the same threshold, parts and secret length as the first
+/// shard read, since shares of different schemes or secrets cannot be interpolated together
+/// even when their bodies have the same length.
+///
+/// # Arguments
+///
+/// * `first_name` - The name of the first shard read.
+/// * `first` - The header of the first shard read.
+/// * `name` - The name of the shard to check.
+/// * `shard_header` - The header of the shard to check.
+///
+/// # Returns
+///
+/// `Error::InconsistentShards` naming both shards and every value on which they disagree.
+pub fn check_same_scheme(
+    first_name: &str,
+    first: &Header,
+    name: &str,
+    shard_header: &Header,
+) -> Result<()> {
+    let conflicts: Vec<String> = [
+        ("secret length", first.secret_len, shard_header.secret_len),
+        (
+            "threshold",
+            first.threshold.into(),
+            shard_header.threshold.into(),
+        ),
+        ("parts", first.parts.into(), shard_header.parts.into()),
+    ]
+    .into_iter()
+    .filter(|(_, expected, actual)| expected != actual)
+    .map(|(field, expected, actual)| format!("{} {} instead of {}", field, actual, expected))
+    .collect();
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    Err(Error::InconsistentShards(format!(
+        "{} declares {} declared by {}",
+        name,
+        conflicts.join(", "),
+        first_name
+    )))
+}
+
+/// Checks the shards read against the sidecar given as `options.manifest`, if any, before
+/// they are combined, so that a deleted or foreign shard is named rather than only making
+/// the combination fail.
+///
+/// Missing shards are reported in a warning, since the others may still reach the
+/// threshold. Unexpected shards fail with `Error::InconsistentShards`, or are only reported
+/// as well when `options.force_combine` is set.
+///
+/// # Returns
+///
+/// `Ok(())` if there is no manifest or the shards may be combined, `Error::InvalidParameters`
+/// if the manifest does not list the shards, or the errors of `sidecar::read_manifest` and
+/// `compare_manifest`.
+fn check_manifest(shards: &[(String, Vec<u8>)], options: &CombineOptions) -> Result<()> {
+    let Some(manifest_path) = &options.manifest else {
+        return Ok(());
+    };
+    let manifest = sidecar::read_manifest(manifest_path)?;
+    if manifest.shards.is_empty() {
+        return Err(Error::InvalidParameters(format!(
+            "{} does not list the shards of the set",
+            manifest_path.display()
+        )));
+    }
+    let (missing, unexpected) = compare_manifest(&manifest, shards)?;
+    if !missing.is_empty() {
+        eprintln!(
+            "{} shards listed in the manifest are missing: {}",
+            "WARNING:".yellow().bold(),
+            missing.join(", ")
+        );
+    }
+    if !unexpected.is_empty() {
+        let error = Error::InconsistentShards(format!(
+            "shards not listed in the manifest: {}",
+            unexpected.join(", ")
+        ));
+        validation_failure(error, options.force_combine)?;
+    }
+    Ok(())
+}
+
+/// Matches the shards read with those listed in a manifest, by file name and x-coordinate.
+///
+/// # Arguments
+///
+/// * `manifest` - The content of the sidecar listing the shards.
+/// * `shards` - The name and raw content of every shard read.
+///
+/// # Returns
+///
+/// The file names of the listed shards which were not read, and the names of the shards read
+/// which are not listed or have another x-coordinate, or `Error::InconsistentShards` naming
+/// a shard whose header has another set id than the manifest.
+fn compare_manifest(
+    manifest: &Manifest,
+    shards: &[(String, Vec<u8>)],
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut present = HashSet::new();
+    let mut unexpected = Vec::new();
+    for (name, data) in shards {
+        let path = Path::new(name);
+        let file_name = path
+            .file_name()
+            .map_or_else(|| name.clone(), |name| name.to_string_lossy().into_owned());
+        // Bare shares have no header, only the x-coordinate of their name.
+        let (x, set_id) = match Header::from_bytes(data) {
+            Ok((shard_header, _)) => (Some(shard_header.x), Some(shard_header.set_id)),
+            Err(_) => (bare_share_x(path), None),
+        };
+        if set_id.is_some_and(|set_id| {
+            !header::ct_eq(
+                &set_id.to_be_bytes(),
+                &manifest.set_header.set_id.to_be_bytes(),
+            )
+        }) {
+            let error = Error::InconsistentShards(
+                "shard does not belong to the set of the manifest".to_string(),
+            );
+            return Err(error.in_shard(name));
+        }
+        match manifest.shards.iter().find(|(file, _)| *file == file_name) {
+            Some((file, listed_x)) if x.is_none_or(|x| x == *listed_x) => {
+                present.insert(file.as_str());
+            }
+            _ => unexpected.push(name.clone()),
+        }
+    }
+    let missing = manifest
+        .shards
+        .iter()
+        .filter(|(file, _)| !present.contains(file.as_str()))
+        .map(|(file, _)| file.clone())
         .collect();
+    Ok((missing, unexpected))
+}
+
+/// Checks that a reconstructed secret is not empty, since empty secrets cannot be split, and
+/// has the length declared in the shard headers.
+///
+/// # Arguments
+///
+/// * `secret` - The reconstructed secret.
+/// * `set_header` - The header of the shards, or `None` for bare shares.
+///
+/// # Returns
+///
+/// `Error::ReconstructionLengthMismatch` if the length is wrong.
+fn check_recovered_len(secret: &[u8], set_header: Option<&Header>) -> Result<()> {
+    let expected = set_header.map(|first| first.secret_len);
+    if secret.is_empty() || expected.is_some_and(|expected| expected != secret.len() as u64) {
+        return Err(Error::ReconstructionLengthMismatch {
+            expected,
+            actual: secret.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Combines shards held in memory into a single secret file.
+///
+/// # Arguments
+///
+/// * `shards` - The name and raw content of every shard.
+/// * `output_path` - The path where the combined result file will be saved.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination before anything is written.
+///
+/// # Returns
+///
+/// This function returns a `Result<PathBuf>`. On success, it returns the path the secret was
+/// written to, which differs from `output_path` when `options.infer_extension` added an
+/// extension. On failure, it returns an `Error` describing an invalid or insufficient shard
+/// set, or an I/O failure while writing the secret file. If `output_path` already exists and
+/// `options.force` is `false`, an `Error::Io` of kind `io::ErrorKind::AlreadyExists` is
+/// returned and the file is left untouched. A named pipe at `output_path` is written to
+/// without being truncated.
+fn combine_shards(
+    shards: Vec<(String, Vec<u8>)>,
+    output_path: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    let (secret, set_header) = if options.partial {
+        recover_partial(shards, options)?
+    } else {
+        recover_shards(shards, options, cancel)?
+    };
+    let secret = if options.recipients.is_empty() {
+        secret
+    } else {
+        encrypt_secret(&options.recipients, &secret)?
+    };
+    let restored = set_header.as_ref().filter(|_| options.restore_metadata);
+    let content_type = set_header
+        .as_ref()
+        .and_then(|first| first.content_type.as_deref());
+    let output_path = match content_type.and_then(extension_for) {
+        _ if options.restore_metadata => restored_path(output_path, restored)?,
+        Some(extension)
+            if options.infer_extension
+                && output_path.extension().is_none()
+                && !is_fifo(output_path) =>
+        {
+            output_path.with_extension(extension)
+        }
+        _ => output_path.to_path_buf(),
+    };
+    timing::measure(
+        options.timings.as_deref(),
+        "write secret",
+        || -> Result<()> {
+            if is_fifo(&output_path) {
+                // A named pipe is streamed to by its reader, such as an HSM import tool, so it is
+                // neither refused as an existing file nor truncated.
+                open_fifo(&output_path, cancel)?.write_all(&secret)?;
+                Ok(())
+            } else {
+                // Shards may come from anyone, so a recorded setuid, setgid or sticky bit is
+                // dropped rather than restored.
+                let mode = options
+                    .mode
+                    .or_else(|| {
+                        restored
+                            .and_then(|first| first.file_mode)
+                            .map(|mode| mode & 0o777)
+                    })
+                    .unwrap_or(SECRET_FILE_MODE);
+                let mtime = restored.and_then(|first| first.file_mtime);
+                write_secret(&output_path, &secret, options.force, mode, mtime, cancel)
+            }
+        },
+    )?;
+
+    Ok(output_path)
+}
 
-    combine_files(&shard_paths, recovered_secret_path)
+/// Refuses an existing recovered secret path unless `options.force` is set, before any shard
+/// is read, so that an existing secret is never clobbered. With `options.restore_metadata`,
+/// the path must instead be the directory the secret file is recreated in.
+fn check_output_path(output_path: &Path, options: &CombineOptions) -> Result<()> {
+    if options.restore_metadata {
+        if !output_path.is_dir() {
+            return Err(Error::InvalidParameters(format!(
+                "{} is not a directory to restore the secret file in",
+                output_path.display()
+            )));
+        }
+    } else if !options.force && output_path.exists() && !is_fifo(output_path) {
+        return Err(already_exists(output_path).into());
+    }
+    Ok(())
+}
+
+/// Returns the path of the secret file recreated in `dir` under the name recorded by
+/// `shard --store-metadata`.
+///
+/// # Returns
+///
+/// The path, `Error::InvalidHeader` if the recorded name is not a plain file name, such as
+/// `../.bashrc`, or `Error::InvalidParameters` if the shards record no name.
+fn restored_path(dir: &Path, set_header: Option<&Header>) -> Result<PathBuf> {
+    match set_header.and_then(|first| first.file_name.as_deref()) {
+        Some(file_name) if is_plain_file_name(file_name) => Ok(dir.join(file_name)),
+        Some(file_name) => Err(Error::InvalidHeader(format!(
+            "unsafe file name {:?}, it must not hold a directory",
+            file_name
+        ))),
+        None => Err(Error::InvalidParameters(
+            "the shards record no file name, they were not split with --store-metadata".to_string(),
+        )),
+    }
+}
+
+/// Recovers as many bytes of a secret as possible from damaged or truncated shards, with
+/// `shamir::combine_partial`, for forensic recovery.
+///
+/// Checksums are not verified, and each shard is read up to the secret length declared in
+/// its header, so a truncated shard lacks the y-coordinates of the last bytes. A warning
+/// lists the bytes which could not be recovered, which are set to 0.
+///
+/// # Arguments
+///
+/// * `shards` - The name and raw content of every shard, all with a readable header.
+/// * `options` - The `CombineOptions` to apply.
+///
+/// # Returns
+///
+/// The secret and the header of the first shard, `Error::InvalidHeader` if a header cannot
+/// be parsed, `Error::InconsistentShards` if the shards belong to different sets,
+/// `Error::Expired` if they expired and `options.enforce_expiry` is set, or
+/// `Error::InsufficientShares` if there is no shard.
+fn recover_partial(
+    shards: Vec<(String, Vec<u8>)>,
+    options: &CombineOptions,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    check_manifest(&shards, options)?;
+    let mut parts = Vec::new();
+    let mut set_header: Option<Header> = None;
+    let mut first_path = String::new();
+    // The most bytes any shard holds after its header.
+    let mut longest = 0;
+    for (shard_path, shard_data) in shards {
+        let (shard_header, header_len) =
+            Header::from_bytes(&shard_data).map_err(|e| e.in_shard(&shard_path))?;
+        longest = longest.max(shard_data.len() - header_len);
+        if let Some(first) = &set_header {
+            if !header::ct_eq(
+                &first.set_id.to_be_bytes(),
+                &shard_header.set_id.to_be_bytes(),
+            ) {
+                let error =
+                    Error::InconsistentShards("shard belongs to a different share set".to_string());
+                return Err(error.in_shard(&shard_path));
+            }
+            // The bodies are not checked against the headers, so only the headers tell
+            // shards of different lengths apart.
+            check_same_scheme(&first_path, first, &shard_path, &shard_header)?;
+        } else {
+            first_path.clone_from(&shard_path);
+        }
+        let end = usize::try_from(shard_header.secret_len)
+            .map_or(shard_data.len(), |len| header_len.saturating_add(len))
+            .min(shard_data.len());
+        parts.push(shamir::PartialShare {
+            x: shard_header.x,
+            ys: shard_data[header_len..end]
+                .iter()
+                .copied()
+                .map(Some)
+                .collect(),
+        });
+        set_header.get_or_insert(shard_header);
+    }
+    let first = set_header.ok_or(Error::InsufficientShares { have: 0, need: 2 })?;
+    check_expiry(&first, timing::unix_now(), options.enforce_expiry)?;
+
+    let (mut secret, mut recovered) =
+        shamir::combine_partial(&parts, first.threshold as usize, 0, options.gf_backend)?;
+    // Bytes after the end of every shard are lost as well, up to the declared length. The
+    // header is not verified, so a length beyond every shard only truncates the secret.
+    let secret_len = usize::try_from(first.secret_len).map_or(longest, |len| len.min(longest));
+    if (secret_len as u64) < first.secret_len {
+        eprintln!(
+            "{} the shards declare a secret of {} bytes but hold at most {}: the secret is \
+             truncated",
+            "WARNING:".yellow().bold(),
+            first.secret_len,
+            secret_len
+        );
+    }
+    secret.resize(secret_len, 0);
+    recovered.resize(secret_len, false);
+    let lost = lost_ranges(&recovered);
+    if !lost.is_empty() {
+        eprintln!(
+            "{} {} of {} bytes could not be recovered and were set to 0: {}",
+            "WARNING:".yellow().bold(),
+            recovered.iter().filter(|&&recovered| !recovered).count(),
+            secret_len,
+            lost.join(", ")
+        );
+    }
+    Ok((secret, Some(first)))
+}
+
+/// Lists the positions of the bytes which were not recovered, as ranges such as `2-5`.
+fn lost_ranges(recovered: &[bool]) -> Vec<String> {
+    let mut ranges = Vec::new();
+    let mut idx = 0;
+    while idx < recovered.len() {
+        if recovered[idx] {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        while idx < recovered.len() && !recovered[idx] {
+            idx += 1;
+        }
+        ranges.push(match idx - start {
+            1 => start.to_string(),
+            _ => format!("{}-{}", start, idx - 1),
+        });
+    }
+    ranges
+}
+
+/// Number of secret bytes written between two checks of the cancel flag.
+const WRITE_CHUNK_LEN: usize = 64 * 1024;
+
+/// Writes the recovered secret so that either the complete secret or no file appears at
+/// `output_path`, even if the combination is interrupted.
+///
+/// The secret is written to a new hidden temporary file next to `output_path`, which is only
+/// moved into place once complete. If `cancel` is set during the write, for instance by
+/// Ctrl-C, or the write fails, the temporary file is removed.
+///
+/// # Arguments
+///
+/// * `output_path` - The path where the secret is saved.
+/// * `secret` - The recovered secret.
+/// * `force` - Whether an existing file at `output_path` may be replaced.
+/// * `mode` - The Unix permissions of the file, such as `SECRET_FILE_MODE`, set when the
+///   temporary file is created so that the secret is never readable by others.
+/// * `mtime` - The modification time to give the file, in seconds since the Unix epoch, or
+///   `None` to leave it at the time of the write.
+/// * `cancel` - A flag which, once set, stops the write and discards it.
+///
+/// # Returns
+///
+/// `Error::Cancelled` if `cancel` was set before the secret was moved into place, or
+/// `Error::Io` if it cannot be written or `output_path` exists and `force` is `false`.
+fn write_secret(
+    output_path: &Path,
+    secret: &[u8],
+    force: bool,
+    mode: u32,
+    mtime: Option<u64>,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let (temp_path, mut temp_file) = create_temp_file(output_path, mode)?;
+
+    let mut write = || -> Result<()> {
+        for chunk in secret.chunks(WRITE_CHUNK_LEN) {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Error::Cancelled);
+            }
+            temp_file.write_all(chunk)?;
+        }
+        if let Some(mtime) = mtime {
+            temp_file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+        temp_file.sync_all()?;
+        if cancel.load(Ordering::Relaxed) {
+            return Err(Error::Cancelled);
+        }
+        persist(&temp_path, output_path, force).map_err(Error::from)
+    };
+    let written = write();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Creates the hidden temporary file in which the secret for `output_path` is written, with
+/// the permissions `mode`.
+///
+/// The name carries a random suffix and the file is created with `create_new`, so an
+/// existing file or symbolic link is never opened, and concurrent combines to the same
+/// `output_path` each write their own file. A name which is already taken is retried.
+pub fn create_temp_file(output_path: &Path, mode: u32) -> io::Result<(PathBuf, File)> {
+    let file_name = output_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    loop {
+        let suffix: u64 = rand::random();
+        let temp_path =
+            output_path.with_file_name(format!(".{}.{:016x}.partial", file_name, suffix));
+        match create_secret_file(&temp_path, mode) {
+            Ok(temp_file) => return Ok((temp_path, temp_file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Creates a new file with the permissions `mode`, whatever the umask, failing if `path`
+/// already exists.
+#[cfg(unix)]
+fn create_secret_file(path: &Path, mode: u32) -> io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    Ok(file)
+}
+
+/// Files keep the permissions inherited from their directory outside Unix.
+#[cfg(not(unix))]
+fn create_secret_file(path: &Path, _mode: u32) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Moves a fully written temporary file to `output_path`.
+///
+/// Without `force`, a hard link is made instead of a rename, so that a file which appeared
+/// at `output_path` while the shards were combined is never replaced. Filesystems without
+/// hard links fall back to checking for the file before renaming.
+pub fn persist(temp_path: &Path, output_path: &Path, force: bool) -> io::Result<()> {
+    if force {
+        return fs::rename(temp_path, output_path);
+    }
+    match fs::hard_link(temp_path, output_path) {
+        Ok(()) => fs::remove_file(temp_path),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(already_exists(output_path)),
+        Err(_) if output_path.exists() => Err(already_exists(output_path)),
+        Err(_) => fs::rename(temp_path, output_path),
+    }
+}
+
+/// Reconstructs the secret from `floor(N / threshold)` disjoint quorums of shares and checks
+/// that every quorum yields the same secret.
+///
+/// A single corrupted share changes the secret reconstructed by its quorum only, so the
+/// disagreement reveals it. With fewer than two quorums a warning is printed and all the
+/// shares are combined at once. The quorums are formed in the order of the x-coordinates, so
+/// that they do not depend on the order in which the shards were given.
+///
+/// # Arguments
+///
+/// * `parts` - The shares to combine, each ending with its x-coordinate.
+/// * `threshold` - The number of shares in each quorum.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The secret reconstructed by the quorum of the lowest x-coordinates, or
+/// `Error::InconsistentShards` if two quorums disagree and `options.force_combine` is not set.
+fn combine_quorums(
+    mut parts: Vec<Vec<u8>>,
+    threshold: usize,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<Vec<u8>> {
+    let progress = options.verbosity > Verbosity::Quiet;
+    let quorums = parts.len() / threshold;
+    if quorums < 2 {
+        if progress {
+            eprintln!(
+                "{} {} shards are required for a redundancy check, only {} are present",
+                "WARNING:".yellow().bold(),
+                2 * threshold,
+                parts.len()
+            );
+        }
+        return shamir::combine(parts, options.gf_backend, Some(cancel), progress);
+    }
+
+    parts.sort_unstable_by_key(|part| part.last().copied());
+    // A progress bar per quorum would flash by several times.
+    let secrets = parts
+        .chunks_exact(threshold)
+        .map(|quorum| shamir::combine(quorum.to_vec(), options.gf_backend, Some(cancel), false))
+        .collect::<Result<Vec<_>>>()?;
+    if secrets.iter().all(|secret| *secret == secrets[0]) {
+        if options.verbosity > Verbosity::Quiet {
+            println!("Secret consistent across {} quorums", quorums);
+        }
+    } else {
+        let error = Error::InconsistentShards(format!(
+            "the secrets reconstructed from {} disjoint quorums disagree, \
+             at least one shard is corrupted",
+            quorums
+        ));
+        validation_failure(error, options.force_combine)?;
+    }
+
+    Ok(secrets.into_iter().next().expect("at least two quorums"))
+}
+
+/// Reconstructs a secret known to have the SHA-256 hash `options.expect_hash`, searching the
+/// subsets of the shards for one which reconstructs it if the whole set does not, so that
+/// corrupted shards without a checksum, such as bare shares, can be identified and left out.
+///
+/// The subsets of `threshold` shards are tried, or every subset from 2 shards up when the
+/// threshold is unknown. Each try combines the whole secret, so the search costs up to
+/// C(shards, threshold) combinations, and is refused beyond `MAX_VERIFIED_QUORUMS`. Every
+/// shard left out of the matching subset which does not reconstruct the same secret with
+/// it is reported as corrupted.
+///
+/// # Arguments
+///
+/// * `parts` - The shares, each ending with its x-coordinate.
+/// * `names` - The name of the shard of every share, in the same order.
+/// * `threshold` - The threshold recorded in the shard headers, if any.
+/// * `options` - The `CombineOptions` to apply, with `expect_hash` set.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The secret matching the hash, `Error::InvalidParameters` if the search would exceed
+/// `MAX_VERIFIED_QUORUMS` subsets, `Error::InsufficientShares` if the set does not match and
+/// has fewer shards than the threshold, `Error::InconsistentShards` if no subset matches, or
+/// the errors of `shamir::combine`.
+fn combine_expecting(
+    parts: Vec<Vec<u8>>,
+    names: &[String],
+    threshold: Option<usize>,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<Vec<u8>> {
+    let expected = options.expect_hash.expect("an expected hash is given");
+    let matches = |secret: &[u8]| header::ct_eq(&Sha256::digest(secret), &expected);
+    let progress = options.verbosity > Verbosity::Quiet;
+    // The candidates which do not match are zeroized when dropped.
+    let mut secret = Zeroizing::new(shamir::combine(
+        parts.clone(),
+        options.gf_backend,
+        Some(cancel),
+        progress,
+    )?);
+    if matches(&secret) {
+        return Ok(std::mem::take(&mut *secret));
+    }
+
+    // `--force-combine` lets fewer shards than the threshold through, of which no subset of
+    // threshold shards can be made.
+    let sizes = match threshold {
+        Some(threshold) if threshold > parts.len() => {
+            return Err(Error::InsufficientShares {
+                have: parts.len(),
+                need: threshold,
+            })
+        }
+        Some(threshold) => threshold..threshold + 1,
+        None => 2..parts.len(),
+    };
+    let subsets: u128 = sizes
+        .clone()
+        .map(|size| quorum_count(parts.len(), size))
+        .fold(0, u128::saturating_add);
+    if subsets > MAX_VERIFIED_QUORUMS {
+        return Err(Error::InvalidParameters(format!(
+            "finding the shards matching the expected hash would combine up to {} subsets, \
+             more than the limit of {}",
+            subsets, MAX_VERIFIED_QUORUMS
+        )));
+    }
+
+    let combine_indices = |indices: &[usize]| {
+        let subset = indices.iter().map(|&i| parts[i].clone()).collect();
+        shamir::combine(subset, options.gf_backend, Some(cancel), false).map(Zeroizing::new)
+    };
+    for size in sizes {
+        let mut subset: Vec<usize> = (0..size).collect();
+        loop {
+            secret = combine_indices(&subset)?;
+            if matches(&secret) {
+                // A shard consistent with the others replaces one of them without changing
+                // the secret.
+                for left_out in (0..parts.len()).filter(|i| !subset.contains(i)) {
+                    let mut swapped = subset.clone();
+                    swapped[size - 1] = left_out;
+                    if !header::ct_eq(&combine_indices(&swapped)?, &secret) {
+                        eprintln!(
+                            "{} {} appears corrupted: the secret matching the expected hash \
+                             was reconstructed without it",
+                            "WARNING:".yellow().bold(),
+                            names[left_out]
+                        );
+                    }
+                }
+                return Ok(std::mem::take(&mut *secret));
+            }
+            if !next_combination(&mut subset, parts.len()) {
+                break;
+            }
+        }
+    }
+
+    Err(Error::InconsistentShards(
+        "no subset of the shards reconstructs a secret matching the expected hash".to_string(),
+    ))
+}
+
+/// Advances `indices`, a strictly increasing selection among `0..n`, to the next one in
+/// lexicographic order.
+///
+/// # Returns
+///
+/// `false`, leaving `indices` unchanged, if it was the last selection.
+fn next_combination(indices: &mut [usize], n: usize) -> bool {
+    let k = indices.len();
+    let Some(i) = (0..k).rev().find(|&i| indices[i] < n - k + i) else {
+        return false;
+    };
+    indices[i] += 1;
+    for j in i + 1..k {
+        indices[j] = indices[j - 1] + 1;
+    }
+    true
+}
+
+/// Checks whether a path is a named pipe, to which the recovered secret can be streamed.
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+/// Named pipes are never detected outside Unix.
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+/// Interval between two attempts to open a named pipe which has no reader yet.
+const FIFO_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Opens a named pipe for writing once a reader has opened it.
+///
+/// A blocking open would wait for a reader for as long as it takes, whatever `cancel`. The
+/// pipe is opened without blocking instead, which fails with `ENXIO` until a reader appears,
+/// and retried every `FIFO_POLL_INTERVAL`. Once open, it is switched back to blocking writes.
+///
+/// # Returns
+///
+/// The pipe, `Error::Cancelled` if `cancel` was set, for instance by `--timeout`, before a
+/// reader appeared, or `Error::Io` if the pipe cannot be opened.
+#[cfg(unix)]
+pub fn open_fifo(path: &Path, cancel: &AtomicBool) -> Result<File> {
+    use std::os::{fd::AsRawFd, unix::fs::OpenOptionsExt};
+
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+        {
+            Ok(file) => {
+                let fd = file.as_raw_fd();
+                // SAFETY: `fd` is the open descriptor of `file`, and `fcntl` only changes
+                // its status flags.
+                let cleared = unsafe {
+                    let flags = libc::fcntl(fd, libc::F_GETFL);
+                    flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) >= 0
+                };
+                if !cleared {
+                    return Err(io::Error::last_os_error().into());
+                }
+                return Ok(file);
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Error::Cancelled);
+                }
+                thread::sleep(FIFO_POLL_INTERVAL);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Named pipes are never detected outside Unix, so they are opened like any file.
+#[cfg(not(unix))]
+pub fn open_fifo(path: &Path, _cancel: &AtomicBool) -> Result<File> {
+    Ok(OpenOptions::new().write(true).open(path)?)
+}
+
+/// Builds the error returned when the recovered secret path is already taken.
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        ),
+    )
+}
+
+/// Reads the shards of one of the shard sets of a bundle.
+///
+/// # Arguments
+///
+/// * `bundle_path` - The path of the bundle.
+/// * `record` - The index of the shard set, or `None` if the bundle holds a single one.
+///
+/// # Returns
+///
+/// The name and raw content of every shard, or the errors of `bundle::select_record` and
+/// `columnar::decode_columns`.
+fn read_bundle(bundle_path: &Path, record: Option<usize>) -> Result<Vec<(String, Vec<u8>)>> {
+    let name = bundle_path.display().to_string();
+    let bytes = fs::read(bundle_path)?;
+    let selected = bundle::select_record(&bytes, record).map_err(|e| e.in_shard(&name))?;
+    record_shards(&name, record.unwrap_or(0), selected)
+}
+
+/// Splits a record of a bundle into its shards, named after the bundle, record and row.
+fn record_shards(bundle_name: &str, index: usize, record: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let record_name = format!("{} record {}", bundle_name, index);
+    Ok(columnar::decode_columns(record)
+        .map_err(|e| e.in_shard(&record_name))?
+        .into_iter()
+        .enumerate()
+        .map(|(row, shard)| (format!("{} row {}", record_name, row), shard))
+        .collect())
+}
+
+/// Reconstructs every record of a bundle and writes each secret to its own file in
+/// `out_dir`, named after the file it was sharded from.
+///
+/// Every record is reconstructed before anything is written, so an invalid record leaves
+/// `out_dir` untouched. A record without a file name is written to `record_<index>`, and a
+/// name already used by an earlier record gets a counter suffix, such as `notes.txt.1`.
+///
+/// # Arguments
+///
+/// * `bundle_path` - The path of the bundle.
+/// * `out_dir` - The directory where the secrets are written, created if needed.
+/// * `options` - The `CombineOptions` to apply; the record, content-type extension and QR
+///   options are ignored.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The paths of the written secrets, in record order, the errors of `bundle::read_records`
+/// and `combine_shards`, or `Error::InvalidHeader` naming the record if its file name is not
+/// a plain file name, such as `../passwd`.
+pub fn unpack_bundle(
+    bundle_path: &Path,
+    out_dir: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    let name = bundle_path.display().to_string();
+    let bytes = fs::read(bundle_path)?;
+    let records = bundle::read_records(&bytes).map_err(|e| e.in_shard(&name))?;
+
+    let mut secrets = Vec::new();
+    let mut used_names = HashSet::new();
+    for (index, record) in records.into_iter().enumerate() {
+        let (secret, set_header) =
+            recover_shards(record_shards(&name, index, record)?, options, cancel)?;
+        let file_name = match set_header.and_then(|first| first.file_name) {
+            Some(file_name) if is_plain_file_name(&file_name) => file_name,
+            Some(file_name) => {
+                return Err(Error::InvalidHeader(format!(
+                    "unsafe file name {:?}, it must not hold a directory",
+                    file_name
+                ))
+                .in_shard(&format!("{} record {}", name, index)))
+            }
+            None => format!("record_{}", index),
+        };
+        let unique_name = (0..)
+            .map(|counter| match counter {
+                0 => file_name.clone(),
+                counter => format!("{}.{}", file_name, counter),
+            })
+            .find(|candidate| !used_names.contains(candidate))
+            .expect("a counter is free");
+        used_names.insert(unique_name.clone());
+        secrets.push((out_dir.join(unique_name), secret));
+    }
+
+    fs::create_dir_all(out_dir)?;
+    let mut paths = Vec::new();
+    for (path, secret) in secrets {
+        let mode = options.mode.unwrap_or(SECRET_FILE_MODE);
+        write_secret(&path, &secret, options.force, mode, None, cancel)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Checks that a file name stored in a bundle names a file directly in the output directory,
+/// rejecting separators of any platform, `.`, `..` and empty names.
+fn is_plain_file_name(file_name: &str) -> bool {
+    !file_name.contains(['/', '\\', '\0'])
+        && matches!(
+            Path::new(file_name).components().collect::<Vec<_>>()[..],
+            [std::path::Component::Normal(_)]
+        )
+}
+
+/// Reads up to `len` bytes from the start of a file, to check its magic.
+fn read_magic(path: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let mut magic = Vec::with_capacity(len);
+    File::open(path)?.take(len as u64).read_to_end(&mut magic)?;
+    Ok(magic)
+}
+
+/// Maximum depth of nested directories searched by a recursive combine.
+const MAX_DEPTH: usize = 8;
+
+/// Collects the shard files found in nested directories.
+///
+/// Files which start with neither the shard nor the gzip magic and are not named like a bare
+/// share are skipped, so unrelated files lying next to the shards are ignored.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to search.
+/// * `depth` - How many directories deep `dir` is below the directory the search started at.
+/// * `shard_paths` - The vector to which the paths of the shards found are appended.
+///
+/// # Returns
+///
+/// This function returns an `io::Result<()>`, failing if a directory or file cannot be read.
+fn find_shards(dir: &Path, depth: usize, shard_paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth < MAX_DEPTH {
+                find_shards(&path, depth + 1, shard_paths)?;
+            }
+            continue;
+        }
+
+        let magic = read_magic(&path, header::MAGIC.len())?;
+        if header::has_magic(&magic)
+            || columnar::has_magic(&magic)
+            || bare_share_x(&path).is_some()
+            || archive::has_gzip_magic(&magic)
+        {
+            shard_paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Orchestrates the combination of shard files found within a specified directory
+/// and writes the result to the given file path.
+///
+/// When some of the files found have the `header::SHARD_EXTENSION` extension, the others are
+/// ignored, so unrelated files can sit next to the shards.
+///
+/// # Arguments
+///
+/// * `shards_dir` - A `PathBuf` pointing to the directory containing the shards, to a tar,
+///   tar.gz or zip archive of the shards, or to a column layout file.
+/// * `recovered_secret_path` - A `PathBuf` specifying the path where the recovered secret will be written.
+/// * `options` - The `CombineOptions` to apply.
+/// * `cancel` - A flag which, once set, stops the combination before anything is written.
+///
+/// # Returns
+///
+/// This function returns a `Result<PathBuf>`. On success, it returns the path the secret was
+/// written to. On failure, it returns an `Error`, with the same errors as `combine_shards`.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::PathBuf;
+///
+/// let shards_dir = PathBuf::from("./shards");
+/// let recovered_secret_path = PathBuf::from("./recovered_secret.txt");
+/// let options = CombineOptions::default();
+/// let cancel = AtomicBool::new(false);
+/// let result = combine_secret(&shards_dir, &recovered_secret_path, &options, &cancel);
+///
+/// assert!(result.is_ok());
+/// ```
+pub fn combine_secret(
+    shards_dir: &Path,
+    recovered_secret_path: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    // Refuse early, before any shard is read, so an existing secret is never clobbered.
+    check_output_path(recovered_secret_path, options)?;
+    if options.stream {
+        return stream::combine_stream(shards_dir, recovered_secret_path, options, cancel);
+    }
+
+    let shards = timing::measure(options.timings.as_deref(), "read shards", || {
+        read_input(shards_dir, options)
+    })?;
+    combine_shards(shards, recovered_secret_path, options, cancel)
+}
+
+/// Reads the shards of any input accepted by `combine_secret`.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The shards directory, archive, bundle or column layout file, or the
+///   directory of QR texts when `options.qr_text` is set.
+/// * `options` - The `CombineOptions` to apply.
+///
+/// # Returns
+///
+/// The name and raw content of every shard, `Error::InvalidParameters` if `options.record` is
+/// set for anything but a bundle, or the error of an unreadable or invalid input.
+fn read_input(shards_dir: &Path, options: &CombineOptions) -> Result<Vec<(String, Vec<u8>)>> {
+    if options.qr_text {
+        return read_qr_texts(shards_dir);
+    }
+    if shards_dir.is_file() {
+        if let Some(format) = archive::detect_format(shards_dir)? {
+            return archive::read_entries(shards_dir, format);
+        }
+        if bundle::has_magic(&read_magic(shards_dir, bundle::MAGIC.len())?) {
+            return read_bundle(shards_dir, options.record);
+        }
+        if options.record.is_none() {
+            if let Some(shards) = read_text_set(shards_dir)? {
+                return Ok(shards);
+            }
+            // A column layout file holds the whole set on its own.
+            return read_shards(&[shards_dir]);
+        }
+    }
+    if options.record.is_some() {
+        return Err(Error::InvalidParameters(
+            "a record can only be chosen among the shard sets of a bundle".to_string(),
+        ));
+    }
+
+    let shards = read_shards(&list_shards(shards_dir, options.recursive)?)?;
+    match sidecar::read(shards_dir)? {
+        Some(set_header) => Ok(attach_sidecar(&set_header, shards)),
+        None => Ok(shards),
+    }
+}
+
+/// Gives the headerless shards among `shards`, those without the shard magic whose name is
+/// an x-coordinate, the header read from the sidecar of their directory.
+fn attach_sidecar(set_header: &Header, shards: NamedShards) -> NamedShards {
+    shards
+        .into_iter()
+        .map(|(name, data)| match bare_share_x(Path::new(&name)) {
+            Some(x) if !header::has_magic(&data) => {
+                let shard = sidecar::attach(set_header, x, &data);
+                (name, shard)
+            }
+            _ => (name, data),
+        })
+        .collect()
+}
+
+/// Reads a file written by `shard --output-format json` or `base64-lines`, holding the
+/// whole set.
+///
+/// # Returns
+///
+/// The name and raw content of every shard, numbered in file order, `None` if
+/// the file is not in either format, or the errors of `export::decode`.
+fn read_text_set(path: &Path) -> Result<Option<NamedShards>> {
+    // Binary shards are not read in full only to be rejected.
+    let magic = read_magic(path, header::MAGIC.len())?;
+    if header::has_magic(&magic) || columnar::has_magic(&magic) {
+        return Ok(None);
+    }
+    let name = path.display().to_string();
+    let shards = export::decode(&fs::read(path)?).map_err(|e| e.in_shard(&name))?;
+    Ok(shards.map(|shards| {
+        shards
+            .into_iter()
+            .enumerate()
+            .map(|(index, shard)| (format!("{} shard {}", name, index), shard))
+            .collect()
+    }))
+}
+
+/// Checks whether a file is compressed with gzip, such as a shard `shard_0.gz`, by its magic
+/// rather than its name.
+fn is_compressed_shard(path: &Path) -> io::Result<bool> {
+    Ok(path.is_file() && archive::has_gzip_magic(&read_magic(path, 2)?))
+}
+
+/// Lists the shard files of a shards directory.
+///
+/// When some of the files found have the `header::SHARD_EXTENSION` extension or are
+/// compressed with gzip, the others are ignored, so unrelated files can sit next to the
+/// shards.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory containing the shards.
+/// * `recursive` - Whether to search its subdirectories with `find_shards`.
+///
+/// # Returns
+///
+/// The paths of the shards, `Error::NoShardsFound` if the directory holds no file, or
+/// `Error::Io` if it cannot be read.
+pub fn list_shards(shards_dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut shard_paths: Vec<PathBuf> = if recursive {
+        let mut shard_paths = Vec::new();
+        find_shards(shards_dir, 0, &mut shard_paths)?;
+        shard_paths
+    } else {
+        fs::read_dir(shards_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let file_name = entry.file_name();
+                file_name != sharding::MANIFEST_FILE && file_name != sidecar::FILE_NAME
+            })
+            .map(|entry| entry.path())
+            .collect()
+    };
+
+    let mut preferred = Vec::with_capacity(shard_paths.len());
+    for path in &shard_paths {
+        preferred.push(
+            path.extension() == Some(header::SHARD_EXTENSION.as_ref())
+                || is_compressed_shard(path)?,
+        );
+    }
+    if preferred.contains(&true) {
+        shard_paths = shard_paths
+            .into_iter()
+            .zip(preferred)
+            .filter_map(|(path, preferred)| preferred.then_some(path))
+            .collect();
+    }
+    if shard_paths.is_empty() {
+        return Err(Error::NoShardsFound {
+            dir: shards_dir.display().to_string(),
+        });
+    }
+
+    Ok(shard_paths)
+}
+
+/// Reconstructs the secret of a set of shards without writing it to disk.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The shards directory, or any other input accepted by `combine_secret`.
+/// * `options` - The `CombineOptions` to apply; the options about the recovered secret file
+///   are ignored.
+/// * `cancel` - A flag which, once set, stops the combination.
+///
+/// # Returns
+///
+/// The secret and the header of the shard set, or `None` if all the shards are bare shares,
+/// with the same errors as `combine_shards`.
+pub fn recover_secret(
+    shards_dir: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Option<Header>)> {
+    let shards = timing::measure(options.timings.as_deref(), "read shards", || {
+        read_input(shards_dir, options)
+    })?;
+    recover_shards(shards, options, cancel)
+}
+
+/// Reads the shards of a set without parsing them.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The shards directory, or any other input accepted by `combine_secret`.
+///
+/// # Returns
+///
+/// The name and raw content of every shard, after decoding compressed and mnemonic shard
+/// files, splitting column layout files into their shards and giving headerless shards the
+/// header of their sidecar, or the errors of reading them as `combine_secret` does.
+pub fn read_raw_shards(shards_dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    read_input(shards_dir, &CombineOptions::default())
+}
+
+/// Reads and parses the shards of a set without combining them, to inspect their shares.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The shards directory, or any other input accepted by `combine_secret`.
+///
+/// # Returns
+///
+/// The name, header and share of every shard, the share ending with its x-coordinate, or the
+/// errors of reading and parsing the shards as `combine_secret` does.
+pub fn read_shares(shards_dir: &Path) -> Result<Vec<(String, Option<Header>, shamir::Share)>> {
+    read_raw_shards(shards_dir)?
+        .into_iter()
+        .map(|(name, data)| {
+            let (shard_header, share) = parse_shard(&name, data, false, false)?;
+            Ok((name, shard_header, share))
+        })
+        .collect()
+}
+
+/// Counts the quorums of `threshold` shards among `parts`, the binomial coefficient
+/// C(parts, threshold), saturating at `u128::MAX`.
+pub fn quorum_count(parts: usize, threshold: usize) -> u128 {
+    if threshold > parts {
+        return 0;
+    }
+    // C(n, i + 1) = C(n, i) * (n - i) / (i + 1) is exact at every step.
+    (0..threshold.min(parts - threshold))
+        .try_fold(1u128, |count, i| {
+            Some(count.checked_mul((parts - i) as u128)? / (i + 1) as u128)
+        })
+        .unwrap_or(u128::MAX)
+}
+
+/// Reads back the shards of a set which was just written and checks that every quorum of
+/// `threshold` of them reconstructs the secret, for maximum assurance on critical secrets.
+///
+/// Each of the C(parts, threshold) quorums is combined in full, so this costs that many
+/// combinations of the secret; see `quorum_count` and `MAX_VERIFIED_QUORUMS`.
+///
+/// # Arguments
+///
+/// * `secret` - The secret which was split.
+/// * `shard_paths` - The paths of the shard files written, read like `combine` reads them.
+/// * `threshold` - The number of shards of every quorum.
+/// * `backend` - The GF(2^8) backend used to combine the quorums.
+/// * `cancel` - A flag which, once set, stops the verification.
+///
+/// # Returns
+///
+/// The number of quorums verified, `Error::InconsistentShards` naming the x-coordinates of
+/// the first quorum which reconstructs another secret, or the errors of reading, parsing and
+/// combining the shards.
+///
+/// # Examples
+///
+/// ```
+/// let shard_paths = shard_bytes(b"secret", Path::new("shards"), &options, &cancel)?;
+/// assert_eq!(verify_quorums(b"secret", &shard_paths, 3, GfBackend::Auto, &cancel)?, 10);
+/// ```
+pub fn verify_quorums(
+    secret: &[u8],
+    shard_paths: &[PathBuf],
+    threshold: usize,
+    backend: GfBackend,
+    cancel: &AtomicBool,
+) -> Result<usize> {
+    let shares = read_shards(shard_paths)?
+        .into_iter()
+        .map(|(name, data)| parse_shard(&name, data, false, false).map(|(_, share)| share))
+        .collect::<Result<Vec<_>>>()?;
+    if threshold < 2 || shares.len() < threshold {
+        return Err(Error::InsufficientShares {
+            have: shares.len(),
+            need: threshold.max(2),
+        });
+    }
+
+    // The indices of the shares of the current quorum, in lexicographic order.
+    let mut quorum: Vec<usize> = (0..threshold).collect();
+    let mut verified = 0;
+    loop {
+        let parts = quorum.iter().map(|&i| shares[i].clone()).collect();
+        let recovered = shamir::combine(parts, backend, Some(cancel), false)?;
+        if !header::ct_eq(&recovered, secret) {
+            let xs: Vec<String> = quorum
+                .iter()
+                .map(|&i| shares[i].last().expect("share is non-empty").to_string())
+                .collect();
+            return Err(Error::InconsistentShards(format!(
+                "the shards with x-coordinates {} do not reconstruct the secret",
+                xs.join(", ")
+            )));
+        }
+        verified += 1;
+
+        if !next_combination(&mut quorum, shares.len()) {
+            return Ok(verified);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{scheme, shard_fixture};
+
+    /// Options which only print errors.
+    fn quiet() -> CombineOptions {
+        CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the shard files and combines them into `output_path`.
+    fn combine_files(
+        shard_paths: &[String],
+        output_path: &Path,
+        options: &CombineOptions,
+        cancel: &AtomicBool,
+    ) -> Result<PathBuf> {
+        combine_shards(read_shards(shard_paths)?, output_path, options, cancel)
+    }
+
+    /// Runs `combine_files` without output or cancellation.
+    fn combine_quietly(shard_paths: &[String], output_path: &Path, force: bool) -> Result<PathBuf> {
+        let options = CombineOptions { force, ..quiet() };
+        combine_files(shard_paths, output_path, &options, &AtomicBool::new(false))
+    }
+
+    /// Shards `secret` into `dir` with the given scheme, returning the shard paths.
+    fn write_shards(dir: &Path, secret: &[u8], parts: usize, threshold: usize) -> Vec<String> {
+        shard_fixture(dir, secret, scheme(parts, threshold))
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    fn parsed_shards(shard_paths: &[String], options: &CombineOptions) -> Vec<ParsedShard> {
+        read_shards(shard_paths)
+            .unwrap()
+            .into_iter()
+            .map(|shard| parse_named_shard(shard, options).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_extension_for() {
+        assert_eq!(extension_for("text/plain; charset=utf-8"), Some("txt"));
+        assert_eq!(extension_for("Application/X-PEM-File"), Some("pem"));
+        assert_eq!(extension_for("application/x-unknown"), None);
+    }
+
+    #[test]
+    fn test_combine_refuses_existing_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 3, 2);
+        let output_path = dir.path().join("secret");
+        fs::write(&output_path, b"precious data").unwrap();
+
+        let err = combine_quietly(&shard_paths, &output_path, false).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read(&output_path).unwrap(), b"precious data");
+
+        combine_quietly(&shard_paths, &output_path, true).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recovered_secret_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(&dir.path().join("shards"), b"private key", 3, 2);
+        let mode_of = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        let output_path = dir.path().join("secret");
+        combine_quietly(&shard_paths, &output_path, false).unwrap();
+        assert_eq!(mode_of(&output_path), 0o600);
+
+        // An explicit mode applies whatever the umask, including to a replaced file.
+        let options = CombineOptions {
+            force: true,
+            mode: Some(0o640),
+            ..quiet()
+        };
+        let cancel = AtomicBool::new(false);
+        combine_files(&shard_paths, &output_path, &options, &cancel).unwrap();
+        assert_eq!(mode_of(&output_path), 0o640);
+        assert_eq!(fs::read(&output_path).unwrap(), b"private key");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("id_ed25519");
+        fs::write(&secret_path, b"private key").unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        File::options()
+            .write(true)
+            .open(&secret_path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        fs::set_permissions(&secret_path, fs::Permissions::from_mode(0o440)).unwrap();
+
+        let shards_dir = dir.path().join("shards");
+        let shard_options = sharding::SharingConfig::builder()
+            .file_metadata(Some(sharding::FileMetadata::read(&secret_path).unwrap()))
+            .verbosity(Verbosity::Quiet);
+        let cancel = AtomicBool::new(false);
+        sharding::shard_secret(
+            &secret_path,
+            &shards_dir,
+            &shard_options.clone().build().unwrap(),
+            &cancel,
+        )
+        .unwrap();
+
+        let restore_dir = dir.path().join("restored");
+        fs::create_dir(&restore_dir).unwrap();
+        let options = CombineOptions {
+            restore_metadata: true,
+            ..quiet()
+        };
+        let restored = combine_secret(&shards_dir, &restore_dir, &options, &cancel).unwrap();
+        assert_eq!(restored, restore_dir.join("id_ed25519"));
+        assert_eq!(fs::read(&restored).unwrap(), b"private key");
+        let metadata = fs::metadata(&restored).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o440);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+
+        // The restored file is not replaced, and the path must be a directory.
+        let err = combine_secret(&shards_dir, &restore_dir, &options, &cancel).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        let err = combine_secret(&shards_dir, &restored, &options, &cancel).unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+
+        // A name which would escape the directory is refused.
+        for file_name in ["../escaped", "/etc/passwd", "..", "a/b"] {
+            let set_header = Header {
+                file_name: Some(file_name.to_string()),
+                ..Default::default()
+            };
+            assert!(matches!(
+                restored_path(&restore_dir, Some(&set_header)),
+                Err(Error::InvalidHeader(_))
+            ));
+        }
+        assert!(matches!(
+            restored_path(&restore_dir, Some(&Header::default())),
+            Err(Error::InvalidParameters(_))
+        ));
+
+        // Shards recording a setuid mode restore the permissions without the setuid bit.
+        let mut file_metadata = sharding::FileMetadata::read(&secret_path).unwrap();
+        file_metadata.mode = Some(0o4755);
+        let shard_options = shard_options
+            .file_metadata(Some(file_metadata))
+            .build()
+            .unwrap();
+        let setuid_dir = dir.path().join("setuid");
+        sharding::shard_secret(&secret_path, &setuid_dir, &shard_options, &cancel).unwrap();
+        fs::remove_file(&restored).unwrap();
+        let restored = combine_secret(&setuid_dir, &restore_dir, &options, &cancel).unwrap();
+        let metadata = fs::metadata(&restored).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_combine_to_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 3, 2);
+        let fifo_path = dir.path().join("secret.fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Opening a FIFO blocks until both ends are open, so the reader runs on its own thread.
+        let reader = std::thread::spawn({
+            let fifo_path = fifo_path.clone();
+            move || fs::read(fifo_path).unwrap()
+        });
+        let output_path = combine_quietly(&shard_paths, &fifo_path, false).unwrap();
+        assert_eq!(output_path, fifo_path);
+        assert_eq!(reader.join().unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_combine_mixed_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 3, 2);
+
+        // Rewrite the first shard in the version 1 format, whose header has no metadata.
+        let data = fs::read(&shard_paths[0]).unwrap();
+        let (_, body) = header::decode_shard(&data).unwrap();
+        let mut v1_header = data[..24].to_vec();
+        v1_header[4] = 1;
+        let mut v1_shard = Vec::new();
+        header::write_shard(&mut v1_shard, &v1_header, body).unwrap();
+        fs::write(&shard_paths[0], &v1_shard).unwrap();
+
+        let output_path = dir.path().join("secret");
+        combine_quietly(&shard_paths[..2], &output_path, false).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+
+        // A version this tool does not know is rejected with the shard named.
+        let mut v9_shard = v1_shard.clone();
+        v9_shard[4] = 9;
+        fs::write(&shard_paths[0], &v9_shard).unwrap();
+        let err = combine_quietly(&shard_paths[..2], &output_path, true).unwrap_err();
+        assert!(err.to_string().contains("unsupported shard version 9"));
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn test_combine_qr_texts() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret: Vec<u8> = (0..2000).map(|i| (i * 7) as u8).collect();
+        let shard_paths = write_shards(&dir.path().join("shards"), &secret, 3, 2);
+
+        // Each shard spans several QR codes, scanned out of order and some of them twice.
+        let texts_dir = dir.path().join("scans");
+        fs::create_dir(&texts_dir).unwrap();
+        for (index, shard_path) in shard_paths[..2].iter().enumerate() {
+            let mut payloads = crate::qr::qr_payloads(&fs::read(shard_path).unwrap());
+            assert!(payloads.len() > 1);
+            payloads.reverse();
+            payloads.push(payloads[0].clone());
+            let text_path = texts_dir.join(format!("shard_{}.txt", index));
+            fs::write(text_path, payloads.join("\n")).unwrap();
+        }
+
+        let output_path = dir.path().join("secret");
+        let options = CombineOptions {
+            qr_text: true,
+            ..quiet()
+        };
+        combine_secret(&texts_dir, &output_path, &options, &AtomicBool::new(false)).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_write_secret_leaves_no_partial_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("secret");
+        let secret = vec![7; 3 * WRITE_CHUNK_LEN];
+
+        // An abort before the secret is complete leaves neither the secret nor the
+        // temporary file behind.
+        let mode = SECRET_FILE_MODE;
+        let err = write_secret(
+            &output_path,
+            &secret,
+            false,
+            mode,
+            None,
+            &AtomicBool::new(true),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        write_secret(
+            &output_path,
+            &secret,
+            false,
+            mode,
+            None,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), secret);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // An interrupted overwrite keeps the previous secret intact.
+        let err = write_secret(
+            &output_path,
+            b"new",
+            true,
+            mode,
+            None,
+            &AtomicBool::new(true),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert_eq!(fs::read(&output_path).unwrap(), secret);
+
+        let err = write_secret(
+            &output_path,
+            b"new",
+            false,
+            mode,
+            None,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_create_temp_file_never_reuses_a_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("secret");
+
+        // Concurrent combines to the same path each write their own temporary file.
+        let (first, _) = create_temp_file(&output_path, SECRET_FILE_MODE).unwrap();
+        let (second, _) = create_temp_file(&output_path, SECRET_FILE_MODE).unwrap();
+        assert_ne!(first, second);
+        for temp_path in [first, second] {
+            let name = temp_path.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with(".secret.") && name.ends_with(".partial"));
+            assert!(temp_path.is_file());
+        }
+    }
+
+    #[test]
+    fn test_combine_from_iter() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"streamed", 5, 3);
+        let cancel = AtomicBool::new(false);
+        let read = |path: &String| Ok(fs::read(path)?);
+        let secret = combine_from_iter(shard_paths.iter().map(read), &quiet(), &cancel).unwrap();
+        assert_eq!(secret, b"streamed");
+
+        // The first shard which fails to arrive stops the combination, leaving the others
+        // unread.
+        let mut pulled = 0;
+        let shards = shard_paths.iter().enumerate().map(|(index, path)| {
+            pulled += 1;
+            match index {
+                1 => Err(io::Error::new(io::ErrorKind::ConnectionReset, "lost").into()),
+                _ => read(path),
+            }
+        });
+        let err = combine_from_iter(shards, &quiet(), &cancel).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::ConnectionReset));
+        assert_eq!(pulled, 2);
+
+        // So does a shard which does not parse.
+        let shards = [Ok(b"SHMR garbage".to_vec())]
+            .into_iter()
+            .chain(shard_paths.iter().map(read));
+        let err = combine_from_iter(shards, &quiet(), &cancel).unwrap_err();
+        assert!(matches!(err, Error::Shard { ref name, .. } if name == "shard 1"));
+
+        let err = combine_from_iter(shard_paths[..2].iter().map(read), &quiet(), &cancel);
+        assert!(matches!(
+            err,
+            Err(Error::InsufficientShares { have: 2, need: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_combine_hex() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(&dir.path().join("shards"), b"pin 1234", 5, 3);
+        let hex_shards: Vec<String> = shard_paths[1..4]
+            .iter()
+            .map(|shard_path| {
+                let shard = fs::read(shard_path).unwrap();
+                shard.iter().map(|b| format!("{:02X}", b)).collect()
+            })
+            .collect();
+
+        let output_path = dir.path().join("secret");
+        combine_hex(&hex_shards, &output_path, &quiet(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"pin 1234");
+
+        assert_eq!(decode_hex(" 00ff7A\n").unwrap(), [0x00, 0xff, 0x7a]);
+        assert_eq!(decode_hex("\u{feff}00ff\r\n").unwrap(), [0x00, 0xff]);
+        for invalid in ["abc", "0g", "+1"] {
+            assert!(matches!(
+                decode_hex(invalid),
+                Err(Error::InvalidParameters(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_combine_text_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = write_shards(&shards_dir, b"pin 1234", 5, 3);
+        // Base64 with a byte order mark, wrapped lines and CRLF line endings, and hex
+        // surrounded by blank lines.
+        let shard = fs::read(&shard_paths[0]).unwrap();
+        let encoded = STANDARD.encode(&shard);
+        let (first, second) = encoded.split_at(20);
+        let text = format!("\u{feff}{}\r\n{}\r\n", first, second);
+        fs::write(&shard_paths[0], text).unwrap();
+        let shard = fs::read(&shard_paths[1]).unwrap();
+        let hex: String = shard.iter().map(|b| format!("{:02x}", b)).collect();
+        fs::write(&shard_paths[1], format!("\n  {}\n\n", hex)).unwrap();
+        for shard_path in &shard_paths[3..] {
+            fs::remove_file(shard_path).unwrap();
+        }
+
+        let output_path = dir.path().join("secret");
+        combine_secret(&shards_dir, &output_path, &quiet(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"pin 1234");
+
+        assert_eq!(decode_text_shard(b"not a shard"), None);
+        assert_eq!(
+            decode_text_shard(STANDARD.encode(b"no magic").as_bytes()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_combine_bare_shares() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths: Vec<String> =
+            shamir::split(b"test", 3, 3, false, GfBackend::Auto, None, false)
+                .unwrap()
+                .into_iter()
+                .map(|share| {
+                    let (&x, body) = share.split_last().unwrap();
+                    let path = dir.path().join(format!("{}.bin", x));
+                    fs::write(&path, body).unwrap();
+                    path.display().to_string()
+                })
+                .collect();
+        let output_path = dir.path().join("secret");
+
+        combine_quietly(&shard_paths, &output_path, false).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_combine_bare_shares_starting_with_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        // Shares of a secret split with threshold 2, the first of which starts with the magic.
+        let first = [&header::MAGIC[..], b"-y"].concat();
+        let second = b"second".to_vec();
+        let shares = vec![[&first[..], &[1]].concat(), [&second[..], &[2]].concat()];
+        let secret = shamir::combine(shares, GfBackend::Auto, None, false).unwrap();
+        let shard_paths: Vec<String> = [(1, &first), (2, &second)]
+            .into_iter()
+            .map(|(x, body)| {
+                let path = dir.path().join(format!("{}.bin", x));
+                fs::write(&path, body).unwrap();
+                path.display().to_string()
+            })
+            .collect();
+        let output_path = dir.path().join("secret");
+
+        combine_quietly(&shard_paths, &output_path, false).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), secret);
+
+        // Without a bare share name, the data is still refused as a shard.
+        let named_path = dir.path().join("shard_1.sss");
+        fs::rename(&shard_paths[0], &named_path).unwrap();
+        let shard_paths = [named_path.display().to_string(), shard_paths[1].clone()];
+        let err = combine_quietly(&shard_paths, &dir.path().join("other"), false).unwrap_err();
+        assert!(
+            matches!(err, Error::Shard { source, .. } if matches!(*source, Error::InvalidHeader(_)))
+        );
+    }
+
+    #[test]
+    fn test_check_recovered_len() {
+        let set_header = Header {
+            secret_len: 10,
+            ..Default::default()
+        };
+        check_recovered_len(&[0; 10], Some(&set_header)).unwrap();
+        check_recovered_len(&[0; 8], None).unwrap();
+
+        let err = check_recovered_len(&[0; 8], Some(&set_header)).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ReconstructionLengthMismatch {
+                expected: Some(10),
+                actual: 8
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "reconstructed secret is 8 bytes but the shards declare 10"
+        );
+        let err = check_recovered_len(&[], None).unwrap_err();
+        assert_eq!(err.exit_code(), 65);
+    }
+
+    #[test]
+    fn test_combine_with_wrong_header_len() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"8 bytes!", 3, 2);
+        // The headers claim 10 bytes for bodies of 8, with valid checksums.
+        for shard_path in &shard_paths {
+            let shard = fs::read(shard_path).unwrap();
+            let (shard_header, body) = header::decode_shard(&shard).unwrap();
+            let claiming_10 = Header {
+                secret_len: 10,
+                ..shard_header
+            };
+            fs::write(shard_path, header::encode_shard(&claiming_10, body)).unwrap();
+        }
+
+        // Every shard is checked against its header when read, so the mismatch is caught before
+        // reconstructing, and `check_recovered_len` is only a last line of defence.
+        let output_path = dir.path().join("secret");
+        let cancel = AtomicBool::new(false);
+        for err in [
+            combine_quietly(&shard_paths, &output_path, false).unwrap_err(),
+            combine_secret(dir.path(), &output_path, &quiet(), &cancel).unwrap_err(),
+        ] {
+            assert!(
+                matches!(err, Error::Shard { ref source, .. }
+                    if matches!(**source, Error::InvalidHeader(ref e) if e.contains("does not match"))),
+                "{:?}",
+                err
+            );
+        }
+        assert!(!output_path.exists());
+    }
+
+    #[cfg(feature = "age")]
+    #[test]
+    fn test_combine_encrypted_to_recipient() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"archived secret", 3, 2);
+        let output_path = dir.path().join("secret.age");
+        let identity = age::x25519::Identity::generate();
+        let options = CombineOptions {
+            recipients: vec![identity.to_public().to_string()],
+            ..quiet()
+        };
+        combine_files(
+            &shard_paths,
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        let encrypted = fs::read(&output_path).unwrap();
+        assert!(!encrypted
+            .windows(b"archived secret".len())
+            .any(|window| window == b"archived secret"));
+        let mut decrypted = Vec::new();
+        age::Decryptor::new(&encrypted[..])
+            .unwrap()
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, b"archived secret");
+    }
+
+    #[test]
+    fn test_combine_mixed_bare_and_full_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut shard_paths = write_shards(dir.path(), b"test", 3, 2);
+
+        // Strip the header and checksum of the first shard, keeping only its y-coordinates.
+        let data = fs::read(&shard_paths[0]).unwrap();
+        let (shard_header, body) = header::decode_shard(&data).unwrap();
+        let bare_path = dir.path().join(format!("{}.bin", shard_header.x));
+        fs::write(&bare_path, body).unwrap();
+        shard_paths[0] = bare_path.display().to_string();
+
+        let output_path = dir.path().join("secret");
+        combine_quietly(&shard_paths[..2], &output_path, false).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_combine_with_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_options = sharding::SharingConfig::builder()
+            .sidecar(true)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let shard_paths = sharding::shard_bytes(
+            b"listed secret",
+            &shards_dir,
+            &shard_options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let manifest_path = shards_dir.join(sidecar::FILE_NAME);
+        let manifest = sidecar::read_manifest(&manifest_path).unwrap();
+        assert_eq!(manifest.shards.len(), 5);
+        let options = CombineOptions {
+            manifest: Some(manifest_path),
+            ..quiet()
+        };
+        let output_path = dir.path().join("secret");
+        let cancel = AtomicBool::new(false);
+
+        // A deleted shard is named, and the other four still reach the threshold.
+        fs::remove_file(&shard_paths[1]).unwrap();
+        let shards = read_input(&shards_dir, &options).unwrap();
+        let (missing, unexpected) = compare_manifest(&manifest, &shards).unwrap();
+        assert_eq!(missing, vec!["shards_1.sss".to_string()]);
+        assert!(unexpected.is_empty());
+        combine_secret(&shards_dir, &output_path, &options, &cancel).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"listed secret");
+
+        // A shard under another name is flagged, and refused unless forced.
+        let extra_path = shards_dir.join("extra.sss");
+        fs::copy(&shard_paths[0], &extra_path).unwrap();
+        let shards = read_input(&shards_dir, &options).unwrap();
+        let (_, unexpected) = compare_manifest(&manifest, &shards).unwrap();
+        assert_eq!(unexpected, vec![extra_path.display().to_string()]);
+        fs::remove_file(&output_path).unwrap();
+        let err = combine_secret(&shards_dir, &output_path, &options, &cancel).unwrap_err();
+        assert!(matches!(err, Error::InconsistentShards(ref e) if e.contains("extra.sss")));
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_combine_legacy_and_current_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut shard_paths = write_shards(dir.path(), b"test", 3, 2);
+
+        // A version 0 shard is the share alone, ending with its x-coordinate.
+        let data = fs::read(&shard_paths[0]).unwrap();
+        let (shard_header, body) = header::decode_shard(&data).unwrap();
+        let legacy_path = dir.path().join("legacy_shard");
+        fs::write(&legacy_path, [body, &[shard_header.x]].concat()).unwrap();
+        shard_paths[0] = legacy_path.display().to_string();
+
+        // It is refused unless legacy shards are allowed.
+        let output_path = dir.path().join("secret");
+        let err = combine_quietly(&shard_paths[..2], &output_path, false).unwrap_err();
+        assert!(matches!(err, Error::Shard { ref source, .. }
+            if matches!(**source, Error::InvalidHeader(ref e) if e.contains("--legacy"))));
+        assert!(!output_path.exists());
+
+        let legacy = CombineOptions {
+            legacy: true,
+            ..quiet()
+        };
+        let cancel = AtomicBool::new(false);
+        combine_files(&shard_paths[..2], &output_path, &legacy, &cancel).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+
+        // A last byte of 0 is not an x-coordinate.
+        fs::write(&legacy_path, [body, &[0]].concat()).unwrap();
+        let legacy = CombineOptions {
+            force: true,
+            ..legacy
+        };
+        let err = combine_files(&shard_paths[..2], &output_path, &legacy, &cancel).unwrap_err();
+        assert!(matches!(err, Error::Shard { ref source, .. }
+            if matches!(**source, Error::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_combine_refuses_files_which_are_not_shards() {
+        use rand::Rng;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        fs::create_dir(&shards_dir).unwrap();
+        for name in ["notes.txt", "photo.jpg"] {
+            let mut data = [0u8; 40];
+            rand::thread_rng().fill(&mut data[..]);
+            // Any last byte but 0 would be an x-coordinate of a version 0 shard.
+            data[39] |= 1;
+            fs::write(shards_dir.join(name), data).unwrap();
+        }
+
+        let output_path = dir.path().join("secret");
+        let err = combine_secret(&shards_dir, &output_path, &quiet(), &AtomicBool::new(false))
+            .unwrap_err();
+        assert!(matches!(err, Error::Shard { ref source, .. }
+            if matches!(**source, Error::InvalidHeader(_))));
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_bare_share_x() {
+        assert_eq!(bare_share_x(Path::new("shards/3.bin")), Some(3));
+        assert_eq!(bare_share_x(Path::new("255")), Some(255));
+        assert_eq!(bare_share_x(Path::new("0.bin")), None);
+        assert_eq!(bare_share_x(Path::new("256.bin")), None);
+        assert_eq!(bare_share_x(Path::new("shards_1")), None);
+    }
+
+    #[test]
+    fn test_combine_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = write_shards(&shards_dir, b"test", 3, 3);
+        for (shard_path, owner) in shard_paths.iter().zip(["alice", "bob", "carol/backup"]) {
+            let owner_dir = shards_dir.join(owner);
+            fs::create_dir_all(&owner_dir).unwrap();
+            fs::rename(shard_path, owner_dir.join("shard")).unwrap();
+        }
+        fs::write(shards_dir.join("alice").join("notes.txt"), b"noise").unwrap();
+        let output_path = dir.path().join("secret");
+        let cancel = AtomicBool::new(false);
+
+        assert!(combine_secret(&shards_dir, &output_path, &quiet(), &cancel).is_err());
+        let options = CombineOptions {
+            recursive: true,
+            ..quiet()
+        };
+        combine_secret(&shards_dir, &output_path, &options, &cancel).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_combine_prefers_shard_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        for (index, shard_path) in write_shards(&shards_dir, b"test", 5, 3).iter().enumerate() {
+            fs::rename(shard_path, shards_dir.join(format!("shard_{}.sss", index))).unwrap();
+        }
+        fs::write(shards_dir.join("README.txt"), b"keep these safe").unwrap();
+        fs::write(shards_dir.join("7"), b"looks like a bare share").unwrap();
+        let output_path = dir.path().join("secret");
+
+        combine_secret(&shards_dir, &output_path, &quiet(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_verify_quorums() {
+        assert_eq!(quorum_count(4, 2), 6);
+        assert_eq!(quorum_count(5, 3), 10);
+        assert_eq!(quorum_count(255, 2), 32385);
+        assert_eq!(quorum_count(255, 255), 1);
+        assert_eq!(quorum_count(2, 3), 0);
+        assert_eq!(quorum_count(255, 127), u128::MAX);
+
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths: Vec<PathBuf> = write_shards(dir.path(), b"verified", 4, 2)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let cancel = AtomicBool::new(false);
+        let verified = verify_quorums(b"verified", &shard_paths, 2, GfBackend::Auto, &cancel);
+        assert_eq!(verified.unwrap(), 6);
+
+        let err = verify_quorums(b"verifiez", &shard_paths, 2, GfBackend::Auto, &cancel);
+        assert!(matches!(err, Err(Error::InconsistentShards(_))));
+        let err = verify_quorums(b"verified", &shard_paths[..1], 2, GfBackend::Auto, &cancel);
+        assert!(matches!(
+            err,
+            Err(Error::InsufficientShares { have: 1, need: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_combine_gzip_compressed_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = write_shards(&shards_dir, b"compressed shards", 5, 3);
+        // Compress every shard on its own, next to an uncompressed one and unrelated files.
+        for (index, shard_path) in shard_paths.iter().enumerate().skip(1) {
+            // Compressed shards are recognized by their content, whatever their name.
+            let compressed_path = shards_dir.join(format!("shard_{}.gz", index));
+            let mut encoder = flate2::write::GzEncoder::new(
+                File::create(&compressed_path).unwrap(),
+                flate2::Compression::default(),
+            );
+            encoder.write_all(&fs::read(shard_path).unwrap()).unwrap();
+            encoder.finish().unwrap();
+            fs::remove_file(shard_path).unwrap();
+        }
+        fs::rename(&shard_paths[0], shards_dir.join("shard_0.sss")).unwrap();
+        fs::write(shards_dir.join("notes.gz"), b"unrelated").unwrap();
+        let output_path = dir.path().join("secret");
+        let cancel = AtomicBool::new(false);
+
+        for recursive in [false, true] {
+            let options = CombineOptions {
+                recursive,
+                force: true,
+                ..quiet()
+            };
+            combine_secret(&shards_dir, &output_path, &options, &cancel).unwrap();
+            assert_eq!(fs::read(&output_path).unwrap(), b"compressed shards");
+        }
+
+        // A corrupted gzip stream is reported for its shard.
+        let truncated_path = shards_dir.join("shard_2.gz");
+        let data = fs::read(&truncated_path).unwrap();
+        fs::write(&truncated_path, &data[..data.len() / 2]).unwrap();
+        let options = CombineOptions {
+            force: true,
+            ..quiet()
+        };
+        let err = combine_secret(&shards_dir, &output_path, &options, &cancel).unwrap_err();
+        assert!(matches!(err, Error::Shard { ref name, .. } if name.ends_with("shard_2.gz")));
+    }
+
+    #[test]
+    fn test_combine_tar_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(&dir.path().join("shards"), b"test", 3, 3);
+
+        let archive_path = dir.path().join("shards.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&archive_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for shard_path in &shard_paths {
+            let name = Path::new(shard_path).file_name().unwrap();
+            builder.append_path_with_name(shard_path, name).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let output_path = dir.path().join("secret");
+        let cancel = AtomicBool::new(false);
+        combine_secret(&archive_path, &output_path, &quiet(), &cancel).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_combine_zip_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(&dir.path().join("shards"), b"test", 3, 3);
+
+        let archive_path = dir.path().join("shards.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        for (index, shard_path) in shard_paths.iter().enumerate() {
+            let options = zip::write::SimpleFileOptions::default();
+            writer
+                .start_file(format!("shard_{}", index), options)
+                .unwrap();
+            writer.write_all(&fs::read(shard_path).unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let output_path = dir.path().join("secret");
+        let cancel = AtomicBool::new(false);
+        combine_secret(&archive_path, &output_path, &quiet(), &cancel).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_combine_rejects_mixed_sets() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = write_shards(&dir.path().join("a"), b"test", 3, 2);
+        let second = write_shards(&dir.path().join("b"), b"test", 3, 2);
+        let output_path = dir.path().join("secret");
+
+        let mixed = vec![first[0].clone(), second[1].clone()];
+        let err = combine_quietly(&mixed, &output_path, false).unwrap_err();
+        let Error::Shard { source, .. } = err else {
+            panic!("unexpected error: {}", err);
+        };
+        assert!(matches!(*source, Error::InconsistentShards(_)));
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_combine_requires_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 5, 3);
+        let output_path = dir.path().join("secret");
+
+        let err = combine_quietly(&shard_paths[..2], &output_path, false).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InsufficientShares { have: 2, need: 3 }
+        ));
+
+        combine_quietly(&shard_paths[..3], &output_path, false).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_force_combine_downgrades_validation_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 3, 3);
+        let output_path = dir.path().join("secret");
+
+        // Move the first shard to another set and corrupt the checksum of the second.
+        let data = fs::read(&shard_paths[0]).unwrap();
+        let (mut shard_header, body) = header::decode_shard(&data).unwrap();
+        shard_header.set_id = shard_header.set_id.wrapping_add(1);
+        fs::write(&shard_paths[0], header::encode_shard(&shard_header, body)).unwrap();
+        let mut data = fs::read(&shard_paths[1]).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&shard_paths[1], data).unwrap();
+
+        let err = combine_quietly(&shard_paths, &output_path, false).unwrap_err();
+        let Error::Shard { source, .. } = err else {
+            panic!("unexpected error: {}", err);
+        };
+        assert!(matches!(*source, Error::ChecksumMismatch));
+        assert!(!output_path.exists());
+
+        let options = CombineOptions {
+            force_combine: true,
+            ..quiet()
+        };
+        combine_files(
+            &shard_paths,
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_force_combine_still_requires_two_shares() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 3, 2);
+        let output_path = dir.path().join("secret");
+
+        let options = CombineOptions {
+            force_combine: true,
+            ..quiet()
+        };
+        let err = combine_files(
+            &shard_paths[..1],
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InsufficientShares { have: 1, need: 2 }
+        ));
+    }
+
+    #[test]
+    fn test_empty_shards_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        fs::create_dir(&shards_dir).unwrap();
+
+        let err = combine_secret(
+            &shards_dir,
+            &dir.path().join("secret"),
+            &quiet(),
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, Error::NoShardsFound { dir } if dir == shards_dir.display().to_string())
+        );
+    }
+
+    /// Linux accepts any bytes in file names, unlike macOS or Windows.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join(OsStr::from_bytes(b"shards_\xff"));
+        // The display of the paths would lose their invalid bytes.
+        let shard_paths = shard_fixture(&shards_dir, b"test", scheme(3, 2));
+        fs::rename(
+            &shard_paths[0],
+            shards_dir.join(OsStr::from_bytes(b"\xfe\xff.sss")),
+        )
+        .unwrap();
+        fs::remove_file(&shard_paths[1]).unwrap();
+        fs::rename(&shard_paths[2], shards_dir.join("shard.sss")).unwrap();
+
+        for recursive in [false, true] {
+            let output_path = dir.path().join(format!("recovered_{}", recursive));
+            let options = CombineOptions {
+                recursive,
+                ..quiet()
+            };
+            combine_secret(&shards_dir, &output_path, &options, &AtomicBool::new(false)).unwrap();
+            assert_eq!(fs::read(&output_path).unwrap(), b"test");
+        }
+    }
+
+    #[test]
+    fn test_redundancy_check_detects_corrupted_shard() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 4, 2);
+        let output_path = dir.path().join("secret");
+        let options = CombineOptions {
+            redundancy_check: true,
+            ..quiet()
+        };
+
+        combine_files(
+            &shard_paths,
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"test");
+        fs::remove_file(&output_path).unwrap();
+
+        // Tamper with the share data of the last shard, keeping its checksum valid.
+        let data = fs::read(&shard_paths[3]).unwrap();
+        let (shard_header, body) = header::decode_shard(&data).unwrap();
+        let mut body = body.to_vec();
+        body[0] ^= 1;
+        fs::write(&shard_paths[3], header::encode_shard(&shard_header, &body)).unwrap();
+
+        let err = combine_files(
+            &shard_paths,
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InconsistentShards(_)));
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_combine_is_order_independent() {
+        use rand::seq::SliceRandom;
+
+        let dir = tempfile::tempdir().unwrap();
+        let secret = b"order does not matter";
+        let shard_paths = write_shards(dir.path(), secret, 6, 3);
+        let mut orders = vec![shard_paths.clone()];
+        orders.push(shard_paths.iter().rev().cloned().collect());
+        for _ in 0..8 {
+            let mut shuffled = shard_paths.clone();
+            shuffled.shuffle(&mut rand::thread_rng());
+            orders.push(shuffled);
+        }
+
+        // Corrupt the last shard, keeping its checksum valid, so that a forced redundancy
+        // check keeps the secret of one quorum.
+        let data = fs::read(&shard_paths[5]).unwrap();
+        let (shard_header, body) = header::decode_shard(&data).unwrap();
+        let mut body = body.to_vec();
+        body[0] ^= 1;
+        let corrupted = dir.path().join("corrupted.sss").display().to_string();
+        fs::write(&corrupted, header::encode_shard(&shard_header, &body)).unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let forced_check = CombineOptions {
+            redundancy_check: true,
+            force_combine: true,
+            ..quiet()
+        };
+        let mut forced = None;
+        for order in &orders {
+            for shards in [&order[..], &order[..3]] {
+                let (recovered, _) =
+                    recover_shards(read_shards(shards).unwrap(), &quiet(), &cancel).unwrap();
+                assert_eq!(recovered, secret, "{:?}", shards);
+            }
+            let (recovered, _) = recover_partial(read_shards(order).unwrap(), &quiet()).unwrap();
+            assert_eq!(recovered, secret, "{:?}", order);
+
+            let with_corrupted: Vec<String> = order
+                .iter()
+                .map(|path| {
+                    if path == &shard_paths[5] {
+                        corrupted.clone()
+                    } else {
+                        path.clone()
+                    }
+                })
+                .collect();
+            let (recovered, _) = recover_shards(
+                read_shards(&with_corrupted).unwrap(),
+                &forced_check,
+                &cancel,
+            )
+            .unwrap();
+            assert_eq!(
+                *forced.get_or_insert(recovered.clone()),
+                recovered,
+                "{:?}",
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_set_keeps_reconstructable_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = AtomicBool::new(false);
+        let old_paths = write_shards(&dir.path().join("old"), b"old secret", 5, 3);
+        let new_paths = write_shards(&dir.path().join("new"), b"new secret", 3, 2);
+        // Two shards of the old set, too few for its threshold, mixed with the new set.
+        let mixed: Vec<String> = [&old_paths[..2], &new_paths[..]].concat();
+        let allow_extra = CombineOptions {
+            allow_extra: true,
+            ..quiet()
+        };
+
+        let err = recover_shards(read_shards(&mixed).unwrap(), &quiet(), &cancel).unwrap_err();
+        assert!(
+            matches!(err, Error::Shard { source, .. } if matches!(*source, Error::InconsistentShards(_)))
+        );
+        let (recovered, _) =
+            recover_shards(read_shards(&mixed).unwrap(), &allow_extra, &cancel).unwrap();
+        assert_eq!(recovered, b"new secret");
+        let kept = select_set(parsed_shards(&mixed, &allow_extra), &allow_extra).unwrap();
+        let names: Vec<String> = kept.into_iter().map(|(name, ..)| name).collect();
+        assert_eq!(names, new_paths);
+
+        // The old set wins once it has more shards reaching its threshold.
+        let mixed: Vec<String> = [&new_paths[..], &old_paths[..]].concat();
+        let (recovered, _) =
+            recover_shards(read_shards(&mixed).unwrap(), &allow_extra, &cancel).unwrap();
+        assert_eq!(recovered, b"old secret");
+
+        // The largest set is reported when no set reaches its threshold.
+        let short: Vec<String> = [&old_paths[..2], &new_paths[..1]].concat();
+        let err = select_set(parsed_shards(&short, &allow_extra), &allow_extra).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InsufficientShares { have: 2, need: 3 }
+        ));
+    }
+
+    #[test]
+    fn test_combine_expecting_hash_skips_corrupted_shard() {
+        let cancel = AtomicBool::new(false);
+        let secret = b"legacy secret".to_vec();
+        let shares = shamir::split(&secret, 4, 3, false, GfBackend::Auto, None, false).unwrap();
+        let expected: [u8; 32] = Sha256::digest(&secret).into();
+        let options = CombineOptions {
+            expect_hash: Some(expected),
+            legacy: true,
+            ..quiet()
+        };
+        let legacy_options = CombineOptions {
+            legacy: true,
+            ..quiet()
+        };
+
+        // Legacy shares carry no checksum, so a corrupted one goes unnoticed without the
+        // expected hash, whatever their threshold.
+        let mut legacy: Vec<(String, Vec<u8>)> = shares
+            .iter()
+            .enumerate()
+            .map(|(i, share)| (format!("share_{}", i), share.clone()))
+            .collect();
+        legacy[2].1[0] ^= 0x5a;
+        let (wrong, _) = recover_shards(legacy.clone(), &legacy_options, &cancel).unwrap();
+        assert_ne!(wrong, secret);
+        let (recovered, _) = recover_shards(legacy, &options, &cancel).unwrap();
+        assert_eq!(recovered, secret);
+
+        // With a threshold of 3 in the headers, only the quorums of 3 shards are tried.
+        let set_header = Header {
+            threshold: 3,
+            parts: 4,
+            set_id: 7,
+            secret_len: secret.len() as u64,
+            ..Default::default()
+        };
+        let mut shards: Vec<(String, Vec<u8>)> = shares
+            .iter()
+            .enumerate()
+            .map(|(i, share)| {
+                let (&x, body) = share.split_last().unwrap();
+                let mut body = body.to_vec();
+                if i == 1 {
+                    body[3] ^= 1;
+                }
+                let shard_header = Header {
+                    x,
+                    ..set_header.clone()
+                };
+                (
+                    format!("shards_{}", i),
+                    header::encode_shard(&shard_header, &body),
+                )
+            })
+            .collect();
+        let (recovered, _) = recover_shards(shards.clone(), &options, &cancel).unwrap();
+        assert_eq!(recovered, secret);
+
+        // Two corrupted shards leave no quorum of 3 matching the hash.
+        let (&x, body) = shares[3].split_last().unwrap();
+        let mut body = body.to_vec();
+        body[0] ^= 1;
+        shards[3].1 = header::encode_shard(&Header { x, ..set_header }, &body);
+        let err = recover_shards(shards.clone(), &options, &cancel).unwrap_err();
+        assert!(matches!(err, Error::InconsistentShards(_)));
+
+        // Forcing fewer shards than the threshold through leaves no subset to search.
+        let forced = CombineOptions {
+            force_combine: true,
+            ..options.clone()
+        };
+        let err = recover_shards(shards[2..].to_vec(), &forced, &cancel).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InsufficientShares { have: 2, need: 3 }
+        ));
+
+        // A large scheme is refused rather than searched for hours.
+        let shares = shamir::split(&secret, 20, 10, false, GfBackend::Auto, None, false).unwrap();
+        let mut legacy: Vec<(String, Vec<u8>)> = shares
+            .into_iter()
+            .enumerate()
+            .map(|(i, share)| (format!("share_{}", i), share))
+            .collect();
+        legacy[0].1[0] ^= 1;
+        let err = recover_shards(legacy, &options, &cancel).unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(e) if e.contains("limit of 10000")));
+    }
+
+    #[test]
+    fn test_check_expiry() {
+        let set_header = Header {
+            created: Some(1_000),
+            expires: Some(2_000),
+            ..Default::default()
+        };
+        assert!(check_expiry(&set_header, 1_999, true).is_ok());
+        // Expired shards are only warned about unless the expiry is enforced.
+        assert!(check_expiry(&set_header, 2_000, false).is_ok());
+        assert!(matches!(
+            check_expiry(&set_header, 2_000, true),
+            Err(Error::Expired { expires: 2_000 })
+        ));
+        assert!(check_expiry(&Header::default(), u64::MAX, true).is_ok());
+    }
+
+    #[test]
+    fn test_combine_rejects_mismatched_declared_lengths() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"test", 3, 2);
+        let output_path = dir.path().join("secret");
+        let data = fs::read(&shard_paths[1]).unwrap();
+        let (shard_header, body) = header::decode_shard(&data).unwrap();
+
+        // A valid shard of a secret one byte longer.
+        let longer = Header {
+            secret_len: 5,
+            ..shard_header.clone()
+        };
+        let shard = header::encode_shard(&longer, &[body, &[0]].concat());
+        fs::write(&shard_paths[1], shard).unwrap();
+        let err = combine_quietly(&shard_paths[..2], &output_path, false).unwrap_err();
+        assert!(matches!(err, Error::InconsistentShards(ref message)
+            if message.contains("secret length 5 instead of 4")
+                && message.starts_with(&shard_paths[1])
+                && message.ends_with(&shard_paths[0])));
+        assert!(!output_path.exists());
+
+        // Bodies of the same length, which only the partial recovery accepts, with a
+        // different declared length and threshold.
+        let shorter = Header {
+            secret_len: 3,
+            threshold: 3,
+            ..shard_header
+        };
+        let shard = [&shorter.to_bytes()[..], body].concat();
+        fs::write(&shard_paths[1], shard).unwrap();
+        let err = recover_partial(read_shards(&shard_paths[..2]).unwrap(), &quiet()).unwrap_err();
+        assert!(matches!(err, Error::InconsistentShards(ref message)
+            if message.contains("secret length 3 instead of 4, threshold 3 instead of 2")));
+    }
+
+    #[test]
+    fn test_combine_partial_truncated_shard() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = write_shards(dir.path(), b"partial recovery", 3, 2);
+        let output_path = dir.path().join("secret");
+        // Keep the header and the first 10 bytes of the body of one shard, and lose another.
+        let data = fs::read(&shard_paths[0]).unwrap();
+        let (_, header_len) = Header::from_bytes(&data).unwrap();
+        fs::write(&shard_paths[0], &data[..header_len + 10]).unwrap();
+
+        let err = combine_quietly(&shard_paths[..2], &output_path, false).unwrap_err();
+        assert!(matches!(err, Error::Shard { ref source, .. }
+            if matches!(**source, Error::InvalidHeader(_))));
+
+        let options = CombineOptions {
+            partial: true,
+            ..quiet()
+        };
+        combine_files(
+            &shard_paths[..2],
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"partial re\0\0\0\0\0\0");
+
+        // A forged secret length beyond every shard is not allocated.
+        let shares = shamir::split(b"abc", 2, 2, false, GfBackend::Auto, None, false).unwrap();
+        let forged: Vec<(String, Vec<u8>)> = shares
+            .iter()
+            .map(|share| {
+                let (&x, body) = share.split_last().unwrap();
+                let shard_header = Header {
+                    threshold: 2,
+                    parts: 2,
+                    x,
+                    set_id: 9,
+                    secret_len: 1 << 40,
+                    ..Default::default()
+                };
+                (x.to_string(), header::encode_shard(&shard_header, body))
+            })
+            .collect();
+        let (secret, _) = recover_partial(forged, &quiet()).unwrap();
+        assert!(secret.starts_with(b"abc") && secret.len() < 64);
+    }
+
+    #[test]
+    fn test_lost_ranges() {
+        let recovered = [false, true, false, false, true, true, false];
+        assert_eq!(lost_ranges(&recovered), ["0", "2-3", "6"]);
+        assert!(lost_ranges(&[true; 4]).is_empty());
+    }
 }
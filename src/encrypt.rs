@@ -0,0 +1,77 @@
+use std::{io::Write, str::FromStr};
+
+use crate::error::{Error, Result};
+
+/// Encrypts a recovered secret to age X25519 recipients, so that it can be archived without
+/// the plaintext ever being written to disk.
+///
+/// # Arguments
+///
+/// * `recipients` - The public keys of the recipients, such as `age1ql3z7hjy54pw3hyww5ay...`.
+///   Any of their identities decrypts the output.
+/// * `secret` - The recovered secret.
+///
+/// # Returns
+///
+/// The binary age file, or `Error::InvalidParameters` if no recipient is given or one of them
+/// is not a valid age public key.
+///
+/// # Examples
+///
+/// ```
+/// let encrypted = encrypt_to(&[public_key.to_string()], b"secret")?;
+/// ```
+pub fn encrypt_to(recipients: &[String], secret: &[u8]) -> Result<Vec<u8>> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| {
+            age::x25519::Recipient::from_str(recipient).map_err(|e| {
+                Error::InvalidParameters(format!("invalid age recipient `{}`: {}", recipient, e))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )
+    .map_err(|e| Error::InvalidParameters(format!("cannot encrypt the secret: {}", e)))?;
+
+    let mut encrypted = Vec::with_capacity(secret.len() + 256);
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(secret)?;
+    writer.finish()?;
+    Ok(encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_encrypt_to() {
+        let identities: Vec<_> = (0..2).map(|_| age::x25519::Identity::generate()).collect();
+        let recipients: Vec<_> = identities
+            .iter()
+            .map(|identity| identity.to_public().to_string())
+            .collect();
+        let encrypted = encrypt_to(&recipients, b"secret").unwrap();
+
+        // Either identity decrypts the secret.
+        for identity in &identities {
+            let decryptor = age::Decryptor::new(&encrypted[..]).unwrap();
+            let mut reader = decryptor
+                .decrypt(std::iter::once(identity as &dyn age::Identity))
+                .unwrap();
+            let mut decrypted = Vec::new();
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, b"secret");
+        }
+
+        for recipients in [&[][..], &["age1invalid".to_string()]] {
+            let err = encrypt_to(recipients, b"secret").unwrap_err();
+            assert!(matches!(err, Error::InvalidParameters(_)));
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+use thiserror::Error;
+
+/// Result type returned by the sharding and combining operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the sharding and combining operations.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The secret or the scheme parameters cannot be split, e.g. a threshold above the
+    /// number of parts.
+    #[error("invalid parameters: {0}")]
+    InvalidParameters(String),
+    /// A shard header is truncated, malformed or uses an unsupported version.
+    #[error("invalid shard header: {0}")]
+    InvalidHeader(String),
+    /// The checksum trailing a shard does not match its content.
+    #[error("shard checksum does not match its content")]
+    ChecksumMismatch,
+    /// A block of a shard does not match its integrity tag, see `header::BlockTags`. The
+    /// offset is that of the start of the block in the shard file.
+    #[error("block {block} of the shard, at byte {offset}, does not match its checksum")]
+    BlockChecksumMismatch { block: u64, offset: u64 },
+    /// The shards cannot be combined together, e.g. they belong to different sets or have
+    /// different lengths.
+    #[error("inconsistent shards: {0}")]
+    InconsistentShards(String),
+    /// Two shares have the same x-coordinate, so interpolating them would divide by zero.
+    #[error("duplicate share with x-coordinate {x}")]
+    DuplicateShare { x: u8 },
+    /// A shards directory holds no file at all.
+    #[error("no shards found in {dir}")]
+    NoShardsFound { dir: String },
+    /// Fewer distinct shards than required were provided.
+    #[error("{have} shards provided but {need} are required to reconstruct the secret")]
+    InsufficientShares { have: usize, need: usize },
+    /// The reconstructed secret is empty, or its length differs from the one declared in the
+    /// shard headers, if any.
+    #[error("reconstructed secret is {actual} bytes {}", expected_len(*.expected))]
+    ReconstructionLengthMismatch {
+        expected: Option<u64>,
+        actual: usize,
+    },
+    /// The shards are past the expiry time recorded in their headers, and expired shards
+    /// are refused.
+    #[error("the shards expired at {expires} (seconds since the Unix epoch)")]
+    Expired { expires: u64 },
+    /// The system random number generator is unavailable or fails a sanity check.
+    #[error("system random number generator unavailable: {0}")]
+    RandomSource(String),
+    /// The self-test found the field arithmetic or the secret sharing to be broken.
+    #[error("self-test failed: {0}")]
+    SelfTest(String),
+    /// The operation was stopped through its cancel flag.
+    #[error("operation cancelled")]
+    Cancelled,
+    /// An error caused by a specific shard.
+    #[error("{name}: {source}")]
+    Shard { name: String, source: Box<Error> },
+}
+
+impl Error {
+    /// Attributes the error to a shard, so that its name is part of the message.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The path or archive entry name of the shard.
+    pub fn in_shard(self, name: &str) -> Self {
+        Error::Shard {
+            name: name.to_string(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Returns the exit status the command line uses for this error, following the
+    /// conventions of `sysexits.h`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Error::ChecksumMismatch.exit_code(), 65);
+    /// ```
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidParameters(_) => 64,
+            Error::InvalidHeader(_)
+            | Error::ChecksumMismatch
+            | Error::BlockChecksumMismatch { .. }
+            | Error::InconsistentShards(_)
+            | Error::DuplicateShare { .. }
+            | Error::InsufficientShares { .. }
+            | Error::ReconstructionLengthMismatch { .. }
+            | Error::Expired { .. } => 65,
+            Error::NoShardsFound { .. } => 66,
+            Error::SelfTest(_) => 70,
+            Error::RandomSource(_) => 71,
+            Error::Io(_) => 74,
+            Error::Cancelled => 75,
+            Error::Shard { source, .. } => source.exit_code(),
+        }
+    }
+}
+
+/// Describes the length a reconstructed secret should have had.
+fn expected_len(expected: Option<u64>) -> String {
+    match expected {
+        Some(expected) => format!("but the shards declare {}", expected),
+        None => "but a secret is never empty".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = Error::InsufficientShares { have: 2, need: 3 }.in_shard("shards_0");
+        assert_eq!(
+            error.to_string(),
+            "shards_0: 2 shards provided but 3 are required to reconstruct the secret"
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(Error::InvalidParameters(String::new()).exit_code(), 64);
+        assert_eq!(Error::ChecksumMismatch.in_shard("shards_0").exit_code(), 65);
+        assert_eq!(Error::from(io::Error::other("disk full")).exit_code(), 74);
+        let no_shards = Error::NoShardsFound {
+            dir: "shards".to_string(),
+        };
+        assert_eq!(no_shards.exit_code(), 66);
+        assert_eq!(Error::Expired { expires: 0 }.exit_code(), 65);
+        assert_eq!(Error::SelfTest(String::new()).exit_code(), 70);
+        assert_eq!(Error::RandomSource(String::new()).exit_code(), 71);
+        assert_eq!(Error::Cancelled.exit_code(), 75);
+    }
+}
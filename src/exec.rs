@@ -0,0 +1,90 @@
+use std::{
+    io::{self, Write},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use crate::error::Result;
+
+/// Runs a shell command with the secret on its standard input, instead of writing the secret
+/// to a file.
+///
+/// The command is run by `sh -c`, or `cmd /C` on Windows, so it may hold arguments and
+/// pipes, such as `kubectl apply -f -`. Its standard output and error are those of this
+/// process. The command may exit without reading the whole secret.
+///
+/// # Arguments
+///
+/// * `command` - The command line to run.
+/// * `secret` - The recovered secret.
+///
+/// # Returns
+///
+/// The exit code of the command, 128 plus the signal number if a signal killed it, or an
+/// `Error::Io` naming the command if it cannot be started or waited for.
+///
+/// # Examples
+///
+/// ```
+/// let code = pipe_secret("systemd-creds encrypt - /etc/credstore/db", &secret)?;
+/// ```
+pub fn pipe_secret(command: &str, secret: &[u8]) -> Result<i32> {
+    #[cfg(windows)]
+    let shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    };
+    #[cfg(not(windows))]
+    let shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    };
+    let status = run_with_stdin(shell, secret)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run `{}`: {}", command, e)))?;
+    Ok(exit_code(status))
+}
+
+/// Spawns a command, writes `input` to its standard input and waits for it to exit.
+fn run_with_stdin(mut command: Command, input: &[u8]) -> io::Result<ExitStatus> {
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    match stdin.write_all(input) {
+        // The command chose not to read all of its input; its exit code tells whether it
+        // succeeded.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+        written => written?,
+    }
+    // Close the pipe so that the command sees the end of the secret.
+    drop(stdin);
+    child.wait()
+}
+
+/// Turns the exit status of a command into an exit code, as a shell does.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipe_secret_exit_code() {
+        assert_eq!(pipe_secret("cat > /dev/null", b"secret").unwrap(), 0);
+        assert_eq!(pipe_secret("exit 3", b"secret").unwrap(), 3);
+        // The command exits without reading the secret.
+        assert_eq!(pipe_secret("true", &[0; 1 << 20]).unwrap(), 0);
+        assert_eq!(pipe_secret("kill -9 $$", b"secret").unwrap(), 137);
+    }
+
+    #[test]
+    fn test_spawn_failure() {
+        let err = run_with_stdin(Command::new("/nonexistent/command"), b"secret").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
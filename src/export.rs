@@ -0,0 +1,262 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::ffi::OsString;
+
+use crate::{
+    error::{Error, Result},
+    header,
+    json::{self, Value},
+};
+
+/// Key of the array holding the shards in the JSON format.
+const JSON_KEY: &str = "shards";
+
+/// Encodes the shards of a set as a JSON object holding the base64 of every shard in a
+/// `shards` array, one per line.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(to_json(&[vec![1], vec![2]]), "{\"shards\":[\n\"AQ==\",\n\"Ag==\"\n]}\n");
+/// ```
+pub fn to_json(shards: &[Vec<u8>]) -> String {
+    let items = shards
+        .iter()
+        .map(|shard| json::string(&STANDARD.encode(shard)))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!("{{{}:[\n{}\n]}}\n", json::string(JSON_KEY), items)
+}
+
+/// Encodes the shards of a set as the base64 of every shard, one per line.
+pub fn to_base64_lines(shards: &[Vec<u8>]) -> String {
+    shards
+        .iter()
+        .map(|shard| STANDARD.encode(shard) + "\n")
+        .collect()
+}
+
+/// Encodes the shards of a set as shell `export` statements, one per line, setting the
+/// variable `{prefix}{index}` to the base64 of the shard of that index.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(
+///     to_env_exports(&[vec![1], vec![2]], "SHARD_"),
+///     "export SHARD_0='AQ=='\nexport SHARD_1='Ag=='\n"
+/// );
+/// ```
+pub fn to_env_exports(shards: &[Vec<u8>], prefix: &str) -> String {
+    shards
+        .iter()
+        .enumerate()
+        .map(|(index, shard)| format!("export {}{}='{}'\n", prefix, index, STANDARD.encode(shard)))
+        .collect()
+}
+
+/// Rejects a prefix which would not make valid environment variable names in a shell, that
+/// is letters, digits and underscores not starting with a digit, with
+/// `Error::InvalidParameters`.
+pub fn check_env_prefix(prefix: &str) -> Result<()> {
+    let valid = prefix
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !prefix.starts_with(|c: char| c.is_ascii_digit());
+    if prefix.is_empty() || !valid {
+        return Err(Error::InvalidParameters(format!(
+            "`{}` is not a valid prefix for environment variables",
+            prefix
+        )));
+    }
+    Ok(())
+}
+
+/// Decodes the shards exported by `to_env_exports` from environment variables.
+///
+/// Only the variables named `prefix` followed by a number are read, so that gaps in the
+/// numbering, such as a shard left out of the environment, are allowed. Other variables
+/// starting with the prefix are ignored.
+///
+/// # Arguments
+///
+/// * `vars` - The environment variables, such as `std::env::vars_os()`.
+/// * `prefix` - The prefix of the variables holding the shards.
+///
+/// # Returns
+///
+/// The name and content of every shard, by increasing number, `Error::InvalidParameters`
+/// naming the variable if one does not hold the base64 of a shard, or
+/// `Error::NoShardsFound` if no variable matches.
+pub fn from_env_vars(
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+    prefix: &str,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    check_env_prefix(prefix)?;
+    let mut shards = vars
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let name = name.into_string().ok()?;
+            let number = name.strip_prefix(prefix)?;
+            if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let number: u64 = number.parse().ok()?;
+            Some((number, name, value))
+        })
+        .collect::<Vec<_>>();
+    if shards.is_empty() {
+        return Err(Error::NoShardsFound {
+            dir: format!("the environment variables {}<N>", prefix),
+        });
+    }
+    shards.sort_unstable_by_key(|(number, _, _)| *number);
+    shards
+        .into_iter()
+        .map(|(_, name, value)| {
+            let shard = value
+                .to_str()
+                .and_then(|value| decode_shard(value.trim()))
+                .ok_or_else(|| {
+                    Error::InvalidParameters(format!(
+                        "{} does not hold the base64 of a shard",
+                        name
+                    ))
+                })?;
+            Ok((name, shard))
+        })
+        .collect()
+}
+
+/// Decodes a file written by `to_json` or `to_base64_lines`.
+///
+/// Whitespace is ignored in the JSON format, so that the file may be reformatted, but the
+/// object must hold the `shards` array alone. A file of a single line is left to be read as
+/// a single shard stored as text.
+///
+/// # Arguments
+///
+/// * `data` - The content of the file.
+///
+/// # Returns
+///
+/// The shards, `None` if the data is in neither format, or `Error::InvalidHeader` if it is a
+/// JSON object which is not a shard set.
+pub fn decode(data: &[u8]) -> Result<Option<Vec<Vec<u8>>>> {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return Ok(None);
+    };
+    let text = text.trim_start_matches('\u{feff}');
+    if text.trim_start().starts_with('{') {
+        return decode_json(text).map(Some);
+    }
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.len() < 2 {
+        return Ok(None);
+    }
+    Ok(lines.iter().map(|line| decode_shard(line)).collect())
+}
+
+/// Decodes the JSON format of `to_json`.
+fn decode_json(text: &str) -> Result<Vec<Vec<u8>>> {
+    let invalid = || Error::InvalidHeader("not a JSON shard set".to_string());
+    let Some(Value::Object(mut fields)) = json::parse(text) else {
+        return Err(invalid());
+    };
+    let Some(Value::Array(items)) = fields.remove(JSON_KEY).filter(|_| fields.is_empty()) else {
+        return Err(invalid());
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::String(item) => decode_shard(&item).ok_or_else(invalid),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// Decodes the base64 of a shard, checking that it starts with the shard magic.
+fn decode_shard(text: &str) -> Option<Vec<u8>> {
+    let shard = STANDARD.decode(text).ok()?;
+    header::has_magic(&shard).then_some(shard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two fake shards, starting with the shard magic.
+    fn shards() -> Vec<Vec<u8>> {
+        (1..=2)
+            .map(|x| [&header::MAGIC[..], &[x; 8]].concat())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = to_json(&shards());
+        assert_eq!(decode(json.as_bytes()).unwrap(), Some(shards()));
+        // Reformatted JSON is accepted as well.
+        let pretty = json.replace("[\n", "[\n    ").replace("{", "{ ");
+        assert_eq!(decode(pretty.as_bytes()).unwrap(), Some(shards()));
+
+        let lines = to_base64_lines(&shards()).replace('\n', "\r\n");
+        assert_eq!(decode(lines.as_bytes()).unwrap(), Some(shards()));
+    }
+
+    #[test]
+    fn test_env_round_trip() {
+        let exports = to_env_exports(&shards(), "SHARD_");
+        let vars: Vec<(OsString, OsString)> = exports
+            .lines()
+            .map(|line| {
+                let (name, value) = line
+                    .strip_prefix("export ")
+                    .and_then(|line| line.split_once('='))
+                    .unwrap();
+                (name.into(), value.trim_matches('\'').into())
+            })
+            .collect();
+        let decoded = from_env_vars(vars.clone(), "SHARD_").unwrap();
+        assert_eq!(decoded[1], ("SHARD_1".to_string(), shards()[1].clone()));
+
+        // Gaps in the numbering are allowed, and the shards are sorted by number.
+        let gapped = vec![
+            ("SHARD_12".into(), vars[1].1.clone()),
+            ("SHARD_PATH".into(), "/tmp".into()),
+            ("SHARD_3".into(), vars[0].1.clone()),
+            ("OTHER_0".into(), vars[0].1.clone()),
+        ];
+        let decoded = from_env_vars(gapped, "SHARD_").unwrap();
+        let names: Vec<&str> = decoded.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["SHARD_3", "SHARD_12"]);
+        assert_eq!(decoded[1].1, shards()[1]);
+
+        assert!(matches!(
+            from_env_vars(vars.clone(), "OTHER_"),
+            Err(Error::NoShardsFound { .. })
+        ));
+        let invalid = vec![("SHARD_0".into(), "aGVsbG8=".into())];
+        assert!(
+            matches!(from_env_vars(invalid, "SHARD_"), Err(Error::InvalidParameters(e)) if e.contains("SHARD_0"))
+        );
+        for prefix in ["", "1SHARD", "SHARD-"] {
+            assert!(check_env_prefix(prefix).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_other_data() {
+        let single = to_base64_lines(&shards()[..1]);
+        assert_eq!(decode(single.as_bytes()).unwrap(), None);
+        assert_eq!(decode(&[0xff, 0xfe]).unwrap(), None);
+        assert_eq!(decode(b"hello\nworld\n").unwrap(), None);
+        assert!(matches!(
+            decode(b"{\"secrets\":[]}"),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+}
@@ -1,3 +1,120 @@
+use std::sync::OnceLock;
+
+/// Secret length from which `GfBackend::Auto` switches to the lookup tables.
+///
+/// Building the tables takes about as long as 90 scalar multiplications, and a table
+/// multiplication is about six times faster than a scalar one, as measured by
+/// `test_table_min_secret_len`. Since splitting takes `parts * threshold` multiplications per
+/// secret byte, the tables pay for themselves from about 8 bytes with the default 5 shards and
+/// threshold of 3, and from about 28 bytes with 2 shards and a threshold of 2, the smallest
+/// scheme. Larger schemes and combining take more multiplications per byte, so from 32 bytes
+/// the tables are faster whatever the scheme.
+pub const TABLE_MIN_SECRET_LEN: usize = 32;
+
+/// Generator of the multiplicative group of GF(2^8) used to build the lookup tables.
+const GENERATOR: u8 = 3;
+
+include!(concat!(env!("OUT_DIR"), "/inverse_table.rs"));
+include!("gf256_mult.rs");
+
+/// Exponential and logarithm tables of GF(2^8), built on first use.
+///
+/// `OnceLock` builds them exactly once even when several rayon workers race to use them, and
+/// the tables are read-only afterwards.
+static TABLES: OnceLock<Tables> = OnceLock::new();
+
+/// Lookup tables turning multiplications in GF(2^8) into additions of logarithms.
+struct Tables {
+    /// Powers of `GENERATOR`, repeated twice so that the sum of two logarithms can be used
+    /// as an index without reducing it modulo 255.
+    exp: [u8; 510],
+    /// Logarithms in base `GENERATOR`; the entry for 0 is unused.
+    log: [u8; 256],
+}
+
+// The tables are read concurrently by the parallel `combine`; fail to compile if they ever
+// stop being shareable across threads.
+const _: () = {
+    const fn assert_sync<T: Sync>() {}
+    assert_sync::<Tables>();
+};
+
+impl Tables {
+    /// Builds the tables using scalar multiplication.
+    fn build() -> Self {
+        let mut tables = Tables {
+            exp: [0; 510],
+            log: [0; 256],
+        };
+        let mut power: u8 = 1;
+        for exponent in 0..255 {
+            tables.exp[exponent] = power;
+            tables.exp[exponent + 255] = power;
+            tables.log[power as usize] = exponent as u8;
+            power = GF256::mult(power, GENERATOR);
+        }
+        tables
+    }
+}
+
+/// Returns the lookup tables, building them on first use.
+fn tables() -> &'static Tables {
+    TABLES.get_or_init(Tables::build)
+}
+
+/// Implementations of the GF(2^8) arithmetic used by `split` and `combine`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum GfBackend {
+    /// Russian peasant multiplication, with no setup cost.
+    Scalar,
+    /// Logarithm and exponential lookup tables, built once per process.
+    Table,
+    /// `Scalar` for secrets shorter than `TABLE_MIN_SECRET_LEN` bytes, `Table` otherwise.
+    #[default]
+    Auto,
+}
+
+impl GfBackend {
+    /// Resolves `Auto` to the backend suited to a secret of the given length.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret_len` - The length of the secret, in bytes.
+    ///
+    /// # Returns
+    ///
+    /// `Scalar` or `Table`; explicit choices are returned unchanged.
+    pub fn resolve(self, secret_len: usize) -> Self {
+        match self {
+            GfBackend::Auto if secret_len < TABLE_MIN_SECRET_LEN => GfBackend::Scalar,
+            GfBackend::Auto => GfBackend::Table,
+            backend => backend,
+        }
+    }
+
+    /// Multiplies two elements in GF(2^8) with this backend. `Auto` behaves as `Scalar`, so
+    /// it should be resolved first.
+    pub fn mult(self, a: u8, b: u8) -> u8 {
+        match self {
+            GfBackend::Table => GF256::mult_table(a, b),
+            GfBackend::Scalar | GfBackend::Auto => GF256::mult(a, b),
+        }
+    }
+
+    /// Divides one element by another in GF(2^8) with this backend. `Auto` behaves as
+    /// `Scalar`, so it should be resolved first.
+    ///
+    /// # Returns
+    ///
+    /// The quotient, or `None` if `b` is 0 because division by zero is undefined.
+    pub fn try_div(self, a: u8, b: u8) -> Option<u8> {
+        match self {
+            GfBackend::Table => GF256::try_div_table(a, b),
+            GfBackend::Scalar | GfBackend::Auto => GF256::try_div(a, b),
+        }
+    }
+}
+
 // Galois Field: GF(2^8)
 pub struct GF256;
 
@@ -42,23 +159,14 @@ impl GF256 {
     /// assert_eq!(GF256::mult(3, 0), 0);
     /// assert_eq!(GF256::mult(0, 3), 0);
     /// ```
-    pub fn mult(mut a: u8, mut b: u8) -> u8 {
-        let mut result: u8 = 0;
-        while b > 0 {
-            if b & 1 != 0 {
-                result ^= a; // If the lowest bit of b is set, XOR result with a.
-            }
-            if a & 0x80 != 0 {
-                a = (a << 1) ^ 0x1B; // XOR with the reduction polynomial if a is about to overflow.
-            } else {
-                a <<= 1; // Otherwise, just shift a to the left.
-            }
-            b >>= 1; // Shift b to the right.
-        }
-        result
+    pub const fn mult(a: u8, b: u8) -> u8 {
+        russian_peasant_mult(a, b)
     }
 
-    /// Computes the multiplicative inverse of an element in GF(2^8).
+    /// Computes the multiplicative inverse of an element in GF(2^8) by raising it to the power
+    /// 254, which takes 11 multiplications. `GF256::inverse_table` returns the same result
+    /// with a single lookup in the table generated by `build.rs`, so this is only kept to
+    /// check that table.
     ///
     /// # Arguments
     ///
@@ -66,21 +174,17 @@ impl GF256 {
     ///
     /// # Returns
     ///
-    /// The multiplicative inverse of `a` in GF(2^8).
-    ///
-    /// # Panics
-    ///
-    /// Panics if `a` is 0 since the inverse does not exist.
+    /// The multiplicative inverse of `a` in GF(2^8), or 0 if `a` is 0 since the inverse does
+    /// not exist.
     ///
     /// # Examples
     ///
     /// ```
-    /// // Note: The example values below are hypothetical and may not represent
-    /// // actual multiplicative inverses in GF(2^8).
-    /// assert_eq!(GF256::inverse(3), some_value);
-    /// assert_eq!(GF256::inverse(9), another_value);
+    /// assert_eq!(GF256::inverse(3), 246);
+    /// assert_eq!(GF256::mult(9, GF256::inverse(9)), 1);
     /// ```
-    pub fn inverse(a: u8) -> u8 {
+    #[cfg(test)]
+    pub const fn inverse(a: u8) -> u8 {
         let mut b = GF256::mult(a, a);
         let mut c = GF256::mult(a, b);
         b = GF256::mult(c, c);
@@ -94,6 +198,26 @@ impl GF256 {
         GF256::mult(b, b)
     }
 
+    /// Looks up the multiplicative inverse of an element in GF(2^8) in `INVERSE`, generated
+    /// at compile time by `build.rs`.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The byte to invert.
+    ///
+    /// # Returns
+    ///
+    /// The same result as `GF256::inverse`, 0 for 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(GF256::inverse_table(3), 246);
+    /// ```
+    pub fn inverse_table(a: u8) -> u8 {
+        INVERSE[a as usize]
+    }
+
     /// Divides one element by another in GF(2^8).
     ///
     /// # Arguments
@@ -120,10 +244,180 @@ impl GF256 {
         if b == 0 {
             panic!("divide by zero");
         }
-        let mut ret = GF256::mult(a, GF256::inverse(b));
+        let mut ret = GF256::mult(a, GF256::inverse_table(b));
         ret = if a == 0 { 0 } else { ret };
         ret
     }
+
+    /// Divides one element by another in GF(2^8), without panicking on a zero divisor.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The dividend.
+    /// * `b` - The divisor.
+    ///
+    /// # Returns
+    ///
+    /// The same result as `GF256::div`, or `None` if `b` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(GF256::try_div(6, 3), Some(2));
+    /// assert_eq!(GF256::try_div(6, 0), None);
+    /// ```
+    pub fn try_div(a: u8, b: u8) -> Option<u8> {
+        (b != 0).then(|| GF256::div(a, b))
+    }
+
+    /// Multiplies two elements in GF(2^8) using the logarithm and exponential tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The first byte to multiply.
+    /// * `b` - The second byte to multiply.
+    ///
+    /// # Returns
+    ///
+    /// The same result as `GF256::mult`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(GF256::mult_table(3, 7), 9);
+    /// assert_eq!(GF256::mult_table(3, 0), 0);
+    /// ```
+    pub fn mult_table(a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        let tables = tables();
+        tables.exp[tables.log[a as usize] as usize + tables.log[b as usize] as usize]
+    }
+
+    /// Divides one element by another in GF(2^8) using the logarithm and exponential tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The dividend.
+    /// * `b` - The divisor.
+    ///
+    /// # Returns
+    ///
+    /// The same result as `GF256::div`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `b` is 0 because division by zero is undefined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(GF256::div_table(6, 3), 2);
+    /// ```
+    pub fn div_table(a: u8, b: u8) -> u8 {
+        if b == 0 {
+            panic!("divide by zero");
+        }
+        if a == 0 {
+            return 0;
+        }
+        let tables = tables();
+        tables.exp[tables.log[a as usize] as usize + 255 - tables.log[b as usize] as usize]
+    }
+
+    /// Divides one element by another in GF(2^8) using the lookup tables, without panicking
+    /// on a zero divisor.
+    ///
+    /// # Returns
+    ///
+    /// The same result as `GF256::div_table`, or `None` if `b` is 0.
+    pub fn try_div_table(a: u8, b: u8) -> Option<u8> {
+        (b != 0).then(|| GF256::div_table(a, b))
+    }
+}
+
+/// Output formats of `dump_tables`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum TableFormat {
+    /// Two CSV tables separated by a blank line: the logarithm, antilogarithm and inverse of
+    /// every element, then the multiplication table.
+    #[default]
+    Csv,
+    /// A single JSON object with the generator and the `log`, `antilog`, `inverse` and `mult`
+    /// arrays.
+    Json,
+}
+
+/// Dumps the lookup tables of GF(2^8), so that they can be diffed against a reference
+/// implementation.
+///
+/// The antilogarithm of `i` is `GENERATOR` raised to the power `i`, for `i` below 255, and
+/// 0, which has no logarithm, is written as an empty CSV cell or `null`. The multiplication
+/// table is computed with `GF256::mult_table`.
+///
+/// # Arguments
+///
+/// * `format` - The output format.
+///
+/// # Returns
+///
+/// The tables, ending with a newline.
+pub fn dump_tables(format: TableFormat) -> String {
+    let tables = tables();
+    let log = |x: usize| (x != 0).then(|| tables.log[x]);
+    let antilog = |x: usize| (x < 255).then(|| tables.exp[x]);
+    let cell = |value: Option<u8>, empty: &str| value.map_or(empty.to_string(), |v| v.to_string());
+    let mult_row = |a: usize, separator: &str| {
+        (0..256)
+            .map(|b| GF256::mult_table(a as u8, b as u8).to_string())
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+
+    match format {
+        TableFormat::Csv => {
+            let mut out = String::from("x,log,antilog,inverse\n");
+            for (x, inverse) in INVERSE.iter().enumerate() {
+                out += &format!(
+                    "{},{},{},{}\n",
+                    x,
+                    cell(log(x), ""),
+                    cell(antilog(x), ""),
+                    inverse
+                );
+            }
+            out += "\na*b";
+            for b in 0..256 {
+                out += &format!(",{}", b);
+            }
+            out += "\n";
+            for a in 0..256 {
+                out += &format!("{},{}\n", a, mult_row(a, ","));
+            }
+            out
+        }
+        TableFormat::Json => {
+            let array = |value: &dyn Fn(usize) -> Option<u8>, len: usize| {
+                (0..len)
+                    .map(|x| cell(value(x), "null"))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            let mult = (0..256)
+                .map(|a| format!("[{}]", mult_row(a, ",")))
+                .collect::<Vec<_>>()
+                .join(",");
+            format!(
+                "{{\"generator\":{},\"log\":[{}],\"antilog\":[{}],\"inverse\":[{}],\"mult\":[{}]}}\n",
+                GENERATOR,
+                array(&log, 256),
+                array(&antilog, 255),
+                array(&|x| Some(INVERSE[x]), 256),
+                mult
+            )
+        }
+    }
 }
 
 #[cfg(test)]
@@ -149,4 +443,148 @@ mod tests {
         assert_eq!(GF256::div(3, 3), 1);
         assert_eq!(GF256::div(6, 3), 2);
     }
+
+    #[test]
+    fn test_field_zero() {
+        for a in 1..=255 {
+            assert_eq!(GF256::div(0, a), 0);
+            assert_eq!(GF256::div_table(0, a), 0);
+            assert_eq!(GF256::mult_table(0, a), 0);
+            assert_eq!(GF256::mult(a, GF256::div(0, a)), 0);
+        }
+    }
+
+    #[test]
+    fn test_field_try_divide() {
+        assert_eq!(GF256::try_div(6, 3), Some(2));
+        assert_eq!(GF256::try_div(6, 0), None);
+        assert_eq!(GF256::try_div_table(6, 3), Some(2));
+        assert_eq!(GF256::try_div_table(0, 0), None);
+    }
+
+    #[test]
+    fn test_inverse_table() {
+        for a in 1..=255 {
+            assert_eq!(GF256::inverse_table(a), GF256::inverse(a));
+            assert_eq!(GF256::mult(a, GF256::inverse_table(a)), 1);
+        }
+        assert_eq!(GF256::inverse_table(0), 0);
+    }
+
+    #[test]
+    fn test_generated_inverse_table() {
+        // The table written by `build.rs` matches the inverse computed at run time.
+        for a in 0..=255 {
+            assert_eq!(INVERSE[a as usize], GF256::inverse(a), "inverse of {}", a);
+        }
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        for a in 0..=255 {
+            for b in 0..=255 {
+                assert_eq!(GF256::mult_table(a, b), GF256::mult(a, b));
+                if b != 0 {
+                    assert_eq!(GF256::div_table(a, b), GF256::div(a, b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_backend_resolve() {
+        assert_eq!(GfBackend::Auto.resolve(1), GfBackend::Scalar);
+        assert_eq!(
+            GfBackend::Auto.resolve(TABLE_MIN_SECRET_LEN),
+            GfBackend::Table
+        );
+        assert_eq!(GfBackend::Scalar.resolve(1 << 20), GfBackend::Scalar);
+        assert_eq!(GfBackend::Table.resolve(1), GfBackend::Table);
+    }
+
+    /// Measures the cost of building the tables and of a scalar and a table multiplication,
+    /// from which `TABLE_MIN_SECRET_LEN` is derived. Timing-based, so only run on demand, with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_table_min_secret_len() {
+        use std::{
+            hint::black_box,
+            time::{Duration, Instant},
+        };
+
+        // The fastest of several runs is the least disturbed by other processes.
+        let fastest = |f: &dyn Fn()| {
+            (0..20)
+                .map(|_| {
+                    let start = Instant::now();
+                    f();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap_or(Duration::ZERO)
+        };
+        let mult_all = |mult: fn(u8, u8) -> u8| {
+            for a in 0..=255 {
+                for b in 0..=255 {
+                    black_box(mult(black_box(a), black_box(b)));
+                }
+            }
+        };
+        tables();
+        let build = fastest(&|| {
+            black_box(Tables::build());
+        })
+        .as_secs_f64();
+        let scalar = fastest(&|| mult_all(GF256::mult)).as_secs_f64() / 65536.0;
+        let table = fastest(&|| mult_all(GF256::mult_table)).as_secs_f64() / 65536.0;
+        let build_in_mults = build / scalar;
+        let speedup = scalar / table;
+        println!(
+            "tables built in {:.0} scalar multiplications; table multiplications {:.1} times faster",
+            build_in_mults, speedup
+        );
+
+        // Splitting evaluates a polynomial of `threshold` coefficients at every x-coordinate,
+        // so every secret byte takes `parts * threshold` multiplications.
+        let break_even = |parts: usize, threshold: usize| {
+            build_in_mults / (1.0 - 1.0 / speedup) / (parts * threshold) as f64
+        };
+        println!(
+            "the tables pay for themselves from {:.1} bytes with 5 shards and a threshold of 3, \
+             {:.1} bytes with 2 shards and a threshold of 2",
+            break_even(5, 3),
+            break_even(2, 2)
+        );
+        assert!(
+            break_even(2, 2) <= TABLE_MIN_SECRET_LEN as f64,
+            "the tables only pay for themselves from {:.1} bytes with the smallest scheme",
+            break_even(2, 2)
+        );
+    }
+
+    #[test]
+    fn test_dump_tables() {
+        let csv = dump_tables(TableFormat::Csv);
+        let (elements, mult) = csv.split_once("\n\n").unwrap();
+        let rows: Vec<Vec<&str>> = elements
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert_eq!(rows.len(), 256);
+        let column = |x: usize, i: usize| rows[x][i].parse::<usize>().ok();
+        for x in 1..256 {
+            let log = column(x, 1).unwrap();
+            assert_eq!(column(log, 2), Some(x));
+            let inverse = column(x, 3).unwrap() as u8;
+            assert_eq!(GF256::mult(x as u8, inverse), 1);
+        }
+        assert_eq!((column(0, 1), column(255, 2)), (None, None));
+        assert_eq!(mult.lines().nth(4).unwrap().split(',').nth(8), Some("9"));
+
+        let json = dump_tables(TableFormat::Json);
+        assert!(json.starts_with("{\"generator\":3,\"log\":[null,0,25,"));
+        assert!(json.ends_with("]]}\n"));
+    }
 }
@@ -0,0 +1,20 @@
+// Shared by `GF256::mult` and the build script generating the inverse table, which cannot
+// depend on the crate itself; both `include!` this file rather than declaring it a module.
+
+/// Multiplies two elements of GF(2^8) with the Russian peasant multiplication algorithm,
+/// modulo the irreducible polynomial x^8 + x^4 + x^3 + x + 1.
+const fn russian_peasant_mult(mut a: u8, mut b: u8) -> u8 {
+    let mut result: u8 = 0;
+    while b > 0 {
+        if b & 1 != 0 {
+            result ^= a; // If the lowest bit of b is set, XOR result with a.
+        }
+        if a & 0x80 != 0 {
+            a = (a << 1) ^ 0x1B; // XOR with the reduction polynomial if a is about to overflow.
+        } else {
+            a <<= 1; // Otherwise, just shift a to the left.
+        }
+        b >>= 1; // Shift b to the right.
+    }
+    result
+}
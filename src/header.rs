@@ -0,0 +1,1145 @@
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    error::{Error, Result},
+    passphrase,
+};
+
+/// Magic bytes identifying a shard file.
+pub const MAGIC: [u8; 4] = *b"SHMR";
+
+/// Extension given to shard files by default, which `combine` prefers when listing a
+/// directory.
+pub const SHARD_EXTENSION: &str = "sss";
+
+/// Current version of the shard file format.
+pub const VERSION: u8 = 2;
+
+/// Oldest version of the shard file format which can still be read.
+pub const MIN_VERSION: u8 = 1;
+
+/// Offset of the x-coordinate in a header, after the magic, version, threshold and parts.
+pub const X_OFFSET: usize = 7;
+
+/// Length in bytes of a version 1 header, which has no metadata entries.
+const V1_HEADER_LEN: usize = 24;
+
+/// Length in bytes of the fixed-size part of the header, preceding its metadata entries.
+pub const FIXED_HEADER_LEN: usize = 26;
+
+/// Length in bytes of the SHA-256 checksum trailing the share data.
+pub const CHECKSUM_LEN: usize = 32;
+
+/// Width to which padded headers are rounded up, so that shards written with
+/// `Header::to_padded_bytes` have the same size whatever metadata their headers carry.
+pub const PADDED_HEADER_BLOCK: usize = 256;
+
+/// Number of body bytes written and hashed at a time by `write_shard`.
+const WRITE_CHUNK_LEN: usize = 64 * 1024;
+
+/// Tag of the metadata entry holding the SHA-256 hash of the secret.
+const TAG_SECRET_HASH: u8 = 1;
+
+/// Tag of the metadata entry made of zeros which pads a header to a fixed width.
+const TAG_PADDING: u8 = 2;
+
+/// Tag of the metadata entry holding the declared content-type of the secret.
+const TAG_CONTENT_TYPE: u8 = 3;
+
+/// Tag of the metadata entry holding the name of the secret file.
+const TAG_FILE_NAME: u8 = 4;
+
+/// Tag of the metadata entry describing the share derived from a passphrase.
+const TAG_PASSPHRASE: u8 = 5;
+
+/// Tag of the metadata entry holding the block length and integrity tags of the share data.
+const TAG_BLOCK_TAGS: u8 = 6;
+
+/// Tag of the metadata entry holding the creation time of the shards, in Unix seconds.
+const TAG_CREATED: u8 = 7;
+
+/// Tag of the metadata entry holding the time the shards expire, in Unix seconds.
+const TAG_EXPIRES: u8 = 8;
+
+/// Tag of the metadata entry holding the Unix permissions of the secret file.
+const TAG_FILE_MODE: u8 = 9;
+
+/// Tag of the metadata entry holding the modification time of the secret file, in Unix
+/// seconds.
+const TAG_FILE_MTIME: u8 = 10;
+
+/// Tag of the metadata entry holding the SHA-256 hash of the master seed of a seeded split.
+const TAG_SEED_HASH: u8 = 11;
+
+/// Length in bytes of the value of a `TAG_PASSPHRASE` entry.
+const PASSPHRASE_ENTRY_LEN: usize = 1 + passphrase::SALT_LEN + passphrase::VERIFIER_LEN;
+
+/// Maximum length in bytes of a declared content-type.
+pub const MAX_CONTENT_TYPE_LEN: usize = 255;
+
+/// Length in bytes of the tag and length preceding the value of a metadata entry.
+const ENTRY_OVERHEAD: usize = 3;
+
+/// Length in bytes of the integrity tag of one block of share data.
+pub const BLOCK_TAG_LEN: usize = 8;
+
+/// Smallest block length given to `BlockTags`; larger secrets get larger blocks.
+pub const MIN_BLOCK_LEN: u32 = 64 * 1024;
+
+/// Largest number of blocks given to `BlockTags`, so that their tags fit in the header
+/// metadata: 4096 tags take 32 KiB.
+const MAX_BLOCKS: u64 = 4096;
+
+/// Metadata stored at the beginning of every shard file.
+///
+/// Multi-byte integers are always stored big-endian so that shards created on one machine
+/// combine on any other. The on-disk layout is:
+///
+/// | Offset | Size           | Field            |
+/// |--------|----------------|------------------|
+/// | 0      | 4              | `MAGIC`          |
+/// | 4      | 1              | `VERSION`        |
+/// | 5      | 1              | `threshold`      |
+/// | 6      | 1              | `parts`          |
+/// | 7      | 1              | `x`              |
+/// | 8      | 8              | `set_id`         |
+/// | 16     | 8              | `secret_len`     |
+/// | 24     | 2              | `metadata_len`   |
+/// | 26     | `metadata_len` | metadata entries |
+///
+/// Each metadata entry is a one-byte tag, a two-byte length and the value. Entries with an
+/// unknown tag are skipped, so optional fields can be added without breaking older readers.
+/// Version 1 headers end after `secret_len`, at offset 24, and carry no metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    /// Minimum number of shards required to reconstruct the secret.
+    pub threshold: u8,
+    /// Total number of shards produced for the secret.
+    pub parts: u8,
+    /// The x-coordinate at which the shard's polynomials were evaluated.
+    pub x: u8,
+    /// Random identifier shared by all shards produced by the same split.
+    pub set_id: u64,
+    /// Length in bytes of the original secret.
+    pub secret_len: u64,
+    /// SHA-256 hash of the original secret, used to verify the reconstruction.
+    pub secret_hash: Option<[u8; 32]>,
+    /// Content-type declared for the secret when it was sharded, such as `text/plain`. It is
+    /// stored in cleartext.
+    pub content_type: Option<String>,
+    /// Name of the secret file, without its directory, recorded for the records of a bundle
+    /// so that `combine --unpack` can restore it, or with `shard --store-metadata` for
+    /// `combine --restore-metadata`. It is stored in cleartext.
+    pub file_name: Option<String>,
+    /// The share derived from a passphrase, which stands in for one physical shard when
+    /// combining, if the secret was split with one.
+    pub passphrase: Option<PassphraseShare>,
+    /// The integrity tags of the blocks of the share data, if the shard was written with
+    /// them, so that a corrupted block is detected as soon as it is read.
+    pub block_tags: Option<BlockTags>,
+    /// When the shards were created, in seconds since the Unix epoch. It is stored in
+    /// cleartext and does not take part in the secret sharing.
+    pub created: Option<u64>,
+    /// When the shards expire, in seconds since the Unix epoch. Expired shards still combine,
+    /// but `combine` and `info` warn about them.
+    pub expires: Option<u64>,
+    /// Unix permissions of the secret file, such as `0o600`, recorded with
+    /// `shard --store-metadata`. It is stored in cleartext.
+    pub file_mode: Option<u32>,
+    /// Modification time of the secret file, in seconds since the Unix epoch, recorded with
+    /// `shard --store-metadata`. It is stored in cleartext.
+    pub file_mtime: Option<u64>,
+    /// SHA-256 hash of the master seed the split was drawn from with `shard --seed`, which
+    /// commits to the seed without revealing it, so that an auditor given the seed can check
+    /// it and reproduce the shards.
+    pub seed_hash: Option<[u8; 32]>,
+}
+
+/// What a shard stores about the share derived from a passphrase. The share itself is never
+/// stored: it is derived again from the passphrase and the salt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassphraseShare {
+    /// The x-coordinate of the share, which no physical shard uses.
+    pub x: u8,
+    /// The salt of the key derivation.
+    pub salt: [u8; passphrase::SALT_LEN],
+    /// A value derived from the key, which tells a wrong passphrase from a right one.
+    pub verifier: [u8; passphrase::VERIFIER_LEN],
+}
+
+/// The share data of a shard cut into blocks of `block_len` bytes, the last one possibly
+/// shorter, each with an integrity tag.
+///
+/// A tag is the first `BLOCK_TAG_LEN` bytes of the SHA-256 hash of the set id, the
+/// x-coordinate and the index of the block, followed by the block. Like the checksum of the
+/// whole shard, the tags detect corruption, not tampering: anyone can compute them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTags {
+    /// Length in bytes of every block but the last.
+    pub block_len: u32,
+    /// The tag of every block, in order.
+    pub tags: Vec<[u8; BLOCK_TAG_LEN]>,
+}
+
+impl BlockTags {
+    /// Cuts the share data of a shard into blocks of `block_len_for` its length and computes
+    /// their tags.
+    ///
+    /// # Arguments
+    ///
+    /// * `set_id` - The set id of the shard.
+    /// * `x` - The x-coordinate of the shard.
+    /// * `body` - The y-coordinates of the shard.
+    pub fn compute(set_id: u64, x: u8, body: &[u8]) -> Self {
+        let block_len = Self::block_len_for(body.len() as u64);
+        let tags = body
+            .chunks(block_len as usize)
+            .enumerate()
+            .map(|(index, block)| block_tag(set_id, x, index as u64, block))
+            .collect();
+        BlockTags { block_len, tags }
+    }
+
+    /// Chooses the block length of share data `len` bytes long: `MIN_BLOCK_LEN`, doubled
+    /// until there are at most `MAX_BLOCKS` blocks.
+    fn block_len_for(len: u64) -> u32 {
+        let mut block_len = u64::from(MIN_BLOCK_LEN);
+        while len.div_ceil(block_len) > MAX_BLOCKS {
+            block_len *= 2;
+        }
+        u32::try_from(block_len).unwrap_or(u32::MAX)
+    }
+
+    /// Checks a block of share data against its tag.
+    ///
+    /// # Returns
+    ///
+    /// Whether block `index` has a tag and `block` matches it.
+    pub fn verify(&self, set_id: u64, x: u8, index: u64, block: &[u8]) -> bool {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.tags.get(index))
+            .is_some_and(|tag| ct_eq(tag, &block_tag(set_id, x, index, block)))
+    }
+}
+
+/// Computes the integrity tag of a block, see `BlockTags`.
+fn block_tag(set_id: u64, x: u8, index: u64, block: &[u8]) -> [u8; BLOCK_TAG_LEN] {
+    let digest = Sha256::new()
+        .chain_update(set_id.to_be_bytes())
+        .chain_update([x])
+        .chain_update(index.to_be_bytes())
+        .chain_update(block)
+        .finalize();
+    digest[..BLOCK_TAG_LEN]
+        .try_into()
+        .expect("a SHA-256 hash is longer than a tag")
+}
+
+impl Header {
+    /// Serializes the header into its on-disk representation.
+    ///
+    /// # Returns
+    ///
+    /// The bytes of the header: magic, version, threshold, parts, x-coordinate, set id,
+    /// secret length and metadata entries.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut metadata = Vec::new();
+        if let Some(secret_hash) = &self.secret_hash {
+            push_entry(&mut metadata, TAG_SECRET_HASH, secret_hash);
+        }
+        if let Some(content_type) = &self.content_type {
+            push_entry(&mut metadata, TAG_CONTENT_TYPE, content_type.as_bytes());
+        }
+        if let Some(file_name) = &self.file_name {
+            push_entry(&mut metadata, TAG_FILE_NAME, file_name.as_bytes());
+        }
+        if let Some(share) = &self.passphrase {
+            let value = [&[share.x][..], &share.salt, &share.verifier].concat();
+            push_entry(&mut metadata, TAG_PASSPHRASE, &value);
+        }
+        if let Some(block_tags) = &self.block_tags {
+            let value = [
+                &block_tags.block_len.to_be_bytes()[..],
+                block_tags.tags.as_flattened(),
+            ]
+            .concat();
+            push_entry(&mut metadata, TAG_BLOCK_TAGS, &value);
+        }
+        if let Some(created) = self.created {
+            push_entry(&mut metadata, TAG_CREATED, &created.to_be_bytes());
+        }
+        if let Some(expires) = self.expires {
+            push_entry(&mut metadata, TAG_EXPIRES, &expires.to_be_bytes());
+        }
+        if let Some(file_mode) = self.file_mode {
+            push_entry(&mut metadata, TAG_FILE_MODE, &file_mode.to_be_bytes());
+        }
+        if let Some(file_mtime) = self.file_mtime {
+            push_entry(&mut metadata, TAG_FILE_MTIME, &file_mtime.to_be_bytes());
+        }
+        if let Some(seed_hash) = &self.seed_hash {
+            push_entry(&mut metadata, TAG_SEED_HASH, seed_hash);
+        }
+
+        let mut bytes = Vec::with_capacity(FIXED_HEADER_LEN + metadata.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&[VERSION, self.threshold, self.parts, self.x]);
+        bytes.extend_from_slice(&self.set_id.to_be_bytes());
+        bytes.extend_from_slice(&self.secret_len.to_be_bytes());
+        bytes.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&metadata);
+        bytes
+    }
+
+    /// Parses a header from the beginning of a shard file.
+    ///
+    /// Every version from `MIN_VERSION` to `VERSION` is accepted. Version 1 headers stop after
+    /// the secret length and carry no metadata entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw bytes of the shard file.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Header` and its length in bytes, or `Error::InvalidHeader` if the bytes
+    /// are too short, do not start with the shard magic, use an unsupported version or
+    /// contain malformed metadata.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        if bytes.len() < V1_HEADER_LEN {
+            return Err(invalid_header("shard is too short to contain a header"));
+        }
+        if !has_magic(bytes) {
+            return Err(invalid_header("shard does not start with the shard magic"));
+        }
+
+        let mut header = Header {
+            threshold: bytes[5],
+            parts: bytes[6],
+            x: bytes[X_OFFSET],
+            set_id: read_u64(&bytes[8..16]),
+            secret_len: read_u64(&bytes[16..24]),
+            secret_hash: None,
+            content_type: None,
+            file_name: None,
+            passphrase: None,
+            block_tags: None,
+            created: None,
+            expires: None,
+            file_mode: None,
+            file_mtime: None,
+            seed_hash: None,
+        };
+
+        match bytes[4] {
+            1 => Ok((header, V1_HEADER_LEN)),
+            2 => {
+                let metadata_len = header.read_metadata(bytes)?;
+                Ok((header, FIXED_HEADER_LEN + metadata_len))
+            }
+            version => Err(invalid_header(format!(
+                "unsupported shard version {}, only versions {} to {} can be read",
+                version, MIN_VERSION, VERSION
+            ))),
+        }
+    }
+
+    /// Reads the metadata entries of a version 2 header into the header.
+    ///
+    /// # Returns
+    ///
+    /// The length in bytes of the metadata entries, or `Error::InvalidHeader` if they are
+    /// truncated or malformed.
+    fn read_metadata(&mut self, bytes: &[u8]) -> Result<usize> {
+        if bytes.len() < FIXED_HEADER_LEN {
+            return Err(invalid_header("shard is too short to contain a header"));
+        }
+        let metadata_len = u16::from_be_bytes([bytes[24], bytes[25]]) as usize;
+        let metadata = bytes
+            .get(FIXED_HEADER_LEN..FIXED_HEADER_LEN + metadata_len)
+            .ok_or_else(|| invalid_header("shard is too short to contain its metadata"))?;
+
+        let mut rest = metadata;
+        while !rest.is_empty() {
+            let (tag, value, next) = read_entry(rest)?;
+            match tag {
+                TAG_SECRET_HASH => {
+                    let secret_hash = value
+                        .try_into()
+                        .map_err(|_| invalid_header("secret hash must be 32 bytes long"))?;
+                    self.secret_hash = Some(secret_hash);
+                }
+                TAG_CONTENT_TYPE => {
+                    let content_type = String::from_utf8(value.to_vec())
+                        .map_err(|_| invalid_header("content-type is not valid UTF-8"))?;
+                    self.content_type = Some(content_type);
+                }
+                TAG_FILE_NAME => {
+                    let file_name = String::from_utf8(value.to_vec())
+                        .map_err(|_| invalid_header("file name is not valid UTF-8"))?;
+                    self.file_name = Some(file_name);
+                }
+                TAG_PASSPHRASE => {
+                    if value.len() != PASSPHRASE_ENTRY_LEN || value[0] == 0 {
+                        return Err(invalid_header("malformed passphrase share entry"));
+                    }
+                    let (salt, verifier) = value[1..].split_at(passphrase::SALT_LEN);
+                    self.passphrase = Some(PassphraseShare {
+                        x: value[0],
+                        salt: salt.try_into().expect("salt length is checked"),
+                        verifier: verifier.try_into().expect("verifier length is checked"),
+                    });
+                }
+                TAG_BLOCK_TAGS => self.block_tags = Some(self.read_block_tags(value)?),
+                TAG_CREATED | TAG_EXPIRES | TAG_FILE_MTIME => {
+                    let time: [u8; 8] = value
+                        .try_into()
+                        .map_err(|_| invalid_header("timestamps must be 8 bytes long"))?;
+                    let time = Some(u64::from_be_bytes(time));
+                    match tag {
+                        TAG_CREATED => self.created = time,
+                        TAG_EXPIRES => self.expires = time,
+                        _ => self.file_mtime = time,
+                    }
+                }
+                TAG_FILE_MODE => {
+                    let file_mode = value
+                        .try_into()
+                        .map(u32::from_be_bytes)
+                        .ok()
+                        .filter(|&file_mode| file_mode <= 0o7777)
+                        .ok_or_else(|| invalid_header("malformed file mode entry"))?;
+                    self.file_mode = Some(file_mode);
+                }
+                TAG_SEED_HASH => {
+                    let seed_hash = value
+                        .try_into()
+                        .map_err(|_| invalid_header("seed hash must be 32 bytes long"))?;
+                    self.seed_hash = Some(seed_hash);
+                }
+                _ => (),
+            }
+            rest = next;
+        }
+
+        Ok(metadata_len)
+    }
+
+    /// Reads the value of a `TAG_BLOCK_TAGS` entry.
+    ///
+    /// # Returns
+    ///
+    /// The block tags, or `Error::InvalidHeader` if the block length is 0 or there is not
+    /// exactly one tag per block of the declared secret length.
+    fn read_block_tags(&self, value: &[u8]) -> Result<BlockTags> {
+        let malformed = || invalid_header("malformed block checksums entry");
+        if value.len() < 4 || !(value.len() - 4).is_multiple_of(BLOCK_TAG_LEN) {
+            return Err(malformed());
+        }
+        let (block_len, tags) = value.split_at(4);
+        let block_len = u32::from_be_bytes(block_len.try_into().expect("length is checked"));
+        if block_len == 0
+            || self.secret_len.div_ceil(u64::from(block_len)) != (tags.len() / BLOCK_TAG_LEN) as u64
+        {
+            return Err(malformed());
+        }
+        let tags = tags
+            .chunks_exact(BLOCK_TAG_LEN)
+            .map(|tag| tag.try_into().expect("length is checked"))
+            .collect();
+        Ok(BlockTags { block_len, tags })
+    }
+
+    /// Serializes the header followed by a padding entry, so that its length is a multiple
+    /// of `PADDED_HEADER_BLOCK`. Readers skip the padding like any unknown entry.
+    ///
+    /// # Returns
+    ///
+    /// The bytes of the header, `PADDED_HEADER_BLOCK` bytes long unless its metadata does not
+    /// fit in a single block.
+    pub fn to_padded_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        let padded_len = (bytes.len() + ENTRY_OVERHEAD).next_multiple_of(PADDED_HEADER_BLOCK);
+        let padding = vec![0; padded_len - bytes.len() - ENTRY_OVERHEAD];
+        push_entry(&mut bytes, TAG_PADDING, &padding);
+
+        let metadata_len = (bytes.len() - FIXED_HEADER_LEN) as u16;
+        bytes[24..FIXED_HEADER_LEN].copy_from_slice(&metadata_len.to_be_bytes());
+        bytes
+    }
+
+    /// Checks a reconstructed secret against the hash stored in the header.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The reconstructed secret.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the header carries no secret hash, otherwise whether the SHA-256 hash of
+    /// `secret` matches it.
+    pub fn verify_secret(&self, secret: &[u8]) -> Option<bool> {
+        self.secret_hash
+            .map(|secret_hash| ct_eq(&Sha256::digest(secret), &secret_hash))
+    }
+
+    /// Returns whether the shards have expired at `now`, in seconds since the Unix epoch.
+    /// Shards without an expiry time never expire.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Checks that a content-type can be stored in a shard header.
+///
+/// # Arguments
+///
+/// * `content_type` - The content-type declared for the secret, such as `text/plain`.
+///
+/// # Returns
+///
+/// `Error::InvalidParameters` if the content-type is empty, longer than
+/// `MAX_CONTENT_TYPE_LEN` bytes, or contains characters other than printable ASCII.
+pub fn validate_content_type(content_type: &str) -> Result<()> {
+    let invalid = match () {
+        _ if content_type.is_empty() => Some("content-type cannot be empty".to_string()),
+        _ if content_type.len() > MAX_CONTENT_TYPE_LEN => Some(format!(
+            "content-type cannot exceed {} bytes",
+            MAX_CONTENT_TYPE_LEN
+        )),
+        _ if !content_type
+            .bytes()
+            .all(|b| b.is_ascii_graphic() || b == b' ') =>
+        {
+            Some("content-type must be printable ASCII".to_string())
+        }
+        _ => None,
+    };
+    match invalid {
+        Some(message) => Err(Error::InvalidParameters(message)),
+        None => Ok(()),
+    }
+}
+
+/// Appends a metadata entry made of its tag, length and value.
+fn push_entry(metadata: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    metadata.push(tag);
+    metadata.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    metadata.extend_from_slice(value);
+}
+
+/// Splits the first metadata entry off `metadata`.
+///
+/// # Returns
+///
+/// The tag and value of the entry along with the remaining metadata, or
+/// `Error::InvalidHeader` if the entry is truncated.
+fn read_entry(metadata: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let truncated = || invalid_header("shard metadata entry is truncated");
+    let (&tag, rest) = metadata.split_first().ok_or_else(truncated)?;
+    let len = rest.get(..2).ok_or_else(truncated)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    let value = rest.get(2..2 + len).ok_or_else(truncated)?;
+    Ok((tag, value, &rest[2 + len..]))
+}
+
+/// Reads a big-endian `u64` from an 8-byte slice.
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buffer = [0; 8];
+    buffer.copy_from_slice(bytes);
+    u64::from_be_bytes(buffer)
+}
+
+/// Checks whether the bytes start with the shard magic.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of a file.
+///
+/// # Returns
+///
+/// `true` if the first bytes are `MAGIC`, compared in constant time.
+pub fn has_magic(bytes: &[u8]) -> bool {
+    bytes.len() >= MAGIC.len() && ct_eq(&bytes[..MAGIC.len()], &MAGIC)
+}
+
+/// Builds the content of a shard file from its header and share data, in memory.
+///
+/// # Arguments
+///
+/// * `header` - The header describing the shard.
+/// * `body` - The y-coordinates of the shard, one per secret byte.
+///
+/// # Returns
+///
+/// The header, followed by the body and a SHA-256 checksum of everything before it.
+pub fn encode_shard(header: &Header, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_shard(&mut bytes, &header.to_bytes(), body).expect("writing to a Vec cannot fail");
+    bytes
+}
+
+/// Writes a shard to `writer`, hashing its content as it is written so that the checksum
+/// is appended without a second pass over the data or an in-memory copy of the shard.
+///
+/// # Arguments
+///
+/// * `writer` - The destination of the shard, typically a buffered file.
+/// * `header_bytes` - The serialized header, as returned by `Header::to_bytes` or
+///   `Header::to_padded_bytes`.
+/// * `body` - The y-coordinates of the shard, one per secret byte.
+///
+/// # Returns
+///
+/// An `io::Error` if writing fails; the writer is not flushed.
+pub fn write_shard<W: Write>(writer: &mut W, header_bytes: &[u8], body: &[u8]) -> io::Result<()> {
+    let mut hasher = Sha256::new();
+    for chunk in std::iter::once(header_bytes).chain(body.chunks(WRITE_CHUNK_LEN)) {
+        writer.write_all(chunk)?;
+        hasher.update(chunk);
+    }
+    writer.write_all(&hasher.finalize())
+}
+
+/// Parses and verifies the content of a shard file.
+///
+/// The blocks of a shard with `BlockTags` are checked before its checksum, so that a
+/// corruption is reported with the block it is in.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of the shard file.
+///
+/// # Returns
+///
+/// The parsed `Header` and a slice over the share data, `Error::InvalidHeader` if the header
+/// is invalid or the length does not match the declared secret length,
+/// `Error::BlockChecksumMismatch` for the first block which does not match its tag, or
+/// `Error::ChecksumMismatch` if the checksum does not match.
+pub fn decode_shard(bytes: &[u8]) -> Result<(Header, &[u8])> {
+    let (header, body, checksum_matches) = decode_shard_unverified(bytes)?;
+    let header_len = bytes.len() - CHECKSUM_LEN - body.len();
+    verify_blocks(&header, header_len, body)?;
+    if !checksum_matches {
+        return Err(Error::ChecksumMismatch);
+    }
+    Ok((header, body))
+}
+
+/// Checks every block of the share data of a shard against its tag, if it has `BlockTags`.
+///
+/// # Arguments
+///
+/// * `header` - The header of the shard.
+/// * `header_len` - The length in bytes of the header, at which the share data starts.
+/// * `body` - The y-coordinates of the shard.
+///
+/// # Returns
+///
+/// `Error::BlockChecksumMismatch` with the index and file offset of the first block which
+/// does not match its tag.
+pub fn verify_blocks(header: &Header, header_len: usize, body: &[u8]) -> Result<()> {
+    let Some(block_tags) = &header.block_tags else {
+        return Ok(());
+    };
+    let block_len = block_tags.block_len as usize;
+    for (index, block) in body.chunks(block_len).enumerate() {
+        if !block_tags.verify(header.set_id, header.x, index as u64, block) {
+            return Err(Error::BlockChecksumMismatch {
+                block: index as u64,
+                offset: (header_len + index * block_len) as u64,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Reads the header at the beginning of a shard file, without reading any share data, so
+/// that the rest of the shard can be streamed.
+///
+/// # Returns
+///
+/// The parsed `Header` and its raw bytes, which the checksum of the shard covers, or the
+/// errors of `Header::from_bytes`.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<(Header, Vec<u8>)> {
+    let mut bytes = Vec::new();
+    let mut read_more = |bytes: &mut Vec<u8>, len: usize| -> Result<()> {
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        reader
+            .read_exact(&mut bytes[start..])
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    invalid_header("shard is too short to contain a header")
+                }
+                _ => e.into(),
+            })
+    };
+    read_more(&mut bytes, V1_HEADER_LEN)?;
+    // Only version 1 headers end before the metadata length.
+    if has_magic(&bytes) && bytes[4] != 1 {
+        read_more(&mut bytes, FIXED_HEADER_LEN - V1_HEADER_LEN)?;
+        let metadata_len = u16::from_be_bytes([bytes[24], bytes[25]]) as usize;
+        read_more(&mut bytes, metadata_len)?;
+    }
+    let (header, _) = Header::from_bytes(&bytes)?;
+    Ok((header, bytes))
+}
+
+/// Parses the content of a shard file without rejecting a checksum mismatch.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of the shard file.
+///
+/// # Returns
+///
+/// The parsed `Header`, a slice over the share data and whether the checksum matches, or
+/// `Error::InvalidHeader` if the header is invalid or the length does not match the declared
+/// secret length.
+pub fn decode_shard_unverified(bytes: &[u8]) -> Result<(Header, &[u8], bool)> {
+    let (header, header_len) = Header::from_bytes(bytes)?;
+
+    let body_len = bytes.len().saturating_sub(header_len + CHECKSUM_LEN);
+    if bytes.len() < header_len + CHECKSUM_LEN || body_len as u64 != header.secret_len {
+        return Err(invalid_header("shard length does not match its header"));
+    }
+
+    let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let checksum_matches = ct_eq(&Sha256::digest(content), checksum);
+
+    Ok((header, &content[header_len..], checksum_matches))
+}
+
+/// Rewrites a shard of an older version of the format in the current one, after checking
+/// its checksum, so that shards can be migrated without splitting the secret again.
+///
+/// The header fields and the share data are kept as they are; only the header layout and
+/// the checksum trailing the shard change.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw bytes of the shard file.
+///
+/// # Returns
+///
+/// The content of the rewritten shard, `None` if the shard already uses `VERSION`, or the
+/// errors of `decode_shard` if the shard is invalid.
+pub fn upgrade_shard(bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    let (header, body) = decode_shard(bytes)?;
+    if bytes[4] == VERSION {
+        return Ok(None);
+    }
+    Ok(Some(encode_shard(&header, body)))
+}
+
+/// Compares two byte slices in constant time with respect to their content.
+///
+/// Slices of different lengths are never equal; only their length may leak through timing.
+///
+/// # Examples
+///
+/// ```
+/// assert!(ct_eq(b"abc", b"abc"));
+/// assert!(!ct_eq(b"abc", b"abd"));
+/// ```
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Builds an `Error::InvalidHeader` with the given message.
+fn invalid_header(message: impl Into<String>) -> Error {
+    Error::InvalidHeader(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            threshold: 3,
+            parts: 5,
+            x: 42,
+            set_id: 0x0123_4567_89ab_cdef,
+            secret_len: 4,
+            secret_hash: None,
+            content_type: None,
+            file_name: None,
+            passphrase: None,
+            block_tags: None,
+            created: None,
+            expires: None,
+            file_mode: None,
+            file_mtime: None,
+            seed_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"a", b"a"));
+        assert!(ct_eq(&[7; 32], &[7; 32]));
+        assert!(!ct_eq(b"a", b"b"));
+        assert!(!ct_eq(b"abc", b"ab"));
+        assert!(!ct_eq(b"", b"a"));
+        assert!(!ct_eq(&[7; 32], &[7; 31]));
+    }
+
+    #[test]
+    fn test_header_layout() {
+        let bytes = header().to_bytes();
+        assert_eq!(
+            bytes,
+            [
+                b'S', b'H', b'M', b'R', // magic
+                VERSION, 3, 5, 42, // version, threshold, parts, x
+                0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, // set id
+                0, 0, 0, 0, 0, 0, 0, 4, // secret length
+                0, 0, // metadata length
+            ]
+        );
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (header(), FIXED_HEADER_LEN)
+        );
+    }
+
+    #[test]
+    fn test_header_secret_hash() {
+        let with_hash = Header {
+            secret_hash: Some([9; 32]),
+            ..header()
+        };
+        let bytes = with_hash.to_bytes();
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + 3 + 32);
+        assert_eq!(&bytes[24..29], &[0, 35, TAG_SECRET_HASH, 0, 32]);
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_hash, bytes.len())
+        );
+    }
+
+    #[test]
+    fn test_header_content_type() {
+        let with_content_type = Header {
+            secret_hash: Some([9; 32]),
+            content_type: Some("application/x-pem-file".to_string()),
+            ..header()
+        };
+        let bytes = with_content_type.to_bytes();
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + 3 + 32 + 3 + 22);
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_content_type, bytes.len())
+        );
+
+        assert!(validate_content_type("text/plain; charset=utf-8").is_ok());
+        for invalid in ["", "text/plain\n", "texte/é"] {
+            assert!(matches!(
+                validate_content_type(invalid),
+                Err(Error::InvalidParameters(_))
+            ));
+        }
+        let too_long = "a".repeat(MAX_CONTENT_TYPE_LEN + 1);
+        assert!(validate_content_type(&too_long).is_err());
+    }
+
+    #[test]
+    fn test_header_file_name() {
+        let with_file_name = Header {
+            file_name: Some("notes.txt".to_string()),
+            ..header()
+        };
+        let bytes = with_file_name.to_bytes();
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + 3 + 9);
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_file_name, bytes.len())
+        );
+    }
+
+    #[test]
+    fn test_header_file_metadata() {
+        let with_metadata = Header {
+            file_name: Some("id_ed25519".to_string()),
+            file_mode: Some(0o600),
+            file_mtime: Some(1_700_000_000),
+            ..header()
+        };
+        let bytes = with_metadata.to_bytes();
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + (3 + 10) + (3 + 4) + (3 + 8));
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_metadata, bytes.len())
+        );
+
+        // Permissions beyond the 12 bits of a Unix mode.
+        let mut malformed = bytes.clone();
+        let mode_offset = FIXED_HEADER_LEN + 3 + 10 + 3;
+        malformed[mode_offset..mode_offset + 4].copy_from_slice(&0o10000u32.to_be_bytes());
+        assert!(matches!(
+            Header::from_bytes(&malformed),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_seed_hash() {
+        let with_seed_hash = Header {
+            seed_hash: Some([5; 32]),
+            ..header()
+        };
+        let bytes = with_seed_hash.to_bytes();
+        assert_eq!(&bytes[24..29], &[0, 35, TAG_SEED_HASH, 0, 32]);
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_seed_hash, bytes.len())
+        );
+
+        let mut truncated = bytes.clone();
+        truncated[26..29].copy_from_slice(&[TAG_SEED_HASH, 0, 31]);
+        truncated[25] = 34;
+        truncated.pop();
+        assert!(matches!(
+            Header::from_bytes(&truncated),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_validity() {
+        let with_validity = Header {
+            created: Some(1_700_000_000),
+            expires: Some(1_700_086_400),
+            ..header()
+        };
+        let bytes = with_validity.to_bytes();
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + 2 * (3 + 8));
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_validity.clone(), bytes.len())
+        );
+        assert!(!with_validity.is_expired(1_700_086_399));
+        assert!(with_validity.is_expired(1_700_086_400));
+        assert!(!header().is_expired(u64::MAX));
+
+        // A creation time of 7 bytes.
+        let mut malformed = bytes.clone();
+        malformed[28] = 7;
+        assert!(matches!(
+            Header::from_bytes(&malformed),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_passphrase() {
+        let with_passphrase = Header {
+            passphrase: Some(PassphraseShare {
+                x: 6,
+                salt: [1; passphrase::SALT_LEN],
+                verifier: [2; passphrase::VERIFIER_LEN],
+            }),
+            ..header()
+        };
+        let bytes = with_passphrase.to_bytes();
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + 3 + PASSPHRASE_ENTRY_LEN);
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap(),
+            (with_passphrase, bytes.len())
+        );
+    }
+
+    #[test]
+    fn test_header_versions() {
+        // A version 1 header is the version 2 one without the metadata length.
+        let mut v1 = header().to_bytes();
+        v1.truncate(V1_HEADER_LEN);
+        v1[4] = 1;
+        assert_eq!(Header::from_bytes(&v1).unwrap(), (header(), V1_HEADER_LEN));
+
+        let mut v3 = header().to_bytes();
+        v3[4] = 3;
+        let Err(Error::InvalidHeader(message)) = Header::from_bytes(&v3) else {
+            panic!("version 3 header was accepted");
+        };
+        assert!(message.contains("unsupported shard version 3"));
+    }
+
+    #[test]
+    fn test_verify_secret() {
+        assert_eq!(header().verify_secret(b"test"), None);
+
+        let with_hash = Header {
+            secret_hash: Some(Sha256::digest(b"test").into()),
+            ..header()
+        };
+        assert_eq!(with_hash.verify_secret(b"test"), Some(true));
+        assert_eq!(with_hash.verify_secret(b"tesT"), Some(false));
+    }
+
+    #[test]
+    fn test_header_skips_unknown_metadata() {
+        let mut bytes = header().to_bytes();
+        bytes[25] = 4;
+        bytes.extend_from_slice(&[0xff, 0, 1, 7]);
+        assert_eq!(Header::from_bytes(&bytes).unwrap(), (header(), bytes.len()));
+
+        // An entry claiming more bytes than the metadata holds is rejected.
+        bytes[28] = 2;
+        assert!(matches!(
+            Header::from_bytes(&bytes),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_shard_round_trip() {
+        let bytes = encode_shard(&header(), b"test");
+        assert_eq!(bytes.len(), FIXED_HEADER_LEN + 4 + CHECKSUM_LEN);
+
+        let (decoded, body) = decode_shard(&bytes).unwrap();
+        assert_eq!(decoded, header());
+        assert_eq!(body, b"test");
+    }
+
+    #[test]
+    fn test_write_shard_checksum() {
+        let body: Vec<u8> = (0..3 * WRITE_CHUNK_LEN + 7).map(|i| i as u8).collect();
+        let header_bytes = header().to_bytes();
+        let mut bytes = Vec::new();
+        write_shard(&mut bytes, &header_bytes, &body).unwrap();
+
+        let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+        assert_eq!(content, [&header_bytes[..], &body[..]].concat());
+        assert_eq!(checksum, &Sha256::digest(content)[..]);
+    }
+
+    #[test]
+    fn test_padded_shard() {
+        let with_hash = Header {
+            secret_hash: Some([9; 32]),
+            ..header()
+        };
+        for shard_header in [header(), with_hash] {
+            let mut bytes = Vec::new();
+            write_shard(&mut bytes, &shard_header.to_padded_bytes(), b"test").unwrap();
+            assert_eq!(bytes.len(), PADDED_HEADER_BLOCK + 4 + CHECKSUM_LEN);
+
+            let (decoded, body) = decode_shard(&bytes).unwrap();
+            assert_eq!(decoded, shard_header);
+            assert_eq!(body, b"test");
+        }
+    }
+
+    #[test]
+    fn test_upgrade_shard() {
+        let current = encode_shard(&header(), b"test");
+        assert_eq!(upgrade_shard(&current).unwrap(), None);
+
+        // A version 1 shard has the same header without the metadata length.
+        let mut v1_header = header().to_bytes();
+        v1_header.truncate(V1_HEADER_LEN);
+        v1_header[4] = 1;
+        let mut v1 = Vec::new();
+        write_shard(&mut v1, &v1_header, b"test").unwrap();
+        let upgraded = upgrade_shard(&v1).unwrap().unwrap();
+        assert_eq!(upgraded, current);
+        assert_eq!(decode_shard(&upgraded).unwrap(), decode_shard(&v1).unwrap());
+
+        let last = v1.len() - 1;
+        v1[last] ^= 1;
+        assert!(matches!(upgrade_shard(&v1), Err(Error::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn test_shard_invalid() {
+        let bytes = encode_shard(&header(), b"test");
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] ^= 1;
+        assert!(matches!(
+            decode_shard(&wrong_magic),
+            Err(Error::InvalidHeader(_))
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[FIXED_HEADER_LEN] ^= 1;
+        assert!(matches!(
+            decode_shard(&corrupted),
+            Err(Error::ChecksumMismatch)
+        ));
+
+        for truncated in [&bytes[..bytes.len() - 1], &bytes[..FIXED_HEADER_LEN]] {
+            assert!(matches!(
+                decode_shard(truncated),
+                Err(Error::InvalidHeader(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_block_tags() {
+        let body: Vec<u8> = (0..3 * MIN_BLOCK_LEN + 5).map(|i| i as u8).collect();
+        let mut with_tags = Header {
+            secret_len: body.len() as u64,
+            ..header()
+        };
+        let block_tags = BlockTags::compute(with_tags.set_id, with_tags.x, &body);
+        assert_eq!(block_tags.block_len, MIN_BLOCK_LEN);
+        assert_eq!(block_tags.tags.len(), 4);
+        with_tags.block_tags = Some(block_tags);
+
+        let bytes = encode_shard(&with_tags, &body);
+        let header_len = with_tags.to_bytes().len();
+        assert_eq!(
+            decode_shard(&bytes).unwrap(),
+            (with_tags.clone(), &body[..])
+        );
+        let (header_read, header_bytes) = read_header(&mut &bytes[..]).unwrap();
+        assert_eq!(
+            (header_read, header_bytes.len()),
+            (with_tags.clone(), header_len)
+        );
+
+        // The corrupted block is named rather than only the whole shard.
+        let mut corrupted = bytes.clone();
+        corrupted[header_len + 2 * MIN_BLOCK_LEN as usize + 1] ^= 1;
+        let err = decode_shard(&corrupted).unwrap_err();
+        let offset = (header_len + 2 * MIN_BLOCK_LEN as usize) as u64;
+        assert!(matches!(err, Error::BlockChecksumMismatch { block: 2, offset: o } if o == offset));
+
+        // Tags are bound to the shard: they do not verify the same block of another shard.
+        let tags = with_tags.block_tags.as_ref().unwrap();
+        assert!(tags.verify(
+            with_tags.set_id,
+            with_tags.x,
+            0,
+            &body[..MIN_BLOCK_LEN as usize]
+        ));
+        assert!(!tags.verify(with_tags.set_id, 1, 0, &body[..MIN_BLOCK_LEN as usize]));
+        assert!(!tags.verify(with_tags.set_id, with_tags.x, 4, &body[..5]));
+
+        // A tag count not matching the secret length is malformed.
+        let mut bad_len = with_tags.clone();
+        bad_len.secret_len += u64::from(MIN_BLOCK_LEN);
+        assert!(matches!(
+            Header::from_bytes(&bad_len.to_bytes()),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_block_tags_bounded() {
+        // 1 GiB is 16384 blocks of 64 KiB, so the blocks are made 256 KiB long.
+        assert_eq!(BlockTags::block_len_for(1 << 30), 256 * 1024);
+        assert_eq!(BlockTags::block_len_for(256 << 20), MIN_BLOCK_LEN);
+        let tags = BlockTags::compute(1, 2, &[0; 3]);
+        assert_eq!((tags.block_len, tags.tags.len()), (MIN_BLOCK_LEN, 1));
+    }
+}
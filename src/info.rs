@@ -0,0 +1,332 @@
+use colored::*;
+use std::{collections::HashSet, fs, io, path::Path};
+
+use crate::{
+    cli::Verbosity,
+    columnar,
+    combine::{self, bare_share_x},
+    error::Result,
+    header, json, mnemonic,
+    shamir::{self, ReconstructStatus, ShardInfo},
+    sharding::{read_manifest, MANIFEST_FILE},
+    sidecar, timing,
+};
+
+/// Prints the header of every shard in a directory and whether they can reconstruct the
+/// secret, without reconstructing it.
+///
+/// Files which are not valid shards are listed with the reason they were rejected and left
+/// out of the reconstruction check. The rows of a column layout file are listed as shards.
+/// The label in the manifest of the directory, if any, is printed first.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory containing the shards.
+///
+/// # Returns
+///
+/// The `ReconstructStatus` of the valid shards, or `Error::Io` if the directory or one of its
+/// files cannot be read.
+///
+/// # Examples
+///
+/// ```
+/// let status = print_info(Path::new("./shards"))?;
+/// assert_eq!(status, ReconstructStatus::Reconstructable);
+/// ```
+pub fn print_info(shards_dir: &Path) -> Result<ReconstructStatus> {
+    let mut shard_paths = fs::read_dir(shards_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    shard_paths.retain(|path| {
+        path.file_name() != Some(MANIFEST_FILE.as_ref())
+            && path.file_name() != Some(sidecar::FILE_NAME.as_ref())
+    });
+    shard_paths.sort();
+
+    if let Some(label) = read_manifest(shards_dir)? {
+        println!("Label:\n{}", label.trim_end());
+    }
+
+    let now = timing::unix_now();
+    // Headerless shards are shown with the parameters of the sidecar.
+    let sidecar_header = sidecar::read(shards_dir)?;
+    let mut headers = Vec::new();
+    for shard_path in shard_paths.iter().filter(|path| path.is_file()) {
+        let mut data = fs::read(shard_path)?;
+        if let (Some(set_header), Some(x)) = (&sidecar_header, bare_share_x(shard_path)) {
+            if !header::has_magic(&data) {
+                data = sidecar::attach(set_header, x, &data);
+            }
+        }
+        if let Some(text) = mnemonic::as_mnemonic(&data) {
+            match mnemonic::decode(text) {
+                Ok(decoded) => data = decoded,
+                Err(e) => {
+                    println!("{}: {} ({})", shard_path.display(), "invalid".red(), e);
+                    continue;
+                }
+            }
+        }
+        let shards = if columnar::has_magic(&data) {
+            // Every row of a column layout file is listed as a shard of its own.
+            match columnar::decode_columns(&data) {
+                Ok(rows) => rows
+                    .into_iter()
+                    .enumerate()
+                    .map(|(row, shard)| (format!("{} row {}", shard_path.display(), row), shard))
+                    .collect(),
+                Err(e) => {
+                    println!("{}: {} ({})", shard_path.display(), "invalid".red(), e);
+                    continue;
+                }
+            }
+        } else {
+            vec![(shard_path.display().to_string(), data)]
+        };
+        for (name, data) in shards {
+            match shamir::validate_shard(&data) {
+                Ok(ShardInfo {
+                    version,
+                    header: shard_header,
+                }) => {
+                    println!(
+                        "{}: version {}, x = {}, threshold {} of {}, set {:016x}, {} bytes",
+                        name,
+                        version,
+                        shard_header.x,
+                        shard_header.threshold,
+                        shard_header.parts,
+                        shard_header.set_id,
+                        shard_header.secret_len
+                    );
+                    if let Some(content_type) = &shard_header.content_type {
+                        println!("  content-type: {}", content_type);
+                    }
+                    if let Some(file_name) = &shard_header.file_name {
+                        println!("  file name: {}", file_name);
+                    }
+                    if let Some(file_mode) = shard_header.file_mode {
+                        println!("  file mode: {:o}", file_mode);
+                    }
+                    if let Some(file_mtime) = shard_header.file_mtime {
+                        println!("  file modified: {}", file_mtime);
+                    }
+                    if let Some(seed_hash) = shard_header.seed_hash {
+                        println!("  seed hash: {}", json::encode_hex(&seed_hash));
+                    }
+                    if let Some(passphrase) = &shard_header.passphrase {
+                        println!("  passphrase share: x = {}", passphrase.x);
+                    }
+                    if let Some(created) = shard_header.created {
+                        println!("  created: {}", created);
+                    }
+                    if let Some(expires) = shard_header.expires {
+                        if shard_header.is_expired(now) {
+                            println!("  expires: {} ({})", expires, "expired".red());
+                        } else {
+                            println!("  expires: {}", expires);
+                        }
+                    }
+                    headers.push(shard_header);
+                }
+                Err(e) => println!("{}: {} ({})", name, "invalid".red(), e),
+            }
+        }
+    }
+
+    if let Some(expires) = headers
+        .iter()
+        .filter(|shard_header| shard_header.is_expired(now))
+        .find_map(|shard_header| shard_header.expires)
+    {
+        eprintln!(
+            "{} the shards expired at {} (seconds since the Unix epoch)",
+            "WARNING:".yellow().bold(),
+            expires
+        );
+    }
+
+    let status = shamir::can_reconstruct(&headers);
+    match status {
+        ReconstructStatus::Reconstructable => {
+            println!("{}", "The shards can reconstruct the secret.".green());
+            let present = headers
+                .iter()
+                .map(|shard_header| shard_header.x)
+                .collect::<HashSet<_>>()
+                .len();
+            let (margin, color) = redundancy_margin(present, headers[0].threshold as usize);
+            let advice = match margin {
+                0 => "losing any shard makes the secret unrecoverable, regenerate shards",
+                1 => "losing one more shard leaves no redundancy",
+                _ => "shards can be lost before the secret is at risk",
+            };
+            println!(
+                "{}",
+                format!("Redundancy margin: {} ({}).", margin, advice).color(color)
+            );
+        }
+        ReconstructStatus::Insufficient { have, need } => println!(
+            "{}",
+            format!("Only {} of the {} required shards are present.", have, need).yellow()
+        ),
+        ReconstructStatus::Inconsistent => println!(
+            "{}",
+            "The shards belong to different sets or disagree on their parameters.".red()
+        ),
+    }
+
+    Ok(status)
+}
+
+/// Computes how many shards of a set can still be lost before its secret becomes
+/// unrecoverable, and the color it is reported in: red without any redundancy left, yellow
+/// with a single shard to spare, green otherwise.
+///
+/// # Arguments
+///
+/// * `present` - The number of distinct shards present.
+/// * `threshold` - The threshold of the set.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(redundancy_margin(5, 3), (2, Color::Green));
+/// ```
+fn redundancy_margin(present: usize, threshold: usize) -> (usize, Color) {
+    let margin = present.saturating_sub(threshold);
+    let color = match margin {
+        0 => Color::Red,
+        1 => Color::Yellow,
+        _ => Color::Green,
+    };
+    (margin, color)
+}
+
+/// Prints a table of the x-coordinate and the first y-coordinates of every share of a set, in
+/// hexadecimal, to teach the scheme or debug a reconstruction.
+///
+/// The shares are listed by x-coordinate. The table reveals share data, so unless `verbosity`
+/// is quiet a warning is printed to stderr first: fewer shares than the threshold reveal
+/// nothing about the secret, but a quorum of them is enough to reconstruct the bytes shown.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The shards directory, or any other input accepted by `combine`.
+/// * `limit` - The maximum number of y-coordinates shown per share.
+/// * `verbosity` - Whether to print the warning.
+///
+/// # Returns
+///
+/// The table, ending with a newline, or the errors of `combine::read_shares`.
+///
+/// # Examples
+///
+/// ```
+/// print!("{}", dump_shares(Path::new("./shards"), 4, Verbosity::Normal)?);
+/// // shard                x    y (4 of 11 bytes)
+/// // shards/shards_0.sss  17   3a 5f 00 c1 ...
+/// ```
+pub fn dump_shares(shards_dir: &Path, limit: usize, verbosity: Verbosity) -> Result<String> {
+    let mut shares = combine::read_shares(shards_dir)?;
+    shares.sort_by_key(|(_, _, share)| share.last().copied());
+    let headers: Vec<_> = shares
+        .iter()
+        .filter_map(|(_, shard_header, _)| shard_header.clone())
+        .collect();
+    // Without headers the threshold is unknown, and `can_reconstruct` would report too few.
+    let warning = if headers.is_empty() {
+        "the threshold of bare shares is unknown: if they reach it, this output reveals the \
+         bytes of the secret shown"
+    } else if let ReconstructStatus::Insufficient { .. } = shamir::can_reconstruct(&headers) {
+        "below the threshold these shares reveal nothing about the secret, but keep the output \
+         away from the other shards"
+    } else {
+        "these shares reach the threshold: this output reveals the bytes of the secret shown"
+    };
+    if verbosity > Verbosity::Quiet {
+        eprintln!(
+            "{} printing share data; {}",
+            "WARNING:".yellow().bold(),
+            warning
+        );
+    }
+
+    let secret_len = shares.first().map_or(0, |(_, _, share)| share.len() - 1);
+    let name_width = shares
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .chain(["shard".len()])
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{:<name_width$}  {:<3}  y ({} of {} bytes)\n",
+        "shard",
+        "x",
+        limit.min(secret_len),
+        secret_len
+    );
+    for (name, _, share) in &shares {
+        let (x, ys) = share.split_last().expect("share is non-empty");
+        let mut hex: Vec<String> = ys
+            .iter()
+            .take(limit)
+            .map(|y| format!("{:02x}", y))
+            .collect();
+        if ys.len() > limit {
+            hex.push("...".to_string());
+        }
+        table += &format!("{:<name_width$}  {:<3}  {}\n", name, x, hex.join(" "));
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sharding::{shard_bytes, SharingConfig};
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_redundancy_margin() {
+        assert_eq!(redundancy_margin(3, 3), (0, Color::Red));
+        assert_eq!(redundancy_margin(4, 3), (1, Color::Yellow));
+        assert_eq!(redundancy_margin(5, 3), (2, Color::Green));
+        assert_eq!(redundancy_margin(255, 2), (253, Color::Green));
+    }
+
+    #[test]
+    fn test_dump_shares() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SharingConfig::builder()
+            .parts(3)
+            .threshold(2)
+            .verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let shard_paths = shard_bytes(
+            b"dumped secret",
+            dir.path(),
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        let table = dump_shares(dir.path(), 4, Verbosity::Quiet).unwrap();
+        let mut lines = table.lines();
+        assert!(lines.next().unwrap().ends_with("y (4 of 13 bytes)"));
+        let mut xs = Vec::new();
+        for line in lines {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let data = fs::read(columns[0]).unwrap();
+            let (shard_header, body) = header::decode_shard(&data).unwrap();
+            assert_eq!(columns[1], shard_header.x.to_string());
+            xs.push(shard_header.x);
+            let ys: Vec<String> = body[..4].iter().map(|y| format!("{:02x}", y)).collect();
+            assert_eq!(columns[2..], [&ys[..], &["...".to_string()]].concat());
+        }
+        assert_eq!(xs.len(), shard_paths.len());
+        assert!(xs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
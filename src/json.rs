@@ -0,0 +1,219 @@
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+/// A value of the subset of JSON written to sidecars and to the JSON output format: unsigned
+/// integers, strings escaping only `"` and `\`, `null`, arrays and objects.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    Object(HashMap<String, Value>),
+    Null,
+}
+
+/// Parses a JSON text in the subset of `Value`, in any formatting.
+///
+/// # Returns
+///
+/// The value, or `None` if the text is malformed, uses JSON outside of the subset, or has
+/// anything but whitespace after the value. A UTF-8 byte order mark is ignored.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(parse(" [1, null] "), Some(Value::Array(vec![Value::Number(1), Value::Null])));
+/// assert_eq!(parse("[1] 2"), None);
+/// ```
+pub fn parse(text: &str) -> Option<Value> {
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    let value = parse_value(&mut chars)?;
+    skip_whitespace(&mut chars).is_none().then_some(value)
+}
+
+/// Writes a string as a JSON string, quoted and with `"` and `\` escaped.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(string("a \"b\""), "\"a \\\"b\\\"\"");
+/// ```
+pub fn string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Encodes bytes as lowercase hexadecimal digits, two per byte.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes bytes written as hexadecimal digits, in either case.
+///
+/// # Returns
+///
+/// The bytes, or `None` if the string has an odd number of digits or characters which are
+/// not hexadecimal digits.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parses a value, skipping the whitespace before it.
+fn parse_value(chars: &mut Peekable<Chars>) -> Option<Value> {
+    let value = match skip_whitespace(chars)? {
+        '"' => {
+            chars.next();
+            Value::String(parse_string(chars)?)
+        }
+        'n' => {
+            for expected in "null".chars() {
+                chars.next_if_eq(&expected)?;
+            }
+            Value::Null
+        }
+        '[' => Value::Array(parse_array(chars)?),
+        '{' => Value::Object(parse_object(chars)?),
+        _ => {
+            let mut digits = String::new();
+            while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                digits.push(c);
+            }
+            Value::Number(digits.parse().ok()?)
+        }
+    };
+    Some(value)
+}
+
+/// Parses an object, stopping after its closing brace.
+fn parse_object(chars: &mut Peekable<Chars>) -> Option<HashMap<String, Value>> {
+    let mut fields = HashMap::new();
+    expect(chars, '{')?;
+    if skip_whitespace(chars) == Some('}') {
+        chars.next();
+        return Some(fields);
+    }
+    loop {
+        expect(chars, '"')?;
+        let key = parse_string(chars)?;
+        expect(chars, ':')?;
+        fields.insert(key, parse_value(chars)?);
+        match skip_whitespace(chars)? {
+            ',' => {
+                chars.next();
+            }
+            '}' => {
+                chars.next();
+                return Some(fields);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Parses an array, stopping after its closing bracket.
+fn parse_array(chars: &mut Peekable<Chars>) -> Option<Vec<Value>> {
+    let mut items = Vec::new();
+    expect(chars, '[')?;
+    if skip_whitespace(chars) == Some(']') {
+        chars.next();
+        return Some(items);
+    }
+    loop {
+        items.push(parse_value(chars)?);
+        match skip_whitespace(chars)? {
+            ',' => {
+                chars.next();
+            }
+            ']' => {
+                chars.next();
+                return Some(items);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Skips whitespace and returns the next character without consuming it.
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.peek().copied()
+}
+
+/// Skips whitespace and consumes `expected`, or returns `None` if another character follows.
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Option<()> {
+    skip_whitespace(chars);
+    chars.next_if_eq(&expected).map(|_| ())
+}
+
+/// Parses the rest of a string whose opening quote was consumed, unescaping `\"` and `\\`.
+fn parse_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\') => string.push(c),
+                _ => return None,
+            },
+            c => string.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let Some(Value::Object(mut fields)) =
+            parse("\u{feff}{ \"a\": [1, \"x \\\"y\\\"\", null], \"b\": {} }\n")
+        else {
+            panic!("expected an object");
+        };
+        assert_eq!(
+            fields.remove("a"),
+            Some(Value::Array(vec![
+                Value::Number(1),
+                Value::String("x \"y\"".to_string()),
+                Value::Null,
+            ]))
+        );
+        assert_eq!(fields.remove("b"), Some(Value::Object(HashMap::new())));
+
+        for invalid in [
+            "",
+            "{",
+            "{\"a\" 1}",
+            "[1,]",
+            "-1",
+            "\"\\n\"",
+            "nul",
+            "{} {}",
+        ] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let value = "a \"b\" \\ c";
+        assert_eq!(
+            parse(&string(value)),
+            Some(Value::String(value.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(encode_hex(&[0x01, 0xab]), "01ab");
+        assert_eq!(decode_hex("01AB"), Some(vec![0x01, 0xab]));
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("1ab"), None);
+        assert_eq!(decode_hex("+1"), None);
+    }
+}
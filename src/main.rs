@@ -1,48 +1,680 @@
 use colored::*;
+use std::{
+    io::{self, Write},
+    num::NonZeroUsize,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, Verbosity};
 
-use crate::combine::combine_secret;
-use crate::sharding::shard_secret;
+use crate::combine::{
+    combine_env, combine_hex, combine_s3, combine_secret, recover_env, recover_hex, recover_s3,
+    recover_secret, unpack_bundle, CombineOptions,
+};
+use crate::error::Error;
+use crate::exec::pipe_secret;
+use crate::gf256::dump_tables;
+use crate::info::{dump_shares, print_info};
+use crate::merge::merge_dirs;
+use crate::passphrase::Passphrase;
+use crate::rehash::rehash_dir;
+use crate::repair::repair;
+use crate::reshard::{compare_sets, reshard};
+use crate::selftest::run_selftest;
+use crate::shamir::ReconstructStatus;
+use crate::sharding::{
+    read_label, read_manifest, shard_bytes, shard_bytes_into_env, shard_bytes_into_s3,
+    shard_into_bundle, shard_into_env, shard_into_s3, shard_secret, FileMetadata, OutputFormat,
+    SharingConfig,
+};
+use crate::timing::Timings;
+use zeroize::Zeroizing;
 
+mod archive;
+mod bundle;
 mod cli;
+#[cfg(feature = "cloud")]
+mod cloud;
+mod columnar;
 mod combine;
+#[cfg(feature = "age")]
+mod encrypt;
+mod error;
+mod exec;
+mod export;
 mod gf256;
+mod header;
+mod info;
+mod json;
+mod merge;
+mod mnemonic;
+#[cfg(feature = "net")]
+mod net;
+mod passphrase;
 mod polynomial;
+#[cfg(feature = "qr")]
+mod qr;
+mod rehash;
+mod repair;
+mod reshard;
+mod rng;
+mod selftest;
+#[cfg(unix)]
+mod serve;
 mod shamir;
 mod sharding;
+mod sidecar;
+mod sink;
+mod stream;
+#[cfg(test)]
+mod test_support;
+mod timing;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// The exit code of a command stopped by a second Ctrl-C, the shell's code for SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+fn main() {
     let cli = Cli::parse();
+    colored::control::set_override(cli.color.enabled());
+    let result = thread_pool(cli.num_threads).and_then(|pool| {
+        let pool = Arc::new(pool);
+        match cli.command {
+            // The server would hold a thread of the pool for as long as it runs, starving the
+            // connections it hands to the pool.
+            #[cfg(unix)]
+            Commands::Serve { .. } => run(cli, &pool),
+            _ => pool.install(|| run(cli, &pool)),
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("{} {}", "error:".red().bold(), e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// The secret split by `shard`.
+enum Secret {
+    /// The secret file or URL.
+    Path(std::path::PathBuf),
+    /// The string given with `--secret-string`.
+    String(Zeroizing<String>),
+}
+
+/// Builds the thread pool running the parallel split and combine.
+///
+/// # Arguments
+///
+/// * `num_threads` - The number of threads, or `None` for one per available core, so that
+///   the CPU quota of a shared or containerized environment can be respected.
+fn thread_pool(num_threads: Option<NonZeroUsize>) -> Result<rayon::ThreadPool, Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads.map_or(0, NonZeroUsize::get))
+        .build()
+        .map_err(|e| io::Error::other(e).into())
+}
+
+/// Runs the command selected on the command line; `pool` runs the requests of `serve`.
+fn run(cli: Cli, pool: &Arc<rayon::ThreadPool>) -> Result<(), Error> {
+    let verbosity = match cli.command {
+        // The shard paths must be the only output when they are NUL-separated.
+        Commands::Shard { print0: true, .. } => Verbosity::Quiet,
+        _ => cli.verbosity(),
+    };
+    let gf_backend = cli.gf_backend;
+    let start = Instant::now();
+    let timings = cli.time.then(|| Arc::new(Timings::default()));
+
+    // The flag is raised by Ctrl-C, or by a timer thread once the timeout elapses, so that
+    // no partial shards or secret are left behind. Not every step polls the flag, such as a
+    // read blocked on a slow device, so a second Ctrl-C exits at once.
+    let cancel = Arc::new(AtomicBool::new(false));
+    let interrupt = Arc::clone(&cancel);
+    ctrlc::set_handler(move || {
+        if interrupt.swap(true, Ordering::Relaxed) {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        if verbosity > Verbosity::Quiet {
+            eprintln!(
+                "{} interrupted, cleaning up (press Ctrl-C again to exit immediately)",
+                "WARNING:".yellow().bold()
+            );
+        }
+    })
+    .map_err(io::Error::other)?;
+    if let Some(timeout) = cli.timeout {
+        let timer = Arc::clone(&cancel);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(timeout));
+            timer.store(true, Ordering::Relaxed);
+        });
+    }
 
     match cli.command {
         Commands::Shard {
             secret_path,
             shards_path,
+            secret_string,
+            export_env,
+            prefix,
+            s3,
             parts,
             threshold,
+            scheme,
+            sequential_x,
+            embed_hash,
+            uniform_size,
+            block_checksums,
+            content_type,
+            created,
+            expires,
+            no_header,
+            sidecar,
+            store_metadata,
+            verify_all,
+            label_file,
+            passphrase,
+            seed,
+            max_secret_size,
+            extension,
+            overwrite_policy,
+            output_format,
+            layout,
+            encoding,
+            bundle,
+            append,
+            qr,
+            print0,
+            no_warn,
         } => {
-            shard_secret(&secret_path, &shards_path, parts, threshold)?;
-            println!("{}", "Sharding complete!".green());
-            println!(
-                "Secret at {} was split into {} parts with a threshold of {}.",
-                shards_path.to_string_lossy().bright_blue(),
-                parts.to_string().cyan(),
-                threshold.to_string().cyan()
-            );
+            let (parts, threshold) = match scheme {
+                Some(scheme) => (scheme.parts, scheme.threshold),
+                None => (parts, threshold),
+            };
+            // `--parts 3 --threshold 5` is usually meant as 3-of-5, so when the swapped scheme is
+            // valid the error suggests it.
+            if threshold > parts && parts >= 2 && threshold <= 255 {
+                let args = std::env::args_os().map(|arg| arg.to_string_lossy().into_owned());
+                return Err(Error::InvalidParameters(format!(
+                    "threshold {} cannot exceed the {} parts; the two look swapped, to split \
+                     into {} shards any {} of which recover the secret, run:\n  {}",
+                    threshold,
+                    parts,
+                    threshold,
+                    parts,
+                    cli::swapped_scheme_command(args, parts, threshold)
+                )));
+            }
+            // The shards are printed with `--export-env` and uploaded with `--s3`.
+            let no_shards_path = export_env || s3.is_some();
+            // With `--secret-string`, the only path given is the shards path. The `secret`
+            // argument group requires one of them.
+            let (secret, shards_path) = match (secret_string, secret_path, shards_path) {
+                (None, Some(secret_path), shards_path) => (Secret::Path(secret_path), shards_path),
+                (None, None, _) => {
+                    return Err(Error::InvalidParameters(
+                        "a secret path or --secret-string is required".to_string(),
+                    ))
+                }
+                (Some(secret), None, None) if no_shards_path => {
+                    (Secret::String(Zeroizing::new(secret)), None)
+                }
+                (Some(secret), Some(shards_path), None) => {
+                    (Secret::String(Zeroizing::new(secret)), Some(shards_path))
+                }
+                (Some(_), None, _) => {
+                    return Err(Error::InvalidParameters(
+                        "a shards path is required with --secret-string".to_string(),
+                    ))
+                }
+                (Some(_), Some(_), Some(_)) => {
+                    return Err(Error::InvalidParameters(
+                        "--secret-string replaces the secret path".to_string(),
+                    ))
+                }
+            };
+            if no_shards_path && shards_path.is_some() {
+                return Err(Error::InvalidParameters(
+                    "--export-env and --s3 replace the shards path".to_string(),
+                ));
+            }
+            if matches!(secret, Secret::String(_)) && output_format != OutputFormat::Files {
+                return Err(Error::InvalidParameters(
+                    "--secret-string only applies to shards written as files".to_string(),
+                ));
+            }
+            // `--bundle` stands for `--output-format bundle` with the bundle as output path.
+            let (output_path, output_format) = match (shards_path, bundle) {
+                (Some(shards_path), None) => (Some(shards_path), output_format),
+                (None, Some(bundle))
+                    if matches!(output_format, OutputFormat::Files | OutputFormat::Bundle) =>
+                {
+                    (Some(bundle), OutputFormat::Bundle)
+                }
+                (None, None) if no_shards_path => (None, output_format),
+                _ => {
+                    return Err(Error::InvalidParameters(
+                        "--bundle replaces the shards path and output format".to_string(),
+                    ))
+                }
+            };
+            if append && output_format != OutputFormat::Bundle {
+                return Err(Error::InvalidParameters(
+                    "--append only applies to bundles".to_string(),
+                ));
+            }
+            if threshold == parts && !no_warn && verbosity > Verbosity::Quiet {
+                eprintln!(
+                    "{} with a threshold equal to the {} parts there is no redundancy: losing \
+                     any single shard makes the secret unrecoverable (--no-warn to silence)",
+                    "WARNING:".yellow().bold(),
+                    parts
+                );
+            }
+            if matches!(secret, Secret::String(_)) && !no_warn && verbosity > Verbosity::Quiet {
+                eprintln!(
+                    "{} the secret given with --secret-string may be saved in the shell history \
+                     and shown in the process list (--no-warn to silence)",
+                    "WARNING:".yellow().bold()
+                );
+            }
+            if seed.is_some() && !no_warn && verbosity > Verbosity::Quiet {
+                eprintln!(
+                    "{} anyone holding the --seed and a single shard can recover the secret: \
+                     keep it like the secret, only its hash is stored with the shards (--no-warn to silence)",
+                    "WARNING:".yellow().bold()
+                );
+            }
+            // The expiry is relative to the creation time, which defaults to now.
+            let created = created
+                .map(|created| created.0)
+                .or_else(|| expires.map(|_| timing::unix_now()));
+            let expires = expires
+                .map(|lifetime| {
+                    created
+                        .and_then(|created| created.checked_add(lifetime.0))
+                        .ok_or_else(|| {
+                            Error::InvalidParameters(
+                                "the expiry time is too far in the future".to_string(),
+                            )
+                        })
+                })
+                .transpose()?;
+            let options = SharingConfig::builder()
+                .parts(parts)
+                .threshold(threshold)
+                .sequential_x(sequential_x)
+                .embed_hash(embed_hash)
+                .uniform_size(uniform_size)
+                .block_checksums(block_checksums)
+                .content_type(content_type)
+                .created(created)
+                .expires(expires)
+                .label(label_file.map(|path| read_label(&path)).transpose()?)
+                .file_metadata(match (&secret, store_metadata) {
+                    (Secret::Path(secret_path), true) => Some(FileMetadata::read(secret_path)?),
+                    _ => None,
+                })
+                .seed(seed)
+                .max_secret_size(max_secret_size)
+                .extension(extension)
+                .overwrite_policy(overwrite_policy)
+                .headerless(no_header)
+                .sidecar(sidecar)
+                .verify_all(verify_all)
+                .output_format(output_format)
+                .layout(layout)
+                .encoding(encoding)
+                .qr(qr)
+                .gf_backend(gf_backend)
+                .verbosity(verbosity)
+                .timings(timings.clone())
+                .build()?;
+            // The options are checked before prompting for the passphrase.
+            let options =
+                options.with_passphrase(passphrase.then(|| Passphrase::read(true)).transpose()?)?;
+            // Fail before reading the secret if shares could not be drawn securely.
+            rng::check_os_rng()?;
+            if let (Some(location), None) = (&s3, &output_path) {
+                let keys = match &secret {
+                    Secret::String(secret) => {
+                        shard_bytes_into_s3(secret.as_bytes(), location, &options, &cancel)?
+                    }
+                    Secret::Path(secret_path) => {
+                        shard_into_s3(secret_path, location, &options, &cancel)?
+                    }
+                };
+                if verbosity > Verbosity::Quiet {
+                    println!("{}", "Sharding complete!".green());
+                    println!(
+                        "{} shards were uploaded to {} with a threshold of {}.",
+                        keys.len().to_string().cyan(),
+                        location.bright_blue(),
+                        threshold.to_string().cyan()
+                    );
+                }
+                if verbosity == Verbosity::Verbose {
+                    for key in &keys {
+                        println!("Uploaded {}", key);
+                    }
+                }
+                return Ok(());
+            }
+            // With `--export-env`, the shards are printed instead of written.
+            let Some(output_path) = output_path else {
+                let exports = match &secret {
+                    Secret::String(secret) => {
+                        shard_bytes_into_env(secret.as_bytes(), &prefix, &options, &cancel)?
+                    }
+                    Secret::Path(secret_path) => {
+                        shard_into_env(secret_path, &prefix, &options, &cancel)?
+                    }
+                };
+                io::stdout().write_all(exports.as_bytes())?;
+                return Ok(());
+            };
+            // Bundles are written directly to report the index of the new record.
+            let (shard_paths, record) = match (output_format, secret) {
+                (_, Secret::String(secret)) => (
+                    shard_bytes(secret.as_bytes(), &output_path, &options, &cancel)?,
+                    None,
+                ),
+                (OutputFormat::Bundle, Secret::Path(secret_path)) => {
+                    let record =
+                        shard_into_bundle(&secret_path, &output_path, append, &options, &cancel)?;
+                    (vec![output_path.clone()], Some(record))
+                }
+                (_, Secret::Path(secret_path)) => (
+                    shard_secret(&secret_path, &output_path, &options, &cancel)?,
+                    None,
+                ),
+            };
+            if print0 {
+                let mut stdout = io::stdout().lock();
+                for shard_path in &shard_paths {
+                    stdout.write_all(shard_path.as_os_str().as_encoded_bytes())?;
+                    stdout.write_all(b"\0")?;
+                }
+                stdout.flush()?;
+            }
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Sharding complete!".green());
+                println!(
+                    "Secret at {} was split into {} parts with a threshold of {}.",
+                    output_path.to_string_lossy().bright_blue(),
+                    parts.to_string().cyan(),
+                    threshold.to_string().cyan()
+                );
+                if let Some(record) = record {
+                    println!(
+                        "The shards were added to the bundle as record {}.",
+                        record.to_string().cyan()
+                    );
+                }
+            }
         }
         Commands::Combine {
             shards_dir,
             recovered_secret_path,
+            hex,
+            from_env,
+            prefix,
+            s3,
+            force,
+            recursive,
+            force_combine,
+            legacy,
+            redundancy_check,
+            expect_hash,
+            allow_extra,
+            subset,
+            record,
+            exec,
+            partial,
+            recipient,
+            manifest,
+            passphrase,
+            enforce_expiry,
+            mode,
+            restore_metadata,
+            unpack,
+            infer_extension,
+            qr_text,
+            stream,
         } => {
-            combine_secret(&shards_dir, &recovered_secret_path)?;
-            println!("{}", "Combine complete!".green());
-            println!(
-                "Recovered secret saved to {}",
-                recovered_secret_path.to_string_lossy().bright_blue()
-            );
+            // Whether the shards are read from the shards directory given.
+            let from_dir = hex.is_empty() && !from_env && s3.is_none();
+            let options = CombineOptions {
+                force,
+                recursive,
+                force_combine,
+                legacy,
+                redundancy_check,
+                infer_extension,
+                qr_text,
+                stream,
+                record,
+                subset,
+                partial,
+                recipients: recipient,
+                manifest,
+                passphrase: passphrase.then(|| Passphrase::read(false)).transpose()?,
+                enforce_expiry,
+                expect_hash,
+                allow_extra,
+                mode,
+                restore_metadata,
+                gf_backend,
+                verbosity,
+                timings: timings.clone(),
+            };
+            if let Some(command) = exec {
+                // The secret only lives in memory, and is zeroized before exiting.
+                let code = {
+                    let (secret, _) = match (shards_dir, &s3) {
+                        (None, None) if from_env => recover_env(&prefix, &options, &cancel)?,
+                        (None, Some(location)) => recover_s3(location, &options, &cancel)?,
+                        (Some(shards_dir), None) if from_dir => {
+                            recover_secret(&shards_dir, &options, &cancel)?
+                        }
+                        (None, None) if !hex.is_empty() => recover_hex(&hex, &options, &cancel)?,
+                        _ => {
+                            return Err(Error::InvalidParameters(
+                                "--hex, --from-env or --s3 shards cannot be combined with a \
+                                 shards directory"
+                                    .to_string(),
+                            ))
+                        }
+                    };
+                    pipe_secret(&command, &Zeroizing::new(secret))?
+                };
+                if code != 0 {
+                    process::exit(code);
+                }
+                return Ok(());
+            }
+            if let (Some(out_dir), Some(bundle)) = (&unpack, &shards_dir) {
+                let secret_paths = unpack_bundle(bundle, out_dir, &options, &cancel)?;
+                if verbosity > Verbosity::Quiet {
+                    println!("{}", "Unpack complete!".green());
+                    println!(
+                        "{} secrets saved to {}",
+                        secret_paths.len().to_string().cyan(),
+                        out_dir.to_string_lossy().bright_blue()
+                    );
+                }
+                if verbosity == Verbosity::Verbose {
+                    for secret_path in &secret_paths {
+                        println!("Wrote {}", secret_path.display());
+                    }
+                }
+                return Ok(());
+            }
+            let label = match &shards_dir {
+                Some(shards_dir) if from_dir && shards_dir.is_dir() => read_manifest(shards_dir)?,
+                _ => None,
+            };
+            // With --hex, --from-env or --s3, the only path given is where the secret is
+            // recovered.
+            let recovered_secret_path = match (shards_dir, recovered_secret_path) {
+                (Some(recovered_secret_path), None) if from_env => {
+                    combine_env(&prefix, &recovered_secret_path, &options, &cancel)?
+                }
+                (Some(recovered_secret_path), None) if s3.is_some() => {
+                    let location = s3.as_deref().expect("--s3 is given");
+                    combine_s3(location, &recovered_secret_path, &options, &cancel)?
+                }
+                (Some(shards_dir), Some(recovered_secret_path)) if from_dir => {
+                    combine_secret(&shards_dir, &recovered_secret_path, &options, &cancel)?
+                }
+                (Some(recovered_secret_path), None) if !hex.is_empty() => {
+                    combine_hex(&hex, &recovered_secret_path, &options, &cancel)?
+                }
+                _ => {
+                    return Err(Error::InvalidParameters(
+                        "--hex, --from-env or --s3 shards cannot be combined with a shards \
+                         directory"
+                            .to_string(),
+                    ))
+                }
+            };
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Combine complete!".green());
+                println!(
+                    "Recovered secret saved to {}",
+                    recovered_secret_path.to_string_lossy().bright_blue()
+                );
+                if let Some(label) = label {
+                    println!("Label:\n{}", label.trim_end());
+                }
+            }
+        }
+        Commands::Info { shards_dir } => {
+            if print_info(&shards_dir)? != ReconstructStatus::Reconstructable {
+                process::exit(1);
+            }
         }
+        Commands::DumpShares { shards_dir, limit } => {
+            print!("{}", dump_shares(&shards_dir, limit, verbosity)?)
+        }
+        Commands::Reshard {
+            shards_dir,
+            new_shards_dir,
+            parts,
+            threshold,
+        } => {
+            rng::check_os_rng()?;
+            let shard_paths = reshard(
+                &shards_dir,
+                &new_shards_dir,
+                parts,
+                threshold,
+                gf_backend,
+                verbosity,
+                &cancel,
+            )?;
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Reshard complete!".green());
+                println!(
+                    "{} new shards saved to {}",
+                    shard_paths.len().to_string().cyan(),
+                    new_shards_dir.to_string_lossy().bright_blue()
+                );
+            }
+        }
+        Commands::Repair {
+            shards_dir,
+            missing_x,
+            out_path,
+        } => {
+            let verified = repair(&shards_dir, missing_x, &out_path, gf_backend)?;
+            if !verified && verbosity > Verbosity::Quiet {
+                eprintln!(
+                    "{} no sidecar lists the x-coordinates of the set, so {} could not be \
+                     verified to be that of one of its shards",
+                    "WARNING:".yellow().bold(),
+                    missing_x
+                );
+            }
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Repair complete!".green());
+                println!(
+                    "Shard with x-coordinate {} saved to {}",
+                    missing_x.to_string().cyan(),
+                    out_path.to_string_lossy().bright_blue()
+                );
+            }
+        }
+        Commands::Compare { dir_a, dir_b } => {
+            let same = compare_sets(&dir_a, &dir_b, gf_backend, verbosity, &cancel)?;
+            if verbosity > Verbosity::Quiet {
+                if same {
+                    println!("{}", "The shard sets hold the same secret.".green());
+                } else {
+                    println!("{}", "The shard sets hold different secrets.".red());
+                }
+            }
+            if !same {
+                process::exit(1);
+            }
+        }
+        Commands::Selftest { exhaustive } => {
+            let checks = run_selftest(exhaustive)?;
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Self-test passed!".green());
+                println!("{} checks succeeded.", checks.to_string().cyan());
+            }
+        }
+        Commands::Completions { shell } => io::stdout().write_all(&Cli::completions(shell))?,
+        Commands::DumpTables { format } => print!("{}", dump_tables(format)),
+        #[cfg(unix)]
+        Commands::Serve { socket } => {
+            rng::check_os_rng()?;
+            crate::serve::serve(
+                &socket,
+                gf_backend,
+                verbosity,
+                Arc::clone(pool),
+                Arc::clone(&cancel),
+            )?;
+        }
+        Commands::Merge { dirs, out_dir } => {
+            let merged_paths = merge_dirs(&dirs, &out_dir, verbosity)?;
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Merge complete!".green());
+                println!(
+                    "{} shards saved to {}",
+                    merged_paths.len().to_string().cyan(),
+                    out_dir.to_string_lossy().bright_blue()
+                );
+            }
+        }
+        Commands::Rehash { shards_dir } => {
+            let shards = rehash_dir(&shards_dir, verbosity)?;
+            let rewritten = shards.iter().filter(|(_, rewritten)| *rewritten).count();
+            if verbosity > Verbosity::Quiet {
+                println!("{}", "Rehash complete!".green());
+                println!(
+                    "{} of {} shards in {} rewritten in the current format.",
+                    rewritten.to_string().cyan(),
+                    shards.len().to_string().cyan(),
+                    shards_dir.to_string_lossy().bright_blue()
+                );
+            }
+        }
+    }
+
+    if verbosity == Verbosity::Verbose {
+        println!("Completed in {:.2?}", start.elapsed());
+    }
+    if let Some(timings) = timings {
+        eprint!("{}", timings.report(start.elapsed()));
     }
 
     Ok(())
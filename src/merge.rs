@@ -0,0 +1,220 @@
+use colored::*;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::Verbosity,
+    error::{Error, Result},
+    header::{self, Header},
+    mnemonic,
+};
+
+/// Prints a shard which was left out of the merge and why.
+fn report_skipped(name: &Path, reason: &str) {
+    eprintln!(
+        "{} skipping {}: {}",
+        "WARNING:".yellow().bold(),
+        name.display(),
+        reason
+    );
+}
+
+/// Collects the valid shards of several directories into a single directory, ready to be
+/// combined.
+///
+/// Only shards whose header and checksum are valid are kept, and those written as words are
+/// copied as words. The first valid shard found
+/// fixes the share set, and shards of another set are skipped. When several copies of a
+/// shard with the same x-coordinate are found, the first one is kept and the others are
+/// reported as conflicts if their content differs. Skipped shards and conflicts are printed
+/// as warnings, whatever the verbosity.
+///
+/// # Arguments
+///
+/// * `dirs` - The directories to collect the shards from, in order of preference.
+/// * `out_dir` - The directory the merged shards are written to; it is created if missing.
+/// * `verbosity` - Whether each shard kept is reported.
+///
+/// # Returns
+///
+/// The paths of the merged shards, ordered by x-coordinate, `Error::InsufficientShares` if
+/// no valid shard was found, or `Error::Io` if a directory cannot be read or a shard cannot
+/// be written. An existing shard file in `out_dir` is never overwritten.
+///
+/// # Examples
+///
+/// ```
+/// let dirs = vec![PathBuf::from("backup_a"), PathBuf::from("backup_b")];
+/// let shard_paths = merge_dirs(&dirs, Path::new("merged"), Verbosity::Normal)?;
+/// println!("{} shards merged", shard_paths.len());
+/// ```
+pub fn merge_dirs(dirs: &[PathBuf], out_dir: &Path, verbosity: Verbosity) -> Result<Vec<PathBuf>> {
+    let mut set_header: Option<Header> = None;
+    // The path, content as read and decoded content of the shard kept for every x-coordinate.
+    let mut shards: BTreeMap<u8, (PathBuf, Vec<u8>, Vec<u8>)> = BTreeMap::new();
+
+    for dir in dirs {
+        let mut shard_paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        shard_paths.sort();
+
+        for shard_path in shard_paths.iter().filter(|path| path.is_file()) {
+            let data = fs::read(shard_path)?;
+            // Shards written as words are checked, and compared with their copies, decoded.
+            let decoded = match mnemonic::as_mnemonic(&data).map(mnemonic::decode) {
+                Some(Ok(decoded)) => decoded,
+                Some(Err(e)) => {
+                    report_skipped(shard_path, &e.to_string());
+                    continue;
+                }
+                None => data.clone(),
+            };
+            let shard_header = match header::decode_shard(&decoded) {
+                Ok((shard_header, _)) => shard_header,
+                Err(e) => {
+                    report_skipped(shard_path, &e.to_string());
+                    continue;
+                }
+            };
+
+            match &set_header {
+                Some(first)
+                    if !header::ct_eq(
+                        &first.set_id.to_be_bytes(),
+                        &shard_header.set_id.to_be_bytes(),
+                    ) =>
+                {
+                    report_skipped(shard_path, "shard belongs to a different share set");
+                    continue;
+                }
+                Some(_) => (),
+                None => set_header = Some(shard_header.clone()),
+            }
+
+            match shards.get(&shard_header.x) {
+                Some((kept_path, _, kept)) => {
+                    if *kept != decoded {
+                        report_skipped(
+                            shard_path,
+                            &format!(
+                                "conflicts with {}, which has the same x-coordinate {}",
+                                kept_path.display(),
+                                shard_header.x
+                            ),
+                        );
+                    }
+                }
+                None => {
+                    shards.insert(shard_header.x, (shard_path.clone(), data, decoded));
+                }
+            }
+        }
+    }
+
+    if shards.is_empty() {
+        return Err(Error::InsufficientShares { have: 0, need: 2 });
+    }
+
+    fs::create_dir_all(out_dir)?;
+    let mut merged_paths = Vec::new();
+    for (x, (source_path, data, _)) in shards {
+        let merged_path = out_dir
+            .join(format!("{}_{}", "shards", x))
+            .with_extension(header::SHARD_EXTENSION);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&merged_path)
+            .and_then(|mut file| file.write_all(&data))
+            .map_err(|e| Error::from(e).in_shard(&merged_path.display().to_string()))?;
+        if verbosity == Verbosity::Verbose {
+            println!(
+                "Merged shard {} (x = {}) from {}",
+                merged_path.display(),
+                x,
+                source_path.display()
+            );
+        }
+        merged_paths.push(merged_path);
+    }
+
+    Ok(merged_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        combine::{combine_secret, CombineOptions},
+        test_support::{scheme, shard_fixture},
+    };
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_merge_partial_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = shard_fixture(&dir.path().join("shards"), b"test", scheme(4, 3));
+
+        // Backup A holds two shards and backup B two others, one of which is also in A.
+        let backup_a = dir.path().join("backup_a");
+        let backup_b = dir.path().join("backup_b");
+        for (backup, range) in [(&backup_a, 0..2), (&backup_b, 1..3)] {
+            fs::create_dir(backup).unwrap();
+            for shard_path in &shard_paths[range] {
+                fs::copy(shard_path, backup.join(shard_path.file_name().unwrap())).unwrap();
+            }
+        }
+        let options = CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        };
+        let partial_path = dir.path().join("partial");
+        assert!(
+            combine_secret(&backup_a, &partial_path, &options, &AtomicBool::new(false)).is_err()
+        );
+
+        let merged_dir = dir.path().join("merged");
+        let merged_paths =
+            merge_dirs(&[backup_a, backup_b], &merged_dir, Verbosity::Quiet).unwrap();
+        assert_eq!(merged_paths.len(), 3);
+
+        let recovered_path = dir.path().join("recovered");
+        combine_secret(
+            &merged_dir,
+            &recovered_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read(recovered_path).unwrap(), b"test");
+    }
+
+    #[test]
+    fn test_merge_skips_invalid_and_foreign_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_paths = shard_fixture(&dir.path().join("shards"), b"test", scheme(4, 3));
+        let other = tempfile::tempdir().unwrap();
+        let other_paths = shard_fixture(&other.path().join("shards"), b"other", scheme(4, 3));
+
+        let backup = dir.path().join("backup");
+        fs::create_dir(&backup).unwrap();
+        fs::copy(&shard_paths[0], backup.join("a.sss")).unwrap();
+        fs::copy(&other_paths[0], backup.join("b.sss")).unwrap();
+        let mut corrupted = fs::read(&shard_paths[1]).unwrap();
+        *corrupted.last_mut().unwrap() ^= 1;
+        fs::write(backup.join("c.sss"), corrupted).unwrap();
+
+        let merged_paths =
+            merge_dirs(&[backup], &dir.path().join("merged"), Verbosity::Quiet).unwrap();
+        assert_eq!(merged_paths.len(), 1);
+        assert_eq!(
+            fs::read(&merged_paths[0]).unwrap(),
+            fs::read(&shard_paths[0]).unwrap()
+        );
+    }
+}
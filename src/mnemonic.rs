@@ -0,0 +1,229 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+
+/// The BIP39 English wordlist: 2048 words, each told apart by its first four letters.
+const WORDLIST: &str = include_str!("bip39-english.txt");
+
+/// Number of bits encoded by a word, the base-2 logarithm of the wordlist length.
+const WORD_BITS: u32 = 11;
+
+/// Number of words per line of the encoded text, so that it can be read aloud in chunks.
+const WORDS_PER_LINE: usize = 8;
+
+/// Returns the words of the wordlist, in the order of the values they encode.
+fn words() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+/// Computes the checksum word of `data`: the first 10 bits of its SHA-256 hash, followed by
+/// the parity of its length, which tells whether the last data word holds a byte of its own.
+fn check_word(data: &[u8]) -> u32 {
+    let hash = Sha256::digest(data);
+    let checksum = (u32::from(hash[0]) << 2) | (u32::from(hash[1]) >> 6);
+    (checksum << 1) | (data.len() % 2) as u32
+}
+
+/// Encodes bytes, such as a whole shard file, as words of the BIP39 English wordlist, so that
+/// they can be read aloud or written on paper.
+///
+/// Every word encodes 11 bits of the data, the last one padded with zero bits, and a last
+/// checksum word is appended, see `check_word`. The words are separated by spaces, with a
+/// line break every 8 words.
+///
+/// # Examples
+///
+/// ```
+/// let text = encode(b"shard");
+/// assert_eq!(text.split_whitespace().count(), 5);
+/// assert_eq!(decode(&text)?, b"shard");
+/// ```
+pub fn encode(data: &[u8]) -> String {
+    let words = words();
+    let mut values = Vec::with_capacity(data.len() * 8 / WORD_BITS as usize + 2);
+    let (mut bits, mut len) = (0u32, 0);
+    for &byte in data {
+        bits = (bits << 8) | u32::from(byte);
+        len += 8;
+        if len >= WORD_BITS {
+            len -= WORD_BITS;
+            values.push(bits >> len);
+            bits &= (1 << len) - 1;
+        }
+    }
+    if len > 0 {
+        values.push(bits << (WORD_BITS - len));
+    }
+    values.push(check_word(data));
+
+    let mut text = String::new();
+    for (index, value) in values.into_iter().enumerate() {
+        if index > 0 {
+            text.push(if index % WORDS_PER_LINE == 0 {
+                '\n'
+            } else {
+                ' '
+            });
+        }
+        text.push_str(words[value as usize]);
+    }
+    text.push('\n');
+    text
+}
+
+/// Returns the text of `data` if it looks like words written by `encode`: ASCII letters
+/// separated by whitespace.
+pub fn as_mnemonic(data: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(data).ok()?;
+    let words_only = text
+        .chars()
+        .all(|c| c.is_ascii_alphabetic() || c.is_whitespace());
+    (words_only && text.split_whitespace().next().is_some()).then_some(text)
+}
+
+/// Decodes the words written by `encode`.
+///
+/// Whitespace between the words is normalized, so that they may be split across lines or
+/// separated by several spaces, and the case is ignored. As in BIP39, a word may be
+/// abbreviated to its first four letters.
+///
+/// # Returns
+///
+/// The data, `Error::InvalidHeader` naming a word which is not in the wordlist or if the
+/// number of words or their padding does not match whole bytes, or `Error::ChecksumMismatch`
+/// if the checksum word does not match the data, such as after a word was misread.
+pub fn decode(text: &str) -> Result<Vec<u8>> {
+    let invalid = |message: String| Error::InvalidHeader(format!("invalid mnemonic: {}", message));
+    let words = words();
+    let by_prefix: HashMap<&str, u32> = words
+        .iter()
+        .enumerate()
+        .map(|(value, word)| (&word[..word.len().min(4)], value as u32))
+        .collect();
+    let values = text
+        .split_whitespace()
+        .map(|word| {
+            let word = word.to_ascii_lowercase();
+            let value = word
+                .get(..word.len().min(4))
+                .and_then(|prefix| by_prefix.get(prefix))
+                .filter(|&&value| words[value as usize].starts_with(&word));
+            value
+                .copied()
+                .ok_or_else(|| invalid(format!("`{}` is not in the wordlist", word)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Some((&check, values)) = values.split_last() else {
+        return Err(invalid("no words".to_string()));
+    };
+
+    let mut data = Vec::with_capacity(values.len() * WORD_BITS as usize / 8);
+    let (mut bits, mut len) = (0u32, 0);
+    for &value in values {
+        bits = (bits << WORD_BITS) | value;
+        len += WORD_BITS;
+        while len >= 8 {
+            len -= 8;
+            data.push((bits >> len) as u8);
+            bits &= (1 << len) - 1;
+        }
+    }
+    // The bits left over are padding, and so is the last byte when the parity of the
+    // length recorded in the checksum word says so.
+    if data.len() % 2 != (check & 1) as usize {
+        let last = data.pop().map_or(0, u32::from);
+        bits |= last << len;
+        len += 8;
+    }
+    if len >= WORD_BITS || bits != 0 {
+        return Err(invalid("the words do not encode whole bytes".to_string()));
+    }
+    if check_word(&data) != check {
+        return Err(Error::ChecksumMismatch);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wordlist() {
+        let words = words();
+        assert_eq!(words.len(), 1 << WORD_BITS);
+        assert_eq!((words[0], words[2047]), ("abandon", "zoo"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for len in 0..=24usize {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            let text = encode(&data);
+            assert_eq!(decode(&text).unwrap(), data, "{}", text);
+            // 11 bits per word, plus the checksum word.
+            let word_count = (len * 8).div_ceil(11) + 1;
+            assert_eq!(text.split_whitespace().count(), word_count);
+        }
+    }
+
+    #[test]
+    fn test_decode_normalizes_input() {
+        let data = b"SHMR shard";
+        let text = encode(data);
+        let messy = format!(
+            "  {}\r\n\n",
+            text.split_whitespace()
+                .map(|word| word.to_uppercase())
+                .collect::<Vec<_>>()
+                .join(" \t\n")
+        );
+        assert_eq!(decode(&messy).unwrap(), data);
+        let abbreviated: Vec<&str> = text
+            .split_whitespace()
+            .map(|word| &word[..word.len().min(4)])
+            .collect();
+        assert_eq!(decode(&abbreviated.join(" ")).unwrap(), data);
+        assert_eq!(as_mnemonic(messy.as_bytes()), Some(messy.as_str()));
+        assert_eq!(as_mnemonic(b"SHMR\x01"), None);
+        assert_eq!(as_mnemonic(b" \n"), None);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let text = encode(b"secret");
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+
+        // A word swapped for another is caught by the checksum.
+        let original = words[1];
+        words[1] = if original == "abandon" {
+            "ability"
+        } else {
+            "abandon"
+        };
+        assert!(matches!(
+            decode(&words.join(" ")),
+            Err(Error::ChecksumMismatch)
+        ));
+        words[1] = original;
+
+        let replaced = |word: &str| {
+            let mut words = words.clone();
+            words[1] = word;
+            words.join(" ")
+        };
+        for invalid in [
+            String::new(),
+            replaced("zzzz"),
+            replaced(&format!("{}x", original)),
+            replaced("café"),
+            // A dropped word leaves the padding wrong, or the checksum.
+            [&words[..words.len() - 2], &words[words.len() - 1..]]
+                .concat()
+                .join(" "),
+        ] {
+            assert!(decode(&invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{CryptoRng, Rng};
+use sha2::{Digest, Sha256};
+use std::{env, fmt};
+use zeroize::Zeroizing;
+
+use crate::{
+    error::{Error, Result},
+    header::{self, PassphraseShare},
+    shamir::Share,
+};
+
+/// Environment variable holding the passphrase, read instead of prompting for it.
+pub const PASSPHRASE_ENV: &str = "SSS_PASSPHRASE";
+
+/// Length in bytes of the random salt of the key derivation.
+pub const SALT_LEN: usize = 16;
+
+/// Length in bytes of the value checking the passphrase before the share is used.
+pub const VERIFIER_LEN: usize = 16;
+
+/// Memory cost of Argon2id in KiB, the minimum recommended by OWASP. The parameters are part
+/// of the shard format: changing them would make existing passphrase shares unrecoverable.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Number of passes of Argon2id over its memory.
+const ARGON2_ITERATIONS: u32 = 2;
+
+/// Domain separation prefix of the blocks expanding the key into a share.
+const SHARE_CONTEXT: &[u8] = b"shamir-encryption passphrase share";
+
+/// Domain separation prefix of the passphrase verifier.
+const VERIFIER_CONTEXT: &[u8] = b"shamir-encryption passphrase verifier";
+
+/// A passphrase, zeroized when dropped and never printed by `Debug`.
+#[derive(Clone)]
+pub struct Passphrase(Zeroizing<String>);
+
+impl Passphrase {
+    /// Wraps a passphrase, rejecting an empty one.
+    pub fn new(passphrase: String) -> Result<Self> {
+        let passphrase = Zeroizing::new(passphrase);
+        if passphrase.is_empty() {
+            return Err(Error::InvalidParameters(
+                "the passphrase cannot be empty".to_string(),
+            ));
+        }
+        Ok(Passphrase(passphrase))
+    }
+
+    /// Reads the passphrase from `PASSPHRASE_ENV`, or prompts for it on the terminal without
+    /// echoing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `confirm` - Whether to prompt twice and check that both entries match, for a new
+    ///   passphrase.
+    ///
+    /// # Returns
+    ///
+    /// The passphrase, `Error::InvalidParameters` if it is empty or the entries differ, or
+    /// `Error::Io` if there is no terminal to prompt on.
+    pub fn read(confirm: bool) -> Result<Self> {
+        if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+            return Passphrase::new(passphrase);
+        }
+        let passphrase = Passphrase::new(rpassword::prompt_password("Passphrase: ")?)?;
+        if confirm {
+            let again = Zeroizing::new(rpassword::prompt_password("Repeat the passphrase: ")?);
+            if !header::ct_eq(passphrase.0.as_bytes(), again.as_bytes()) {
+                return Err(Error::InvalidParameters(
+                    "the passphrases do not match".to_string(),
+                ));
+            }
+        }
+        Ok(passphrase)
+    }
+
+    /// Derives the key from which the share and verifier are computed, with Argon2id.
+    fn derive_key(&self, salt: &[u8; SALT_LEN]) -> Result<Zeroizing<[u8; 32]>> {
+        let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, 1, Some(32))
+            .expect("Argon2 parameters are valid");
+        let mut key = Zeroizing::new([0; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(self.0.as_bytes(), salt, &mut *key)
+            .map_err(|e| Error::InvalidParameters(format!("cannot derive the key: {}", e)))?;
+        Ok(key)
+    }
+
+    /// Derives a new passphrase share for a secret.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - The x-coordinate of the share.
+    /// * `len` - The length of the secret.
+    /// * `rng` - The generator the salt is drawn from.
+    ///
+    /// # Returns
+    ///
+    /// The share, ending with `x`, and its description to store in the shard headers, with a
+    /// new random salt.
+    pub fn new_share<R: Rng + CryptoRng>(
+        &self,
+        x: u8,
+        len: usize,
+        rng: &mut R,
+    ) -> Result<(Zeroizing<Share>, PassphraseShare)> {
+        let salt = rng.gen();
+        let key = self.derive_key(&salt)?;
+        let description = PassphraseShare {
+            x,
+            salt,
+            verifier: verifier(&key),
+        };
+        Ok((expand(&key, x, len), description))
+    }
+
+    /// Derives the passphrase share described in the shard headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `description` - The x-coordinate, salt and verifier stored in the shard headers.
+    /// * `len` - The length of the secret.
+    ///
+    /// # Returns
+    ///
+    /// The share, ending with its x-coordinate, or `Error::InvalidParameters` if the
+    /// passphrase differs from the one the secret was split with.
+    pub fn share(&self, description: &PassphraseShare, len: usize) -> Result<Zeroizing<Share>> {
+        let key = self.derive_key(&description.salt)?;
+        if !header::ct_eq(&verifier(&key), &description.verifier) {
+            return Err(Error::InvalidParameters(
+                "wrong passphrase for these shards".to_string(),
+            ));
+        }
+        Ok(expand(&key, description.x, len))
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+/// Expands the key into the y-coordinates of a share, followed by its x-coordinate.
+fn expand(key: &[u8; 32], x: u8, len: usize) -> Zeroizing<Share> {
+    let mut share = Zeroizing::new(Vec::with_capacity(len + 1));
+    for counter in 0u64.. {
+        if share.len() >= len {
+            break;
+        }
+        let block = Sha256::new()
+            .chain_update(SHARE_CONTEXT)
+            .chain_update(key)
+            .chain_update(counter.to_be_bytes())
+            .finalize();
+        let take = block.len().min(len - share.len());
+        share.extend_from_slice(&block[..take]);
+    }
+    share.push(x);
+    share
+}
+
+/// Computes the value stored in the shard headers to check the passphrase.
+fn verifier(key: &[u8; 32]) -> [u8; VERIFIER_LEN] {
+    let digest = Sha256::new()
+        .chain_update(VERIFIER_CONTEXT)
+        .chain_update(key)
+        .finalize();
+    digest[..VERIFIER_LEN]
+        .try_into()
+        .expect("digest is longer than the verifier")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passphrase_share() {
+        let passphrase = Passphrase::new("correct horse".to_string()).unwrap();
+        let (share, description) = passphrase
+            .new_share(9, 40, &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(share.len(), 41);
+        assert_eq!(share.last(), Some(&9));
+        assert_eq!(passphrase.share(&description, 40).unwrap(), share);
+
+        let wrong = Passphrase::new("wrong horse".to_string()).unwrap();
+        assert!(matches!(
+            wrong.share(&description, 40),
+            Err(Error::InvalidParameters(_))
+        ));
+        assert!(Passphrase::new(String::new()).is_err());
+        assert_eq!(format!("{:?}", passphrase), "Passphrase(..)");
+    }
+}
@@ -1,5 +1,5 @@
-use crate::gf256::GF256;
-use rand::Rng;
+use crate::gf256::{GfBackend, GF256};
+use rand::{CryptoRng, Rng};
 
 /// Represents a polynomial where the coefficients are elements of GF(2^8).
 pub struct Polynomial {
@@ -10,10 +10,14 @@ pub struct Polynomial {
 impl Polynomial {
     /// Creates a new polynomial with a given intercept and random coefficients for the remaining terms.
     ///
+    /// Only cryptographically secure generators are accepted, since the coefficients are all
+    /// that hides the intercept.
+    ///
     /// # Arguments
     ///
     /// * `intercept` - The constant term of the polynomial.
     /// * `degree` - The degree of the polynomial which determines the number of random coefficients to generate.
+    /// * `rng` - The generator drawing the coefficients, typically a `rng::ShareRng`.
     ///
     /// # Returns
     ///
@@ -22,13 +26,11 @@ impl Polynomial {
     /// # Examples
     ///
     /// ```
-    /// let p = Polynomial::new(1, 3);
+    /// let p = Polynomial::new(1, 3, &mut rng::share_rng()?);
     /// assert_eq!(p.coefficients[0], 1); // intercept is the first coefficient
     /// assert!(p.coefficients.len() == 4); // degree 3 means 4 coefficients
     /// ```
-    pub fn new(intercept: u8, degree: usize) -> Self {
-        let mut rng = rand::thread_rng();
-
+    pub fn new<R: Rng + CryptoRng>(intercept: u8, degree: usize, rng: &mut R) -> Self {
         // Generate random coefficients and set the first one to the intercept.
         let coefficients: Vec<u8> = std::iter::once(intercept)
             .chain((0..degree).map(|_| rng.gen_range(0..=255)))
@@ -37,11 +39,39 @@ impl Polynomial {
         Polynomial { coefficients }
     }
 
+    /// Creates the random polynomial used to share one byte of a secret with a given threshold.
+    ///
+    /// A polynomial of degree `threshold - 1` is fully determined by `threshold` points, and
+    /// fewer points reveal nothing about its intercept.
+    ///
+    /// # Arguments
+    ///
+    /// * `intercept` - The constant term of the polynomial, i.e. the secret byte.
+    /// * `threshold` - The number of shares required to recover the intercept.
+    /// * `rng` - The generator drawing the coefficients.
+    ///
+    /// # Returns
+    ///
+    /// A `Polynomial` with exactly `threshold` coefficients.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let p = Polynomial::for_threshold(1, 3, &mut rng::share_rng()?);
+    /// assert_eq!(p.coefficients.len(), 3);
+    /// ```
+    pub fn for_threshold<R: Rng + CryptoRng>(intercept: u8, threshold: usize, rng: &mut R) -> Self {
+        let polynomial = Polynomial::new(intercept, threshold - 1, rng);
+        debug_assert_eq!(polynomial.coefficients.len(), threshold);
+        polynomial
+    }
+
     /// Evaluates the polynomial at a given point `x` using Horner's method.
     ///
     /// # Arguments
     ///
     /// * `x` - The point at which to evaluate the polynomial.
+    /// * `backend` - The resolved GF(2^8) backend to use for multiplications.
     ///
     /// # Returns
     ///
@@ -50,35 +80,57 @@ impl Polynomial {
     /// # Examples
     ///
     /// ```
-    /// let p = Polynomial::new(1, 3); // let's assume it generates 1 + 2x + 3x^2 + 4x^3
-    /// let value = p.evaluate(2); // evaluates 1 + 2*2 + 3*2^2 + 4*2^3 in GF(2^8)
+    /// let p = Polynomial::new(1, 3, &mut rng::share_rng()?); // let's assume it generates 1 + 2x + 3x^2 + 4x^3
+    /// let value = p.evaluate(2, GfBackend::Scalar); // evaluates 1 + 2*2 + 3*2^2 + 4*2^3 in GF(2^8)
     /// // Note: The actual result would depend on the GF(2^8) field arithmetic
     /// ```
-    pub fn evaluate(&self, x: u8) -> u8 {
+    pub fn evaluate(&self, x: u8, backend: GfBackend) -> u8 {
         self.coefficients
             .iter()
             .rev()
-            .fold(0, |acc, &coeff| GF256::add(GF256::mult(acc, x), coeff))
+            .fold(0, |acc, &coeff| GF256::add(backend.mult(acc, x), coeff))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{gf256::GF256, polynomial::Polynomial};
+    use crate::{
+        gf256::{GfBackend, GF256},
+        polynomial::Polynomial,
+    };
 
     #[test]
     fn test_polynomial_random() {
-        let p = Polynomial::new(42, 2);
+        let p = Polynomial::new(42, 2, &mut rand::thread_rng());
         assert_eq!(p.coefficients[0], 42);
     }
 
+    #[test]
+    fn test_polynomial_for_threshold() {
+        for threshold in 2..=255 {
+            let p = Polynomial::for_threshold(42, threshold, &mut rand::thread_rng());
+            assert_eq!(p.coefficients.len(), threshold);
+            assert_eq!(p.coefficients[0], 42);
+        }
+    }
+
     #[test]
     fn test_polynomial_eval() {
-        let p = Polynomial::new(42, 1);
-        let mut out = p.evaluate(0);
+        let p = Polynomial::new(42, 1, &mut rand::thread_rng());
+        let mut out = p.evaluate(0, GfBackend::Scalar);
         assert_eq!(out, 42);
-        out = p.evaluate(1);
+        out = p.evaluate(1, GfBackend::Scalar);
         let exp = GF256::add(42, GF256::mult(1, p.coefficients[1]));
         assert_eq!(out, exp);
     }
+
+    #[test]
+    fn test_polynomial_eval_zero() {
+        let p = Polynomial {
+            coefficients: vec![0, 0, 0],
+        };
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            assert!((0..=255).all(|x| p.evaluate(x, backend) == 0));
+        }
+    }
 }
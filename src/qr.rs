@@ -0,0 +1,370 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use qrcode::{Color, EcLevel, QrCode};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Error, Result};
+
+/// Number of base64 characters of a shard stored in each QR code. A code holds up to 2331
+/// bytes at the medium error correction level, but smaller codes are easier to scan from
+/// paper.
+pub const QR_CHUNK_LEN: usize = 1200;
+
+/// Size in pixels of a QR module, and of a pixel of the label glyphs.
+const MODULE_PX: usize = 4;
+
+/// Blank margin around a code and its label, in modules, as the QR specification requires.
+const QUIET_ZONE: usize = 4;
+
+/// Width of a label glyph in glyph pixels, followed by one pixel of spacing.
+const GLYPH_WIDTH: usize = 3;
+
+/// Height of a label glyph in glyph pixels.
+const GLYPH_HEIGHT: usize = 5;
+
+/// Returns the rows of a label glyph, each row holding `GLYPH_WIDTH` bits with the leftmost
+/// pixel in the highest bit. Characters without a glyph are left blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'x' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Splits a shard into the texts stored in its QR codes.
+///
+/// The shard is base64-encoded and cut into chunks of `QR_CHUNK_LEN` characters. Each text
+/// starts with the number of its chunk, counted from 1, and the number of chunks, such as
+/// `2/3:`, so that scanned chunks can be put back in order.
+///
+/// # Arguments
+///
+/// * `shard` - The full content of the shard file.
+///
+/// # Returns
+///
+/// The text of every QR code, in reassembly order.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(qr_payloads(b"shard"), vec!["1/1:c2hhcmQ=".to_string()]);
+/// ```
+pub fn qr_payloads(shard: &[u8]) -> Vec<String> {
+    let encoded = STANDARD.encode(shard);
+    let chunks: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(QR_CHUNK_LEN)
+        .map(|chunk| std::str::from_utf8(chunk).expect("base64 is ASCII"))
+        .collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| format!("{}/{}:{}", index + 1, chunks.len(), chunk))
+        .collect()
+}
+
+/// Reassembles a shard from the texts of its scanned QR codes.
+///
+/// The texts are the `qr_payloads` of the shard, one per line, in any order. Blank lines
+/// are ignored and a chunk scanned several times is only used once.
+///
+/// # Arguments
+///
+/// * `text` - The scanned texts, one per line.
+///
+/// # Returns
+///
+/// The content of the shard file, `Error::InvalidHeader` if a line is not a QR code text of
+/// a shard, or `Error::InconsistentShards` if chunks are missing, more chunks are announced
+/// than lines were scanned, texts disagree on the number of chunks or a chunk was scanned
+/// twice with different content.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(decode_qr_text("1/1:c2hhcmQ=\n").unwrap(), b"shard");
+/// ```
+pub fn decode_qr_text(text: &str) -> Result<Vec<u8>> {
+    let mut chunks: Vec<Option<&str>> = Vec::new();
+
+    // A UTF-8 byte order mark may start texts saved by some editors.
+    let text = text.trim_start_matches('\u{feff}');
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    for line in &lines {
+        let (index, count, chunk) = parse_payload(line).ok_or_else(|| {
+            Error::InvalidHeader("QR text is not of the form <chunk>/<chunks>:<base64>".into())
+        })?;
+        // Every chunk is scanned on a line of its own, so a text announcing more chunks than
+        // it has lines is refused before anything is allocated for them.
+        if count > lines.len() {
+            return Err(Error::InconsistentShards(format!(
+                "QR texts announce {} chunks on only {} scanned lines",
+                count,
+                lines.len()
+            )));
+        }
+        if chunks.is_empty() {
+            chunks.resize(count, None);
+        } else if chunks.len() != count {
+            return Err(Error::InconsistentShards(format!(
+                "QR texts disagree on the number of chunks, {} or {}",
+                chunks.len(),
+                count
+            )));
+        }
+        match chunks[index - 1] {
+            Some(scanned) if scanned != chunk => {
+                return Err(Error::InconsistentShards(format!(
+                    "QR chunk {} was scanned twice with different content",
+                    index
+                )))
+            }
+            _ => chunks[index - 1] = Some(chunk),
+        }
+    }
+
+    let mut encoded = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk = chunk.ok_or_else(|| {
+            Error::InconsistentShards(format!(
+                "QR chunk {} of {} is missing",
+                index + 1,
+                chunks.len()
+            ))
+        })?;
+        encoded.push_str(chunk);
+    }
+    if encoded.is_empty() {
+        return Err(Error::InvalidHeader("QR text is empty".into()));
+    }
+    STANDARD
+        .decode(encoded)
+        .map_err(|e| Error::InvalidHeader(format!("QR text is not valid base64: {}", e)))
+}
+
+/// Splits a QR code text into its chunk number, counted from 1, number of chunks and
+/// base64 chunk.
+fn parse_payload(line: &str) -> Option<(usize, usize, &str)> {
+    let (position, chunk) = line.split_once(':')?;
+    let (index, count) = position.split_once('/')?;
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    (1..=count)
+        .contains(&index)
+        .then_some((index, count, chunk))
+}
+
+/// Renders a QR code with a label below it as 8-bit grayscale pixels.
+///
+/// # Returns
+///
+/// The width and height of the image, and its pixels row by row.
+fn render(code: &QrCode, label: &str) -> (usize, usize, Vec<u8>) {
+    let code_px = (code.width() + 2 * QUIET_ZONE) * MODULE_PX;
+    let label_px = label.chars().count() * (GLYPH_WIDTH + 1) * MODULE_PX;
+    let width = code_px.max(label_px + 2 * QUIET_ZONE * MODULE_PX);
+    let height = code_px + (GLYPH_HEIGHT + QUIET_ZONE) * MODULE_PX;
+    let mut pixels = vec![u8::MAX; width * height];
+    let mut fill = |left: usize, top: usize| {
+        for row in top..top + MODULE_PX {
+            pixels[row * width + left..row * width + left + MODULE_PX].fill(0);
+        }
+    };
+
+    let code_left = (width - code_px) / 2 + QUIET_ZONE * MODULE_PX;
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let (row, column) = (index / code.width(), index % code.width());
+            fill(
+                code_left + column * MODULE_PX,
+                (QUIET_ZONE + row) * MODULE_PX,
+            );
+        }
+    }
+
+    let label_left = (width - label_px) / 2;
+    for (position, c) in label.chars().enumerate() {
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    fill(
+                        label_left + (position * (GLYPH_WIDTH + 1) + column) * MODULE_PX,
+                        code_px + row * MODULE_PX,
+                    );
+                }
+            }
+        }
+    }
+
+    (width, height, pixels)
+}
+
+/// Writes grayscale pixels to a PNG file.
+fn write_png(path: &Path, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes a shard as QR-code PNG images for a paper backup.
+///
+/// Each image holds one of the `qr_payloads` of the shard, with the x-coordinate of the
+/// shard and the chunk number printed below the code, such as `x=42 1/2`. A shard that fits
+/// in one code is written to `<stem>.png`; larger shards are written to `<stem>_1.png`,
+/// `<stem>_2.png` and so on, and must be scanned back in that order.
+///
+/// # Arguments
+///
+/// * `shard` - The full content of the shard file.
+/// * `x` - The x-coordinate of the shard, printed in the label.
+/// * `shard_path` - The path of the shard file, whose extension is replaced.
+///
+/// # Returns
+///
+/// The paths of the images written, in reassembly order, or `Error::Io` if one cannot be
+/// written, in which case the images already written are removed.
+pub fn write_qr_codes(shard: &[u8], x: u8, shard_path: &Path) -> Result<Vec<PathBuf>> {
+    let payloads = qr_payloads(shard);
+    let stem = shard_path.with_extension("");
+    let mut image_paths = Vec::new();
+
+    for (index, payload) in payloads.iter().enumerate() {
+        let code = QrCode::with_error_correction_level(payload, EcLevel::M)
+            .map_err(|e| Error::InvalidParameters(format!("cannot encode QR code: {}", e)))?;
+        let image_path = if payloads.len() == 1 {
+            stem.with_extension("png")
+        } else {
+            PathBuf::from(format!("{}_{}.png", stem.display(), index + 1))
+        };
+        let label = format!("x={} {}/{}", x, index + 1, payloads.len());
+        let (width, height, pixels) = render(&code, &label);
+        if let Err(e) = write_png(&image_path, width, height, &pixels) {
+            let _ = fs::remove_file(&image_path);
+            image_paths.iter().for_each(|path| {
+                let _ = fs::remove_file(path);
+            });
+            return Err(e.into());
+        }
+        image_paths.push(image_path);
+    }
+
+    Ok(image_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a PNG file, returning its width, height and pixels.
+    fn read_png(path: &Path) -> (u32, u32, Vec<u8>) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        (info.width, info.height, pixels)
+    }
+
+    #[test]
+    fn test_qr_payloads() {
+        assert_eq!(qr_payloads(b"shard"), vec!["1/1:c2hhcmQ=".to_string()]);
+
+        let shard: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+        let payloads = qr_payloads(&shard);
+        assert_eq!(payloads.len(), 3);
+        let encoded: String = payloads
+            .iter()
+            .enumerate()
+            .map(|(index, payload)| {
+                let prefix = format!("{}/3:", index + 1);
+                payload.strip_prefix(&prefix).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(STANDARD.decode(encoded).unwrap(), shard);
+    }
+
+    #[test]
+    fn test_decode_qr_text() {
+        let shard: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let mut payloads = qr_payloads(&shard);
+        assert_eq!(payloads.len(), 4);
+
+        // Scans come back out of order, with a duplicate and blank lines.
+        payloads.reverse();
+        payloads.push(payloads[1].clone());
+        let text = payloads.join("\n\n");
+        assert_eq!(decode_qr_text(&text).unwrap(), shard);
+        let edited = format!("\u{feff}{}\r\n", payloads.join("\r\n"));
+        assert_eq!(decode_qr_text(&edited).unwrap(), shard);
+
+        let missing = payloads[1..].join("\n");
+        assert!(matches!(
+            decode_qr_text(&missing),
+            Err(Error::InconsistentShards(_))
+        ));
+        let announcing = format!("1/{}:c2hhcmQ=", usize::MAX);
+        assert!(matches!(
+            decode_qr_text(&announcing),
+            Err(Error::InconsistentShards(e)) if e.contains("on only 1 scanned lines")
+        ));
+        let conflicting = format!("{}\n{}x", text, payloads[0]);
+        assert!(matches!(
+            decode_qr_text(&conflicting),
+            Err(Error::InconsistentShards(_))
+        ));
+        for invalid in ["", "c2hhcmQ=", "0/1:c2hhcmQ=", "1/1:not base64"] {
+            assert!(matches!(
+                decode_qr_text(invalid),
+                Err(Error::InvalidHeader(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_write_qr_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let shard_path = dir.path().join("shards_0.sss");
+
+        let image_paths = write_qr_codes(b"small shard", 42, &shard_path).unwrap();
+        assert_eq!(image_paths, vec![dir.path().join("shards_0.png")]);
+        let (width, height, pixels) = read_png(&image_paths[0]);
+        assert!(height > width);
+        assert!(pixels.contains(&0) && pixels.contains(&u8::MAX));
+
+        let shard = vec![7; 2000];
+        let image_paths = write_qr_codes(&shard, 42, &shard_path).unwrap();
+        assert_eq!(image_paths.len(), 3);
+        assert_eq!(image_paths[2], dir.path().join("shards_0_3.png"));
+        for image_path in &image_paths {
+            read_png(image_path);
+            assert!(fs::metadata(image_path).unwrap().len() > 0);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::Verbosity,
+    combine,
+    error::{Error, Result},
+    header, mnemonic,
+};
+
+/// Rewrites the shards of a directory which use an older version of the format in the
+/// current one, with a new checksum, leaving their share data unchanged.
+///
+/// Every shard is checked against its current checksum before any is rewritten, so that a
+/// corrupted shard aborts the migration instead of being given a valid checksum. Each shard
+/// is replaced atomically, by renaming a complete copy over it. Shards written as words by
+/// `shard --encoding mnemonic` are rewritten as words. Files which are not shards, such as
+/// the label manifest, are left alone.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory holding the shards.
+/// * `verbosity` - Whether each rewritten shard is reported.
+///
+/// # Returns
+///
+/// The path of every shard and whether it was rewritten, ordered by path,
+/// `Error::NoShardsFound` if the directory holds no shard, the errors of
+/// `header::decode_shard` naming the first invalid shard, or `Error::Io` if a shard cannot be
+/// read or replaced.
+///
+/// # Examples
+///
+/// ```
+/// let shards = rehash_dir(Path::new("shards"), Verbosity::Normal)?;
+/// let rewritten = shards.iter().filter(|(_, rewritten)| *rewritten).count();
+/// ```
+pub fn rehash_dir(shards_dir: &Path, verbosity: Verbosity) -> Result<Vec<(PathBuf, bool)>> {
+    let mut shard_paths = fs::read_dir(shards_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    shard_paths.sort();
+
+    let mut upgrades = Vec::new();
+    for shard_path in shard_paths.into_iter().filter(|path| path.is_file()) {
+        let data = fs::read(&shard_path)?;
+        let name = shard_path.display().to_string();
+        // Shards written as words are rewritten as words.
+        let upgraded = if header::has_magic(&data) {
+            header::upgrade_shard(&data).map_err(|e| e.in_shard(&name))?
+        } else if let Some(text) = mnemonic::as_mnemonic(&data) {
+            let Some(decoded) = mnemonic::decode(text)
+                .ok()
+                .filter(|decoded| header::has_magic(decoded))
+            else {
+                continue;
+            };
+            header::upgrade_shard(&decoded)
+                .map_err(|e| e.in_shard(&name))?
+                .map(|upgraded| mnemonic::encode(&upgraded).into_bytes())
+        } else {
+            continue;
+        };
+        upgrades.push((shard_path, upgraded));
+    }
+    if upgrades.is_empty() {
+        return Err(Error::NoShardsFound {
+            dir: shards_dir.display().to_string(),
+        });
+    }
+
+    let mut shards = Vec::with_capacity(upgrades.len());
+    for (shard_path, upgraded) in upgrades {
+        if let Some(data) = &upgraded {
+            replace_file(&shard_path, data)
+                .map_err(|e| Error::from(e).in_shard(&shard_path.display().to_string()))?;
+            if verbosity == Verbosity::Verbose {
+                println!("Rewrote {}", shard_path.display());
+            }
+        }
+        shards.push((shard_path, upgraded.is_some()));
+    }
+    Ok(shards)
+}
+
+/// Replaces the content of `path` with `data` by writing a temporary file next to it and
+/// renaming it over the file, so that the file is never left half written.
+///
+/// The temporary file is created like the recovered secrets, by `combine::create_temp_file`,
+/// with the permissions of the file it replaces, and the directory is synced once it is
+/// renamed, so that the new content survives a crash.
+fn replace_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let (temp_path, mut temp_file) = combine::create_temp_file(path, file_mode(path)?)?;
+    let written = temp_file
+        .write_all(data)
+        .and_then(|()| temp_file.sync_all())
+        .and_then(|()| combine::persist(&temp_path, path, true));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        return written;
+    }
+    sync_parent(path)
+}
+
+/// Returns the permissions of the file at `path`.
+#[cfg(unix)]
+fn file_mode(path: &Path) -> io::Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(fs::metadata(path)?.permissions().mode() & 0o777)
+}
+
+/// Files keep the permissions inherited from their directory outside Unix.
+#[cfg(not(unix))]
+fn file_mode(_: &Path) -> io::Result<u32> {
+    Ok(combine::SECRET_FILE_MODE)
+}
+
+/// Syncs the directory holding `path`, so that a rename in it is durable.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    File::open(parent.unwrap_or(Path::new(".")))?.sync_all()
+}
+
+/// Directories cannot be opened to be synced outside Unix.
+#[cfg(not(unix))]
+fn sync_parent(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{scheme, shard_fixture};
+
+    #[test]
+    fn test_rehash_upgrades_old_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = shard_fixture(&shards_dir, b"test", scheme(4, 3));
+        let current: Vec<Vec<u8>> = shard_paths.iter().map(|p| fs::read(p).unwrap()).collect();
+        // Downgrade two shards to version 1, which has no metadata length.
+        let v1_shards: Vec<Vec<u8>> = current[..2]
+            .iter()
+            .map(|data| {
+                let (shard_header, body) = header::decode_shard(data).unwrap();
+                let mut v1_header = shard_header.to_bytes();
+                v1_header.truncate(header::FIXED_HEADER_LEN - 2);
+                v1_header[4] = 1;
+                let mut v1 = Vec::new();
+                header::write_shard(&mut v1, &v1_header, body).unwrap();
+                v1
+            })
+            .collect();
+        for (shard_path, v1) in shard_paths.iter().zip(&v1_shards) {
+            fs::write(shard_path, v1).unwrap();
+        }
+        fs::write(shards_dir.join("label.txt"), "not a shard").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&shard_paths[0], fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let shards = rehash_dir(&shards_dir, Verbosity::Quiet).unwrap();
+        let rewritten: Vec<bool> = shards.iter().map(|(_, rewritten)| *rewritten).collect();
+        assert_eq!(rewritten, [true, true, false, false]);
+        for (shard_path, data) in shard_paths.iter().zip(&current) {
+            assert_eq!(&fs::read(shard_path).unwrap(), data);
+        }
+        assert_eq!(fs::read_dir(&shards_dir).unwrap().count(), 5);
+        // The rewritten shards keep their permissions.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&shard_paths[0]).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        let rehashed = rehash_dir(&shards_dir, Verbosity::Quiet).unwrap();
+        assert!(rehashed.iter().all(|(_, rewritten)| !rewritten));
+
+        // A corrupted shard aborts the migration before anything is rewritten.
+        fs::write(&shard_paths[0], &v1_shards[0]).unwrap();
+        let mut corrupted = fs::read(&shard_paths[3]).unwrap();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        fs::write(&shard_paths[3], corrupted).unwrap();
+        let err = rehash_dir(&shards_dir, Verbosity::Quiet).unwrap_err();
+        assert!(err.to_string().contains("shards_3"));
+        assert_eq!(fs::read(&shard_paths[0]).unwrap(), v1_shards[0]);
+
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        assert!(matches!(
+            rehash_dir(&empty, Verbosity::Quiet),
+            Err(Error::NoShardsFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rehash_mnemonic_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = shard_fixture(&shards_dir, b"test", scheme(3, 2));
+        let current = fs::read(&shard_paths[0]).unwrap();
+        // Downgrade the first shard to version 1, then write every shard as words.
+        let (shard_header, body) = header::decode_shard(&current).unwrap();
+        let mut v1_header = shard_header.to_bytes();
+        v1_header.truncate(header::FIXED_HEADER_LEN - 2);
+        v1_header[4] = 1;
+        let mut v1 = Vec::new();
+        header::write_shard(&mut v1, &v1_header, body).unwrap();
+        fs::write(&shard_paths[0], mnemonic::encode(&v1)).unwrap();
+        for shard_path in &shard_paths[1..] {
+            let data = fs::read(shard_path).unwrap();
+            fs::write(shard_path, mnemonic::encode(&data)).unwrap();
+        }
+
+        let shards = rehash_dir(&shards_dir, Verbosity::Quiet).unwrap();
+        let rewritten: Vec<bool> = shards.iter().map(|(_, rewritten)| *rewritten).collect();
+        assert_eq!(rewritten, [true, false, false]);
+        let text = fs::read_to_string(&shard_paths[0]).unwrap();
+        assert_eq!(mnemonic::decode(&text).unwrap(), current);
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    combine::{self, bare_share_x},
+    error::{Error, Result},
+    gf256::GfBackend,
+    header::{self, BlockTags, Header},
+    shamir, sidecar,
+};
+
+/// Regenerates a lost shard of a set from a quorum of the surviving ones, identical to the
+/// lost shard.
+///
+/// The polynomial of every secret byte is interpolated from the surviving shares and
+/// evaluated at the x-coordinate of the lost shard, with `shamir::share_at`. The header of
+/// the new shard is the header of a surviving shard, byte for byte, with the x-coordinate
+/// replaced, so that it keeps the metadata and padding of the set. Headerless shards, read
+/// with the header of their sidecar, are regenerated as bare y-coordinates like the others.
+/// The shard is always written in binary, even if the surviving shards were encoded as words.
+///
+/// Any x-coordinate would give a valid share, so `missing_x` is only known to be that of a
+/// shard of the set when the sidecar of the shards directory lists the x-coordinates of the
+/// set; it must then be one of them.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory containing the surviving shards, or any other input
+///   accepted by `combine_secret`.
+/// * `missing_x` - The x-coordinate of the lost shard, as printed by `info` or listed in the
+///   sidecar.
+/// * `out_path` - The file the regenerated shard is written to; it must not exist.
+/// * `gf_backend` - The GF(2^8) backend used to interpolate.
+///
+/// # Returns
+///
+/// Whether `missing_x` was checked against the x-coordinates listed by the sidecar,
+/// `Error::InvalidParameters` if `out_path` exists, if a surviving shard has the x-coordinate
+/// `missing_x`, it is the x-coordinate of the passphrase share or it is not listed by the
+/// sidecar, or if no shard of the set is missing, the errors of `header::decode_shard` naming an invalid shard,
+/// `Error::InconsistentShards` if the shards belong to different sets, or the errors of
+/// `shamir::share_at`, such as `Error::InsufficientShares` without a quorum.
+///
+/// # Examples
+///
+/// ```
+/// let verified = repair(Path::new("shards"), 42, Path::new("shards/shards_3.sss"), GfBackend::Auto)?;
+/// ```
+pub fn repair(
+    shards_dir: &Path,
+    missing_x: u8,
+    out_path: &Path,
+    gf_backend: GfBackend,
+) -> Result<bool> {
+    let already_exists =
+        || Error::InvalidParameters(format!("{} already exists", out_path.display()));
+    if out_path.exists() {
+        return Err(already_exists());
+    }
+    let sidecar_path = shards_dir.join(sidecar::FILE_NAME);
+    let listed: Vec<u8> = if shards_dir.is_dir() && sidecar_path.is_file() {
+        sidecar::read_manifest(&sidecar_path)?
+            .shards
+            .into_iter()
+            .map(|(_, x)| x)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if !listed.is_empty() && !listed.contains(&missing_x) {
+        return Err(Error::InvalidParameters(format!(
+            "the x-coordinate {} is not that of a shard of the set listed in {}",
+            missing_x,
+            sidecar_path.display()
+        )));
+    }
+
+    let mut shares = Vec::new();
+    let mut first: Option<(String, Header, Vec<u8>)> = None;
+    for (name, data) in combine::read_raw_shards(shards_dir)? {
+        let (shard_header, body) = header::decode_shard(&data).map_err(|e| e.in_shard(&name))?;
+        if shard_header.x == missing_x {
+            return Err(Error::InvalidParameters(format!(
+                "{} already has the x-coordinate {}",
+                name, missing_x
+            )));
+        }
+        match &first {
+            Some((first_name, first_header, _)) => {
+                if !header::ct_eq(
+                    &first_header.set_id.to_be_bytes(),
+                    &shard_header.set_id.to_be_bytes(),
+                ) {
+                    let error = Error::InconsistentShards(
+                        "shard belongs to a different share set".to_string(),
+                    );
+                    return Err(error.in_shard(&name));
+                }
+                combine::check_same_scheme(first_name, first_header, &name, &shard_header)?;
+            }
+            None => {
+                let header_len = data.len() - header::CHECKSUM_LEN - body.len();
+                first = Some((
+                    name.clone(),
+                    shard_header.clone(),
+                    data[..header_len].to_vec(),
+                ));
+            }
+        }
+        let mut share = body.to_vec();
+        share.push(shard_header.x);
+        shares.push(share);
+    }
+    let Some((first_name, set_header, mut header_bytes)) = first else {
+        return Err(Error::NoShardsFound {
+            dir: shards_dir.display().to_string(),
+        });
+    };
+
+    if set_header
+        .passphrase
+        .as_ref()
+        .is_some_and(|passphrase| passphrase.x == missing_x)
+    {
+        return Err(Error::InvalidParameters(format!(
+            "the x-coordinate {} is that of the passphrase share, which is never written",
+            missing_x
+        )));
+    }
+    if shares.len() >= set_header.parts as usize {
+        return Err(Error::InvalidParameters(format!(
+            "all {} shards of the set are present",
+            set_header.parts
+        )));
+    }
+
+    let share = shamir::share_at(
+        &shares,
+        set_header.threshold as usize,
+        missing_x,
+        gf_backend,
+    )?;
+    let body = &share[..share.len() - 1];
+    let shard = if bare_share_x(Path::new(&first_name)).is_some() {
+        body.to_vec()
+    } else {
+        if set_header.block_tags.is_some() {
+            // The block tags depend on the x-coordinate, so they are computed again rather
+            // than copied, padding the header as the one copied was.
+            let padded = header_bytes != set_header.to_bytes();
+            let header = Header {
+                x: missing_x,
+                block_tags: Some(BlockTags::compute(set_header.set_id, missing_x, body)),
+                ..set_header
+            };
+            header_bytes = if padded {
+                header.to_padded_bytes()
+            } else {
+                header.to_bytes()
+            };
+        } else {
+            header_bytes[header::X_OFFSET] = missing_x;
+        }
+        let mut shard = Vec::new();
+        header::write_shard(&mut shard, &header_bytes, body)?;
+        shard
+    };
+    // A file created in the meantime, or a dangling symbolic link, is never written through.
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(out_path)
+    {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(already_exists()),
+        opened => opened?,
+    };
+    if let Err(e) = file.write_all(&shard).and_then(|()| file.sync_all()) {
+        let _ = fs::remove_file(out_path);
+        return Err(e.into());
+    }
+    Ok(!listed.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sharding::SharingConfig, test_support::shard_fixture};
+
+    #[test]
+    fn test_repair_regenerates_identical_shard() {
+        for options in [
+            SharingConfig::builder(),
+            SharingConfig::builder()
+                .uniform_size(true)
+                .embed_hash(true)
+                .content_type(Some("text/plain".to_string())),
+            SharingConfig::builder().headerless(true),
+            SharingConfig::builder().block_checksums(true),
+            SharingConfig::builder()
+                .block_checksums(true)
+                .uniform_size(true),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let shards_dir = dir.path().join("shards");
+            let shard_paths = shard_fixture(&shards_dir, b"repair me", options);
+            let lost_path = shard_paths
+                .iter()
+                .find(|path| path.file_name() != Some("scheme.json".as_ref()))
+                .unwrap();
+            let original = fs::read(lost_path).unwrap();
+            let missing_x = match bare_share_x(lost_path) {
+                Some(x) => x,
+                None => header::decode_shard(&original).unwrap().0.x,
+            };
+            fs::remove_file(lost_path).unwrap();
+
+            let verified = repair(&shards_dir, missing_x, lost_path, GfBackend::Auto).unwrap();
+            assert_eq!(verified, shards_dir.join(sidecar::FILE_NAME).exists());
+            assert_eq!(fs::read(lost_path).unwrap(), original, "{:?}", lost_path);
+
+            // The regenerated shard exists now, and is not regenerated again.
+            let other_path = dir.path().join("again");
+            assert!(matches!(
+                repair(&shards_dir, missing_x, &other_path, GfBackend::Auto),
+                Err(Error::InvalidParameters(_))
+            ));
+            assert!(matches!(
+                repair(&shards_dir, missing_x, lost_path, GfBackend::Auto),
+                Err(Error::InvalidParameters(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_repair_checks_listed_x() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SharingConfig::builder().sidecar(true);
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = shard_fixture(&shards_dir, b"repair me", options);
+        let manifest = sidecar::read_manifest(&shards_dir.join(sidecar::FILE_NAME)).unwrap();
+        let unlisted = (1..=255)
+            .find(|x| manifest.shards.iter().all(|(_, listed)| listed != x))
+            .unwrap();
+        fs::remove_file(&shard_paths[0]).unwrap();
+
+        let out_path = dir.path().join("repaired");
+        let err = repair(&shards_dir, unlisted, &out_path, GfBackend::Auto).unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(e) if e.contains("not that of a shard")));
+        assert!(!out_path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_repair_does_not_follow_dangling_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = shard_fixture(&shards_dir, b"repair me", SharingConfig::builder());
+        let data = fs::read(&shard_paths[0]).unwrap();
+        let missing_x = header::decode_shard(&data).unwrap().0.x;
+        fs::remove_file(&shard_paths[0]).unwrap();
+
+        let target = dir.path().join("target");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(matches!(
+            repair(&shards_dir, missing_x, &link, GfBackend::Auto),
+            Err(Error::InvalidParameters(_))
+        ));
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_repair_without_quorum() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let shard_paths = shard_fixture(&shards_dir, b"repair me", SharingConfig::builder());
+        let xs: Vec<u8> = shard_paths
+            .iter()
+            .map(|path| header::decode_shard(&fs::read(path).unwrap()).unwrap().0.x)
+            .collect();
+        for path in &shard_paths[..3] {
+            fs::remove_file(path).unwrap();
+        }
+
+        let out_path = dir.path().join("repaired");
+        assert!(matches!(
+            repair(&shards_dir, xs[0], &out_path, GfBackend::Auto),
+            Err(Error::InsufficientShares { have: 2, need: 3 })
+        ));
+        assert!(!out_path.exists());
+    }
+}
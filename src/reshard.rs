@@ -0,0 +1,279 @@
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    cli::Verbosity,
+    combine::{recover_secret, CombineOptions},
+    error::{Error, Result},
+    gf256::GfBackend,
+    header,
+    sharding::{shard_bytes, FileMetadata, OverwritePolicy, SharingConfig},
+};
+
+/// Re-splits the secret of a shard set into a new, independent shard set, without writing
+/// the secret to disk.
+///
+/// The number of parts and the threshold default to those recorded in the headers of the
+/// existing shards, so that resharding never silently changes the scheme. The new shards
+/// keep the content-type of the old ones, and embed a hash of the secret if they did. They
+/// carry a new set id, so they cannot be combined with the old shards, which are left
+/// untouched.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory containing the existing shards.
+/// * `new_shards_dir` - The directory where the new shards are written; it must not already
+///   hold shards.
+/// * `parts` - The number of new shards, or `None` to keep the number of the existing set.
+/// * `threshold` - The threshold of the new shards, or `None` to keep the existing one.
+/// * `gf_backend` - The GF(2^8) backend used to combine and split.
+/// * `verbosity` - Controls the progress bars and whether each shard is reported.
+/// * `cancel` - A flag which, once set, stops the operation and removes the new shards
+///   written so far.
+///
+/// # Returns
+///
+/// The paths of the new shards, `Error::InvalidParameters` if the existing shards are bare
+/// shares, whose scheme is unknown, and `parts` or `threshold` is missing, or any error of
+/// `combine_secret` and `shard_secret`.
+///
+/// # Examples
+///
+/// ```
+/// let cancel = AtomicBool::new(false);
+/// let shard_paths = reshard(
+///     Path::new("shards"),
+///     Path::new("new_shards"),
+///     None,
+///     None,
+///     GfBackend::Auto,
+///     Verbosity::Normal,
+///     &cancel,
+/// )?;
+/// ```
+pub fn reshard(
+    shards_dir: &Path,
+    new_shards_dir: &Path,
+    parts: Option<usize>,
+    threshold: Option<usize>,
+    gf_backend: GfBackend,
+    verbosity: Verbosity,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    let combine_options = CombineOptions {
+        gf_backend,
+        verbosity,
+        ..Default::default()
+    };
+    let (secret, set_header) = recover_secret(shards_dir, &combine_options, cancel)?;
+    let secret = Zeroizing::new(secret);
+
+    let set_header = match (set_header, parts, threshold) {
+        (Some(set_header), _, _) => set_header,
+        (None, Some(_), Some(_)) => Default::default(),
+        (None, _, _) => {
+            return Err(Error::InvalidParameters(
+                "the shards have no header, so the parts and threshold of the new shards \
+                 must be given"
+                    .to_string(),
+            ))
+        }
+    };
+    let options = SharingConfig::builder()
+        .parts(parts.unwrap_or(set_header.parts as usize))
+        .threshold(threshold.unwrap_or(set_header.threshold as usize))
+        .embed_hash(set_header.secret_hash.is_some())
+        .content_type(set_header.content_type)
+        .created(set_header.created)
+        .expires(set_header.expires)
+        .file_metadata(set_header.file_name.map(|name| FileMetadata {
+            name,
+            mode: set_header.file_mode,
+            mtime: set_header.file_mtime,
+        }))
+        .overwrite_policy(OverwritePolicy::Fail)
+        .gf_backend(gf_backend)
+        .verbosity(verbosity)
+        .build()?;
+    shard_bytes(&secret, new_shards_dir, &options, cancel)
+}
+
+/// Checks that two shard sets hold the same secret, such as a set and its reshare, without
+/// writing either secret to disk.
+///
+/// Each secret is reconstructed in memory in turn, hashed with SHA-256 and zeroized, and only
+/// the hashes are compared.
+///
+/// # Arguments
+///
+/// * `shards_dir_a` - The directory containing the shards of the first set.
+/// * `shards_dir_b` - The directory containing the shards of the second set.
+/// * `gf_backend` - The GF(2^8) backend used to combine.
+/// * `verbosity` - Controls the progress bars.
+/// * `cancel` - A flag which, once set, stops the operation.
+///
+/// # Returns
+///
+/// Whether the secrets are the same, or any error of `combine_secret` for either set.
+///
+/// # Examples
+///
+/// ```
+/// let cancel = AtomicBool::new(false);
+/// let same = compare_sets(
+///     Path::new("shards"),
+///     Path::new("new_shards"),
+///     GfBackend::Auto,
+///     Verbosity::Normal,
+///     &cancel,
+/// )?;
+/// assert!(same);
+/// ```
+pub fn compare_sets(
+    shards_dir_a: &Path,
+    shards_dir_b: &Path,
+    gf_backend: GfBackend,
+    verbosity: Verbosity,
+    cancel: &AtomicBool,
+) -> Result<bool> {
+    let combine_options = CombineOptions {
+        gf_backend,
+        verbosity,
+        ..Default::default()
+    };
+    let hash = |shards_dir: &Path| -> Result<[u8; 32]> {
+        let secret = Zeroizing::new(recover_secret(shards_dir, &combine_options, cancel)?.0);
+        Ok(Sha256::digest(&*secret).into())
+    };
+    let hash_a = hash(shards_dir_a)?;
+    let hash_b = hash(shards_dir_b)?;
+    Ok(header::ct_eq(&hash_a, &hash_b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        combine::combine_secret,
+        header,
+        test_support::{scheme, shard_fixture},
+    };
+    use std::fs;
+
+    /// Reads the header of every shard.
+    fn headers(shard_paths: &[PathBuf]) -> Vec<header::Header> {
+        shard_paths
+            .iter()
+            .map(|path| header::decode_shard(&fs::read(path).unwrap()).unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_reshard_keeps_scheme() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        let old_paths = shard_fixture(&shards_dir, b"test", scheme(7, 4));
+        let old_set_id = headers(&old_paths)[0].set_id;
+
+        let new_dir = dir.path().join("new");
+        let cancel = AtomicBool::new(false);
+        let shard_paths = reshard(
+            &shards_dir,
+            &new_dir,
+            None,
+            None,
+            GfBackend::Auto,
+            Verbosity::Quiet,
+            &cancel,
+        )
+        .unwrap();
+        assert_eq!(shard_paths.len(), 7);
+        for header in headers(&shard_paths) {
+            assert_eq!((header.threshold, header.parts), (4, 7));
+            assert_ne!(header.set_id, old_set_id);
+        }
+
+        let recovered_path = dir.path().join("recovered");
+        let options = CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        };
+        combine_secret(&new_dir, &recovered_path, &options, &cancel).unwrap();
+        assert_eq!(fs::read(recovered_path).unwrap(), b"test");
+
+        // Resharding again into a directory which already holds shards is refused.
+        let result = reshard(
+            &shards_dir,
+            &new_dir,
+            None,
+            None,
+            GfBackend::Auto,
+            Verbosity::Quiet,
+            &cancel,
+        );
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_compare_sets() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        shard_fixture(&shards_dir, b"test", scheme(5, 3));
+        let new_dir = dir.path().join("new");
+        let cancel = AtomicBool::new(false);
+        reshard(
+            &shards_dir,
+            &new_dir,
+            Some(3),
+            Some(2),
+            GfBackend::Auto,
+            Verbosity::Quiet,
+            &cancel,
+        )
+        .unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        let other_shards_dir = other_dir.path().join("shards");
+        shard_fixture(&other_shards_dir, b"tesT", scheme(5, 3));
+
+        let compare = |a: &Path, b: &Path| {
+            compare_sets(a, b, GfBackend::Auto, Verbosity::Quiet, &cancel).unwrap()
+        };
+        assert!(compare(&shards_dir, &new_dir));
+        assert!(!compare(&shards_dir, &other_shards_dir));
+        assert!(!compare(&other_shards_dir, &new_dir));
+
+        let err = compare_sets(
+            &shards_dir,
+            &dir.path().join("missing"),
+            GfBackend::Auto,
+            Verbosity::Quiet,
+            &cancel,
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_reshard_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_dir = dir.path().join("shards");
+        shard_fixture(&shards_dir, b"test", scheme(7, 4));
+
+        let shard_paths = reshard(
+            &shards_dir,
+            &dir.path().join("new"),
+            None,
+            Some(2),
+            GfBackend::Auto,
+            Verbosity::Quiet,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        for header in headers(&shard_paths) {
+            assert_eq!((header.threshold, header.parts), (2, 7));
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+/// Random number generator drawing the x-coordinates and polynomial coefficients of a split.
+///
+/// ChaCha20 is a cryptographically secure generator whose stream is fully determined by its
+/// 32-byte seed. Every split seeds a new one straight from the operating system's generator,
+/// `OsRng`, instead of relying on the thread-local generator, unless it is given a master
+/// seed to be reproducible, see `seeded_rng`.
+pub type ShareRng = ChaCha20Rng;
+
+/// Master seed of a reproducible split, from which all of its randomness is derived.
+pub type Seed = [u8; 32];
+
+/// Number of bytes drawn from the operating system's generator by `check_os_rng`.
+const CHECK_LEN: usize = 32;
+
+/// Seeds a new `ShareRng` from the operating system's generator.
+///
+/// # Returns
+///
+/// The generator, or `Error::RandomSource` if the operating system's generator is
+/// unavailable.
+pub fn share_rng() -> Result<ShareRng> {
+    ShareRng::from_rng(OsRng).map_err(|e| Error::RandomSource(e.to_string()))
+}
+
+/// Seeds a new `ShareRng` from a master seed, so that a split drawing from it can be
+/// reproduced byte for byte by anyone holding the seed, such as an auditor.
+///
+/// The seed determines every coefficient, so that it and a single share reveal the secret: it
+/// must be drawn at random, kept like the secret, and only its hash, `seed_hash`, recorded.
+///
+/// # Examples
+///
+/// ```
+/// let mut rng = seeded_rng(&[7; 32]);
+/// assert_eq!(rng.next_u64(), seeded_rng(&[7; 32]).next_u64());
+/// ```
+pub fn seeded_rng(seed: &Seed) -> ShareRng {
+    ShareRng::from_seed(*seed)
+}
+
+/// Computes the commitment to a master seed recorded with the shards: its SHA-256 hash, which
+/// lets an auditor check a seed they are given without the shards revealing it.
+pub fn seed_hash(seed: &Seed) -> [u8; 32] {
+    Sha256::digest(seed).into()
+}
+
+/// Checks that the operating system's generator works before anything is shared, so that a
+/// missing or broken random source fails fast instead of weakening the shares.
+///
+/// # Returns
+///
+/// `Error::RandomSource` if no bytes can be drawn from `OsRng`, or if the bytes drawn are
+/// all equal, which a working generator produces with negligible probability.
+pub fn check_os_rng() -> Result<()> {
+    let mut bytes = [0; CHECK_LEN];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|e| Error::RandomSource(e.to_string()))?;
+    if bytes.iter().all(|&b| b == bytes[0]) {
+        return Err(Error::RandomSource(format!(
+            "{} bytes drawn from the system generator are all equal",
+            CHECK_LEN
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::CryptoRng;
+
+    /// Only compiles for generators marked as cryptographically secure.
+    fn assert_crypto_rng<R: RngCore + CryptoRng>(_: &R) {}
+
+    #[test]
+    fn test_share_rng_is_cryptographic() {
+        check_os_rng().unwrap();
+        let mut rng = share_rng().unwrap();
+        assert_crypto_rng(&rng);
+
+        // Two generators seeded from the system do not produce the same stream.
+        let mut other = share_rng().unwrap();
+        assert_ne!(rng.next_u64(), other.next_u64());
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        let mut rng = seeded_rng(&[7; 32]);
+        assert_crypto_rng(&rng);
+        let mut same = seeded_rng(&[7; 32]);
+        let mut other = seeded_rng(&[8; 32]);
+        let first = rng.next_u64();
+        assert_eq!(first, same.next_u64());
+        assert_ne!(first, other.next_u64());
+
+        assert_eq!(
+            seed_hash(&[7; 32]),
+            <[u8; 32]>::from(Sha256::digest([7; 32]))
+        );
+        assert_ne!(seed_hash(&[7; 32]), seed_hash(&[8; 32]));
+    }
+}
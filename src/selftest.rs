@@ -0,0 +1,143 @@
+use crate::{
+    error::{Error, Result},
+    gf256::{GfBackend, GF256},
+    shamir,
+};
+
+/// Elements of GF(2^8) checked by the fast self-test, and used as the third operand of the
+/// associativity and distributivity checks of the exhaustive one: the identities, the
+/// generator, elements with a single bit or all bits set, and a few others.
+const SAMPLE: [u8; 16] = [
+    0, 1, 2, 3, 4, 7, 0x10, 0x1b, 0x53, 0x80, 0x8d, 0xca, 0xe5, 0xf6, 0xfe, 0xff,
+];
+
+/// Secret split and combined back by the self-test.
+const ROUND_TRIP_SECRET: &[u8] = b"shamir self-test round trip secret";
+
+/// Fails the self-test with `message` unless `holds` is true, and counts the check.
+fn check(holds: bool, checks: &mut usize, message: impl FnOnce() -> String) -> Result<()> {
+    *checks += 1;
+    if holds {
+        Ok(())
+    } else {
+        Err(Error::SelfTest(message()))
+    }
+}
+
+/// Checks the field axioms of GF(2^8) for one backend.
+///
+/// Multiplication must be commutative, associative and distribute over addition for every
+/// pair of `elements` with every sampled third operand, division must undo multiplication,
+/// and every nonzero element must have an inverse.
+///
+/// # Arguments
+///
+/// * `backend` - The resolved backend to check.
+/// * `elements` - The elements whose pairs are checked.
+/// * `checks` - The number of checks run so far, incremented by each check.
+fn check_field(backend: GfBackend, elements: &[u8], checks: &mut usize) -> Result<()> {
+    for &a in elements {
+        for &b in elements {
+            let ab = backend.mult(a, b);
+            check(ab == backend.mult(b, a), checks, || {
+                format!("{:?}: {} * {} is not commutative", backend, a, b)
+            })?;
+            check(ab == GF256::mult(a, b), checks, || {
+                format!(
+                    "{:?}: {} * {} differs from the scalar product",
+                    backend, a, b
+                )
+            })?;
+            for &c in &SAMPLE {
+                check(
+                    backend.mult(ab, c) == backend.mult(a, backend.mult(b, c)),
+                    checks,
+                    || format!("{:?}: ({} * {}) * {} is not associative", backend, a, b, c),
+                )?;
+                check(
+                    backend.mult(a, GF256::add(b, c)) == GF256::add(ab, backend.mult(a, c)),
+                    checks,
+                    || format!("{:?}: {} * ({} + {}) does not distribute", backend, a, b, c),
+                )?;
+            }
+            if b != 0 {
+                check(backend.try_div(ab, b) == Some(a), checks, || {
+                    format!("{:?}: {} * {} / {} is not {}", backend, a, b, b, a)
+                })?;
+            }
+        }
+    }
+
+    for a in 1..=255 {
+        let inverse = backend.try_div(1, a);
+        check(
+            inverse.is_some_and(|inverse| backend.mult(a, inverse) == 1),
+            checks,
+            || format!("{:?}: {} has no inverse", backend, a),
+        )?;
+    }
+    check(backend.try_div(1, 0).is_none(), checks, || {
+        format!("{:?}: division by 0 is defined", backend)
+    })
+}
+
+/// Checks that the GF(2^8) arithmetic and the secret sharing work on this machine.
+///
+/// The fast self-test checks the field axioms on a sample of elements, every inverse, and a
+/// split and combination with both backends. The exhaustive self-test checks the field
+/// axioms on all 65536 pairs of elements instead, running about 250 times more checks.
+///
+/// # Arguments
+///
+/// * `exhaustive` - Whether to check every pair of elements rather than a sample.
+///
+/// # Returns
+///
+/// The number of checks run, or `Error::SelfTest` describing the first check that failed.
+///
+/// # Examples
+///
+/// ```
+/// let checks = run_selftest(false)?;
+/// println!("{} checks passed", checks);
+/// ```
+pub fn run_selftest(exhaustive: bool) -> Result<usize> {
+    let all: Vec<u8> = (0..=255).collect();
+    let elements = if exhaustive { &all[..] } else { &SAMPLE[..] };
+
+    let mut checks = 0;
+    for backend in [GfBackend::Scalar, GfBackend::Table] {
+        check_field(backend, elements, &mut checks)?;
+
+        let shares = shamir::split(ROUND_TRIP_SECRET, 5, 3, false, backend, None, false)?;
+        let secret = shamir::combine_checked(shares[2..].to_vec(), 3, backend, None, false)?;
+        check(secret == ROUND_TRIP_SECRET, &mut checks, || {
+            format!(
+                "{:?}: the combined secret differs from the split one",
+                backend
+            )
+        })?;
+    }
+
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes() {
+        let fast = run_selftest(false).unwrap();
+        let exhaustive = run_selftest(true).unwrap();
+        assert!(exhaustive > fast);
+    }
+
+    #[test]
+    fn test_check_reports_failure() {
+        let mut checks = 0;
+        let err = check(false, &mut checks, || "broken".to_string()).unwrap_err();
+        assert!(matches!(err, Error::SelfTest(message) if message == "broken"));
+        assert_eq!(checks, 1);
+    }
+}
@@ -0,0 +1,387 @@
+use colored::*;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    cli::Verbosity,
+    combine::{combine_from_iter, CombineOptions},
+    error::{Error, Result},
+    gf256::GfBackend,
+    sharding::{encode_shards, SharingConfig},
+};
+
+/// Largest frame accepted from a client or sent to it, so that a bogus length or a request
+/// for many shards of a large secret cannot exhaust the memory.
+const MAX_FRAME_LEN: usize = 16 << 20;
+
+/// Most connections served at once, each of which holds a thread and up to two frames.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Request operation splitting the secret it carries into shards.
+const OP_SHARD: u8 = 1;
+/// Request operation reconstructing a secret from the shards it carries.
+const OP_COMBINE: u8 = 2;
+
+/// Status of a response to a successful request; other statuses are exit codes.
+const STATUS_OK: u8 = 0;
+
+/// How often the listener checks whether it was asked to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a client may stay idle, or take to send a request, before it is disconnected,
+/// so that idle clients cannot hold every connection slot.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Serves shard and combine requests on a Unix domain socket until `cancel` is set, so that
+/// services handling many small secrets do not start a process for each of them.
+///
+/// Every message is a frame made of its length, as a 4-byte big-endian integer, followed by
+/// that many bytes. A client may send several requests on a connection, each answered by a
+/// response before the next one is read, and up to `MAX_CONNECTIONS` connections are served
+/// concurrently; further clients get an error response and are disconnected. The socket is
+/// only accessible to its owner.
+///
+/// A request starts with its operation:
+/// - `1`, shard: the number of parts and the threshold, one byte each, then the secret.
+/// - `2`, combine: the shards, each as its 4-byte big-endian length followed by its content.
+///
+/// A response starts with a status byte. On success, it is `0` followed by the shards, in the
+/// same encoding as a combine request, or by the recovered secret. Otherwise, it is the exit
+/// code the CLI would have returned for the error, followed by its message in UTF-8.
+///
+/// # Arguments
+///
+/// * `socket_path` - The path of the socket, which must not exist and is removed on exit.
+/// * `gf_backend` - The GF(2^8) backend used for every request.
+/// * `verbosity` - Controls whether the socket path and failed requests are reported.
+/// * `pool` - The thread pool splitting and combining the secrets of every connection.
+/// * `cancel` - A flag which, once set, stops the server.
+///
+/// # Returns
+///
+/// `Ok(())` once `cancel` is set, or an `Error::Io` if the socket cannot be created.
+///
+/// # Examples
+///
+/// ```
+/// let cancel = Arc::new(AtomicBool::new(false));
+/// let pool = Arc::new(rayon::ThreadPoolBuilder::new().build()?);
+/// serve(Path::new("/run/sss.sock"), GfBackend::Auto, Verbosity::Normal, pool, cancel)?;
+/// ```
+pub fn serve(
+    socket_path: &Path,
+    gf_backend: GfBackend,
+    verbosity: Verbosity,
+    pool: Arc<rayon::ThreadPool>,
+    cancel: Arc<AtomicBool>,
+) -> Result<()> {
+    let listener = bind_private(socket_path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("cannot listen on {}: {}", socket_path.display(), e),
+        )
+    })?;
+    // Polling lets Ctrl-C and --timeout stop the server, which a blocking accept would miss.
+    listener.set_nonblocking(true)?;
+    if verbosity > Verbosity::Quiet {
+        println!(
+            "Listening on {}",
+            socket_path.to_string_lossy().bright_blue()
+        );
+    }
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    while !cancel.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                let Some(slot) = ConnectionSlot::acquire(&connections) else {
+                    let e = Error::Io(io::Error::other(format!(
+                        "too many connections, at most {} are served at once",
+                        MAX_CONNECTIONS
+                    )));
+                    // The client is dropped whether or not it gets the response.
+                    let _ = write_frame(&mut stream, &error_response(&e));
+                    if verbosity == Verbosity::Verbose {
+                        eprintln!("{} {}", "WARNING:".yellow().bold(), e);
+                    }
+                    continue;
+                };
+                let cancel = Arc::clone(&cancel);
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(e) = handle_connection(stream, gf_backend, &pool, &cancel) {
+                        if verbosity == Verbosity::Verbose {
+                            eprintln!("{} {}", "WARNING:".yellow().bold(), e);
+                        }
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => eprintln!("{} {}", "WARNING:".yellow().bold(), e),
+        }
+    }
+    fs::remove_file(socket_path)?;
+    Ok(())
+}
+
+/// Creates the socket at `socket_path` accessible to its owner only.
+///
+/// The socket is created with these permissions rather than changed afterwards, since
+/// another user could connect in between.
+fn bind_private(socket_path: &Path) -> io::Result<UnixListener> {
+    // SAFETY: `umask` only swaps the file mode creation mask of the process. No other thread
+    // creates files while the server starts.
+    let previous = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket_path);
+    // SAFETY: as above, restoring the mask of the process.
+    unsafe { libc::umask(previous) };
+    listener
+}
+
+/// A connection counted against `MAX_CONNECTIONS` until it is dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    /// Counts a new connection, or returns `None` if `MAX_CONNECTIONS` are already served.
+    fn acquire(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < MAX_CONNECTIONS).then_some(count + 1)
+            })
+            .ok()?;
+        Some(ConnectionSlot(Arc::clone(connections)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Answers the requests of a client until it closes the connection, or stays idle for
+/// `IDLE_TIMEOUT`.
+///
+/// The requests are read and the responses written on the thread of the connection, so that
+/// idle or slow clients do not hold threads of `pool`, which only runs the requests.
+fn handle_connection(
+    mut stream: UnixStream,
+    gf_backend: GfBackend,
+    pool: &rayon::ThreadPool,
+    cancel: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(IDLE_TIMEOUT))?;
+    while let Some(request) = read_frame(&mut stream)? {
+        let response = match pool.install(|| handle_request(&request, gf_backend, cancel)) {
+            Ok(payload) => {
+                let mut response = Zeroizing::new(Vec::with_capacity(payload.len() + 1));
+                response.push(STATUS_OK);
+                response.extend_from_slice(&payload);
+                response
+            }
+            Err(e) => error_response(&e),
+        };
+        write_frame(&mut stream, &response)?;
+    }
+    Ok(())
+}
+
+/// Builds the response reporting `e`: its exit code followed by its message.
+fn error_response(e: &Error) -> Zeroizing<Vec<u8>> {
+    let mut response = Zeroizing::new(vec![e.exit_code() as u8]);
+    response.extend_from_slice(e.to_string().as_bytes());
+    response
+}
+
+/// Runs a request and returns the payload of its response, without the status.
+fn handle_request(
+    request: &[u8],
+    gf_backend: GfBackend,
+    cancel: &AtomicBool,
+) -> Result<Zeroizing<Vec<u8>>> {
+    match request.split_first() {
+        Some((&OP_SHARD, [parts, threshold, secret @ ..])) => {
+            let options = SharingConfig::builder()
+                .parts(*parts as usize)
+                .threshold(*threshold as usize)
+                .gf_backend(gf_backend)
+                .verbosity(Verbosity::Quiet)
+                .build()?;
+            // Every shard is at least as long as the secret, so larger responses are refused
+            // before any is built.
+            let too_large = || {
+                Error::InvalidParameters(format!(
+                    "{} shards of a {}-byte secret do not fit in a frame of {} bytes",
+                    parts,
+                    secret.len(),
+                    MAX_FRAME_LEN
+                ))
+            };
+            if (*parts as usize).saturating_mul(secret.len() + 4) >= MAX_FRAME_LEN {
+                return Err(too_large());
+            }
+            let shards = encode_shards(secret, &options, cancel)?;
+            let mut payload = Zeroizing::new(Vec::new());
+            for shard in &shards {
+                push_item(&mut payload, shard)?;
+            }
+            // The headers of the shards may still push the response over the limit.
+            if payload.len() >= MAX_FRAME_LEN {
+                return Err(too_large());
+            }
+            Ok(payload)
+        }
+        Some((&OP_COMBINE, mut items)) => {
+            // Every shard is turned into its share as soon as it is split off the request.
+            let shards = std::iter::from_fn(|| {
+                if items.is_empty() {
+                    return None;
+                }
+                let split = split_item(items);
+                items = split.as_ref().map_or(&[], |(_, rest)| *rest);
+                Some(split.map(|(shard, _)| shard.to_vec()))
+            });
+            let options = CombineOptions {
+                gf_backend,
+                verbosity: Verbosity::Quiet,
+                ..CombineOptions::default()
+            };
+            Ok(Zeroizing::new(combine_from_iter(shards, &options, cancel)?))
+        }
+        Some((&op, _)) => Err(Error::InvalidParameters(format!(
+            "unknown operation {} or truncated request",
+            op
+        ))),
+        None => Err(Error::InvalidParameters("empty request".to_string())),
+    }
+}
+
+/// Reads a frame, or returns `None` if the client closed the connection between frames.
+fn read_frame(stream: &mut UnixStream) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        read => read?,
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes, above the limit of {}",
+                len, MAX_FRAME_LEN
+            ),
+        ));
+    }
+    let mut frame = Zeroizing::new(vec![0; len]);
+    stream.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+/// Writes `payload` as a frame.
+fn write_frame(stream: &mut UnixStream, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "response too large for a frame",
+        )
+    })?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(payload)
+}
+
+/// Appends `item` to a payload, preceded by its length.
+fn push_item(payload: &mut Vec<u8>, item: &[u8]) -> Result<()> {
+    let len = u32::try_from(item.len())
+        .map_err(|_| Error::InvalidParameters("shard too large for a frame".to_string()))?;
+    payload.extend_from_slice(&len.to_be_bytes());
+    payload.extend_from_slice(item);
+    Ok(())
+}
+
+/// Splits the first length-prefixed item off a payload.
+fn split_item(payload: &[u8]) -> Result<(&[u8], &[u8])> {
+    let truncated = || Error::InvalidParameters("truncated shard in request".to_string());
+    let (len, rest) = payload.split_first_chunk::<4>().ok_or_else(truncated)?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    Ok(rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_and_combine_requests() {
+        let cancel = AtomicBool::new(false);
+        let mut request = vec![OP_SHARD, 5, 3];
+        request.extend_from_slice(b"secret");
+        let mut shards = &handle_request(&request, GfBackend::default(), &cancel).unwrap()[..];
+
+        let mut request = vec![OP_COMBINE];
+        for _ in 0..3 {
+            let (shard, rest) = split_item(shards).unwrap();
+            push_item(&mut request, shard).unwrap();
+            shards = rest;
+        }
+        let secret = handle_request(&request, GfBackend::default(), &cancel).unwrap();
+        assert_eq!(&secret[..], b"secret");
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let cancel = AtomicBool::new(false);
+        for request in [&[][..], &[OP_SHARD, 5], &[9], &[OP_COMBINE, 0, 0, 0, 9, 1]] {
+            let err = handle_request(request, GfBackend::default(), &cancel).unwrap_err();
+            assert!(matches!(err, Error::InvalidParameters(_)));
+        }
+        // A response cannot grow past the frame limit.
+        let mut request = vec![OP_SHARD, 255, 2];
+        request.resize(3 + MAX_FRAME_LEN / 255, 0);
+        let err = handle_request(&request, GfBackend::default(), &cancel).unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+        // Too few shards is reported as with files.
+        let err = handle_request(&[OP_COMBINE], GfBackend::default(), &cancel).unwrap_err();
+        assert!(matches!(err, Error::InsufficientShares { .. }));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| ConnectionSlot::acquire(&connections).unwrap())
+            .collect();
+        assert!(ConnectionSlot::acquire(&connections).is_none());
+        drop(slots);
+        assert!(ConnectionSlot::acquire(&connections).is_some());
+    }
+
+    #[test]
+    fn test_socket_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("sss.sock");
+        let _listener = bind_private(&socket_path).unwrap();
+        let mode = fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
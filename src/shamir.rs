@@ -1,13 +1,89 @@
-use crate::{gf256::GF256, polynomial::Polynomial};
+use crate::{
+    error::{Error, Result},
+    gf256::{GfBackend, GF256},
+    header::{self, Header},
+    polynomial::Polynomial,
+    rng,
+};
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::seq::IteratorRandom;
+use rand::{seq::IteratorRandom, CryptoRng, Rng};
 use rayon::prelude::*;
 use std::{
     collections::HashSet,
-    sync::{Arc, Mutex},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-type Shares = Vec<Vec<u8>>;
+/// Number of secret bytes `combine_into` hands to a rayon worker at a time, and combines
+/// between two updates of the progress bar.
+const COMBINE_CHUNK_LEN: usize = 4096;
+
+/// A share as produced by `split`: the y-coordinates followed by the x-coordinate.
+pub type Share = Vec<u8>;
+
+type Shares = Vec<Share>;
+
+/// Whether a set of shards can reconstruct their secret, as reported by `can_reconstruct`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconstructStatus {
+    /// At least as many distinct shards as the threshold are present.
+    Reconstructable,
+    /// The shards agree with each other, but `have` distinct shards are present out of the
+    /// `need` required.
+    Insufficient { have: usize, need: usize },
+    /// The shards belong to different sets, disagree on the scheme parameters, or declare
+    /// parameters which `split` never produces.
+    Inconsistent,
+}
+
+/// Fields of a shard which passed `validate_shard`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardInfo {
+    /// The version of the shard file format the shard was written with.
+    pub version: u8,
+    /// The parsed header of the shard.
+    pub header: Header,
+}
+
+/// Checks the structural integrity of a single shard, without any other shard of its set.
+///
+/// The magic, version, metadata, length and checksum of the shard are verified, along with
+/// scheme parameters that `split` could have produced: a threshold of at least 2 and at most
+/// the number of parts, and a nonzero x-coordinate.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw content of the shard file.
+///
+/// # Returns
+///
+/// The `ShardInfo` of the shard, `Error::InvalidHeader` if its header, length or parameters
+/// are invalid, or `Error::ChecksumMismatch` if its checksum does not match.
+///
+/// # Examples
+///
+/// ```
+/// let shard_info = validate_shard(&fs::read("shards/shards_0.sss")?)?;
+/// println!("x = {}", shard_info.header.x);
+/// ```
+pub fn validate_shard(bytes: &[u8]) -> Result<ShardInfo> {
+    let (header, _) = header::decode_shard(bytes)?;
+    if header.threshold < 2 || header.threshold > header.parts {
+        return Err(Error::InvalidHeader(format!(
+            "threshold {} is invalid for {} parts",
+            header.threshold, header.parts
+        )));
+    }
+    if header.x == 0 {
+        return Err(Error::InvalidHeader(
+            "x-coordinate 0 would hold the secret itself".to_string(),
+        ));
+    }
+
+    Ok(ShardInfo {
+        version: bytes[header::MAGIC.len()],
+        header,
+    })
+}
 
 /// Interpolates a polynomial at a given x-coordinate using Lagrange interpolation
 /// in the finite field GF(2^8).
@@ -17,38 +93,194 @@ type Shares = Vec<Vec<u8>>;
 /// * `x_samples` - A slice of x-coordinates from the shares.
 /// * `y_samples` - A slice of y-coordinates from the shares.
 /// * `x` - The x-coordinate at which to interpolate the polynomial.
+/// * `backend` - The resolved GF(2^8) backend to use.
 ///
 /// # Returns
 ///
-/// The interpolated value at the given x-coordinate.
+/// The interpolated value at the given x-coordinate, or `Error::DuplicateShare` if two
+/// samples share an x-coordinate, which would divide by zero.
+///
+/// Two samples, as combined for the common 2-of-N schemes, define a line, which is
+/// interpolated by `interpolate_line` without the nested loop over the samples.
 ///
 /// # Examples
 ///
 /// ```
 /// let x_samples = vec![1, 2, 3];
 /// let y_samples = vec![5, 8, 15]; // for a polynomial like f(x) = 2x + 3
-/// let y_at_4 = interpolate_polynomial(&x_samples, &y_samples, 4);
+/// let y_at_4 = interpolate_polynomial(&x_samples, &y_samples, 4, GfBackend::Scalar)?;
 /// // Assuming GF(2^8) arithmetic, the result would be the evaluation at x = 4.
 /// ```
-fn interpolate_polynomial(x_samples: &[u8], y_samples: &[u8], x: u8) -> u8 {
-    x_samples
+fn interpolate_polynomial(
+    x_samples: &[u8],
+    y_samples: &[u8],
+    x: u8,
+    backend: GfBackend,
+) -> Result<u8> {
+    match (x_samples, y_samples) {
+        (&[x0, x1], &[y0, y1]) => interpolate_line([x0, x1], [y0, y1], x, backend),
+        _ => interpolate_lagrange(x_samples, y_samples, x, backend),
+    }
+}
+
+/// Interpolates the line through two samples at a given x-coordinate, with the same result
+/// and errors as `interpolate_lagrange`.
+///
+/// Since addition and subtraction are the same in GF(2^8), the line through `(x0, y0)` and
+/// `(x1, y1)` is `y0 * (x + x1) / (x0 + x1) + y1 * (x + x0) / (x0 + x1)`.
+fn interpolate_line(
+    x_samples: [u8; 2],
+    y_samples: [u8; 2],
+    x: u8,
+    backend: GfBackend,
+) -> Result<u8> {
+    let [x0, x1] = x_samples;
+    let [y0, y1] = y_samples;
+    let denom = GF256::add(x0, x1);
+    let basis0 = backend
+        .try_div(GF256::add(x, x1), denom)
+        .ok_or(Error::DuplicateShare { x: x0 })?;
+    let basis1 = backend
+        .try_div(GF256::add(x, x0), denom)
+        .ok_or(Error::DuplicateShare { x: x0 })?;
+    Ok(GF256::add(
+        backend.mult(y0, basis0),
+        backend.mult(y1, basis1),
+    ))
+}
+
+/// Interpolates a polynomial at a given x-coordinate from any number of samples, computing
+/// every Lagrange basis polynomial in turn; see `interpolate_polynomial`.
+fn interpolate_lagrange(
+    x_samples: &[u8],
+    y_samples: &[u8],
+    x: u8,
+    backend: GfBackend,
+) -> Result<u8> {
+    x_samples.iter().enumerate().try_fold(0, |acc, (i, &xi)| {
+        let basis = x_samples
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| i != j)
+            .try_fold(1, |basis, (_, &xj)| -> Result<u8> {
+                let num = GF256::add(x, xj);
+                let denom = GF256::add(xi, xj);
+                let term = backend
+                    .try_div(num, denom)
+                    .ok_or(Error::DuplicateShare { x: xi })?;
+                Ok(backend.mult(basis, term))
+            })?;
+
+        Ok(GF256::add(acc, backend.mult(y_samples[i], basis)))
+    })
+}
+
+/// Recovers every coefficient of the polynomials used to split a secret, not just their
+/// intercepts, for debugging and teaching.
+///
+/// Each Lagrange basis polynomial is expanded into its coefficients, so the shares must come
+/// from a single split and number exactly the threshold for the result to be meaningful.
+///
+/// # Arguments
+///
+/// * `shares` - The shares, each made of the y-coordinates followed by the x-coordinate.
+///
+/// # Returns
+///
+/// One polynomial per secret byte, with as many coefficients as there are shares, the first
+/// one being the secret byte.
+#[cfg(test)]
+pub fn recover_polynomials(shares: &[Vec<u8>]) -> Vec<Polynomial> {
+    let x_samples: Vec<u8> = shares.iter().map(|share| share[share.len() - 1]).collect();
+
+    // basis[i] holds the coefficients of the Lagrange basis polynomial of x_samples[i].
+    let basis: Vec<Vec<u8>> = x_samples
         .iter()
         .enumerate()
         .map(|(i, &xi)| {
-            let basis = x_samples
-                .iter()
-                .enumerate()
-                .filter(|&(j, _)| i != j)
-                .map(|(_, &xj)| {
-                    let num = GF256::add(x, xj);
-                    let denom = GF256::add(xi, xj);
-                    GF256::div(num, denom)
-                })
-                .fold(1, GF256::mult);
+            let mut coefficients = vec![1];
+            let mut denom = 1;
+            for (_, &xj) in x_samples.iter().enumerate().filter(|&(j, _)| i != j) {
+                // Multiply by (x + xj), subtraction and addition being the same in GF(2^8).
+                let mut product = vec![0; coefficients.len() + 1];
+                for (degree, &coeff) in coefficients.iter().enumerate() {
+                    product[degree] = GF256::add(product[degree], GF256::mult(coeff, xj));
+                    product[degree + 1] = GF256::add(product[degree + 1], coeff);
+                }
+                coefficients = product;
+                denom = GF256::mult(denom, GF256::add(xi, xj));
+            }
+            coefficients
+                .into_iter()
+                .map(|coeff| GF256::div(coeff, denom))
+                .collect()
+        })
+        .collect();
 
-            GF256::mult(y_samples[i], basis)
+    (0..shares[0].len() - 1)
+        .map(|idx| {
+            let mut coefficients = vec![0; shares.len()];
+            for (share, basis) in shares.iter().zip(&basis) {
+                for (coeff, &term) in coefficients.iter_mut().zip(basis) {
+                    *coeff = GF256::add(*coeff, GF256::mult(share[idx], term));
+                }
+            }
+            Polynomial { coefficients }
         })
-        .fold(0, GF256::add)
+        .collect()
+}
+
+/// Checks whether shards can reconstruct their secret, from their headers alone.
+///
+/// The y-coordinates are never read, so this is cheap even for large secrets. Shards sharing
+/// an x-coordinate count once.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the available shards.
+///
+/// # Returns
+///
+/// `ReconstructStatus::Inconsistent` if the headers disagree on their set id, threshold, parts
+/// or secret length, or declare a threshold below 2 or above parts or an x-coordinate of 0.
+/// Otherwise `Reconstructable` or `Insufficient`; with no headers at all, the threshold is
+/// unknown and the smallest possible one, 2, is reported as needed.
+///
+/// # Examples
+///
+/// ```
+/// let status = can_reconstruct(&[]);
+/// assert_eq!(status, ReconstructStatus::Insufficient { have: 0, need: 2 });
+/// ```
+pub fn can_reconstruct(headers: &[Header]) -> ReconstructStatus {
+    let Some(first) = headers.first() else {
+        return ReconstructStatus::Insufficient { have: 0, need: 2 };
+    };
+
+    let consistent = first.threshold >= 2
+        && first.threshold <= first.parts
+        && headers.iter().all(|header| {
+            header.x != 0
+                && header::ct_eq(&header.set_id.to_be_bytes(), &first.set_id.to_be_bytes())
+                && header.threshold == first.threshold
+                && header.parts == first.parts
+                && header.secret_len == first.secret_len
+        });
+    if !consistent {
+        return ReconstructStatus::Inconsistent;
+    }
+
+    let have = headers
+        .iter()
+        .map(|header| header.x)
+        .collect::<HashSet<_>>()
+        .len();
+    let need = first.threshold as usize;
+    if have >= need {
+        ReconstructStatus::Reconstructable
+    } else {
+        ReconstructStatus::Insufficient { have, need }
+    }
 }
 
 /// Splits a secret into a given number of parts, with a defined threshold of parts
@@ -59,38 +291,185 @@ fn interpolate_polynomial(x_samples: &[u8], y_samples: &[u8], x: u8) -> u8 {
 /// * `secret` - A byte slice representing the secret to be split.
 /// * `parts` - The number of shares to produce.
 /// * `threshold` - The minimum number of shares required to reconstruct the secret.
+/// * `sequential_x` - Whether the x-coordinates are `1..=parts`, in order, instead of drawn
+///   at random.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+/// * `cancel` - An optional flag which, once set, stops the split before the next byte.
+/// * `progress` - Whether to display a progress bar.
 ///
 /// # Returns
 ///
-/// A vector of shares, each of which is a vector of bytes.
+/// A vector of shares, each of which is a vector of bytes, `Error::InvalidParameters` if the
+/// parts are fewer than the threshold or exceed 255, if the threshold is less than 2 or if
+/// the secret is empty, `Error::RandomSource` if the system random number generator is
+/// unavailable, or `Error::Cancelled` if `cancel` was set before the end.
 ///
-/// # Panics
+/// The coefficients, and the x-coordinates unless `sequential_x` is set, are drawn from a
+/// `rng::ShareRng` seeded from the operating system for this split. The generator is owned by
+/// the call, so concurrent splits never share random state.
 ///
-/// The function panics if the parts are fewer than the threshold, exceed 255,
-/// if the threshold is less than 2, exceeds 255, or if the secret is empty.
+/// Sequential x-coordinates make the shares predictable by index, so a lost share can be
+/// recomputed for the same x-coordinate from any `threshold` others, but reveal how many
+/// shares were made and which one a holder has. Since the security of the scheme rests on
+/// the random coefficients, and the x-coordinates are stored in the shares anyway, this
+/// does not make the secret any easier to recover with fewer than `threshold` shares.
 ///
 /// # Examples
 ///
 /// ```
 /// let secret = b"Rust secret".to_vec();
-/// let shares = split(&secret, 5, 3); // split the secret into 5 parts, 3 needed to reconstruct
+/// let shares = split(&secret, 5, 3, false, GfBackend::Auto, None, true)?; // split the secret into 5 parts, 3 needed to reconstruct
 /// // Each share should contain a piece of the secret and an identifier.
 /// ```
-pub fn split(secret: &[u8], parts: usize, threshold: usize) -> Shares {
-    match () {
-        _ if parts < threshold => panic!("parts cannot be less than threshold"),
-        _ if parts > 255 => panic!("parts cannot exceed 255"),
-        _ if threshold < 2 => panic!("threshold must be at least 2"),
-        _ if threshold > 255 => panic!("threshold cannot exceed 255"),
-        _ if secret.is_empty() => panic!("cannot split an empty secret"),
-        _ => (),
+pub fn split(
+    secret: &[u8],
+    parts: usize,
+    threshold: usize,
+    sequential_x: bool,
+    backend: GfBackend,
+    cancel: Option<&AtomicBool>,
+    progress: bool,
+) -> Result<Shares> {
+    check_split(secret, parts, threshold)?;
+    let mut rng = rng::share_rng()?;
+    let x_coordinates = draw_x_coordinates(parts, sequential_x, &mut rng)?;
+    split_with_rng(
+        secret,
+        &x_coordinates,
+        threshold,
+        backend,
+        cancel,
+        progress,
+        &mut rng,
+    )
+}
+
+/// Rejects the parameters of a split that `split` cannot honour with
+/// `Error::InvalidParameters`.
+fn check_split(secret: &[u8], parts: usize, threshold: usize) -> Result<()> {
+    let invalid = match () {
+        _ if parts < threshold => Some("parts cannot be less than threshold"),
+        _ if parts > 255 => Some("parts cannot exceed 255"),
+        _ if threshold < 2 => Some("threshold must be at least 2"),
+        _ if secret.is_empty() => Some("cannot split an empty secret"),
+        _ => None,
+    };
+    match invalid {
+        Some(message) => Err(Error::InvalidParameters(message.to_string())),
+        None => Ok(()),
     }
+}
+
+/// Draws the x-coordinates of the shares of a split.
+///
+/// # Arguments
+///
+/// * `parts` - The number of shares.
+/// * `sequential_x` - Whether the x-coordinates are `1..=parts`, in order, in which case
+///   nothing is drawn from `rng`.
+/// * `rng` - The generator the distinct, nonzero x-coordinates are drawn from otherwise.
+///
+/// # Returns
+///
+/// The x-coordinates, or `Error::InvalidParameters` if `parts` exceeds 255.
+pub fn draw_x_coordinates<R: Rng + CryptoRng>(
+    parts: usize,
+    sequential_x: bool,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    if parts > 255 {
+        return Err(Error::InvalidParameters(
+            "parts cannot exceed 255".to_string(),
+        ));
+    }
+    Ok(if sequential_x {
+        (1..=parts as u8).collect()
+    } else {
+        (1..=255_u8).choose_multiple(rng, parts)
+    })
+}
+
+/// Moves one share of a split to prescribed y-coordinates, such as a share derived from a
+/// passphrase, and updates the other shares to match.
+///
+/// For every byte, `c * x` is added to the polynomial, with `c` chosen so that it takes the
+/// prescribed value at the x-coordinate of `moved`. The intercept, and so the secret, is
+/// unchanged, and since the linear coefficient of the polynomial was uniformly random, so is
+/// the new one: fewer than `threshold` shares still reveal nothing about the secret, unless
+/// they include the prescribed share.
+///
+/// # Arguments
+///
+/// * `shares` - The other shares of the split, updated in place.
+/// * `moved` - The share to move, ending with its nonzero x-coordinate.
+/// * `target` - The y-coordinates `moved` must take, one per secret byte.
+/// * `backend` - The GF(2^8) backend to use.
+pub fn move_share(shares: &mut [Share], moved: &[u8], target: &[u8], backend: GfBackend) {
+    let (&moved_x, moved_y) = moved.split_last().expect("share is non-empty");
+    let backend = backend.resolve(target.len());
+    for (idx, (&y, &target_y)) in moved_y.iter().zip(target).enumerate() {
+        let c = backend
+            .try_div(GF256::add(y, target_y), moved_x)
+            .expect("x-coordinates are nonzero");
+        for share in shares.iter_mut() {
+            let x = *share.last().expect("share is non-empty");
+            share[idx] = GF256::add(share[idx], backend.mult(c, x));
+        }
+    }
+}
+
+/// Splits a secret into one share per x-coordinate, like `split`, drawing the coefficients
+/// from `rng`, byte after byte.
+///
+/// The shares are then fully determined by the state of `rng`: with the x-coordinates drawn
+/// from a generator seeded by `rng::seeded_rng`, and the coefficients from the same one, a
+/// master seed reproduces the whole split.
+///
+/// # Arguments
+///
+/// * `secret` - The secret to split.
+/// * `x_coordinates` - The distinct, nonzero x-coordinates of the shares, such as drawn by
+///   `draw_x_coordinates`.
+/// * `threshold` - The minimum number of shares required to reconstruct the secret.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+/// * `cancel` - An optional flag which, once set, stops the split before the next byte.
+/// * `progress` - Whether to display a progress bar.
+/// * `rng` - The generator the coefficients are drawn from.
+///
+/// # Returns
+///
+/// The shares, in the order of `x_coordinates`, or the errors of `split`.
+pub fn split_with_rng<R: Rng + CryptoRng>(
+    secret: &[u8],
+    x_coordinates: &[u8],
+    threshold: usize,
+    backend: GfBackend,
+    cancel: Option<&AtomicBool>,
+    progress: bool,
+    rng: &mut R,
+) -> Result<Shares> {
+    check_split(secret, x_coordinates.len(), threshold)?;
+    let backend = backend.resolve(secret.len());
 
-    let mut rng = rand::thread_rng();
-    let x_coordinates: Vec<u8> = (1..=255_u8).choose_multiple(&mut rng, parts);
+    if let [value] = *secret {
+        // A single byte needs one polynomial and no progress bar, which would cost more to
+        // set up than the split itself. The random draws are the same as below.
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(Error::Cancelled);
+        }
+        let polynomial = Polynomial::for_threshold(value, threshold, rng);
+        return Ok(x_coordinates
+            .iter()
+            .map(|&x| vec![polynomial.evaluate(x, backend), x])
+            .collect());
+    }
 
     // Create a progress bar with the total number of steps equal to the length of the secret
-    let pb = ProgressBar::new((secret.len() - 1) as u64);
+    let pb = if progress {
+        ProgressBar::new((secret.len() - 1) as u64)
+    } else {
+        ProgressBar::hidden()
+    };
     let style = ProgressStyle::with_template(
         "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
     )
@@ -110,16 +489,24 @@ pub fn split(secret: &[u8], parts: usize, threshold: usize) -> Shares {
         .collect();
 
     // Fill shares with evaluated polynomial values
-    secret.iter().enumerate().for_each(|(idx, &value)| {
-        let polynomial = Polynomial::new(value, threshold - 1);
+    let filled = secret.iter().enumerate().try_for_each(|(idx, &value)| {
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(Error::Cancelled);
+        }
+        let polynomial = Polynomial::for_threshold(value, threshold, rng);
         x_coordinates.iter().enumerate().for_each(|(i, &x)| {
-            shares[i][idx] = polynomial.evaluate(x);
+            shares[i][idx] = polynomial.evaluate(x, backend);
         });
         pb.inc(1);
+        Ok(())
     });
+    if let Err(e) = filled {
+        pb.abandon();
+        return Err(e);
+    }
 
     pb.finish_with_message("Sharding complete");
-    shares
+    Ok(shares)
 }
 
 /// Combines shares to reconstruct a secret using Shamir's Secret Sharing scheme.
@@ -127,53 +514,123 @@ pub fn split(secret: &[u8], parts: usize, threshold: usize) -> Shares {
 /// # Arguments
 ///
 /// * `parts` - A vector of shares where each share is a vector of bytes.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+/// * `cancel` - An optional flag which, once set, stops the combination before the next byte.
+/// * `progress` - Whether to display a progress bar.
 ///
 /// # Returns
 ///
-/// A vector of bytes representing the reconstructed secret.
+/// A vector of bytes representing the reconstructed secret, `Error::InsufficientShares` if
+/// less than two shares are provided, `Error::InconsistentShards` if the shares are not all
+/// the same length and at least two bytes long, `Error::DuplicateShare` if two shares have
+/// the same x-coordinate, or `Error::Cancelled` if `cancel` was set before the end.
 ///
-/// # Panics
+/// # Examples
+///
+/// ```
+/// let shares = split(&b"Rust secret".to_vec(), 5, 3, false, GfBackend::Auto, None, true)?; // Assuming `split` was successful
+/// let reconstructed_secret = combine(shares, GfBackend::Auto, None, true)?; // Combine the shares to reconstruct the secret
+/// assert_eq!(reconstructed_secret, b"Rust secret".to_vec());
+/// ```
+pub fn combine(
+    parts: Shares,
+    backend: GfBackend,
+    cancel: Option<&AtomicBool>,
+    progress: bool,
+) -> Result<Vec<u8>> {
+    let mut secret = vec![0; parts.first().map_or(0, |part| part.len().saturating_sub(1))];
+    let secret_len = combine_into(&parts, &mut secret, backend, cancel, progress)?;
+    secret.truncate(secret_len);
+    Ok(secret)
+}
+
+/// Combines shares to reconstruct a secret into a caller-provided buffer, without allocating
+/// the secret.
+///
+/// # Arguments
+///
+/// * `parts` - The shares, each ending with its x-coordinate.
+/// * `out` - The buffer receiving the secret; it must hold at least one byte less than a share.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+/// * `cancel` - An optional flag which, once set, stops the combination before the next byte.
+/// * `progress` - Whether to display a progress bar.
+///
+/// # Returns
 ///
-/// The function panics if less than two shares are provided, if all shares
-/// are not the same length, at least two bytes long, or if duplicate shares are detected.
+/// The number of bytes of the secret written at the start of `out`, the errors of `combine`,
+/// or `Error::InvalidParameters` if `out` is too small to hold the secret. The content of `out`
+/// is unspecified after an error.
 ///
 /// # Examples
 ///
 /// ```
-/// let shares = split(&b"Rust secret".to_vec(), 5, 3); // Assuming `split` was successful
-/// let reconstructed_secret = combine(shares); // Combine the shares to reconstruct the secret
-/// assert_eq!(reconstructed_secret, b"Rust secret".to_vec());
+/// let shares = split(b"Rust secret", 5, 3, false, GfBackend::Auto, None, false)?;
+/// let mut buffer = [0; 64];
+/// let len = combine_into(&shares, &mut buffer, GfBackend::Auto, None, false)?;
+/// assert_eq!(&buffer[..len], b"Rust secret");
 /// ```
-pub fn combine(parts: Shares) -> Vec<u8> {
+pub fn combine_into(
+    parts: &[Share],
+    out: &mut [u8],
+    backend: GfBackend,
+    cancel: Option<&AtomicBool>,
+    progress: bool,
+) -> Result<usize> {
     let parts_len = parts.len();
     if parts_len < 2 {
-        panic!("less than two parts cannot be used to reconstruct the secret");
+        return Err(Error::InsufficientShares {
+            have: parts_len,
+            need: 2,
+        });
     }
 
     // Ensure all parts are the same length and have at least two bytes
-    let first_part_len = parts.get(0).map_or(0, Vec::len);
+    let first_part_len = parts.first().map_or(0, Vec::len);
     if first_part_len < 2 || parts.iter().any(|part| part.len() != first_part_len) {
-        panic!("all parts must be at least two bytes and the same length");
+        return Err(Error::InconsistentShards(
+            "all parts must be at least two bytes and the same length".to_string(),
+        ));
     }
 
     // Create a hash set to check for duplicate x-coordinates
     let mut check_map = HashSet::new();
     let x_samples: Vec<u8> = parts
         .iter()
-        .map(|part| {
-            let x = *part.last().expect("part is non-empty");
-            if !check_map.insert(x) {
-                panic!("duplicate part detected");
-            }
-            x
-        })
+        .map(|part| *part.last().expect("part is non-empty"))
         .collect();
+    if let Some(&x) = x_samples.iter().find(|&&x| !check_map.insert(x)) {
+        return Err(Error::DuplicateShare { x });
+    }
 
-    // Initialize the secret vector
-    let mut secret = vec![0; first_part_len - 1];
+    // The secret is written to the start of the output buffer
+    let secret_len = first_part_len - 1;
+    if out.len() < secret_len {
+        return Err(Error::InvalidParameters(format!(
+            "output buffer holds {} bytes but the secret is {} bytes long",
+            out.len(),
+            secret_len
+        )));
+    }
+    let secret = &mut out[..secret_len];
+    let backend = backend.resolve(secret.len());
+
+    if first_part_len == 2 {
+        // A single byte is interpolated directly, without the progress bar and the thread
+        // pool, whose setup dominates the combination.
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(Error::Cancelled);
+        }
+        let y_samples: Vec<u8> = parts.iter().map(|part| part[0]).collect();
+        secret[0] = interpolate_polynomial(&x_samples, &y_samples, 0, backend)?;
+        return Ok(secret_len);
+    }
 
     // Create a progress bar with the total number of steps equal to the length of the secret
-    let pb = ProgressBar::new((secret.len() - 1) as u64);
+    let pb = if progress {
+        ProgressBar::new((secret.len() - 1) as u64)
+    } else {
+        ProgressBar::hidden()
+    };
     let style = ProgressStyle::with_template(
         "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
     )
@@ -182,54 +639,380 @@ pub fn combine(parts: Shares) -> Vec<u8> {
     // Set the progress characters
     pb.set_style(style.progress_chars("#>-"));
 
-    // Arc and Mutex to safely share the progress bar between threads
-    let pb_thread = Arc::new(Mutex::new(pb));
-
     // Parallelization with rayon
-    // Interpolate the polynomial at 0 for each byte of the secret
-    secret
-        .par_iter_mut()
+    // Interpolate the polynomial at 0 for each byte of the secret, a chunk at a time so that
+    // the workers update the progress bar once per chunk rather than contending on it for
+    // every byte. Every worker refills its own buffer of y-coordinates rather than allocating
+    // one per byte.
+    let filled = secret
+        .par_chunks_mut(COMBINE_CHUNK_LEN)
         .enumerate()
-        .for_each(|(idx, secret_byte)| {
-            let y_samples: Vec<u8> = parts.iter().map(|part| part[idx]).collect();
-            *secret_byte = interpolate_polynomial(&x_samples, &y_samples, 0);
-
-            // Incr Progress bar like thread-safe
-            let pb_i = pb_thread.lock().unwrap();
-            pb_i.inc(1);
-            drop(pb_i); // Release the lock immediately after use
+        .try_for_each_init(
+            || Vec::with_capacity(parts.len()),
+            |y_samples, (chunk_index, chunk)| {
+                let start = chunk_index * COMBINE_CHUNK_LEN;
+                for (offset, secret_byte) in chunk.iter_mut().enumerate() {
+                    if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                        return Err(Error::Cancelled);
+                    }
+                    y_samples.clear();
+                    y_samples.extend(parts.iter().map(|part| part[start + offset]));
+                    *secret_byte = interpolate_polynomial(&x_samples, y_samples, 0, backend)?;
+                }
+                pb.inc(chunk.len() as u64);
+                Ok(())
+            },
+        );
+
+    if let Err(e) = filled {
+        pb.abandon();
+        return Err(e);
+    }
+    pb.finish_with_message("Combination complete");
+
+    Ok(secret_len)
+}
+
+/// Reconstructs a secret from exactly the shares with the given x-coordinates, e.g. "use
+/// shares 1, 3 and 5", ignoring the others.
+///
+/// # Arguments
+///
+/// * `shares` - The shares available, each ending with its x-coordinate.
+/// * `xs` - The x-coordinates of the shares to use.
+/// * `threshold` - The minimum number of shares to use, as recorded in the shard headers.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+/// * `cancel` - An optional flag which, once set, stops the combination before the next byte.
+/// * `progress` - Whether to display a progress bar.
+///
+/// # Returns
+///
+/// The reconstructed secret, `Error::DuplicateShare` if an x-coordinate is requested twice,
+/// `Error::InvalidParameters` if no share has a requested x-coordinate,
+/// `Error::InsufficientShares` if fewer x-coordinates than `threshold` are requested, or any
+/// error of `combine`.
+///
+/// # Examples
+///
+/// ```
+/// let shares = split(b"Rust secret", 5, 3, false, GfBackend::Auto, None, false)?;
+/// let xs: Vec<u8> = shares.iter().step_by(2).map(|share| *share.last().unwrap()).collect();
+/// let secret = combine_subset(&shares, &xs, 3, GfBackend::Auto, None, false)?;
+/// assert_eq!(secret, b"Rust secret");
+/// ```
+pub fn combine_subset(
+    shares: &[Share],
+    xs: &[u8],
+    threshold: usize,
+    backend: GfBackend,
+    cancel: Option<&AtomicBool>,
+    progress: bool,
+) -> Result<Vec<u8>> {
+    let mut requested = HashSet::new();
+    let selected = xs
+        .iter()
+        .map(|&x| {
+            if !requested.insert(x) {
+                return Err(Error::DuplicateShare { x });
+            }
+            shares
+                .iter()
+                .find(|share| share.last() == Some(&x))
+                .cloned()
+                .ok_or_else(|| {
+                    Error::InvalidParameters(format!("no share has the x-coordinate {}", x))
+                })
+        })
+        .collect::<Result<Shares>>()?;
+
+    combine_checked(selected, threshold, backend, cancel, progress)
+}
+
+/// Combines shares to reconstruct a secret like `combine`, refusing to do so with fewer
+/// distinct shares than the threshold of the split.
+///
+/// `combine` cannot tell how many shares the secret was split for, so fewer than the
+/// threshold silently yield a wrong secret. Callers who know the threshold should use this
+/// function instead.
+///
+/// # Arguments
+///
+/// * `parts` - The shares, each ending with its x-coordinate.
+/// * `threshold` - The number of shares required to reconstruct the secret.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+/// * `cancel` - An optional flag which, once set, stops the combination before the next byte.
+/// * `progress` - Whether to display a progress bar.
+///
+/// # Returns
+///
+/// The reconstructed secret, `Error::InsufficientShares` if fewer than `threshold` shares
+/// have distinct x-coordinates, or any error of `combine`.
+///
+/// # Examples
+///
+/// ```
+/// let shares = split(b"Rust secret", 5, 3, false, GfBackend::Auto, None, false)?;
+/// let secret = combine_checked(shares[..3].to_vec(), 3, GfBackend::Auto, None, false)?;
+/// assert_eq!(secret, b"Rust secret");
+/// assert!(combine_checked(shares[..2].to_vec(), 3, GfBackend::Auto, None, false).is_err());
+/// ```
+pub fn combine_checked(
+    parts: Shares,
+    threshold: usize,
+    backend: GfBackend,
+    cancel: Option<&AtomicBool>,
+    progress: bool,
+) -> Result<Vec<u8>> {
+    let distinct_x: HashSet<u8> = parts
+        .iter()
+        .filter_map(|part| part.last().copied())
+        .collect();
+    if distinct_x.len() < threshold {
+        return Err(Error::InsufficientShares {
+            have: distinct_x.len(),
+            need: threshold,
         });
+    }
+
+    combine(parts, backend, cancel, progress)
+}
+
+/// A share of which only some y-coordinates survived, for `combine_partial`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialShare {
+    /// The x-coordinate of the share.
+    pub x: u8,
+    /// The y-coordinate for every secret byte, or `None` where it was lost.
+    pub ys: Vec<Option<u8>>,
+}
+
+/// Reconstructs as many bytes of a secret as possible from damaged shares, for forensic
+/// recovery.
+///
+/// Every byte is interpolated from the first `threshold` shares holding a y-coordinate for
+/// it, and bytes with fewer surviving y-coordinates are set to `sentinel`. This is a best
+/// effort outside of the Shamir scheme: a wrong but present y-coordinate yields a wrong byte
+/// without any error, and which bytes are recovered tells which positions the shards lost.
+///
+/// # Arguments
+///
+/// * `parts` - The shares, as long as the secret or shorter if their end was lost.
+/// * `threshold` - The number of y-coordinates needed for a byte, as recorded in the shards.
+/// * `sentinel` - The value of the bytes which cannot be recovered.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+///
+/// # Returns
+///
+/// The secret, as long as the longest share, and whether each of its bytes was recovered,
+/// `Error::InvalidParameters` if `threshold` is below 2, or `Error::DuplicateShare` if two
+/// shares have the same x-coordinate.
+///
+/// # Examples
+///
+/// ```
+/// let (secret, recovered) = combine_partial(&damaged, 3, b'?', GfBackend::Auto)?;
+/// let lost = recovered.iter().filter(|&&recovered| !recovered).count();
+/// ```
+pub fn combine_partial(
+    parts: &[PartialShare],
+    threshold: usize,
+    sentinel: u8,
+    backend: GfBackend,
+) -> Result<(Vec<u8>, Vec<bool>)> {
+    if threshold < 2 {
+        return Err(Error::InvalidParameters(
+            "threshold must be at least 2".to_string(),
+        ));
+    }
+    let mut seen = HashSet::new();
+    if let Some(part) = parts.iter().find(|part| !seen.insert(part.x)) {
+        return Err(Error::DuplicateShare { x: part.x });
+    }
 
-    Arc::try_unwrap(pb_thread)
-        .expect("PB still has multiple owners")
-        .into_inner()
-        .expect("Mutex cannot be locked")
-        .finish_with_message("Combination complete");
+    let secret_len = parts.iter().map(|part| part.ys.len()).max().unwrap_or(0);
+    let backend = backend.resolve(secret_len);
+    let mut secret = vec![sentinel; secret_len];
+    let mut recovered = vec![false; secret_len];
+    let mut x_samples = Vec::with_capacity(threshold);
+    let mut y_samples = Vec::with_capacity(threshold);
+    for idx in 0..secret_len {
+        x_samples.clear();
+        y_samples.clear();
+        for (x, y) in parts
+            .iter()
+            .filter_map(|part| Some((part.x, (*part.ys.get(idx)?)?)))
+            .take(threshold)
+        {
+            x_samples.push(x);
+            y_samples.push(y);
+        }
+        if x_samples.len() == threshold {
+            secret[idx] = interpolate_polynomial(&x_samples, &y_samples, 0, backend)?;
+            recovered[idx] = true;
+        }
+    }
+    Ok((secret, recovered))
+}
 
-    secret
+/// Recomputes the share a split gave, or would have given, another x-coordinate, such as to
+/// replace a lost shard with an identical one.
+///
+/// The polynomial of every byte is interpolated from the first `threshold` shares and
+/// evaluated at `x`. The shares beyond those must lie on the same polynomials, which catches
+/// a corrupted share or a wrong threshold instead of recomputing a wrong share.
+///
+/// # Arguments
+///
+/// * `parts` - The shares, each ending with its x-coordinate.
+/// * `threshold` - The number of shares required to reconstruct the secret.
+/// * `x` - The x-coordinate of the share to recompute.
+/// * `backend` - The GF(2^8) backend to use; `GfBackend::Auto` picks one from the secret length.
+///
+/// # Returns
+///
+/// The share, ending with `x`, `Error::InvalidParameters` if `x` is 0 or already the
+/// x-coordinate of a share, or if `threshold` is below 2, `Error::InsufficientShares` if
+/// there are fewer than `threshold` shares, `Error::DuplicateShare` if two shares have the
+/// same x-coordinate, or `Error::InconsistentShards` if the shares are not all the same
+/// length and at least two bytes long, or do not lie on the same polynomials.
+///
+/// # Examples
+///
+/// ```
+/// let shares = split(b"Rust secret", 5, 3, false, GfBackend::Auto, None, false)?;
+/// let x = *shares[4].last().unwrap();
+/// assert_eq!(share_at(&shares[..4], 3, x, GfBackend::Auto)?, shares[4]);
+/// ```
+pub fn share_at(parts: &[Share], threshold: usize, x: u8, backend: GfBackend) -> Result<Share> {
+    let invalid = match () {
+        _ if x == 0 => Some("x-coordinate 0 would hold the secret itself"),
+        _ if threshold < 2 => Some("threshold must be at least 2"),
+        _ => None,
+    };
+    if let Some(message) = invalid {
+        return Err(Error::InvalidParameters(message.to_string()));
+    }
+    let share_len = parts.first().map_or(0, Vec::len);
+    if parts.len() < threshold {
+        return Err(Error::InsufficientShares {
+            have: parts.len(),
+            need: threshold,
+        });
+    }
+    if share_len < 2 || parts.iter().any(|part| part.len() != share_len) {
+        return Err(Error::InconsistentShards(
+            "all parts must be at least two bytes and the same length".to_string(),
+        ));
+    }
+    let x_samples: Vec<u8> = parts
+        .iter()
+        .map(|part| *part.last().expect("part is non-empty"))
+        .collect();
+    let mut seen = HashSet::new();
+    if let Some(&duplicate) = x_samples.iter().find(|&&x| !seen.insert(x)) {
+        return Err(Error::DuplicateShare { x: duplicate });
+    }
+    if seen.contains(&x) {
+        return Err(Error::InvalidParameters(format!(
+            "a share already has the x-coordinate {}",
+            x
+        )));
+    }
+
+    let backend = backend.resolve(share_len - 1);
+    let (quorum_x, other_x) = x_samples.split_at(threshold);
+    let mut y_samples = Vec::with_capacity(threshold);
+    let mut share = Vec::with_capacity(share_len);
+    for idx in 0..share_len - 1 {
+        y_samples.clear();
+        y_samples.extend(parts[..threshold].iter().map(|part| part[idx]));
+        share.push(interpolate_polynomial(quorum_x, &y_samples, x, backend)?);
+        for (other, &other_x) in parts[threshold..].iter().zip(other_x) {
+            if interpolate_polynomial(quorum_x, &y_samples, other_x, backend)? != other[idx] {
+                return Err(Error::InconsistentShards(format!(
+                    "the shares do not lie on polynomials of degree {}, so one of them is \
+                     corrupted or the threshold is wrong",
+                    threshold - 1
+                )));
+            }
+        }
+    }
+    share.push(x);
+    Ok(share)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Only compiles for types which can be moved and shared across threads.
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_public_types_are_send_sync() {
+        assert_send_sync::<Error>();
+        assert_send_sync::<GfBackend>();
+        assert_send_sync::<Header>();
+        assert_send_sync::<Polynomial>();
+        assert_send_sync::<ReconstructStatus>();
+        assert_send_sync::<ShardInfo>();
+        assert_send_sync::<rng::ShareRng>();
+        assert_send_sync::<crate::combine::CombineOptions>();
+        assert_send_sync::<crate::sharding::SharingConfig>();
+    }
+
+    #[test]
+    fn test_concurrent_splits() {
+        let secrets: Vec<Vec<u8>> = (0..8_u8).map(|i| vec![i; 64]).collect();
+
+        let shares: Vec<Shares> = std::thread::scope(|scope| {
+            let handles: Vec<_> = secrets
+                .iter()
+                .map(|secret| {
+                    scope.spawn(move || split(secret, 5, 3, false, GfBackend::Table, None, false))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap().unwrap())
+                .collect()
+        });
+
+        for (secret, shares) in secrets.iter().zip(shares) {
+            let recovered = combine(shares[..3].to_vec(), GfBackend::Table, None, false).unwrap();
+            assert_eq!(&recovered, secret);
+        }
+    }
+
     #[test]
-    #[should_panic]
     fn test_split_invalid() {
         let secret = b"test".to_vec();
 
-        let _ = split(&secret, 0, 0);
-        let _ = split(&secret, 2, 3);
-        let _ = split(&secret, 1000, 3);
-        let _ = split(&secret, 10, 1);
-        let _ = split(&[], 3, 2);
+        for (secret, parts, threshold) in [
+            (&secret[..], 0, 0),
+            (&secret[..], 2, 3),
+            (&secret[..], 1000, 3),
+            (&secret[..], 10, 1),
+            (&[][..], 3, 2),
+        ] {
+            let result = split(
+                secret,
+                parts,
+                threshold,
+                false,
+                GfBackend::Auto,
+                None,
+                false,
+            );
+            assert!(matches!(result, Err(Error::InvalidParameters(_))));
+        }
     }
 
     #[test]
     fn test_split() {
         let secret = b"test".to_vec();
 
-        let out = split(&secret, 5, 3);
+        let out = split(&secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
         assert_eq!(out.len(), 5);
 
         out.iter().for_each(|share| {
@@ -238,24 +1021,29 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_combine_invalid() {
-        let _ = combine(vec![]);
+        let result = combine(vec![], GfBackend::Auto, None, false);
+        assert!(matches!(
+            result,
+            Err(Error::InsufficientShares { have: 0, need: 2 })
+        ));
 
         let parts = [b"foo".to_vec(), b"ba".to_vec()];
-        let _ = combine(parts.to_vec());
-
         let short_parts = [b"f".to_vec(), b"b".to_vec()];
-        let _ = combine(short_parts.to_vec());
+        for parts in [parts, short_parts] {
+            let result = combine(parts.to_vec(), GfBackend::Auto, None, false);
+            assert!(matches!(result, Err(Error::InconsistentShards(_))));
+        }
 
         let same_parts = [b"foo".to_vec(), b"foo".to_vec()];
-        let _ = combine(same_parts.to_vec());
+        let result = combine(same_parts.to_vec(), GfBackend::Auto, None, false);
+        assert!(matches!(result, Err(Error::DuplicateShare { x: b'o' })));
     }
 
     #[test]
     fn test_combine() {
         let secret = b"test".to_vec();
-        let out = split(&secret, 5, 3);
+        let out = split(&secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
 
         for i in 0..5 {
             for j in 0..5 {
@@ -267,21 +1055,616 @@ mod tests {
                         continue;
                     }
                     let parts = vec![out[i].clone(), out[j].clone(), out[k].clone()];
-                    let recomb = combine(parts);
+                    let recomb = combine(parts, GfBackend::Auto, None, false).unwrap();
                     assert_eq!(recomb, secret);
                 }
             }
         }
     }
 
+    #[test]
+    fn test_uniform_secrets() {
+        // Zero bytes hit the zero branches of the field arithmetic on every share.
+        for secret in [vec![0; 16], vec![0xff; 16], vec![0], vec![0xff]] {
+            for backend in [GfBackend::Scalar, GfBackend::Table] {
+                let out = split(&secret, 5, 3, false, backend, None, false).unwrap();
+                for quorum in [[0, 1, 2], [0, 2, 4], [1, 3, 4], [2, 3, 4]] {
+                    let parts = quorum.iter().map(|&i| out[i].clone()).collect();
+                    assert_eq!(combine(parts, backend, None, false).unwrap(), secret);
+                }
+                assert_eq!(combine(out, backend, None, false).unwrap(), secret);
+            }
+        }
+    }
+
+    #[test]
+    fn test_move_share() {
+        let mut shares = split(b"test", 4, 3, false, GfBackend::Auto, None, false).unwrap();
+        let moved = shares.pop().unwrap();
+        let target = [9, 8, 7, 6, *moved.last().unwrap()];
+        move_share(&mut shares, &moved, &target[..4], GfBackend::Auto);
+
+        // The moved share takes the target values and still combines with the others.
+        let recomb = combine(
+            vec![shares[0].clone(), shares[2].clone(), target.to_vec()],
+            GfBackend::Auto,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(recomb, b"test");
+        assert_eq!(
+            combine(shares, GfBackend::Auto, None, false).unwrap(),
+            b"test"
+        );
+    }
+
+    #[test]
+    fn test_split_sequential_x() {
+        for secret in [&b"x"[..], b"test"] {
+            let out = split(secret, 7, 3, true, GfBackend::Auto, None, false).unwrap();
+            let xs: Vec<u8> = out.iter().map(|share| *share.last().unwrap()).collect();
+            assert_eq!(xs, (1..=7).collect::<Vec<u8>>());
+            let recomb = combine(out[4..].to_vec(), GfBackend::Auto, None, false).unwrap();
+            assert_eq!(recomb, secret);
+        }
+    }
+
+    /// Guards against a regression making `split` superlinear in the secret length, such as
+    /// reallocating the shares for every byte. Timing depends on the machine's load, so it is
+    /// only run on demand, with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_split_is_linear() {
+        use std::time::{Duration, Instant};
+
+        // The fastest of several runs is the least disturbed by other processes.
+        let fastest_split = |len: usize| {
+            let secret = vec![0xa5; len];
+            (0..5)
+                .map(|_| {
+                    let start = Instant::now();
+                    split(&secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap_or(Duration::ZERO)
+        };
+        let small = fastest_split(100 * 1024);
+        let large = fastest_split(200 * 1024);
+        assert!(
+            large.as_secs_f64() <= 2.5 * small.as_secs_f64(),
+            "splitting twice the data took {:?} instead of about twice {:?}",
+            large,
+            small
+        );
+    }
+
+    #[test]
+    fn test_combine_matches_per_byte_interpolation() {
+        use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+        // Not a multiple of the chunk length, so that the last chunk is shorter.
+        let mut rng = StdRng::seed_from_u64(175);
+        let mut secret = vec![0; 16 * COMBINE_CHUNK_LEN + 3];
+        rng.fill_bytes(&mut secret);
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            let shares = split(&secret, 5, 3, false, backend, None, false).unwrap();
+            let quorum = shares[1..4].to_vec();
+            let x_samples: Vec<u8> = quorum.iter().map(|part| part[secret.len()]).collect();
+            let expected: Vec<u8> = (0..secret.len())
+                .map(|idx| {
+                    let y_samples: Vec<u8> = quorum.iter().map(|part| part[idx]).collect();
+                    interpolate_polynomial(&x_samples, &y_samples, 0, backend).unwrap()
+                })
+                .collect();
+            assert_eq!(expected, secret);
+            assert_eq!(combine(quorum, backend, None, true).unwrap(), expected);
+        }
+    }
+
+    /// Reports the time `combine` takes to reconstruct a 1 MB secret, to compare revisions.
+    /// Timing-based, so only run on demand, with
+    /// `cargo test --release test_combine_1mb -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_combine_1mb() {
+        use std::time::{Duration, Instant};
+
+        let secret = vec![0xa5; 1024 * 1024];
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            let shares = split(&secret, 5, 3, false, backend, None, false).unwrap();
+            // The fastest of several runs is the least disturbed by other processes.
+            let fastest = (0..5)
+                .map(|_| {
+                    let quorum = shares[..3].to_vec();
+                    let start = Instant::now();
+                    let combined = combine(quorum, backend, None, false).unwrap();
+                    let elapsed = start.elapsed();
+                    assert_eq!(combined, secret);
+                    elapsed
+                })
+                .min()
+                .unwrap_or(Duration::ZERO);
+            println!("1 MB secret, {:?} backend: {:?}", backend, fastest);
+        }
+    }
+
+    #[test]
+    fn test_single_byte_secret() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            let x_coordinates = [7, 1, 200, 42, 3];
+            let fast = split_with_rng(
+                &[42],
+                &x_coordinates,
+                3,
+                backend,
+                None,
+                false,
+                &mut StdRng::seed_from_u64(7),
+            )
+            .unwrap();
+            assert!(fast.iter().all(|share| share.len() == 2));
+
+            // The general path, as taken by a secret of two bytes, draws the same coefficients
+            // for the first byte.
+            let general = split_with_rng(
+                &[42, 0],
+                &x_coordinates,
+                3,
+                backend,
+                None,
+                false,
+                &mut StdRng::seed_from_u64(7),
+            )
+            .unwrap();
+            for (fast, general) in fast.iter().zip(&general) {
+                assert_eq!(fast[..], [general[0], general[2]]);
+            }
+
+            assert_eq!(
+                combine(fast[..3].to_vec(), backend, None, false).unwrap(),
+                [42]
+            );
+        }
+
+        let cancel = AtomicBool::new(true);
+        let err = split(&[42], 5, 3, false, GfBackend::Auto, Some(&cancel), false).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+    }
+
+    #[test]
+    fn test_combine_into() {
+        let secret = b"test".to_vec();
+        let shares = split(&secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
+
+        let mut exact = [0; 4];
+        let len = combine_into(&shares, &mut exact, GfBackend::Auto, None, false).unwrap();
+        assert_eq!((len, &exact[..]), (4, &secret[..]));
+
+        // Bytes past the secret are left untouched.
+        let mut oversized = [0xaa; 8];
+        let len = combine_into(&shares, &mut oversized, GfBackend::Auto, None, false).unwrap();
+        assert_eq!(len, 4);
+        assert_eq!(oversized, *b"test\xaa\xaa\xaa\xaa");
+
+        let mut undersized = [0; 3];
+        let err = combine_into(&shares, &mut undersized, GfBackend::Auto, None, false);
+        assert!(matches!(err, Err(Error::InvalidParameters(_))));
+    }
+
+    #[test]
+    fn test_share_at() {
+        let shares = split(b"Rust secret", 5, 3, false, GfBackend::Auto, None, false).unwrap();
+        let lost_x = *shares[1].last().unwrap();
+        let others: Shares = [&shares[..1], &shares[2..]].concat();
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            assert_eq!(share_at(&others, 3, lost_x, backend).unwrap(), shares[1]);
+            assert_eq!(
+                share_at(&others[1..], 3, lost_x, backend).unwrap(),
+                shares[1]
+            );
+        }
+
+        // A share at a new x-coordinate combines with the others.
+        let new_x = (1..=255).find(|x| others.iter().all(|share| share.last() != Some(x)));
+        let new_share = share_at(&others, 3, new_x.unwrap(), GfBackend::Auto).unwrap();
+        let quorum = vec![new_share, others[0].clone(), others[3].clone()];
+        assert_eq!(
+            combine(quorum, GfBackend::Auto, None, false).unwrap(),
+            b"Rust secret"
+        );
+
+        let existing_x = *others[0].last().unwrap();
+        for x in [0, existing_x] {
+            assert!(matches!(
+                share_at(&others, 3, x, GfBackend::Auto),
+                Err(Error::InvalidParameters(_))
+            ));
+        }
+        assert!(matches!(
+            share_at(&others[..2], 3, lost_x, GfBackend::Auto),
+            Err(Error::InsufficientShares { have: 2, need: 3 })
+        ));
+        let mut corrupted = others.clone();
+        corrupted[3][4] ^= 1;
+        assert!(matches!(
+            share_at(&corrupted, 3, lost_x, GfBackend::Auto),
+            Err(Error::InconsistentShards(_))
+        ));
+        // With the wrong threshold, the fourth share does not fit.
+        assert!(matches!(
+            share_at(&others, 2, lost_x, GfBackend::Auto),
+            Err(Error::InconsistentShards(_))
+        ));
+    }
+
+    #[test]
+    fn test_combine_partial() {
+        let secret = b"partial recovery";
+        let shares = split(secret, 4, 3, false, GfBackend::Auto, None, false).unwrap();
+        let mut parts: Vec<PartialShare> = shares
+            .iter()
+            .map(|share| {
+                let (&x, ys) = share.split_last().unwrap();
+                PartialShare {
+                    x,
+                    ys: ys.iter().copied().map(Some).collect(),
+                }
+            })
+            .collect();
+        // One shard lost bytes 2 to 5, and another lost its end.
+        parts[0].ys[2..6].fill(None);
+        parts[1].ys.truncate(12);
+
+        let (recovered_secret, recovered) =
+            combine_partial(&parts[..3], 3, b'?', GfBackend::Auto).unwrap();
+        assert_eq!(recovered_secret, b"pa????l reco????");
+        let lost: Vec<usize> = (0..secret.len()).filter(|&i| !recovered[i]).collect();
+        assert_eq!(lost, [2, 3, 4, 5, 12, 13, 14, 15]);
+
+        // With one more share, every byte has enough y-coordinates.
+        let (recovered_secret, recovered) =
+            combine_partial(&parts, 3, b'?', GfBackend::Auto).unwrap();
+        assert_eq!(recovered_secret, secret);
+        assert!(recovered.iter().all(|&recovered| recovered));
+
+        parts[3].x = parts[0].x;
+        assert!(matches!(
+            combine_partial(&parts, 3, b'?', GfBackend::Auto),
+            Err(Error::DuplicateShare { .. })
+        ));
+    }
+
+    #[test]
+    fn test_combine_subset() {
+        let secret = b"test".to_vec();
+        let shares = split(&secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
+        let x = |i: usize| *shares[i].last().unwrap();
+
+        let full = combine(shares.clone(), GfBackend::Auto, None, false).unwrap();
+        let subset = combine_subset(
+            &shares,
+            &[x(0), x(2), x(4)],
+            3,
+            GfBackend::Auto,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(subset, full);
+        assert_eq!(subset, secret);
+
+        for (xs, expected) in [
+            (vec![x(0), x(2)], "2 shards provided but 3 are required"),
+            (vec![x(0), x(2), x(0)], "duplicate share"),
+        ] {
+            let err = combine_subset(&shares, &xs, 3, GfBackend::Auto, None, false).unwrap_err();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+        let missing = (1..=255).find(|x| !shares.iter().any(|s| s.last() == Some(x)));
+        let err = combine_subset(
+            &shares,
+            &[x(0), x(1), missing.unwrap()],
+            3,
+            GfBackend::Auto,
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let secret = b"test".to_vec();
+        let cancel = AtomicBool::new(true);
+
+        let err = split(&secret, 5, 3, false, GfBackend::Auto, Some(&cancel), false).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+
+        let out = split(&secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
+        let err = combine(out, GfBackend::Auto, Some(&cancel), false).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+    }
+
     #[test]
     fn test_interpolate_rand() {
         for i in 0..255 {
-            let p = Polynomial::new(i, 2);
+            let p = Polynomial::new(i, 2, &mut rand::thread_rng());
             let x_vals = vec![1, 2, 3];
-            let y_vals = vec![p.evaluate(1), p.evaluate(2), p.evaluate(3)];
-            let out = interpolate_polynomial(&x_vals, &y_vals, 0);
+            let y_vals = vec![
+                p.evaluate(1, GfBackend::Scalar),
+                p.evaluate(2, GfBackend::Scalar),
+                p.evaluate(3, GfBackend::Scalar),
+            ];
+            let out = interpolate_polynomial(&x_vals, &y_vals, 0, GfBackend::Scalar).unwrap();
             assert_eq!(out, i);
         }
     }
+
+    #[test]
+    fn test_combine_checked() {
+        let secret = b"checked secret";
+        let shares = split(secret, 5, 3, false, GfBackend::Auto, None, false).unwrap();
+        let recovered =
+            combine_checked(shares[1..4].to_vec(), 3, GfBackend::Auto, None, false).unwrap();
+        assert_eq!(recovered, secret);
+
+        // Two shares interpolate a wrong secret with `combine`, but are refused here.
+        let below = shares[..2].to_vec();
+        assert_ne!(
+            combine(below.clone(), GfBackend::Auto, None, false).unwrap(),
+            secret
+        );
+        let err = combine_checked(below, 3, GfBackend::Auto, None, false).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InsufficientShares { have: 2, need: 3 }
+        ));
+
+        // A repeated share does not count twice.
+        let repeated = vec![shares[0].clone(), shares[1].clone(), shares[0].clone()];
+        let err = combine_checked(repeated, 3, GfBackend::Auto, None, false).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InsufficientShares { have: 2, need: 3 }
+        ));
+    }
+
+    #[test]
+    fn test_interpolate_line_matches_lagrange() {
+        let mut rng = rand::thread_rng();
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            for _ in 0..200 {
+                let x_vals = [rng.gen(), rng.gen()];
+                let y_vals = [rng.gen(), rng.gen()];
+                let x = rng.gen();
+                let line = interpolate_line(x_vals, y_vals, x, backend);
+                let lagrange = interpolate_lagrange(&x_vals, &y_vals, x, backend);
+                match (line, lagrange) {
+                    (Ok(line), Ok(lagrange)) => assert_eq!(line, lagrange),
+                    (Err(Error::DuplicateShare { x: a }), Err(Error::DuplicateShare { x: b })) => {
+                        assert_eq!(a, b)
+                    }
+                    (line, lagrange) => panic!("{:?} differs from {:?}", line, lagrange),
+                }
+            }
+        }
+
+        // Every pair of shares of a 2-of-N split reconstructs the same secret through both
+        // paths.
+        let secret: Vec<u8> = (0..=255).collect();
+        let shares = split(&secret, 4, 2, false, GfBackend::Auto, None, false).unwrap();
+        for (i, a) in shares.iter().enumerate() {
+            for b in &shares[i + 1..] {
+                let x_vals = [a[secret.len()], b[secret.len()]];
+                let general: Vec<u8> = (0..secret.len())
+                    .map(|idx| {
+                        interpolate_lagrange(&x_vals, &[a[idx], b[idx]], 0, GfBackend::Scalar)
+                            .unwrap()
+                    })
+                    .collect();
+                assert_eq!(general, secret);
+                let pair = vec![a.clone(), b.clone()];
+                assert_eq!(combine(pair, GfBackend::Auto, None, false).unwrap(), secret);
+            }
+        }
+        assert!(matches!(
+            interpolate_polynomial(&[3, 3], &[1, 2], 0, GfBackend::Scalar),
+            Err(Error::DuplicateShare { x: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_backends_interoperate() {
+        let secret: Vec<u8> = (0..=255).collect();
+        for (split_backend, combine_backend) in [
+            (GfBackend::Scalar, GfBackend::Table),
+            (GfBackend::Table, GfBackend::Scalar),
+        ] {
+            let out = split(&secret, 5, 3, false, split_backend, None, false).unwrap();
+            let recomb = combine(out[1..4].to_vec(), combine_backend, None, false).unwrap();
+            assert_eq!(recomb, secret);
+        }
+
+        let p = Polynomial::new(42, 4, &mut rand::thread_rng());
+        let x_vals: Vec<u8> = (1..=5).collect();
+        let y_vals: Vec<u8> = x_vals
+            .iter()
+            .map(|&x| p.evaluate(x, GfBackend::Table))
+            .collect();
+        for x in 0..=255 {
+            assert_eq!(
+                interpolate_polynomial(&x_vals, &y_vals, x, GfBackend::Scalar).unwrap(),
+                interpolate_polynomial(&x_vals, &y_vals, x, GfBackend::Table).unwrap()
+            );
+        }
+    }
+
+    /// Builds the header of shard `x` of a 3-of-5 set.
+    fn shard_header(x: u8) -> Header {
+        Header {
+            threshold: 3,
+            parts: 5,
+            x,
+            set_id: 42,
+            secret_len: 4,
+            secret_hash: None,
+            content_type: None,
+            file_name: None,
+            passphrase: None,
+            block_tags: None,
+            created: None,
+            expires: None,
+            file_mode: None,
+            file_mtime: None,
+            seed_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_shard() {
+        let bytes = header::encode_shard(&shard_header(1), b"test");
+        assert_eq!(
+            validate_shard(&bytes).unwrap(),
+            ShardInfo {
+                version: header::VERSION,
+                header: shard_header(1),
+            }
+        );
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(
+            validate_shard(truncated),
+            Err(Error::InvalidHeader(_))
+        ));
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(
+            validate_shard(&wrong_magic),
+            Err(Error::InvalidHeader(_))
+        ));
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            validate_shard(&corrupted),
+            Err(Error::ChecksumMismatch)
+        ));
+
+        let zero_x = header::encode_shard(&shard_header(0), b"test");
+        assert!(matches!(
+            validate_shard(&zero_x),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_can_reconstruct() {
+        let headers: Vec<Header> = (1..=5).map(shard_header).collect();
+        assert_eq!(
+            can_reconstruct(&headers[..3]),
+            ReconstructStatus::Reconstructable
+        );
+        assert_eq!(
+            can_reconstruct(&headers),
+            ReconstructStatus::Reconstructable
+        );
+    }
+
+    #[test]
+    fn test_can_reconstruct_insufficient() {
+        let headers = vec![shard_header(1), shard_header(2), shard_header(2)];
+        assert_eq!(
+            can_reconstruct(&headers),
+            ReconstructStatus::Insufficient { have: 2, need: 3 }
+        );
+        assert_eq!(
+            can_reconstruct(&[]),
+            ReconstructStatus::Insufficient { have: 0, need: 2 }
+        );
+    }
+
+    #[test]
+    fn test_can_reconstruct_inconsistent() {
+        let base: Vec<Header> = (1..=3).map(shard_header).collect();
+        let changes: [fn(&mut Header); 5] = [
+            |header| header.set_id = 7,
+            |header| header.threshold = 2,
+            |header| header.secret_len = 5,
+            |header| header.x = 0,
+            |header| header.parts = 2,
+        ];
+        for change in changes {
+            let mut headers = base.clone();
+            change(&mut headers[2]);
+            assert_eq!(can_reconstruct(&headers), ReconstructStatus::Inconsistent);
+        }
+    }
+
+    #[test]
+    fn test_split_all_255_parts() {
+        let secret = b"test".to_vec();
+        let threshold = 3;
+        let out = split(&secret, 255, threshold, false, GfBackend::Auto, None, false).unwrap();
+        assert_eq!(out.len(), 255);
+
+        let mut x_coordinates: Vec<u8> = out.iter().map(|share| share[secret.len()]).collect();
+        x_coordinates.sort_unstable();
+        assert_eq!(x_coordinates, (1..=255).collect::<Vec<u8>>());
+
+        // Every share takes part in a reconstruction, along with the shares following it.
+        for i in 0..255 {
+            let parts: Shares = (0..threshold).map(|k| out[(i + k) % 255].clone()).collect();
+            let recomb = combine(parts, GfBackend::Auto, None, false).unwrap();
+            assert_eq!(recomb, secret);
+        }
+    }
+
+    #[test]
+    fn test_split_256_parts() {
+        let result = split(b"test", 256, 3, false, GfBackend::Auto, None, false);
+        assert!(matches!(result, Err(Error::InvalidParameters(_))));
+    }
+
+    #[test]
+    fn test_recover_polynomials() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let polynomials: Vec<Polynomial> = (0..8)
+            .map(|_| Polynomial {
+                coefficients: (0..4).map(|_| rng.gen()).collect(),
+            })
+            .collect();
+        let shares: Shares = [3, 17, 99, 254]
+            .into_iter()
+            .map(|x| {
+                let mut share: Vec<u8> = polynomials
+                    .iter()
+                    .map(|p| p.evaluate(x, GfBackend::Scalar))
+                    .collect();
+                share.push(x);
+                share
+            })
+            .collect();
+
+        let recovered = recover_polynomials(&shares);
+        assert_eq!(recovered.len(), polynomials.len());
+        for (recovered, polynomial) in recovered.iter().zip(&polynomials) {
+            assert_eq!(recovered.coefficients, polynomial.coefficients);
+        }
+    }
+
+    #[test]
+    fn test_interpolate_duplicate_x() {
+        for backend in [GfBackend::Scalar, GfBackend::Table] {
+            let result = interpolate_polynomial(&[1, 2, 1], &[5, 8, 5], 0, backend);
+            assert!(matches!(result, Err(Error::DuplicateShare { x: 1 })));
+        }
+    }
 }
@@ -1,66 +1,1534 @@
 use std::{
     fs::{self, File},
-    io::{self, Read, Write},
+    io::{self, BufRead, BufReader, Read},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::shamir;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    cli::Verbosity,
+    columnar, combine,
+    error::{Error, Result},
+    export,
+    gf256::GfBackend,
+    header::{self, BlockTags, Header},
+    mnemonic,
+    passphrase::Passphrase,
+    rng, shamir, sidecar,
+    sink::{BundleSink, ColumnSink, DirectorySink, HeaderlessSink, ShardSink},
+    timing::{self, Timings},
+};
+
+/// Maximum number of shards written at the same time with the `parallel` feature. Writing
+/// is I/O-bound, so a few threads are enough to keep fast storage busy.
+#[cfg(feature = "parallel")]
+const WRITE_THREADS: usize = 8;
+
+/// Name of the manifest written next to the shards, holding the label of the set.
+pub const MANIFEST_FILE: &str = "manifest.txt";
+
+/// Largest label accepted, in bytes; a label is meant for recovery instructions or contact
+/// details, not for data.
+const MAX_LABEL_LEN: u64 = 64 * 1024;
+
+/// What to do with the shards already present in the shards directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum OverwritePolicy {
+    /// Refuse to shard if the directory already holds shards.
+    Fail,
+    /// Write the new shards over the existing files, then remove the rest of the old set.
+    #[default]
+    Overwrite,
+    /// Rename the existing shards with a timestamp suffix before writing the new ones.
+    Backup,
+}
+
+/// How the shards of a set are laid out on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum Layout {
+    /// One file per shard.
+    #[default]
+    Row,
+    /// A single file holding the share matrix column by column, so that byte `i` of every
+    /// shard is contiguous, for storage layers which stripe data.
+    Column,
+}
+
+/// How every shard file is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum Encoding {
+    /// The raw bytes of the shard.
+    #[default]
+    Binary,
+    /// Words of the BIP39 English wordlist with a checksum word, to read aloud or write on
+    /// paper, see `mnemonic::encode`.
+    Mnemonic,
+}
+
+/// Where the shards of a set are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ArgEnum)]
+pub enum OutputFormat {
+    /// Shard files in a shards directory, laid out according to `SharingConfig::layout`.
+    #[default]
+    Files,
+    /// A new bundle file holding the set as its only record.
+    Bundle,
+    /// A JSON file holding the base64 of every shard, see `export::to_json`.
+    Json,
+    /// A text file holding the base64 of every shard, one per line.
+    Base64Lines,
+}
+
+/// The name, permissions and modification time of a secret file, recorded in cleartext in
+/// the shard headers by `shard --store-metadata` so that `combine --restore-metadata` can
+/// recreate the file as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// The name of the file, without its directory.
+    pub name: String,
+    /// The Unix permissions of the file, or `None` outside Unix.
+    pub mode: Option<u32>,
+    /// The modification time of the file, in seconds since the Unix epoch, or `None` if the
+    /// platform does not report it.
+    pub mtime: Option<u64>,
+}
+
+impl FileMetadata {
+    /// Reads the metadata of the secret file at `path`.
+    ///
+    /// # Returns
+    ///
+    /// The metadata, `Error::InvalidParameters` if `path` is not a regular file, such as a
+    /// URL or a pipe, or if its name is not valid UTF-8, or `Error::Io` if it cannot be read.
+    pub fn read(path: &Path) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Error::InvalidParameters(format!(
+                "cannot store the metadata of {}: {}",
+                path.display(),
+                reason
+            ))
+        };
+        if !path.is_file() {
+            return Err(invalid("it is not a regular file"));
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid("its name is not valid UTF-8"))?;
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_secs());
+        Ok(FileMetadata {
+            name: name.to_string(),
+            mode: file_mode(&metadata),
+            mtime,
+        })
+    }
+}
+
+/// Returns the Unix permissions of a file, without its type bits nor the setuid, setgid and
+/// sticky bits, which a recovered secret must never carry.
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+/// Files have no Unix permissions outside Unix.
+#[cfg(not(unix))]
+fn file_mode(_: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Options controlling how a secret is sharded.
+#[derive(Debug, Clone)]
+pub struct SharingConfig {
+    /// The number of shards to create.
+    parts: usize,
+    /// The minimum number of shards required to reconstruct the secret.
+    threshold: usize,
+    /// Whether the shards get the x-coordinates `1..=parts` in order, rather than random
+    /// ones, so that the shard given to a holder can be recomputed. See `shamir::split`.
+    sequential_x: bool,
+    /// Whether to store the SHA-256 hash of the secret in every shard header, so that
+    /// combining can verify the reconstruction.
+    embed_hash: bool,
+    /// Whether to pad every shard header to the same width, so that shard files have the same
+    /// size whatever metadata they carry.
+    uniform_size: bool,
+    /// Whether to store the integrity tag of every block of share data in the shard headers,
+    /// see `header::BlockTags`, so that `combine --stream` detects a corrupted block as soon
+    /// as it reads it.
+    block_checksums: bool,
+    /// The content-type declared for the secret, stored in cleartext in every shard header.
+    content_type: Option<String>,
+    /// When the shards were created, in seconds since the Unix epoch, stored in cleartext in
+    /// every shard header, or `None` not to record it.
+    created: Option<u64>,
+    /// When the shards expire, in seconds since the Unix epoch, stored in cleartext in every
+    /// shard header, or `None` for never.
+    expires: Option<u64>,
+    /// A cleartext description of the secret, written once to the manifest of the shards
+    /// directory instead of to every shard.
+    label: Option<String>,
+    /// The name, permissions and modification time of the secret file, stored in cleartext
+    /// in every shard header and in the sidecar, or `None` not to record them.
+    file_metadata: Option<FileMetadata>,
+    /// The passphrase from which one share is derived instead of being written, so that it
+    /// can stand in for a missing shard, or `None` for none.
+    passphrase: Option<Passphrase>,
+    /// The master seed all the randomness of the split is derived from, so that the same
+    /// seed reproduces the same shards, or `None` to draw it from the operating system. Only
+    /// its hash is stored, in every shard header and in the sidecar.
+    seed: Option<Zeroizing<rng::Seed>>,
+    /// The largest secret accepted, in bytes, or `None` for no limit. A larger secret file is
+    /// rejected before it is read.
+    max_secret_size: Option<u64>,
+    /// The extension of the shard files, without the leading dot; empty for none.
+    extension: String,
+    /// What to do with the shards already present in the shards directory.
+    overwrite_policy: OverwritePolicy,
+    /// Whether to write the bare y-coordinates of every shard to a file named after its
+    /// x-coordinate, with the parameters of the set in a single `sidecar::FILE_NAME` file,
+    /// instead of a header in every shard.
+    headerless: bool,
+    /// Whether to also write the parameters of the set, with the file name and x-coordinate of
+    /// every shard, to a `sidecar::FILE_NAME` file, which `combine --manifest` checks the
+    /// shards against. Headerless shards always have one.
+    sidecar: bool,
+    /// Whether to read the shards back once written and check that every quorum of threshold
+    /// shards reconstructs the secret, with `combine::verify_quorums`.
+    verify_all: bool,
+    /// Whether to write the shards to a shards directory, a bundle or a single text file.
+    output_format: OutputFormat,
+    /// Whether to write one file per shard or a single column layout file.
+    layout: Layout,
+    /// How every shard file is encoded; only shards written as files in the row layout can
+    /// be encoded as words.
+    encoding: Encoding,
+    /// Whether to also write every shard as QR-code PNG images, which requires the `qr`
+    /// feature.
+    qr: bool,
+    /// The GF(2^8) backend used to compute the shares.
+    gf_backend: GfBackend,
+    /// Controls the progress bar and whether each shard written is reported.
+    verbosity: Verbosity,
+    /// Collects the durations of reading the secret, splitting it and writing the shards,
+    /// or `None` not to measure them.
+    timings: Option<Arc<Timings>>,
+}
+
+impl Default for SharingConfig {
+    /// Uses the same defaults as the command line: 5 parts with a threshold of 3.
+    fn default() -> Self {
+        SharingConfig {
+            parts: 5,
+            threshold: 3,
+            sequential_x: false,
+            embed_hash: false,
+            uniform_size: false,
+            block_checksums: false,
+            content_type: None,
+            created: None,
+            expires: None,
+            label: None,
+            file_metadata: None,
+            passphrase: None,
+            seed: None,
+            max_secret_size: None,
+            extension: header::SHARD_EXTENSION.to_string(),
+            overwrite_policy: OverwritePolicy::default(),
+            headerless: false,
+            sidecar: false,
+            verify_all: false,
+            output_format: OutputFormat::default(),
+            layout: Layout::default(),
+            encoding: Encoding::default(),
+            qr: false,
+            gf_backend: GfBackend::default(),
+            verbosity: Verbosity::default(),
+            timings: None,
+        }
+    }
+}
+
+impl SharingConfig {
+    /// Starts building options from the defaults, so that they are checked once by
+    /// `SharingConfigBuilder::build` rather than by every function sharding with them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = SharingConfig::builder()
+    ///     .parts(5)
+    ///     .threshold(3)
+    ///     .output_format(OutputFormat::Base64Lines)
+    ///     .build()?;
+    /// ```
+    pub fn builder() -> SharingConfigBuilder {
+        SharingConfigBuilder(SharingConfig::default())
+    }
+
+    /// Sets the passphrase from which one share is derived on options already built, so that
+    /// it is only prompted for once the other options are known to be valid.
+    ///
+    /// # Returns
+    ///
+    /// The options, or `Error::InvalidParameters` if there is a passphrase and no room for
+    /// its share, as with `SharingConfigBuilder::build`.
+    pub fn with_passphrase(mut self, passphrase: Option<Passphrase>) -> Result<SharingConfig> {
+        self.passphrase = passphrase;
+        check_options(&self)?;
+        Ok(self)
+    }
+}
+
+/// Builds `SharingConfig`, see `SharingConfig::builder`.
+#[derive(Debug, Clone)]
+pub struct SharingConfigBuilder(SharingConfig);
+
+impl SharingConfigBuilder {
+    /// Sets the number of shards to create.
+    pub fn parts(mut self, parts: usize) -> Self {
+        self.0.parts = parts;
+        self
+    }
+
+    /// Sets the minimum number of shards required to reconstruct the secret.
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.0.threshold = threshold;
+        self
+    }
+
+    /// Sets whether the shards get the x-coordinates `1..=parts` in order.
+    pub fn sequential_x(mut self, sequential_x: bool) -> Self {
+        self.0.sequential_x = sequential_x;
+        self
+    }
+
+    /// Sets whether to store the SHA-256 hash of the secret in every shard header.
+    pub fn embed_hash(mut self, embed_hash: bool) -> Self {
+        self.0.embed_hash = embed_hash;
+        self
+    }
+
+    /// Sets whether to pad every shard header to the same width.
+    pub fn uniform_size(mut self, uniform_size: bool) -> Self {
+        self.0.uniform_size = uniform_size;
+        self
+    }
+
+    /// Sets whether to store the integrity tag of every block of share data.
+    pub fn block_checksums(mut self, block_checksums: bool) -> Self {
+        self.0.block_checksums = block_checksums;
+        self
+    }
+
+    /// Sets the content-type declared for the secret.
+    pub fn content_type(mut self, content_type: Option<String>) -> Self {
+        self.0.content_type = content_type;
+        self
+    }
+
+    /// Sets the creation time of the shards, in seconds since the Unix epoch.
+    pub fn created(mut self, created: Option<u64>) -> Self {
+        self.0.created = created;
+        self
+    }
+
+    /// Sets the time the shards expire, in seconds since the Unix epoch.
+    pub fn expires(mut self, expires: Option<u64>) -> Self {
+        self.0.expires = expires;
+        self
+    }
+
+    /// Sets the description of the secret written to the manifest.
+    pub fn label(mut self, label: Option<String>) -> Self {
+        self.0.label = label;
+        self
+    }
+
+    /// Sets the metadata of the secret file recorded in the shard headers.
+    pub fn file_metadata(mut self, file_metadata: Option<FileMetadata>) -> Self {
+        self.0.file_metadata = file_metadata;
+        self
+    }
+
+    /// Sets the master seed the randomness of the split is derived from.
+    pub fn seed(mut self, seed: Option<rng::Seed>) -> Self {
+        self.0.seed = seed.map(Zeroizing::new);
+        self
+    }
+
+    /// Sets the largest secret accepted, in bytes.
+    pub fn max_secret_size(mut self, max_secret_size: Option<u64>) -> Self {
+        self.0.max_secret_size = max_secret_size;
+        self
+    }
+
+    /// Sets the extension of the shard files, without the leading dot.
+    pub fn extension(mut self, extension: String) -> Self {
+        self.0.extension = extension;
+        self
+    }
+
+    /// Sets what to do with the shards already present.
+    pub fn overwrite_policy(mut self, overwrite_policy: OverwritePolicy) -> Self {
+        self.0.overwrite_policy = overwrite_policy;
+        self
+    }
+
+    /// Sets whether to write bare shares with a sidecar instead of headers.
+    pub fn headerless(mut self, headerless: bool) -> Self {
+        self.0.headerless = headerless;
+        self
+    }
+
+    /// Sets whether to also write a sidecar listing the shards.
+    pub fn sidecar(mut self, sidecar: bool) -> Self {
+        self.0.sidecar = sidecar;
+        self
+    }
+
+    /// Sets whether to check that every quorum of the shards written reconstructs the secret.
+    pub fn verify_all(mut self, verify_all: bool) -> Self {
+        self.0.verify_all = verify_all;
+        self
+    }
+
+    /// Sets whether to write a shards directory, a bundle or a text file.
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.0.output_format = output_format;
+        self
+    }
+
+    /// Sets whether to write one file per shard or a single column layout file.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.0.layout = layout;
+        self
+    }
+
+    /// Sets how every shard file is encoded.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.0.encoding = encoding;
+        self
+    }
+
+    /// Sets whether to also write every shard as QR-code PNG images.
+    pub fn qr(mut self, qr: bool) -> Self {
+        self.0.qr = qr;
+        self
+    }
+
+    /// Sets the GF(2^8) backend used to compute the shares.
+    pub fn gf_backend(mut self, gf_backend: GfBackend) -> Self {
+        self.0.gf_backend = gf_backend;
+        self
+    }
+
+    /// Sets the verbosity of the progress bar and reports.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.0.verbosity = verbosity;
+        self
+    }
+
+    /// Sets where the durations of the phases are collected.
+    pub fn timings(mut self, timings: Option<Arc<Timings>>) -> Self {
+        self.0.timings = timings;
+        self
+    }
+
+    /// Checks the options as a whole.
+    ///
+    /// # Returns
+    ///
+    /// The options, or `Error::InvalidParameters` if the scheme is out of range or options
+    /// conflict, such as QR codes with another output format than files.
+    pub fn build(self) -> Result<SharingConfig> {
+        check_options(&self.0)?;
+        Ok(self.0)
+    }
+}
+
+/// Reads the label of a shard set from a text file.
+///
+/// # Arguments
+///
+/// * `path` - The file holding the label, which may span several lines.
+///
+/// # Returns
+///
+/// The label, or `Error::InvalidParameters` if the file is larger than `MAX_LABEL_LEN` bytes
+/// or is not UTF-8 text.
+pub fn read_label(path: &Path) -> Result<String> {
+    let len = fs::metadata(path)?.len();
+    if len > MAX_LABEL_LEN {
+        return Err(Error::InvalidParameters(format!(
+            "the label file is {} bytes, larger than the maximum of {} bytes",
+            len, MAX_LABEL_LEN
+        )));
+    }
+    String::from_utf8(fs::read(path)?)
+        .map_err(|_| Error::InvalidParameters("the label file is not UTF-8 text".to_string()))
+}
+
+/// Writes the label to the manifest of a shards directory, or removes the manifest left by a
+/// previous split when there is no label, so that it is not shown for the new shards.
+fn write_manifest(shards_dir: &Path, label: Option<&str>) -> Result<()> {
+    let path = shards_dir.join(MANIFEST_FILE);
+    match label {
+        Some(label) => fs::write(path, label)?,
+        None => match fs::remove_file(path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        },
+    }
+    Ok(())
+}
+
+/// Reads the label from the manifest of a shards directory.
+///
+/// # Returns
+///
+/// The label, `None` if the directory has no manifest, or `Error::Io` if it cannot be read.
+pub fn read_manifest(shards_dir: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(shards_dir.join(MANIFEST_FILE)) {
+        Ok(label) => Ok(Some(label)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Length from which a secret file is mapped into memory rather than read onto the heap, see
+/// `read_secret`.
+#[cfg(feature = "mmap")]
+const MIN_MAPPED_LEN: u64 = 64 * 1024;
+
+/// Reads the whole secret file onto the heap.
+#[cfg(not(feature = "mmap"))]
+fn read_secret(path: &Path) -> io::Result<Vec<u8>> {
+    fs::read(path)
+}
+
+/// Maps the secret file into memory, so that large secrets are not copied onto the heap.
+///
+/// Only regular files of at least `MIN_MAPPED_LEN` bytes are mapped. Smaller files, such as
+/// empty ones, which cannot be mapped on every platform, are read onto the heap, and so are
+/// pipes and other special files, which report no size and cannot be mapped.
+#[cfg(feature = "mmap")]
+fn read_secret(path: &Path) -> io::Result<Box<dyn std::ops::Deref<Target = [u8]>>> {
+    use std::io::Read;
+
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() < MIN_MAPPED_LEN {
+        let mut secret = Vec::new();
+        file.read_to_end(&mut secret)?;
+        return Ok(Box::new(secret));
+    }
+    // SAFETY: the mapping is only valid as long as the file is neither modified nor truncated
+    // while it is being sharded. A concurrent write changes the secret under the split, and
+    // reading a page past the end of a truncated file raises SIGBUS, which kills the process.
+    // The secret file belongs to the user, who must not change it during the split.
+    let mapping = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(Box::new(mapping))
+}
+
+/// Fetches the secret from a URL when the `net` feature is enabled.
+#[cfg(feature = "net")]
+fn fetch_secret(url: &str, max_len: Option<u64>) -> Result<Zeroizing<Vec<u8>>> {
+    crate::net::fetch_secret(url, max_len)
+}
+
+/// URLs are rejected when the `net` feature is disabled.
+#[cfg(not(feature = "net"))]
+fn fetch_secret(_: &str, _: Option<u64>) -> Result<Vec<u8>> {
+    Err(Error::InvalidParameters(
+        "reading the secret from a URL requires building with the `net` feature".to_string(),
+    ))
+}
+
+/// Reads the secret at `path`, or fetches it if `path` is an `http://` or `https://` URL, and
+/// passes it to `f`. A fetched secret is zeroized once `f` returns.
+///
+/// A file larger than `options.max_secret_size` is rejected from its metadata, before it is
+/// read. Since special files such as pipes report no size, the length read is checked as well.
+fn with_secret<T>(
+    path: &Path,
+    options: &SharingConfig,
+    f: impl FnOnce(&[u8]) -> Result<T>,
+) -> Result<T> {
+    let max_len = options.max_secret_size;
+    let timings = options.timings.as_deref();
+    let url = path
+        .to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"));
+    if let Some(url) = url {
+        let secret = timing::measure(timings, "read secret", || fetch_secret(url, max_len))?;
+        return f(&secret);
+    }
+
+    let secret = timing::measure(timings, "read secret", || -> Result<_> {
+        check_secret_len(fs::metadata(path)?.len(), max_len)?;
+        let secret = read_secret(path)?;
+        check_secret_len(secret.len() as u64, max_len)?;
+        Ok(secret)
+    })?;
+    f(&secret)
+}
+
+/// Rejects a secret longer than `max_len` bytes with `Error::InvalidParameters`.
+fn check_secret_len(len: u64, max_len: Option<u64>) -> Result<()> {
+    match max_len {
+        Some(max_len) if len > max_len => Err(Error::InvalidParameters(format!(
+            "the secret is {} bytes, larger than the maximum of {} bytes",
+            len, max_len
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Writes a shard as QR-code PNG images next to its shard file.
+///
+/// # Returns
+///
+/// The paths of the images, in reassembly order.
+#[cfg(feature = "qr")]
+fn write_qr_codes(shard: &[u8], x: u8, shard_path: &Path) -> Result<Vec<PathBuf>> {
+    crate::qr::write_qr_codes(shard, x, shard_path)
+}
+
+/// QR codes are rejected when the `qr` feature is disabled.
+#[cfg(not(feature = "qr"))]
+fn write_qr_codes(_: &[u8], _: u8, _: &Path) -> Result<Vec<PathBuf>> {
+    Err(Error::InvalidParameters(
+        "QR codes require building with the `qr` feature".to_string(),
+    ))
+}
 
 /// Reads the contents of a file and shards it into multiple parts based on Shamir's Secret Sharing.
 ///
+/// Each shard is written with a header carrying the scheme parameters and a set id common to
+/// all shards of this split, followed by the share data and a checksum. If anything fails or
+/// the operation is cancelled, the shards already written are removed.
+///
 /// # Arguments
 ///
-/// * `path` - A reference to the `Path` where the original file is located.
-/// * `parts` - The number of shards to create.
-/// * `threshold` - The minimum number of shards required to reconstruct the original file.
+/// * `path` - A reference to the `Path` where the original file is located, or an `http://`
+///   or `https://` URL to fetch the secret from with the `net` feature.
+/// * `shards_path` - The directory where the shards are written.
+/// * `options` - The `SharingConfig` to apply.
+/// * `cancel` - A flag which, once set, stops the sharding as soon as possible.
 ///
 /// # Returns
 ///
-/// An `io::Result` which is either:
-/// - `Ok(Vec<String>)`: A vector of strings representing the file paths of the created shards.
-/// - `Err(io::Error)`: An error that occurred during the sharding process.
+/// A `Result` which is either:
+/// - `Ok(Vec<PathBuf>)`: A vector of the file paths of the created shards.
+/// - `Err(Error)`: An error that occurred during the sharding process.
 ///
 /// # Examples
 ///
 /// ```
 /// let file_path = Path::new("path/to/myfile.txt");
-/// match shard_file(file_path, 5, 3) {
+/// let cancel = AtomicBool::new(false);
+/// match shard_file(file_path, Path::new("shards"), &SharingConfig::default(), &cancel) {
 ///     Ok(shard_paths) => println!("Shards created: {:?}", shard_paths),
 ///     Err(e) => eprintln!("An error occurred: {}", e),
 /// }
 /// ```
-fn shard_file(path: &Path, parts: usize, threshold: usize) -> io::Result<Vec<String>> {
-    let mut file = File::open(path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
+fn shard_file(
+    path: &Path,
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    check_options(options)?;
+    with_secret(path, options, |secret| {
+        shard_data(secret, shards_path, options, cancel)
+    })
+}
+
+/// Rejects options which cannot be applied whatever the secret: a threshold or parts out of
+/// range, an invalid content-type, QR codes without the `qr` feature or with the column
+/// layout, or options of a shards directory with another output format.
+fn check_options(options: &SharingConfig) -> Result<()> {
+    // The passphrase share comes on top of the parts.
+    let invalid_scheme = match () {
+        _ if options.threshold < 2 => Some("threshold must be at least 2"),
+        _ if options.parts < options.threshold => Some("parts cannot be less than threshold"),
+        _ if options.parts > 255 => Some("parts cannot exceed 255"),
+        _ if options.parts == 255 && options.passphrase.is_some() => {
+            Some("parts cannot exceed 254 with a passphrase")
+        }
+        _ => None,
+    };
+    if let Some(message) = invalid_scheme {
+        return Err(Error::InvalidParameters(message.to_string()));
+    }
+    if let Some(content_type) = &options.content_type {
+        header::validate_content_type(content_type)?;
+    }
+    if let (Some(created), Some(expires)) = (options.created, options.expires) {
+        if expires <= created {
+            return Err(Error::InvalidParameters(format!(
+                "the shards would expire at {}, not after their creation at {}",
+                expires, created
+            )));
+        }
+    }
+    if options.qr && !cfg!(feature = "qr") {
+        return Err(Error::InvalidParameters(
+            "QR codes require building with the `qr` feature".to_string(),
+        ));
+    }
+    if options.qr && options.layout == Layout::Column {
+        return Err(Error::InvalidParameters(
+            "QR codes can only be written with the row layout".to_string(),
+        ));
+    }
+    let directory_only = options.qr
+        || options.layout == Layout::Column
+        || options.label.is_some()
+        || options.sidecar;
+    if options.output_format != OutputFormat::Files && directory_only {
+        return Err(Error::InvalidParameters(
+            "QR codes, the column layout, labels and sidecars require the files output format"
+                .to_string(),
+        ));
+    }
+    if options.verify_all && options.output_format != OutputFormat::Files {
+        return Err(Error::InvalidParameters(
+            "only shards written as files can be verified".to_string(),
+        ));
+    }
+    let quorums = combine::quorum_count(options.parts, options.threshold);
+    if options.verify_all && quorums > combine::MAX_VERIFIED_QUORUMS {
+        return Err(Error::InvalidParameters(format!(
+            "verifying every quorum would combine {} quorums, more than the {} allowed",
+            quorums,
+            combine::MAX_VERIFIED_QUORUMS
+        )));
+    }
+    if options.sidecar && options.layout == Layout::Column {
+        return Err(Error::InvalidParameters(
+            "a sidecar can only list shards in the row layout".to_string(),
+        ));
+    }
+    // A bundle record holds the whole set under a single header, in the column layout.
+    if options.block_checksums
+        && (options.layout == Layout::Column || options.output_format == OutputFormat::Bundle)
+    {
+        return Err(Error::InvalidParameters(
+            "block checksums can only be written with the row layout, not to a bundle".to_string(),
+        ));
+    }
+    let binary_only = options.qr
+        || options.headerless
+        || options.layout == Layout::Column
+        || options.output_format != OutputFormat::Files;
+    if options.encoding == Encoding::Mnemonic && binary_only {
+        return Err(Error::InvalidParameters(
+            "mnemonic shards can only be written as files in the row layout, with a header and \
+             without QR codes"
+                .to_string(),
+        ));
+    }
+    let with_header = options.passphrase.is_some()
+        || options.uniform_size
+        || options.block_checksums
+        || options.qr
+        || options.layout == Layout::Column
+        || options.output_format != OutputFormat::Files;
+    if options.headerless && with_header {
+        return Err(Error::InvalidParameters(
+            "headerless shards can only be written as files in the row layout, without a \
+             passphrase, uniform size, block checksums or QR codes"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Splits a secret held in memory and writes its shards, removing the shards already written
+/// if anything fails.
+///
+/// # Arguments
+///
+/// * `data` - The secret.
+/// * `shards_path` - The directory where the shards are written.
+/// * `options` - The `SharingConfig` to apply, already checked by `check_options`.
+/// * `cancel` - A flag which, once set, stops the sharding as soon as possible.
+///
+/// # Returns
+///
+/// The paths of the created shards, with the same errors as `shard_file`.
+fn shard_data(
+    data: &[u8],
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    let (shards, set_header) = split_secret(data, options, cancel)?;
+    let shard_paths = timing::measure(options.timings.as_deref(), "write shards", || {
+        write_set(&shards, &set_header, shards_path, options, cancel)
+    })?;
+    if options.verify_all {
+        // The shards are left in place when a quorum fails, so that they can be inspected.
+        let quorums = timing::measure(options.timings.as_deref(), "verify", || {
+            combine::verify_quorums(
+                data,
+                &shard_paths,
+                options.threshold,
+                options.gf_backend,
+                cancel,
+            )
+        })?;
+        if options.verbosity > Verbosity::Quiet {
+            println!("All {} quorums of shards reconstruct the secret.", quorums);
+        }
+    }
+    Ok(shard_paths)
+}
 
-    let shards = shamir::split(&data, parts, threshold);
+/// Writes the shards of a set to the shards directory, in the layout and with the headers
+/// selected by `options`, removing the shards already written if anything fails.
+///
+/// # Returns
+///
+/// The paths of the created shards, with the same errors as `shard_file`.
+fn write_set(
+    shards: &[Vec<u8>],
+    set_header: &Header,
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    if options.headerless {
+        return write_headerless_shards(shards, set_header, shards_path, options, cancel);
+    }
+    if options.layout == Layout::Column {
+        return write_column_file(shards, set_header, shards_path, options, cancel);
+    }
+    let mut sink = DirectorySink::new(shards_path, &options.extension);
+    sink.begin_set(set_header)?;
+    let write_qr_codes = |index: usize, shard: &[u8]| {
+        if options.qr {
+            let x = shards[index].last().expect("share is non-empty");
+            write_qr_codes(shard, *x, &sink.shard_path(index))
+        } else {
+            Ok(Vec::new())
+        }
+    };
     let mut shard_paths = Vec::new();
+    // The QR codes and the sidecar.
+    let mut other_paths = Vec::new();
+    let mut errors = Vec::new();
+    let outcomes = write_row_shards(shards, set_header, &sink, options, cancel, &write_qr_codes)?;
+    for (index, written) in outcomes.into_iter().enumerate() {
+        match written {
+            Ok(paths) => other_paths.extend(paths),
+            Err(e) => errors.push(e),
+        }
+        shard_paths.push(sink.shard_path(index));
+    }
+    if errors.is_empty() && options.sidecar {
+        let listed: Vec<_> = shard_paths
+            .iter()
+            .zip(shards)
+            .map(|(shard_path, share)| {
+                let x = share.last().expect("share is non-empty");
+                (file_name_of(shard_path), *x)
+            })
+            .collect();
+        other_paths.push(shards_path.join(sidecar::FILE_NAME));
+        if let Err(e) = sidecar::write(shards_path, set_header, &listed) {
+            errors.push(e.into());
+        }
+    }
+    if errors.is_empty() {
+        if let Err(e) = sink.finish() {
+            errors.push(e.into());
+        }
+    }
+    if !errors.is_empty() {
+        // Never leave an incomplete set of shards behind.
+        shard_paths.iter().chain(&other_paths).for_each(|path| {
+            let _ = fs::remove_file(path);
+        });
+        // Writers stopped because another one failed report `Error::Cancelled`, so report
+        // the failure which stopped them.
+        let first = errors
+            .iter()
+            .position(|e| !matches!(e, Error::Cancelled))
+            .unwrap_or(0);
+        return Err(errors.swap_remove(first));
+    }
+    if options.verbosity == Verbosity::Verbose {
+        for (shard_path, shard) in shard_paths.iter().zip(shards) {
+            let x = shard.last().expect("share is non-empty");
+            println!("Wrote shard {} (x = {})", shard_path.display(), x);
+        }
+    }
 
-    for (index, shard) in shards.into_iter().enumerate() {
-        let shard_path = format!("{}_{}", "shards", index);
-        let mut shard_file = File::create(&shard_path)?;
-        shard_file.write_all(&shard)?;
-        shard_paths.push(shard_path);
+    Ok(shard_paths)
+}
+
+/// Writes the y-coordinates of every share to a file named after its x-coordinate, such as
+/// `17.sss`, and the parameters of the set to the sidecar, removing everything written if a
+/// write fails.
+///
+/// # Arguments
+///
+/// * `shares` - The shares produced by `shamir::split`.
+/// * `set_header` - The header shared by the shards of the set, written to the sidecar.
+/// * `shards_path` - The directory where the shards are written.
+/// * `options` - The `SharingConfig` to apply.
+/// * `cancel` - A flag which, once set, stops the sharding and removes the files written.
+///
+/// # Returns
+///
+/// The paths of the shards, without the sidecar.
+fn write_headerless_shards(
+    shares: &[Vec<u8>],
+    set_header: &Header,
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    let sidecar_path = shards_path.join(sidecar::FILE_NAME);
+    let mut sink = HeaderlessSink::new(shards_path, &options.extension);
+    let shard_paths: Vec<PathBuf> = shares
+        .iter()
+        .map(|share| sink.shard_path(*share.last().expect("share is non-empty")))
+        .collect();
+    let written = write_to_sink(&mut sink, shares, set_header, options, cancel)
+        .and_then(|()| Ok(sink.finish()?));
+    if let Err(e) = written {
+        shard_paths.iter().chain([&sidecar_path]).for_each(|path| {
+            let _ = fs::remove_file(path);
+        });
+        return Err(e);
+    }
+    if options.verbosity == Verbosity::Verbose {
+        for shard_path in &shard_paths {
+            println!("Wrote headerless shard {}", shard_path.display());
+        }
+        println!(
+            "Wrote the parameters of the set to {}",
+            sidecar_path.display()
+        );
+    }
+    Ok(shard_paths)
+}
+
+/// Encodes a share as the content of its shard file: its header, with the x-coordinate the
+/// share ends with, followed by its y-coordinates and the checksum.
+///
+/// # Arguments
+///
+/// * `share` - The share, ending with its x-coordinate.
+/// * `set_header` - The header common to all the shards of the set.
+/// * `options` - The `SharingConfig` to apply; only the uniform size and block checksums
+///   apply.
+fn encode_shard(share: &[u8], set_header: &Header, options: &SharingConfig) -> Vec<u8> {
+    let (&x, body) = share.split_last().expect("share is non-empty");
+    let header = Header {
+        x,
+        block_tags: options
+            .block_checksums
+            .then(|| BlockTags::compute(set_header.set_id, x, body)),
+        ..set_header.clone()
+    };
+    let header_bytes = if options.uniform_size {
+        header.to_padded_bytes()
+    } else {
+        header.to_bytes()
+    };
+    let mut shard = Vec::with_capacity(header_bytes.len() + share.len() + 32);
+    header::write_shard(&mut shard, &header_bytes, body).expect("writing to a Vec cannot fail");
+    shard
+}
+
+/// Encodes the shares of a set and hands them to `sink` in order, after the header of the
+/// set, without finishing it.
+///
+/// # Returns
+///
+/// `Error::Cancelled` if `cancel` was set before every shard was written, or the error of
+/// the sink.
+fn write_to_sink(
+    sink: &mut impl ShardSink,
+    shares: &[Vec<u8>],
+    set_header: &Header,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    sink.begin_set(set_header)?;
+    for (index, share) in shares.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(Error::Cancelled);
+        }
+        sink.write_shard(index, &encode_shard(share, set_header, options))?;
     }
+    Ok(())
+}
+
+/// Writes one shard of a set in the row layout, then what goes with it, such as its QR
+/// codes.
+///
+/// # Arguments
+///
+/// * `index` - The index of the shard.
+/// * `share` - The share, ending with its x-coordinate.
+/// * `set_header` - The header common to all the shards of the set.
+/// * `sink` - Where the shard is stored.
+/// * `options` - The `SharingConfig` to apply.
+/// * `stop` - Returns whether to give up before writing the shard.
+/// * `write_extras` - Writes what goes with the shard from its index and content, returning
+///   the paths of the files written.
+///
+/// # Returns
+///
+/// The paths returned by `write_extras`, or the error which stopped the write, in which case
+/// the shard may be partially written.
+fn write_row_shard(
+    index: usize,
+    share: &[u8],
+    set_header: &Header,
+    sink: &mut impl ShardSink,
+    options: &SharingConfig,
+    stop: impl Fn() -> bool,
+    write_extras: &impl Fn(usize, &[u8]) -> Result<Vec<PathBuf>>,
+) -> Result<Vec<PathBuf>> {
+    if stop() {
+        return Err(Error::Cancelled);
+    }
+    let shard = match options.encoding {
+        Encoding::Binary => encode_shard(share, set_header, options),
+        Encoding::Mnemonic => mnemonic::encode(&encode_shard(share, set_header, options)).into(),
+    };
+    sink.write_shard(index, &shard)?;
+    write_extras(index, &shard)
+}
+
+/// Returns the file name of a shard written to the shards directory, as listed in the sidecar.
+fn file_name_of(shard_path: &Path) -> String {
+    shard_path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+}
 
+/// Writes the shards of a set in the row layout one after the other, stopping at the first
+/// failure.
+///
+/// # Returns
+///
+/// The outcome of `write_row_shard` for every shard attempted, in order of index.
+#[cfg(not(feature = "parallel"))]
+fn write_row_shards<S: ShardSink + Clone>(
+    shares: &[Vec<u8>],
+    set_header: &Header,
+    sink: &S,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+    write_extras: &(impl Fn(usize, &[u8]) -> Result<Vec<PathBuf>> + Sync),
+) -> Result<Vec<Result<Vec<PathBuf>>>> {
+    let mut sink = sink.clone();
+    let mut written = Vec::new();
+    for (index, share) in shares.iter().enumerate() {
+        let stop = || cancel.load(Ordering::Relaxed);
+        let result = write_row_shard(
+            index,
+            share,
+            set_header,
+            &mut sink,
+            options,
+            stop,
+            write_extras,
+        );
+        let failed = result.is_err();
+        written.push(result);
+        if failed {
+            break;
+        }
+    }
+    Ok(written)
+}
+
+/// Writes the shards of a set in the row layout concurrently, on at most `WRITE_THREADS`
+/// threads, each through its own clone of `sink`, which must store the shards wherever the
+/// sink it was cloned from does, like `DirectorySink`. Once a write fails, the shards which
+/// have not started are given up.
+///
+/// # Returns
+///
+/// The outcome of `write_row_shard` for every shard, in order of index, or an `Error::Io` if
+/// the threads cannot be started.
+#[cfg(feature = "parallel")]
+fn write_row_shards<S: ShardSink + Clone + Send + Sync>(
+    shares: &[Vec<u8>],
+    set_header: &Header,
+    sink: &S,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+    write_extras: &(impl Fn(usize, &[u8]) -> Result<Vec<PathBuf>> + Sync),
+) -> Result<Vec<Result<Vec<PathBuf>>>> {
+    use rayon::prelude::*;
+
+    // Stay within the thread pool of `--num-threads`.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(
+            WRITE_THREADS
+                .min(shares.len())
+                .min(rayon::current_num_threads()),
+        )
+        .build()
+        .map_err(io::Error::other)?;
+    let failed = AtomicBool::new(false);
+    Ok(pool.install(|| {
+        shares
+            .par_iter()
+            .enumerate()
+            .map_init(
+                || sink.clone(),
+                |sink, (index, share)| {
+                    let stop = || cancel.load(Ordering::Relaxed) || failed.load(Ordering::Relaxed);
+                    let result = write_row_shard(
+                        index,
+                        share,
+                        set_header,
+                        sink,
+                        options,
+                        stop,
+                        write_extras,
+                    );
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    result
+                },
+            )
+            .collect()
+    }))
+}
+
+/// Splits a secret held in memory into shares.
+///
+/// With a passphrase, one more share is drawn and moved onto the share derived from the
+/// passphrase with `shamir::move_share`, then left out: the passphrase stands in for one of
+/// the `threshold` shards when combining.
+///
+/// All the randomness of the split is drawn from a single `rng::ShareRng`, in a fixed order:
+/// the x-coordinates, the coefficients of every byte in turn, the salt of the passphrase and
+/// the set id. Seeded from a master seed, it makes the shards a function of the secret, the
+/// options and the seed alone.
+///
+/// # Arguments
+///
+/// * `data` - The secret.
+/// * `options` - The `SharingConfig` to apply.
+/// * `cancel` - A flag which, once set, stops the split.
+///
+/// # Returns
+///
+/// The shares produced by `shamir::split_with_rng` and the header common to all the shards
+/// of the set, with a new set id and an x-coordinate of 0, `Error::RandomSource` if the
+/// operating system's generator is unavailable, or the errors of `shamir::split_with_rng`.
+fn split_secret(
+    data: &[u8],
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<(Vec<Vec<u8>>, Header)> {
+    // The passphrase share comes on top of the parts, which `shamir::split` cannot check.
+    let invalid = match () {
+        _ if options.passphrase.is_none() => None,
+        _ if options.parts >= 255 => Some("parts cannot exceed 254 with a passphrase"),
+        _ if options.parts < options.threshold => Some("parts cannot be less than threshold"),
+        _ => None,
+    };
+    if let Some(message) = invalid {
+        return Err(Error::InvalidParameters(message.to_string()));
+    }
+    let progress = options.verbosity > Verbosity::Quiet;
+    let mut rng = match &options.seed {
+        Some(seed) => rng::seeded_rng(seed),
+        None => rng::share_rng()?,
+    };
+    let mut shares = timing::measure(options.timings.as_deref(), "split", || {
+        let parts = options.parts + usize::from(options.passphrase.is_some());
+        let x_coordinates = shamir::draw_x_coordinates(parts, options.sequential_x, &mut rng)?;
+        shamir::split_with_rng(
+            data,
+            &x_coordinates,
+            options.threshold,
+            options.gf_backend,
+            Some(cancel),
+            progress,
+            &mut rng,
+        )
+    })?;
+    let passphrase_share = match &options.passphrase {
+        Some(passphrase) => {
+            let drawn = Zeroizing::new(shares.pop().expect("parts is at least 2"));
+            let x = *drawn.last().expect("share is non-empty");
+            let (target, description) = passphrase.new_share(x, data.len(), &mut rng)?;
+            shamir::move_share(
+                &mut shares,
+                &drawn,
+                &target[..data.len()],
+                options.gf_backend,
+            );
+            Some(description)
+        }
+        None => None,
+    };
+    let file_metadata = options.file_metadata.as_ref();
+    let set_header = Header {
+        threshold: options.threshold as u8,
+        parts: options.parts as u8,
+        x: 0,
+        set_id: rng.gen(),
+        secret_len: data.len() as u64,
+        secret_hash: options.embed_hash.then(|| Sha256::digest(data).into()),
+        content_type: options.content_type.clone(),
+        file_name: file_metadata.map(|metadata| metadata.name.clone()),
+        passphrase: passphrase_share,
+        block_tags: None,
+        created: options.created,
+        expires: options.expires,
+        file_mode: file_metadata.and_then(|metadata| metadata.mode),
+        file_mtime: file_metadata.and_then(|metadata| metadata.mtime),
+        seed_hash: options.seed.as_deref().map(rng::seed_hash),
+    };
+    Ok((shares, set_header))
+}
+
+/// Shards a secret contained in a file and adds its shards to a bundle, as a single record
+/// in the column layout. The name of the secret file is recorded in the header of the record,
+/// for `combine --unpack`.
+///
+/// # Arguments
+///
+/// * `secret_path` - The file that contains the secret, or a URL as for `shard_secret`.
+/// * `bundle_path` - The bundle file, created unless `append` is set. An existing bundle is
+///   handled according to `options.overwrite_policy`, as for `shard_into_text_file`.
+/// * `append` - Whether to append the shards to an existing bundle instead of creating one.
+/// * `options` - The `SharingConfig` to apply; the layout, extension and QR codes do not
+///   apply to bundles.
+/// * `cancel` - A flag which, once set, stops the sharding before the bundle is written.
+///
+/// # Returns
+///
+/// The index of the new record in the bundle, with the errors of `shard_secret` and
+/// `bundle::write_record`.
+pub fn shard_into_bundle(
+    secret_path: &Path,
+    bundle_path: &Path,
+    append: bool,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<usize> {
+    check_options(options)?;
+    let (shares, mut set_header) = with_secret(secret_path, options, |secret| {
+        split_secret(secret, options, cancel)
+    })?;
+    // A URL has no file name, and names which are not UTF-8 cannot be stored.
+    set_header.file_name = secret_path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .filter(|_| secret_path.is_file())
+        .map(str::to_string);
+    let mut sink = BundleSink::new(bundle_path, append, options.uniform_size);
+    write_to_sink(&mut sink, &shares, &set_header, options, cancel)?;
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Error::Cancelled);
+    }
+    let index = if append {
+        timing::measure(options.timings.as_deref(), "write shards", || {
+            sink.finish_record()
+        })?
+    } else {
+        with_output_file(bundle_path, options, || {
+            timing::measure(options.timings.as_deref(), "write shards", || {
+                sink.finish_record()
+            })
+        })?
+    };
+    if options.verbosity == Verbosity::Verbose {
+        println!(
+            "Wrote {} shards to {} as record {}",
+            shares.len(),
+            bundle_path.display(),
+            index
+        );
+    }
+    Ok(index)
+}
+
+/// Shards a secret contained in a file into a single text file holding the whole set, in the
+/// format given by `options.output_format`, `OutputFormat::Json` or
+/// `OutputFormat::Base64Lines`.
+///
+/// An existing file is handled according to `options.overwrite_policy`, see
+/// `with_output_file`.
+///
+/// # Returns
+///
+/// The path of the file, in a single-element vector, with the errors of `shard_secret`.
+fn shard_into_text_file(
+    secret_path: &Path,
+    output_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    check_options(options)?;
+    let shards = with_secret(secret_path, options, |secret| {
+        encode_shards(secret, options, cancel)
+    })?;
+    let text = match options.output_format {
+        OutputFormat::Json => export::to_json(&shards),
+        _ => export::to_base64_lines(&shards),
+    };
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Error::Cancelled);
+    }
+
+    with_output_file(output_path, options, || {
+        timing::measure(options.timings.as_deref(), "write shards", || {
+            fs::write(output_path, text)
+        })
+        .map_err(Error::from)
+    })?;
+    if options.verbosity == Verbosity::Verbose {
+        println!("Wrote {} shards to {}", shards.len(), output_path.display());
+    }
+    Ok(vec![output_path.to_path_buf()])
+}
+
+/// Writes all the shares of a split to a single column layout file, removing it if the
+/// write fails.
+///
+/// # Arguments
+///
+/// * `shares` - The shares produced by `shamir::split`.
+/// * `set_header` - The header shared by the shards of the set.
+/// * `shards_path` - The directory where the file is written.
+/// * `options` - The `SharingConfig` to apply.
+/// * `cancel` - A flag which, once set, stops the sharding before the file is written.
+///
+/// # Returns
+///
+/// The path of the column layout file, in a single-element vector.
+fn write_column_file(
+    shares: &[Vec<u8>],
+    set_header: &Header,
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    let columns_path = shards_path
+        .join(columnar::FILE_STEM)
+        .with_extension(&options.extension);
+    let mut sink = ColumnSink::new(&columns_path, options.uniform_size);
+    write_to_sink(&mut sink, shares, set_header, options, cancel)?;
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Error::Cancelled);
+    }
+    sink.finish()?;
+    if options.verbosity == Verbosity::Verbose {
+        println!(
+            "Wrote {} shards to {}",
+            shares.len(),
+            columns_path.display()
+        );
+    }
+    Ok(vec![columns_path])
+}
+
+/// Lists the shard files directly inside a directory, recognized by their magic, once
+/// decoded for those written as words, and the headerless shards and their sidecar if the
+/// directory has one.
+///
+/// Headerless shards are the files listed by the sidecar, or the files named after an
+/// x-coordinate with the extension `extension` if it lists none, so that other files with a
+/// numeric name, such as `1.pdf`, are left alone.
+fn existing_shards(dir: &Path, extension: &str) -> io::Result<Vec<PathBuf>> {
+    let sidecar_path = dir.join(sidecar::FILE_NAME);
+    let headerless = sidecar_path.is_file();
+    // An unreadable sidecar lists no shard.
+    let listed = if headerless {
+        sidecar::read_manifest(&sidecar_path).map_or_else(
+            |_| Vec::new(),
+            |manifest| manifest.shards.into_iter().map(|(name, _)| name).collect(),
+        )
+    } else {
+        Vec::new()
+    };
+    let is_headerless_shard = |path: &Path| {
+        headerless
+            && combine::bare_share_x(path).is_some()
+            && if listed.is_empty() {
+                has_extension(path, extension)
+            } else {
+                listed.contains(&file_name_of(path))
+            }
+    };
+    let mut shard_paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        if has_shard_magic(&path)? || is_headerless_shard(&path) {
+            shard_paths.push(path);
+        }
+    }
+    if headerless {
+        shard_paths.push(sidecar_path);
+    }
+    shard_paths.sort();
     Ok(shard_paths)
 }
 
-/// Shards a secret contained in a file and stores the shards in a specified directory.
+/// Returns whether the file at `path` starts with the magic of a shard or of a column
+/// layout file, or is a shard written as words by `mnemonic::encode`.
+fn has_shard_magic(path: &Path) -> io::Result<bool> {
+    let mut file = BufReader::new(File::open(path)?);
+    let start = file.fill_buf()?;
+    if header::has_magic(start) || columnar::has_magic(start) {
+        return Ok(true);
+    }
+    // Shards written as words are decoded whole, since their last word is a checksum.
+    if mnemonic::as_mnemonic(start).is_none() {
+        return Ok(false);
+    }
+    let mut text = String::new();
+    if file.read_to_string(&mut text).is_err() {
+        return Ok(false);
+    }
+    Ok(mnemonic::decode(&text).is_ok_and(|decoded| header::has_magic(&decoded)))
+}
+
+/// Returns whether the file at `path` has the extension `extension`, or none if it is empty.
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .map_or(extension.is_empty(), |found| found == extension)
+}
+
+/// Renames shards with a suffix made of the current Unix time, such as
+/// `shards_0.sss.1700000000`, adding a counter if that name is already taken.
+///
+/// # Returns
+///
+/// The original and backup path of every shard renamed, so that the renames can be undone.
+fn back_up_shards(shard_paths: &[PathBuf]) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut renamed = Vec::new();
+
+    for shard_path in shard_paths {
+        let mut backup_path = PathBuf::from(format!("{}.{}", shard_path.display(), timestamp));
+        for counter in 1.. {
+            if !backup_path.exists() {
+                break;
+            }
+            backup_path = PathBuf::from(format!(
+                "{}.{}-{}",
+                shard_path.display(),
+                timestamp,
+                counter
+            ));
+        }
+        if let Err(e) = fs::rename(shard_path, &backup_path) {
+            restore_backups(&renamed);
+            return Err(e);
+        }
+        renamed.push((shard_path.clone(), backup_path));
+    }
+
+    Ok(renamed)
+}
+
+/// Moves backed up shards back to their original paths.
+fn restore_backups(renamed: &[(PathBuf, PathBuf)]) {
+    for (shard_path, backup_path) in renamed {
+        let _ = fs::rename(backup_path, shard_path);
+    }
+}
+
+/// Removes the shards of a previous set which the new shards did not overwrite, such as
+/// `shards_5.sss` and `shards_6.sss` when 7 shards are replaced by 5.
+///
+/// Only the files named like the shards the set writes are removed: `shards_<index>` and,
+/// for headerless shards, `<x>` with the extension `extension`. Backups, whose name carries
+/// a suffix after the shard extension, are kept.
+fn remove_stale_shards(
+    existing: &[PathBuf],
+    shard_paths: &[PathBuf],
+    extension: &str,
+    verbosity: Verbosity,
+) -> io::Result<()> {
+    let is_shard_name = |path: &Path| {
+        let indexed = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix("shards_"))
+            .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()));
+        indexed || (combine::bare_share_x(path).is_some() && has_extension(path, extension))
+    };
+    for stale_path in existing
+        .iter()
+        .filter(|path| is_shard_name(path) && !shard_paths.contains(path))
+    {
+        fs::remove_file(stale_path)?;
+        if verbosity == Verbosity::Verbose {
+            println!("Removed stale shard {}", stale_path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Shards a secret contained in a file and stores the shards in a specified directory, or in
+/// a single file for an `options.output_format` other than `OutputFormat::Files`.
+///
+/// Shards already present in the directory are handled according to
+/// `options.overwrite_policy`. With `OverwritePolicy::Backup`, they are moved back in place
+/// if the new shards cannot be written. With `OverwritePolicy::Overwrite`, the old shards
+/// which were not overwritten are removed once the new set is complete, so that the two
+/// sets are never mixed.
 ///
 /// # Arguments
 ///
-/// * `secret_path` - A `PathBuf` pointing to the file that contains the secret.
-/// * `shards_path` - A `PathBuf` specifying the directory where the shards should be stored.
-/// * `parts` - The number of shards to create.
-/// * `threshold` - The minimum number of shards required to reconstruct the secret.
+/// * `secret_path` - A `PathBuf` pointing to the file that contains the secret, or an
+///   `http://` or `https://` URL to fetch it from when built with the `net` feature.
+/// * `shards_path` - A `PathBuf` specifying the directory where the shards should be stored, or
+///   the bundle or text file to create.
+/// * `options` - The `SharingConfig` to apply.
+/// * `cancel` - A flag which, once set, stops the sharding and removes the shards written so far.
 ///
 /// # Returns
 ///
-/// An `io::Result<()>` which is:
-/// - `Ok(())`: On successful sharding of the secret.
-/// - `Err(io::Error)`: If any error occurs during the sharding process.
+/// A `Result` which is:
+/// - `Ok(Vec<PathBuf>)`: The paths of the created shards, on successful sharding of the secret.
+/// - `Err(Error)`: If any error occurs during the sharding process, including
+///   `Error::InvalidParameters` for an empty secret or one larger than
+///   `options.max_secret_size`, `Error::Cancelled` when `cancel` was set, and an `Error::Io`
+///   of kind `io::ErrorKind::AlreadyExists` when the directory holds shards and
+///   `options.overwrite_policy` is `OverwritePolicy::Fail`.
 ///
 /// # Panics
 ///
@@ -72,34 +1540,1225 @@ fn shard_file(path: &Path, parts: usize, threshold: usize) -> io::Result<Vec<Str
 /// ```
 /// let secret_file = PathBuf::from("path/to/secret.txt");
 /// let shards_directory = PathBuf::from("path/to/shards");
-/// match shard_secret(&secret_file, &shards_directory, 5, 3) {
-///     Ok(()) => println!("Secret successfully sharded."),
+/// let cancel = AtomicBool::new(false);
+/// let options = SharingConfig::default();
+/// match shard_secret(&secret_file, &shards_directory, &options, &cancel) {
+///     Ok(shard_paths) => println!("Secret successfully sharded into {:?}.", shard_paths),
 ///     Err(e) => eprintln!("Failed to shard the secret: {}", e),
 /// }
 /// ```
 pub fn shard_secret(
     secret_path: &Path,
-    shards_path: &PathBuf,
-    parts: usize,
-    threshold: usize,
-) -> io::Result<()> {
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    match options.output_format {
+        OutputFormat::Files => (),
+        OutputFormat::Bundle => {
+            shard_into_bundle(secret_path, shards_path, false, options, cancel)?;
+            return Ok(vec![shards_path.to_path_buf()]);
+        }
+        OutputFormat::Json | OutputFormat::Base64Lines => {
+            return shard_into_text_file(secret_path, shards_path, options, cancel);
+        }
+    }
+    let shard_paths = with_overwrite_policy(shards_path, options, || {
+        shard_file(secret_path, shards_path, options, cancel)
+    })?;
+    write_manifest(shards_path, options.label.as_deref())?;
+    Ok(shard_paths)
+}
+
+/// Shards a secret held in memory, such as one recovered from other shards or given on the
+/// command line, and stores the shards in a directory with their label, like `shard_secret`.
+///
+/// # Arguments
+///
+/// * `secret` - The secret.
+/// * `shards_path` - The directory where the shards are written.
+/// * `options` - The `SharingConfig` to apply.
+/// * `cancel` - A flag which, once set, stops the sharding and removes the shards written so far.
+///
+/// # Returns
+///
+/// The paths of the created shards, with the same errors as `shard_secret`.
+pub fn shard_bytes(
+    secret: &[u8],
+    shards_path: &Path,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<PathBuf>> {
+    check_options(options)?;
+    check_secret_len(secret.len() as u64, options.max_secret_size)?;
+    let shard_paths = with_overwrite_policy(shards_path, options, || {
+        shard_data(secret, shards_path, options, cancel)
+    })?;
+    write_manifest(shards_path, options.label.as_deref())?;
+    Ok(shard_paths)
+}
+
+/// Shards a secret held in memory without writing anything to disk.
+///
+/// # Arguments
+///
+/// * `secret` - The secret.
+/// * `options` - The `SharingConfig` to apply; only the parts, threshold, x-coordinates,
+///   hash, uniform size, block checksums, content-type and backend apply to shards kept in
+///   memory.
+/// * `cancel` - A flag which, once set, stops the sharding.
+///
+/// # Returns
+///
+/// The content of each shard, as it would be written to its file, with the errors of
+/// `shamir::split` and `Error::InvalidParameters` for an invalid content-type.
+pub fn encode_shards(
+    secret: &[u8],
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<Vec<u8>>> {
+    if let Some(content_type) = &options.content_type {
+        header::validate_content_type(content_type)?;
+    }
+    let (shares, set_header) = split_secret(secret, options, cancel)?;
+    let mut shards = vec![Vec::new(); shares.len()];
+    write_to_sink(&mut shards, &shares, &set_header, options, cancel)?;
+    Ok(shards)
+}
+
+/// Shards a secret contained in a file into shell `export` statements, one per shard, as
+/// printed by `shard --export-env`, without writing anything to disk.
+///
+/// # Arguments
+///
+/// * `secret_path` - The path of the secret file.
+/// * `prefix` - The prefix of the variables, followed by the index of each shard.
+/// * `options` - The `SharingConfig` to apply, as for `encode_shards`.
+/// * `cancel` - A flag which, once set, stops the sharding.
+///
+/// # Returns
+///
+/// The statements, with the errors of `shard_bytes_into_env` and of reading the secret.
+///
+/// # Examples
+///
+/// ```
+/// let exports = shard_into_env(Path::new("secret"), "SHARD_", &options, &cancel)?;
+/// // export SHARD_0='U0hNUgEC...'
+/// ```
+pub fn shard_into_env(
+    secret_path: &Path,
+    prefix: &str,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<String> {
+    export::check_env_prefix(prefix)?;
+    with_secret(secret_path, options, |secret| {
+        shard_bytes_into_env(secret, prefix, options, cancel)
+    })
+}
+
+/// Shards a secret held in memory into shell `export` statements, like `shard_into_env`.
+///
+/// # Returns
+///
+/// The statements, with the errors of `encode_shards`, or `Error::InvalidParameters` if
+/// the prefix is not valid in variable names or the secret is too large.
+pub fn shard_bytes_into_env(
+    secret: &[u8],
+    prefix: &str,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<String> {
+    check_options(options)?;
+    export::check_env_prefix(prefix)?;
+    check_secret_len(secret.len() as u64, options.max_secret_size)?;
+    let shards = encode_shards(secret, options, cancel)?;
+    Ok(export::to_env_exports(&shards, prefix))
+}
+
+/// Shards a secret contained in a file and uploads every shard to its own object of an
+/// S3-compatible object store, as `shard --s3` does, without writing anything to disk.
+///
+/// # Arguments
+///
+/// * `secret_path` - The file that contains the secret, or a URL as for `shard_secret`.
+/// * `location` - The bucket and key prefix, such as `backups/vault`; the shards are stored
+///   as `vault/shards_0.sss`, `vault/shards_1.sss` and so on.
+/// * `options` - The `SharingConfig` to apply, as for `encode_shards`.
+/// * `cancel` - A flag which, once set, stops the sharding.
+///
+/// # Returns
+///
+/// The keys of the shards, with the errors of `shard_bytes_into_s3` and of reading the
+/// secret.
+///
+/// # Examples
+///
+/// ```
+/// let keys = shard_into_s3(Path::new("secret"), "backups/vault", &options, &cancel)?;
+/// ```
+pub fn shard_into_s3(
+    secret_path: &Path,
+    location: &str,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<String>> {
+    check_options(options)?;
+    with_secret(secret_path, options, |secret| {
+        shard_bytes_into_s3(secret, location, options, cancel)
+    })
+}
+
+/// Shards a secret held in memory and uploads its shards, like `shard_into_s3`.
+///
+/// # Returns
+///
+/// The keys of the shards, with the errors of `encode_shards`, `Error::InvalidParameters` if
+/// the location is invalid, the store is not configured or the `cloud` feature is disabled,
+/// or an `Error::Io` naming the shards uploaded and those which were not if an upload
+/// failed, so that they can be uploaded again.
+pub fn shard_bytes_into_s3(
+    secret: &[u8],
+    location: &str,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<String>> {
+    check_options(options)?;
+    check_secret_len(secret.len() as u64, options.max_secret_size)?;
+    if let Some(content_type) = &options.content_type {
+        header::validate_content_type(content_type)?;
+    }
+    let (shares, set_header) = split_secret(secret, options, cancel)?;
+    upload_shards(location, &shares, &set_header, options, cancel)
+}
+
+/// Uploads the shards of a set with a `cloud::S3Sink`, handling the shards already stored
+/// under the prefix according to `options.overwrite_policy` like `with_overwrite_policy`:
+/// backups are copied to a prefix below it, and the old shards which were not overwritten
+/// are removed once the new set is uploaded.
+#[cfg(feature = "cloud")]
+fn upload_shards(
+    location: &str,
+    shares: &[Vec<u8>],
+    set_header: &Header,
+    options: &SharingConfig,
+    cancel: &AtomicBool,
+) -> Result<Vec<String>> {
+    use colored::Colorize;
+
+    let mut sink = crate::cloud::S3Sink::new(location.parse()?, &options.extension)?;
+    let existing = sink.existing_shards()?;
+    if options.overwrite_policy == OverwritePolicy::Fail && !existing.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already holds {} shards, use --overwrite-policy overwrite or backup",
+                location,
+                existing.len()
+            ),
+        )
+        .into());
+    }
+    if options.overwrite_policy == OverwritePolicy::Backup {
+        let backup_keys = sink.back_up(&existing)?;
+        if options.verbosity == Verbosity::Verbose {
+            for (key, backup_key) in existing.iter().zip(&backup_keys) {
+                println!("Backed up {} to {}", key, backup_key);
+            }
+        }
+    }
+    timing::measure(options.timings.as_deref(), "write shards", || {
+        if let Err(e) = write_to_sink(&mut sink, shares, set_header, options, cancel) {
+            // The shards already uploaded are left in place, so the operator must know them.
+            if options.verbosity > Verbosity::Quiet && !sink.uploaded().is_empty() {
+                eprintln!(
+                    "{} the upload stopped after {} were stored",
+                    "WARNING:".yellow().bold(),
+                    sink.uploaded().join(", ")
+                );
+            }
+            return Err(e);
+        }
+        sink.finish_upload(&existing)
+    })
+}
+
+/// Object stores are rejected when the `cloud` feature is disabled.
+#[cfg(not(feature = "cloud"))]
+fn upload_shards(
+    _: &str,
+    _: &[Vec<u8>],
+    _: &Header,
+    _: &SharingConfig,
+    _: &AtomicBool,
+) -> Result<Vec<String>> {
+    Err(Error::InvalidParameters(
+        "uploading the shards to an object store requires building with the `cloud` feature"
+            .to_string(),
+    ))
+}
+
+/// Runs `write` to create a single output file, such as a bundle, after handling an existing
+/// file according to `options.overwrite_policy`.
+///
+/// The existing file is renamed with a timestamp suffix rather than overwritten, and moved
+/// back in place if `write` fails; with `OverwritePolicy::Overwrite`, it is removed once the
+/// new file is written.
+///
+/// # Returns
+///
+/// The result of `write`, or an `Error::Io` of kind `io::ErrorKind::AlreadyExists` if the
+/// file exists and the policy is `OverwritePolicy::Fail`.
+fn with_output_file<T>(
+    output_path: &Path,
+    options: &SharingConfig,
+    write: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let renamed = match options.overwrite_policy {
+        _ if !output_path.exists() => Vec::new(),
+        OverwritePolicy::Fail => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists, use --overwrite-policy overwrite or backup",
+                    output_path.display()
+                ),
+            )
+            .into())
+        }
+        OverwritePolicy::Overwrite | OverwritePolicy::Backup => {
+            back_up_shards(&[output_path.to_path_buf()])?
+        }
+    };
+    let written = write();
+    if written.is_err() {
+        let _ = fs::remove_file(output_path);
+        restore_backups(&renamed);
+        return written;
+    }
+    for (_, backup_path) in &renamed {
+        if options.overwrite_policy == OverwritePolicy::Overwrite {
+            fs::remove_file(backup_path)?;
+        } else if options.verbosity == Verbosity::Verbose {
+            println!(
+                "Backed up {} to {}",
+                output_path.display(),
+                backup_path.display()
+            );
+        }
+    }
+    written
+}
+
+/// Creates the shards directory if needed and runs `write` after handling the shards already
+/// in it according to `options.overwrite_policy`.
+///
+/// # Arguments
+///
+/// * `shards_path` - The directory where the shards are written.
+/// * `options` - The `SharingConfig` to apply.
+/// * `write` - Writes the new shards and returns their paths.
+fn with_overwrite_policy(
+    shards_path: &Path,
+    options: &SharingConfig,
+    write: impl FnOnce() -> Result<Vec<PathBuf>>,
+) -> Result<Vec<PathBuf>> {
     // Check if the shards directory exists, if not, create it.
     if !shards_path.exists() {
         fs::create_dir_all(shards_path)?;
     }
 
-    let shard_paths = shard_file(secret_path, parts, threshold)?;
+    let existing = existing_shards(shards_path, &options.extension)?;
+    if options.overwrite_policy == OverwritePolicy::Fail && !existing.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already holds {} shards, use --overwrite-policy overwrite or backup",
+                shards_path.display(),
+                existing.len()
+            ),
+        )
+        .into());
+    }
 
-    // Move shard files to the desired directory if necessary
-    let mut new_shard_paths = Vec::new();
-    for shard_path in shard_paths {
-        let shard_name = Path::new(&shard_path)
-            .file_name()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to extract file name"))?;
-        let new_path = shards_path.join(shard_name);
-        fs::rename(&shard_path, &new_path)?;
-        new_shard_paths.push(new_path.to_string_lossy().to_string());
+    let renamed = match options.overwrite_policy {
+        OverwritePolicy::Backup => back_up_shards(&existing)?,
+        _ => Vec::new(),
+    };
+    let sharded = write();
+    match &sharded {
+        Err(_) => restore_backups(&renamed),
+        Ok(shard_paths) if options.overwrite_policy == OverwritePolicy::Overwrite => {
+            remove_stale_shards(
+                &existing,
+                shard_paths,
+                &options.extension,
+                options.verbosity,
+            )?;
+        }
+        Ok(_) => {}
     }
+    if sharded.is_ok() && options.verbosity == Verbosity::Verbose {
+        for (shard_path, backup_path) in &renamed {
+            println!(
+                "Backed up {} to {}",
+                shard_path.display(),
+                backup_path.display()
+            );
+        }
+    }
+    sharded
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, sync::Arc, thread, time::Duration};
+
+    /// Default options which only print errors.
+    fn quiet() -> SharingConfig {
+        SharingConfig {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shard_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let shards_path = dir.path().join("shards");
+
+        shard_secret(
+            &secret_path,
+            &shards_path,
+            &quiet(),
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 5);
+    }
+
+    #[test]
+    fn test_options_builder() {
+        let options = SharingConfig::builder()
+            .parts(4)
+            .threshold(2)
+            .output_format(OutputFormat::Base64Lines)
+            .build()
+            .unwrap();
+        assert_eq!((options.parts, options.threshold), (4, 2));
+        assert_eq!(options.output_format, OutputFormat::Base64Lines);
+        assert_eq!(options.extension, header::SHARD_EXTENSION);
+
+        for (builder, message) in [
+            (SharingConfig::builder().threshold(1), "at least 2"),
+            (SharingConfig::builder().parts(2).threshold(3), "less than"),
+            (
+                SharingConfig::builder().parts(256).threshold(3),
+                "exceed 255",
+            ),
+            (
+                SharingConfig::builder().content_type(Some("text\nplain".to_string())),
+                "content-type",
+            ),
+            (
+                SharingConfig::builder()
+                    .label(Some("label".to_string()))
+                    .output_format(OutputFormat::Json),
+                "files output format",
+            ),
+            (
+                SharingConfig::builder().headerless(true).uniform_size(true),
+                "headerless",
+            ),
+            (
+                SharingConfig::builder()
+                    .block_checksums(true)
+                    .layout(Layout::Column),
+                "block checksums",
+            ),
+            (
+                SharingConfig::builder()
+                    .block_checksums(true)
+                    .output_format(OutputFormat::Bundle),
+                "not to a bundle",
+            ),
+        ] {
+            match builder.build() {
+                Err(Error::InvalidParameters(e)) => assert!(e.contains(message), "{}", e),
+                other => panic!("expected an invalid parameters error, got {:?}", other),
+            }
+        }
+
+        // A passphrase set on built options is checked too.
+        let options = SharingConfig::builder().parts(255).build().unwrap();
+        let passphrase = Passphrase::new("correct horse".to_string()).unwrap();
+        match options.with_passphrase(Some(passphrase)) {
+            Err(Error::InvalidParameters(e)) => assert!(e.contains("exceed 254"), "{}", e),
+            other => panic!("expected an invalid parameters error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_shard_verify_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SharingConfig {
+            parts: 4,
+            threshold: 2,
+            verify_all: true,
+            ..quiet()
+        };
+        for (layout, headerless) in [
+            (Layout::Row, false),
+            (Layout::Column, false),
+            (Layout::Row, true),
+        ] {
+            let shards_path = dir.path().join(format!("{:?}_{}", layout, headerless));
+            let options = SharingConfig {
+                layout,
+                headerless,
+                ..options.clone()
+            };
+            shard_bytes(b"test", &shards_path, &options, &AtomicBool::new(false)).unwrap();
+        }
+
+        let timings = Arc::new(Timings::default());
+        let options = SharingConfig {
+            timings: Some(Arc::clone(&timings)),
+            ..options
+        };
+        let shards_path = dir.path().join("timed");
+        shard_bytes(b"test", &shards_path, &options, &AtomicBool::new(false)).unwrap();
+        assert!(timings.report(Duration::ZERO).contains("verify"));
+
+        let err = SharingConfig::builder()
+            .parts(200)
+            .threshold(3)
+            .verify_all(true)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(ref e) if e.contains("1313400 quorums")));
+    }
+
+    #[test]
+    fn test_shard_mnemonic_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_path = dir.path().join("shards");
+        let options = SharingConfig {
+            parts: 3,
+            threshold: 2,
+            encoding: Encoding::Mnemonic,
+            verify_all: true,
+            ..quiet()
+        };
+        let cancel = AtomicBool::new(false);
+        let shard_paths = shard_bytes(b"seed phrase", &shards_path, &options, &cancel).unwrap();
+        let text = fs::read_to_string(&shard_paths[0]).unwrap();
+        assert!(mnemonic::as_mnemonic(text.as_bytes()).is_some(), "{}", text);
+        assert!(header::has_magic(&mnemonic::decode(&text).unwrap()));
+
+        // Transcribed by hand, on a single line and in capitals.
+        let transcribed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        fs::write(&shard_paths[0], transcribed.to_uppercase()).unwrap();
+        fs::remove_file(&shard_paths[1]).unwrap();
+        let combine_options = combine::CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        };
+        let (secret, _) = combine::recover_secret(&shards_path, &combine_options, &cancel).unwrap();
+        assert_eq!(secret, b"seed phrase");
+
+        let column = SharingConfig {
+            layout: Layout::Column,
+            ..options
+        };
+        assert!(matches!(
+            check_options(&column),
+            Err(Error::InvalidParameters(_))
+        ));
+    }
+
+    #[test]
+    fn test_shard_seeded_is_reproducible() {
+        let seed = [3; 32];
+        let options = SharingConfig {
+            passphrase: Some(Passphrase::new("correct horse".to_string()).unwrap()),
+            ..quiet()
+        };
+        let seeded = SharingConfig {
+            seed: Some(Zeroizing::new(seed)),
+            ..options.clone()
+        };
+        let cancel = AtomicBool::new(false);
+        let shards = encode_shards(b"secret", &seeded, &cancel).unwrap();
+        assert_eq!(encode_shards(b"secret", &seeded, &cancel).unwrap(), shards);
+        assert_ne!(encode_shards(b"secret", &options, &cancel).unwrap(), shards);
+        let other_seed = SharingConfig {
+            seed: Some(Zeroizing::new([4; 32])),
+            ..seeded.clone()
+        };
+        assert_ne!(
+            encode_shards(b"secret", &other_seed, &cancel).unwrap(),
+            shards
+        );
+
+        // Only the hash of the seed is recorded, in the shards and in the sidecar.
+        let (shard_header, _) = Header::from_bytes(&shards[0]).unwrap();
+        assert_eq!(shard_header.seed_hash, Some(rng::seed_hash(&seed)));
+        assert!(!shards[0].windows(seed.len()).any(|window| window == seed));
+        let dir = tempfile::tempdir().unwrap();
+        let with_sidecar = SharingConfig {
+            passphrase: None,
+            sidecar: true,
+            ..seeded
+        };
+        shard_bytes(
+            b"secret",
+            &dir.path().join("shards"),
+            &with_sidecar,
+            &cancel,
+        )
+        .unwrap();
+        let set_header = sidecar::read(&dir.path().join("shards")).unwrap().unwrap();
+        assert_eq!(set_header.seed_hash, Some(rng::seed_hash(&seed)));
+    }
+
+    #[test]
+    fn test_shard_secret_output_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        for output_format in [
+            OutputFormat::Bundle,
+            OutputFormat::Json,
+            OutputFormat::Base64Lines,
+        ] {
+            let output_path = dir.path().join(format!("{:?}", output_format));
+            let options = SharingConfig {
+                output_format,
+                overwrite_policy: OverwritePolicy::Fail,
+                ..quiet()
+            };
+            let shard_paths = shard_secret(
+                &secret_path,
+                &output_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+            assert_eq!(shard_paths, [output_path.as_path()]);
+            assert!(output_path.is_file());
+
+            // The file is never overwritten with the fail policy, nor a bundle at all.
+            let err = shard_secret(
+                &secret_path,
+                &output_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+            .unwrap_err();
+            assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        }
+
+        let options = SharingConfig {
+            output_format: OutputFormat::Json,
+            label: Some("label".to_string()),
+            ..quiet()
+        };
+        let output_path = dir.path().join("labelled");
+        let err = shard_secret(
+            &secret_path,
+            &output_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+    }
+
+    #[test]
+    fn test_headerless_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let shards_path = dir.path().join("shards");
+        let options = SharingConfig {
+            headerless: true,
+            overwrite_policy: OverwritePolicy::Fail,
+            ..quiet()
+        };
+
+        let shard_paths = shard_secret(
+            &secret_path,
+            &shards_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(shard_paths.len(), 5);
+        let manifest = sidecar::read_manifest(&shards_path.join(sidecar::FILE_NAME)).unwrap();
+        let set_header = manifest.set_header;
+        assert_eq!((set_header.threshold, set_header.parts), (3, 5));
+        assert_eq!(manifest.shards.len(), 5);
+        for shard_path in &shard_paths {
+            assert!(combine::bare_share_x(shard_path).is_some());
+            assert_eq!(fs::read(shard_path).unwrap().len(), 4);
+        }
+
+        // Headerless shards and their sidecar count as existing shards, other files named
+        // after a number do not.
+        let unlisted = (6..=255)
+            .find(|x| manifest.shards.iter().all(|(_, listed)| listed != x))
+            .unwrap();
+        let unlisted_path = shards_path.join(format!("{}.sss", unlisted));
+        fs::write(shards_path.join("1.pdf"), b"not a shard").unwrap();
+        fs::write(&unlisted_path, b"not listed").unwrap();
+        assert_eq!(existing_shards(&shards_path, "sss").unwrap().len(), 6);
+        let err = shard_secret(
+            &secret_path,
+            &shards_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+
+        // Overwriting the set removes its stale shards and nothing else.
+        let overwrite = SharingConfig {
+            sequential_x: true,
+            overwrite_policy: OverwritePolicy::Overwrite,
+            ..options.clone()
+        };
+        let shard_paths = shard_secret(
+            &secret_path,
+            &shards_path,
+            &overwrite,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 8);
+        assert_eq!(fs::read(shards_path.join("1.pdf")).unwrap(), b"not a shard");
+        assert_eq!(fs::read(&unlisted_path).unwrap(), b"not listed");
+        assert!(shard_paths.iter().all(|shard_path| shard_path.is_file()));
+
+        let options = SharingConfig {
+            layout: Layout::Column,
+            ..options
+        };
+        let err = shard_secret(
+            &secret_path,
+            &dir.path().join("columns"),
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+    }
+
+    #[test]
+    fn test_shard_secret_from_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = shard_secret(
+            Path::new("http://127.0.0.1:9/secret"),
+            &dir.path().join("shards"),
+            &quiet(),
+            &AtomicBool::new(false),
+        );
+        // The URL is fetched rather than opened as a file, and nothing answers on the discard
+        // port.
+        if cfg!(feature = "net") {
+            assert!(matches!(result, Err(Error::Io(_))));
+        } else {
+            assert!(matches!(result, Err(Error::InvalidParameters(_))));
+        }
+    }
+
+    #[test]
+    fn test_shard_secret_overwrite_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let shards_path = dir.path().join("shards");
+        let shard_with = |overwrite_policy| {
+            let options = SharingConfig {
+                overwrite_policy,
+                ..quiet()
+            };
+            shard_secret(
+                &secret_path,
+                &shards_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+        };
+        let old_paths = shard_with(OverwritePolicy::Fail).unwrap();
+        let old_shard = fs::read(&old_paths[0]).unwrap();
+
+        let err = shard_with(OverwritePolicy::Fail).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read(&old_paths[0]).unwrap(), old_shard);
+
+        let new_paths = shard_with(OverwritePolicy::Overwrite).unwrap();
+        assert_eq!(new_paths, old_paths);
+        assert_ne!(fs::read(&new_paths[0]).unwrap(), old_shard);
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 5);
+
+        let replaced_shard = fs::read(&new_paths[0]).unwrap();
+        shard_with(OverwritePolicy::Backup).unwrap();
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 10);
+        let backup_path = fs::read_dir(&shards_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("shards_0.sss.")
+            })
+            .unwrap();
+        assert_eq!(fs::read(backup_path).unwrap(), replaced_shard);
+        assert_ne!(fs::read(&new_paths[0]).unwrap(), replaced_shard);
+    }
+
+    #[test]
+    fn test_bundle_overwrite_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let bundle_path = dir.path().join("bundle.sss");
+        let shard_with = |overwrite_policy| {
+            let options = SharingConfig {
+                overwrite_policy,
+                output_format: OutputFormat::Bundle,
+                ..quiet()
+            };
+            shard_secret(
+                &secret_path,
+                &bundle_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+        };
+        shard_with(OverwritePolicy::Fail).unwrap();
+        let old_bundle = fs::read(&bundle_path).unwrap();
+
+        let err = shard_with(OverwritePolicy::Fail).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read(&bundle_path).unwrap(), old_bundle);
+
+        // The bundle is replaced, not appended to.
+        shard_with(OverwritePolicy::Overwrite).unwrap();
+        let replaced_bundle = fs::read(&bundle_path).unwrap();
+        assert_ne!(replaced_bundle, old_bundle);
+        assert_eq!(
+            crate::bundle::read_records(&replaced_bundle).unwrap().len(),
+            1
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        shard_with(OverwritePolicy::Backup).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+        let backup_path = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("bundle.sss.")
+            })
+            .unwrap();
+        assert_eq!(fs::read(backup_path).unwrap(), replaced_bundle);
+    }
+
+    #[test]
+    fn test_shard_secret_overwrite_removes_stale_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let shards_path = dir.path().join("shards");
+        let shard_with = |parts, threshold, overwrite_policy| {
+            let options = SharingConfig {
+                parts,
+                threshold,
+                overwrite_policy,
+                ..quiet()
+            };
+            shard_secret(
+                &secret_path,
+                &shards_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+            .unwrap()
+        };
+        let file_count = || fs::read_dir(&shards_path).unwrap().count();
+
+        shard_with(6, 4, OverwritePolicy::Overwrite);
+        shard_with(7, 4, OverwritePolicy::Backup);
+        assert_eq!(file_count(), 13);
+
+        // Shards 5 and 6 of the 4-of-7 set are removed, the backups of the first set are kept.
+        let new_paths = shard_with(5, 3, OverwritePolicy::Overwrite);
+        assert_eq!(file_count(), 11);
+        assert!(!shards_path.join("shards_5.sss").exists());
+        assert!(!shards_path.join("shards_6.sss").exists());
+        for new_path in &new_paths {
+            assert!(new_path.is_file());
+        }
+    }
+
+    #[test]
+    fn test_overwrite_policy_over_mnemonic_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let shards_path = dir.path().join("shards");
+        let shard_with = |parts, overwrite_policy| {
+            let options = SharingConfig {
+                parts,
+                threshold: 3,
+                overwrite_policy,
+                encoding: Encoding::Mnemonic,
+                ..quiet()
+            };
+            shard_secret(
+                &secret_path,
+                &shards_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+        };
+        let file_count = || fs::read_dir(&shards_path).unwrap().count();
+
+        let old_paths = shard_with(7, OverwritePolicy::Fail).unwrap();
+        let old_shard = fs::read(&old_paths[0]).unwrap();
+        let err = shard_with(7, OverwritePolicy::Fail).unwrap_err();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read(&old_paths[0]).unwrap(), old_shard);
+
+        shard_with(7, OverwritePolicy::Backup).unwrap();
+        assert_eq!(file_count(), 14);
+
+        // Shards 5 and 6 of the 3-of-7 set are removed, the backups are kept.
+        shard_with(5, OverwritePolicy::Overwrite).unwrap();
+        assert_eq!(file_count(), 12);
+        assert!(!shards_path.join("shards_5.sss").exists());
+        assert!(!shards_path.join("shards_6.sss").exists());
+    }
+
+    #[test]
+    fn test_shard_secret_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+
+        let shard_paths = shard_secret(
+            &secret_path,
+            &dir.path().join("default"),
+            &quiet(),
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert!(shard_paths
+            .iter()
+            .all(|path| path.extension() == Some("sss".as_ref())));
+
+        let options = SharingConfig {
+            extension: String::new(),
+            ..quiet()
+        };
+        let shard_paths = shard_secret(
+            &secret_path,
+            &dir.path().join("bare"),
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert!(shard_paths.iter().all(|path| path.extension().is_none()));
+    }
+
+    #[test]
+    fn test_shard_secret_column_layout() {
+        use crate::combine::{combine_secret, CombineOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+        let shards_path = dir.path().join("shards");
+
+        let options = SharingConfig {
+            layout: Layout::Column,
+            embed_hash: true,
+            ..quiet()
+        };
+        let cancel = AtomicBool::new(false);
+        let shard_paths = shard_secret(&secret_path, &shards_path, &options, &cancel).unwrap();
+        assert_eq!(shard_paths, [shards_path.join("shards_columns.sss")]);
+        // Magic, header with the hash entry, 5 shares of 4 bytes and their x-coordinates,
+        // and checksum.
+        assert_eq!(
+            fs::metadata(&shard_paths[0]).unwrap().len(),
+            4 + 26 + 35 + 5 * 5 + 32
+        );
+
+        let options = CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        };
+        for (source, recovered) in [(&shard_paths[0], "from_file"), (&shards_path, "from_dir")] {
+            let recovered_path = dir.path().join(recovered);
+            combine_secret(source, &recovered_path, &options, &cancel).unwrap();
+            assert_eq!(fs::read(recovered_path).unwrap(), b"test");
+        }
+    }
+
+    #[test]
+    fn test_shard_secret_uniform_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"test").unwrap();
+
+        let mut sizes = HashSet::new();
+        for embed_hash in [false, true] {
+            let shards_path = dir.path().join(format!("shards_{}", embed_hash));
+            let options = SharingConfig {
+                embed_hash,
+                uniform_size: true,
+                ..quiet()
+            };
+            shard_secret(
+                &secret_path,
+                &shards_path,
+                &options,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+            for entry in fs::read_dir(&shards_path).unwrap() {
+                sizes.insert(entry.unwrap().metadata().unwrap().len());
+            }
+        }
+        assert_eq!(sizes.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shard_secret_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret.fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&secret_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let shards_path = dir.path().join("shards");
+
+        // The flag is raised before the end of the secret is written, so the job is always
+        // under way, and never over, when it is cancelled.
+        let cancel = Arc::new(AtomicBool::new(false));
+        let timer = Arc::clone(&cancel);
+        let writer_path = secret_path.clone();
+        let writer = thread::spawn(move || {
+            let mut pipe = File::create(writer_path).unwrap();
+            std::io::Write::write_all(&mut pipe, &vec![42; 1 << 20]).unwrap();
+            timer.store(true, Ordering::Relaxed);
+        });
+
+        let err = shard_secret(&secret_path, &shards_path, &quiet(), &cancel).unwrap_err();
+        writer.join().unwrap();
+        assert!(matches!(err, Error::Cancelled));
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_read_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let read = |path: &Path| with_secret(path, &quiet(), |secret| Ok(secret.to_vec()));
+        // Small and large files, read onto the heap or mapped with the `mmap` feature.
+        for len in [0, 5, 1 << 20] {
+            let secret_path = dir.path().join(format!("secret_{}", len));
+            let secret: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            fs::write(&secret_path, &secret).unwrap();
+            assert_eq!(read(&secret_path).unwrap(), secret);
+        }
+
+        // A pipe reports no size, and is read to its end.
+        #[cfg(unix)]
+        {
+            let fifo_path = dir.path().join("secret.fifo");
+            let status = std::process::Command::new("mkfifo")
+                .arg(&fifo_path)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let writer_path = fifo_path.clone();
+            let writer = thread::spawn(move || fs::write(writer_path, b"piped secret").unwrap());
+            assert_eq!(read(&fifo_path).unwrap(), b"piped secret");
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_shard_empty_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"").unwrap();
+        let shards_path = dir.path().join("shards");
+
+        let err = shard_secret(
+            &secret_path,
+            &shards_path,
+            &quiet(),
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_shard_many_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_path = dir.path().join("shards");
+        let options = SharingConfig {
+            parts: 50,
+            threshold: 20,
+            ..quiet()
+        };
+
+        let shard_paths =
+            shard_bytes(b"test", &shards_path, &options, &AtomicBool::new(false)).unwrap();
+        assert_eq!(shard_paths.len(), 50);
+        let mut xs = HashSet::new();
+        for (index, shard_path) in shard_paths.iter().enumerate() {
+            assert_eq!(
+                shard_path,
+                &shards_path.join(format!("shards_{}.sss", index))
+            );
+            let shard = fs::read(shard_path).unwrap();
+            let (header, body) = header::decode_shard(&shard).unwrap();
+            assert_eq!((header.threshold, header.parts, body.len()), (20, 50, 4));
+            assert!(xs.insert(header.x));
+        }
+    }
+
+    #[test]
+    fn test_shard_failed_write_removes_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_path = dir.path().join("shards");
+        // A directory in the way of one shard makes its write fail.
+        fs::create_dir_all(shards_path.join("shards_17.sss")).unwrap();
+        let options = SharingConfig {
+            parts: 50,
+            threshold: 20,
+            ..quiet()
+        };
+
+        let err =
+            shard_bytes(b"test", &shards_path, &options, &AtomicBool::new(false)).unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+        let left: Vec<_> = fs::read_dir(&shards_path).unwrap().collect();
+        assert_eq!(left.len(), 1);
+    }
+
+    #[test]
+    fn test_shard_secret_over_max_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("secret");
+        fs::write(&secret_path, b"0123456789").unwrap();
+        let shards_path = dir.path().join("shards");
+        let options = SharingConfig {
+            max_secret_size: Some(9),
+            ..quiet()
+        };
+
+        let err = shard_secret(
+            &secret_path,
+            &shards_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+        assert_eq!(fs::read_dir(&shards_path).unwrap().count(), 0);
+
+        let options = SharingConfig {
+            max_secret_size: Some(10),
+            ..quiet()
+        };
+        let shards = shard_secret(
+            &secret_path,
+            &shards_path,
+            &options,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(shards.len(), options.parts);
+    }
+
+    #[test]
+    fn test_read_label() {
+        let dir = tempfile::tempdir().unwrap();
+        let label_path = dir.path().join("label");
+        fs::write(&label_path, "Ask Alice\nthen Bob\n").unwrap();
+        assert_eq!(read_label(&label_path).unwrap(), "Ask Alice\nthen Bob\n");
+
+        fs::write(&label_path, vec![b'a'; MAX_LABEL_LEN as usize + 1]).unwrap();
+        let err = read_label(&label_path).unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+
+        fs::write(&label_path, [0xff, 0xfe]).unwrap();
+        let err = read_label(&label_path).unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+    }
+
+    #[test]
+    fn test_manifest_removed_without_label() {
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = AtomicBool::new(false);
+        let labelled = SharingConfig {
+            label: Some("Ask Alice".to_string()),
+            ..quiet()
+        };
+        shard_bytes(b"secret", dir.path(), &labelled, &cancel).unwrap();
+        assert_eq!(read_manifest(dir.path()).unwrap().unwrap(), "Ask Alice");
+
+        // Splitting another secret without a label does not leave the old one behind.
+        shard_bytes(b"other secret", dir.path(), &quiet(), &cancel).unwrap();
+        assert_eq!(read_manifest(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_passphrase_share_replaces_a_shard() {
+        use crate::combine::{combine_from_iter, CombineOptions};
+
+        let passphrase = Passphrase::new("correct horse".to_string()).unwrap();
+        let options = SharingConfig {
+            passphrase: Some(passphrase.clone()),
+            ..quiet()
+        };
+        let cancel = AtomicBool::new(false);
+        let shards = encode_shards(b"secret", &options, &cancel).unwrap();
+        assert_eq!(shards.len(), options.parts);
+
+        let combine_with = |passphrase: Option<Passphrase>| {
+            let options = CombineOptions {
+                passphrase,
+                verbosity: Verbosity::Quiet,
+                ..Default::default()
+            };
+            combine_from_iter(shards[..2].iter().cloned().map(Ok), &options, &cancel)
+        };
+        let secret = combine_with(Some(passphrase)).unwrap();
+        assert_eq!(secret, b"secret");
+
+        let wrong = Passphrase::new("wrong horse".to_string()).unwrap();
+        assert!(matches!(
+            combine_with(Some(wrong)),
+            Err(Error::InvalidParameters(_))
+        ));
+        assert!(matches!(
+            combine_with(None),
+            Err(Error::InsufficientShares { have: 2, need: 3 })
+        ));
+    }
 }
@@ -0,0 +1,375 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    error::{Error, Result},
+    header::{self, Header},
+    json::{self, Value},
+};
+
+/// Name of the file holding the parameters of a set of headerless shards, next to them.
+pub const FILE_NAME: &str = "scheme.json";
+
+/// Current version of the sidecar format.
+const VERSION: u64 = 1;
+
+/// The content of a sidecar: the parameters of a shard set and, optionally, its shards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// The header common to the shards of the set, with an x-coordinate of 0.
+    pub set_header: Header,
+    /// The file name and x-coordinate of every shard of the set, or empty if they are not
+    /// listed.
+    pub shards: Vec<(String, u8)>,
+}
+
+/// Serializes the parameters of a shard set as the JSON object stored in the sidecar.
+///
+/// The set id and the hash are written as hexadecimal strings, and missing fields as `null`.
+/// The creation and expiry times, and the file name, mode and modification time recorded by
+/// `shard --store-metadata`, and the hash of the seed of `shard --seed`, are only written when
+/// the set has them. The x-coordinate and
+/// passphrase share are left out: headerless shards carry their x-coordinate in their file
+/// name and cannot be split with a passphrase.
+///
+/// # Arguments
+///
+/// * `set_header` - The header common to the shards of the set.
+/// * `shards` - The file name and x-coordinate of every shard, listed in a `shards` array
+///   unless empty.
+pub fn to_json(set_header: &Header, shards: &[(String, u8)]) -> String {
+    let string =
+        |value: Option<String>| value.map_or("null".to_string(), |value| json::string(&value));
+    let hex = |hash: [u8; 32]| json::encode_hex(&hash);
+    let hash = set_header.secret_hash.map(hex);
+    let file_name = set_header
+        .file_name
+        .clone()
+        .map(|file_name| format!(",\n  \"file_name\": {}", string(Some(file_name))));
+    let seed_hash = set_header
+        .seed_hash
+        .map(|seed_hash| format!(",\n  \"seed_hash\": {}", string(Some(hex(seed_hash)))));
+    let optional: String = [
+        ("created", set_header.created),
+        ("expires", set_header.expires),
+        ("file_mode", set_header.file_mode.map(u64::from)),
+        ("file_mtime", set_header.file_mtime),
+    ]
+    .iter()
+    .filter_map(|(key, value)| value.map(|value| format!(",\n  \"{}\": {}", key, value)))
+    .chain(file_name)
+    .chain(seed_hash)
+    .collect();
+    let shards = if shards.is_empty() {
+        String::new()
+    } else {
+        let entries: Vec<String> = shards
+            .iter()
+            .map(|(file, x)| {
+                format!(
+                    "    {{\"file\": {}, \"x\": {}}}",
+                    string(Some(file.clone())),
+                    x
+                )
+            })
+            .collect();
+        format!(",\n  \"shards\": [\n{}\n  ]", entries.join(",\n"))
+    };
+    format!(
+        "{{\n  \"version\": {},\n  \"threshold\": {},\n  \"parts\": {},\n  \"set_id\": \"{:016x}\",\n  \"secret_len\": {},\n  \"secret_hash\": {},\n  \"content_type\": {}{}{}\n}}\n",
+        VERSION,
+        set_header.threshold,
+        set_header.parts,
+        set_header.set_id,
+        set_header.secret_len,
+        string(hash),
+        string(set_header.content_type.clone()),
+        optional,
+        shards
+    )
+}
+
+/// Parses the JSON object written by `to_json`, in any formatting and key order.
+///
+/// # Returns
+///
+/// The parameters of the set and its shards, or `Error::InvalidHeader` if the object is
+/// malformed, of an unknown version, holds a threshold or parts out of range, or lists a
+/// shard without a file name or with an invalid x-coordinate.
+pub fn from_json(text: &str) -> Result<Manifest> {
+    let invalid = |message: &str| Error::InvalidHeader(format!("invalid sidecar: {}", message));
+    let Some(Value::Object(mut fields)) = json::parse(text) else {
+        return Err(invalid("not a JSON object"));
+    };
+    let mut field = |key: &str| fields.remove(key).unwrap_or(Value::Null);
+
+    let number = |value: Value, key: &str| match value {
+        Value::Number(number) => Ok(number),
+        _ => Err(invalid(&format!("`{}` must be a number", key))),
+    };
+    let optional_number = |value: Value, key: &str| match value {
+        Value::Number(number) => Ok(Some(number)),
+        Value::Null => Ok(None),
+        _ => Err(invalid(&format!("`{}` must be a number", key))),
+    };
+    let optional_string = |value: Value, key: &str| match value {
+        Value::String(string) => Ok(Some(string)),
+        Value::Null => Ok(None),
+        _ => Err(invalid(&format!("`{}` must be a string", key))),
+    };
+
+    if number(field("version"), "version")? != VERSION {
+        return Err(invalid("unsupported version"));
+    }
+    let threshold = u8::try_from(number(field("threshold"), "threshold")?)
+        .ok()
+        .filter(|&threshold| threshold >= 2)
+        .ok_or_else(|| invalid("threshold must be between 2 and 255"))?;
+    let parts = u8::try_from(number(field("parts"), "parts")?)
+        .ok()
+        .filter(|&parts| parts >= threshold)
+        .ok_or_else(|| invalid("parts must be between the threshold and 255"))?;
+    let set_id = optional_string(field("set_id"), "set_id")?
+        .and_then(|set_id| decode_hex::<8>(&set_id))
+        .ok_or_else(|| invalid("`set_id` must be 16 hexadecimal digits"))?;
+    let secret_hash = match optional_string(field("secret_hash"), "secret_hash")? {
+        Some(hash) => Some(
+            decode_hex::<32>(&hash)
+                .ok_or_else(|| invalid("`secret_hash` must be 64 hexadecimal digits"))?,
+        ),
+        None => None,
+    };
+    let seed_hash = match optional_string(field("seed_hash"), "seed_hash")? {
+        Some(hash) => Some(
+            decode_hex::<32>(&hash)
+                .ok_or_else(|| invalid("`seed_hash` must be 64 hexadecimal digits"))?,
+        ),
+        None => None,
+    };
+    let content_type = optional_string(field("content_type"), "content_type")?;
+    if let Some(content_type) = &content_type {
+        header::validate_content_type(content_type).map_err(|e| invalid(&e.to_string()))?;
+    }
+    let file_mode = match optional_number(field("file_mode"), "file_mode")? {
+        Some(file_mode) => Some(
+            u32::try_from(file_mode)
+                .ok()
+                .filter(|&file_mode| file_mode <= 0o7777)
+                .ok_or_else(|| invalid("`file_mode` must be Unix permissions"))?,
+        ),
+        None => None,
+    };
+    let shards = match field("shards") {
+        Value::Null => Vec::new(),
+        Value::Array(entries) => entries
+            .into_iter()
+            .map(|entry| {
+                let Value::Object(mut entry) = entry else {
+                    return Err(invalid("every shard must be an object"));
+                };
+                let file = match entry.remove("file") {
+                    Some(Value::String(file)) if !file.is_empty() => file,
+                    _ => return Err(invalid("every shard must have a `file` name")),
+                };
+                let x = match entry.remove("x") {
+                    Some(Value::Number(x)) => u8::try_from(x).ok().filter(|&x| x != 0),
+                    _ => None,
+                };
+                let x = x.ok_or_else(|| invalid("`x` must be between 1 and 255"))?;
+                Ok((file, x))
+            })
+            .collect::<Result<_>>()?,
+        _ => return Err(invalid("`shards` must be an array")),
+    };
+
+    let set_header = Header {
+        threshold,
+        parts,
+        x: 0,
+        set_id: u64::from_be_bytes(set_id),
+        secret_len: number(field("secret_len"), "secret_len")?,
+        secret_hash,
+        content_type,
+        file_name: optional_string(field("file_name"), "file_name")?,
+        passphrase: None,
+        block_tags: None,
+        created: optional_number(field("created"), "created")?,
+        expires: optional_number(field("expires"), "expires")?,
+        file_mode,
+        file_mtime: optional_number(field("file_mtime"), "file_mtime")?,
+        seed_hash,
+    };
+    Ok(Manifest { set_header, shards })
+}
+
+/// Writes the sidecar of a set of shards to their directory.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory of the shards.
+/// * `set_header` - The header common to the shards of the set.
+/// * `shards` - The file name and x-coordinate of every shard, see `to_json`.
+pub fn write(shards_dir: &Path, set_header: &Header, shards: &[(String, u8)]) -> io::Result<()> {
+    fs::write(shards_dir.join(FILE_NAME), to_json(set_header, shards))
+}
+
+/// Reads the sidecar of a shards directory.
+///
+/// # Returns
+///
+/// The header common to the shards, `None` if the directory has no sidecar, or the errors of
+/// `from_json` naming the sidecar.
+pub fn read(shards_dir: &Path) -> Result<Option<Header>> {
+    let path = shards_dir.join(FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    read_manifest(&path).map(|manifest| Some(manifest.set_header))
+}
+
+/// Reads a sidecar from any path, such as one given to `combine --manifest`.
+///
+/// # Returns
+///
+/// The content of the sidecar, or the errors of `from_json` naming it.
+pub fn read_manifest(path: &Path) -> Result<Manifest> {
+    let text = fs::read_to_string(path)?;
+    from_json(&text).map_err(|e| e.in_shard(&path.display().to_string()))
+}
+
+/// Gives a headerless shard the header described by the sidecar, so that it is validated and
+/// combined like any other shard.
+///
+/// # Arguments
+///
+/// * `set_header` - The header read from the sidecar.
+/// * `x` - The x-coordinate of the shard, taken from its file name.
+/// * `body` - The y-coordinates of the shard, the whole content of its file.
+///
+/// # Returns
+///
+/// The content of the shard with its header and checksum.
+pub fn attach(set_header: &Header, x: u8, body: &[u8]) -> Vec<u8> {
+    let shard_header = Header {
+        x,
+        ..set_header.clone()
+    };
+    header::encode_shard(&shard_header, body)
+}
+
+/// Decodes exactly `N` bytes written as hexadecimal digits.
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    json::decode_hex(hex)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_header() -> Header {
+        Header {
+            threshold: 3,
+            parts: 5,
+            set_id: 0x0123_4567_89ab_cdef,
+            secret_len: 42,
+            secret_hash: Some([7; 32]),
+            content_type: Some("text/plain; name=\"a\\b\"".to_string()),
+            created: Some(1_700_000_000),
+            expires: Some(1_700_086_400),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let json = to_json(&set_header(), &[]);
+        assert!(json.contains("\"set_id\": \"0123456789abcdef\""));
+        assert!(!json.contains("shards"));
+        assert_eq!(from_json(&json).unwrap().set_header, set_header());
+
+        assert!(json.contains("\"expires\": 1700086400"));
+        let with_metadata = Header {
+            file_name: Some("id_ed25519".to_string()),
+            file_mode: Some(0o600),
+            file_mtime: Some(1_700_000_000),
+            ..set_header()
+        };
+        let json_with_metadata = to_json(&with_metadata, &[]);
+        assert!(json_with_metadata.contains("\"file_mode\": 384"));
+        assert_eq!(
+            from_json(&json_with_metadata).unwrap().set_header,
+            with_metadata
+        );
+        let with_seed_hash = Header {
+            seed_hash: Some([0xab; 32]),
+            ..set_header()
+        };
+        let json_with_seed_hash = to_json(&with_seed_hash, &[]);
+        assert!(json_with_seed_hash.contains(&format!("\"seed_hash\": \"{}\"", "ab".repeat(32))));
+        assert_eq!(
+            from_json(&json_with_seed_hash).unwrap().set_header,
+            with_seed_hash
+        );
+        let minimal = Header {
+            secret_hash: None,
+            content_type: None,
+            created: None,
+            expires: None,
+            ..set_header()
+        };
+        assert_eq!(
+            from_json(&to_json(&minimal, &[])).unwrap().set_header,
+            minimal
+        );
+        // Reformatted and reordered.
+        let compact = "{\"secret_len\":42,\"set_id\":\"0123456789ABCDEF\",\"parts\":5,\
+                       \"threshold\":3,\"version\":1}";
+        assert_eq!(from_json(compact).unwrap().set_header, minimal);
+    }
+
+    #[test]
+    fn test_sidecar_shards() {
+        let shards = vec![
+            ("shards_0.sss".to_string(), 7),
+            ("a \"b\".sss".to_string(), 255),
+        ];
+        let manifest = from_json(&to_json(&set_header(), &shards)).unwrap();
+        assert_eq!(manifest.set_header, set_header());
+        assert_eq!(manifest.shards, shards);
+
+        let json = to_json(&set_header(), &shards[..1]);
+        for invalid in [
+            json.replace("\"x\": 7", "\"x\": 0"),
+            json.replace("\"x\": 7", "\"x\": 256"),
+            json.replace("\"file\": \"shards_0.sss\"", "\"file\": 7"),
+            json.replace("[", "[[").replace("]", "]]"),
+        ] {
+            assert!(
+                matches!(from_json(&invalid), Err(Error::InvalidHeader(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_sidecar() {
+        let json = to_json(&set_header(), &[]);
+        for invalid in [
+            "",
+            "[]",
+            &json.replace("\"version\": 1", "\"version\": 2"),
+            &json.replace("\"threshold\": 3", "\"threshold\": 1"),
+            &json.replace("\"parts\": 5", "\"parts\": 2"),
+            &json.replace("0123456789abcdef", "0123"),
+            &json.replace("\"secret_len\": 42", "\"secret_len\": \"42\""),
+            &json.replace("\"version\": 1", "\"version\": 1, \"file_mode\": 4096"),
+            &(json.clone() + "}"),
+        ] {
+            assert!(
+                matches!(from_json(invalid), Err(Error::InvalidHeader(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
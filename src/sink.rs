@@ -0,0 +1,456 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    bundle, columnar,
+    error::{Error, Result},
+    header::{self, Header},
+    sidecar,
+};
+
+/// Where the shards of a set are stored, whatever their format: every shard is handed over
+/// as it would be written to its own file, with its header and checksum.
+pub trait ShardSink {
+    /// Receives the header common to the shards of the set, before any shard is written.
+    ///
+    /// Sinks which store the shards as they are handed over have no use for it.
+    fn begin_set(&mut self, _set_header: &Header) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Stores the shard of index `index`, numbered from 0 in the order of the shares.
+    fn write_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()>;
+
+    /// Completes the set once every shard was written, such as by writing what was buffered.
+    fn finish(self) -> io::Result<()>;
+}
+
+/// Writes every shard to its own file in a shards directory, named after its index such as
+/// `shards_0.sss`.
+///
+/// The sink holds no state besides the directory, so that the shards can be written
+/// concurrently through clones of it.
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    dir: PathBuf,
+    extension: String,
+}
+
+impl DirectorySink {
+    /// Creates a sink writing to `dir`, which must exist, with files of extension `extension`,
+    /// which may be empty.
+    pub fn new(dir: &Path, extension: &str) -> Self {
+        DirectorySink {
+            dir: dir.to_path_buf(),
+            extension: extension.to_string(),
+        }
+    }
+
+    /// Returns the path of the file holding the shard of index `index`.
+    pub fn shard_path(&self, index: usize) -> PathBuf {
+        self.dir
+            .join(format!("{}_{}", "shards", index))
+            .with_extension(&self.extension)
+    }
+}
+
+impl ShardSink for DirectorySink {
+    fn write_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        fs::write(self.shard_path(index), bytes)
+    }
+
+    /// Every shard is written by `write_shard`, so nothing is left to do.
+    fn finish(self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the y-coordinates of every share to a file named after its x-coordinate, such as
+/// `17.sss`, and the header of the set to the sidecar once every shard is written, as
+/// `shard --no-header` does.
+#[derive(Debug)]
+pub struct HeaderlessSink {
+    dir: PathBuf,
+    extension: String,
+    set_header: Option<Header>,
+    /// The file name and x-coordinate of every shard written, as listed in the sidecar.
+    listed: Vec<(String, u8)>,
+}
+
+impl HeaderlessSink {
+    /// Creates a sink writing to `dir`, which must exist, with files of extension `extension`,
+    /// which may be empty.
+    pub fn new(dir: &Path, extension: &str) -> Self {
+        HeaderlessSink {
+            dir: dir.to_path_buf(),
+            extension: extension.to_string(),
+            set_header: None,
+            listed: Vec::new(),
+        }
+    }
+
+    /// Returns the path of the file holding the shard of x-coordinate `x`.
+    pub fn shard_path(&self, x: u8) -> PathBuf {
+        self.dir.join(x.to_string()).with_extension(&self.extension)
+    }
+}
+
+impl ShardSink for HeaderlessSink {
+    fn begin_set(&mut self, set_header: &Header) -> io::Result<()> {
+        self.set_header = Some(set_header.clone());
+        Ok(())
+    }
+
+    fn write_shard(&mut self, _index: usize, bytes: &[u8]) -> io::Result<()> {
+        let (shard_header, body) = header::decode_shard(bytes).map_err(into_io_error)?;
+        let shard_path = self.shard_path(shard_header.x);
+        fs::write(&shard_path, body)?;
+        let file_name = shard_path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        self.listed.push((file_name, shard_header.x));
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        let set_header = self
+            .set_header
+            .ok_or_else(|| into_io_error(no_set_header()))?;
+        sidecar::write(&self.dir, &set_header, &self.listed)
+    }
+}
+
+/// The shares of a set gathered by index, for the sinks storing the whole set as a single
+/// matrix in the column layout.
+#[derive(Debug, Default)]
+struct ShareMatrix {
+    /// The header of the set, with an x-coordinate of 0.
+    set_header: Option<Header>,
+    /// The shares written so far by index, each ending with its x-coordinate, or empty if the
+    /// shard of that index was not written.
+    shares: Vec<Zeroizing<Vec<u8>>>,
+}
+
+impl ShareMatrix {
+    /// Keeps the header of the set.
+    fn begin_set(&mut self, set_header: &Header) {
+        self.set_header = Some(Header {
+            x: 0,
+            ..set_header.clone()
+        });
+    }
+
+    /// Keeps the share of the shard of index `index`.
+    fn insert(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        let (shard_header, body) = header::decode_shard(bytes).map_err(into_io_error)?;
+        if self.shares.len() <= index {
+            self.shares.resize_with(index + 1, Zeroizing::default);
+        }
+        let mut share = Zeroizing::new(Vec::with_capacity(body.len() + 1));
+        share.extend_from_slice(body);
+        share.push(shard_header.x);
+        self.shares[index] = share;
+        Ok(())
+    }
+
+    /// Returns the header of the matrix and its columns.
+    ///
+    /// # Returns
+    ///
+    /// `Error::InvalidParameters` if the header of the set was not given or a shard was not
+    /// written.
+    fn into_columns(self, padded: bool) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        let set_header = self.set_header.ok_or_else(no_set_header)?;
+        if let Some(missing) = self.shares.iter().position(|share| share.is_empty()) {
+            return Err(Error::InvalidParameters(format!(
+                "shard {} of the set was not written",
+                missing
+            )));
+        }
+        if self.shares.is_empty() {
+            return Err(Error::InvalidParameters(
+                "no shard of the set was written".to_string(),
+            ));
+        }
+        Ok((
+            columnar::header_bytes(&set_header, padded),
+            Zeroizing::new(columnar::transpose(&self.shares)),
+        ))
+    }
+}
+
+/// Writes the shards of a set to a single file in the column layout, as `shard --layout
+/// column` does, once every shard is written. The file is removed if it cannot be written.
+#[derive(Debug)]
+pub struct ColumnSink {
+    path: PathBuf,
+    padded: bool,
+    matrix: ShareMatrix,
+}
+
+impl ColumnSink {
+    /// Creates a sink writing to the file at `path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The column layout file, created or replaced.
+    /// * `padded` - Whether to pad the header of the file like `Header::to_padded_bytes`.
+    pub fn new(path: &Path, padded: bool) -> Self {
+        ColumnSink {
+            path: path.to_path_buf(),
+            padded,
+            matrix: ShareMatrix::default(),
+        }
+    }
+}
+
+impl ShardSink for ColumnSink {
+    fn begin_set(&mut self, set_header: &Header) -> io::Result<()> {
+        self.matrix.begin_set(set_header);
+        Ok(())
+    }
+
+    fn write_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        self.matrix.insert(index, bytes)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        let (header_bytes, columns) = self
+            .matrix
+            .into_columns(self.padded)
+            .map_err(into_io_error)?;
+        let written = File::create(&self.path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            header::write_shard(&mut writer, &header_bytes, &columns)?;
+            writer.flush()
+        });
+        if written.is_err() {
+            let _ = fs::remove_file(&self.path);
+        }
+        written
+    }
+}
+
+/// Gathers the shards of a set and adds them to a bundle file as a single record in the
+/// column layout, as `shard --bundle` does.
+#[derive(Debug)]
+pub struct BundleSink {
+    path: PathBuf,
+    append: bool,
+    padded: bool,
+    matrix: ShareMatrix,
+}
+
+impl BundleSink {
+    /// Creates a sink adding a record to the bundle at `path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The bundle file, created unless `append` is set.
+    /// * `append` - Whether to append the record to an existing bundle.
+    /// * `padded` - Whether to pad the header of the record like `Header::to_padded_bytes`.
+    pub fn new(path: &Path, append: bool, padded: bool) -> Self {
+        BundleSink {
+            path: path.to_path_buf(),
+            append,
+            padded,
+            matrix: ShareMatrix::default(),
+        }
+    }
+
+    /// Writes the record gathered from the shards to the bundle, like `finish`.
+    ///
+    /// # Returns
+    ///
+    /// The index of the new record in the bundle, `Error::InvalidParameters` if the header of
+    /// the set was not given or a shard was not written, or the errors of
+    /// `bundle::write_record`.
+    pub fn finish_record(self) -> Result<usize> {
+        let (header_bytes, columns) = self.matrix.into_columns(self.padded)?;
+        let mut record = Zeroizing::new(Vec::new());
+        header::write_shard(&mut *record, &header_bytes, &columns)?;
+        bundle::write_record(&self.path, &record, self.append)
+    }
+}
+
+impl ShardSink for BundleSink {
+    fn begin_set(&mut self, set_header: &Header) -> io::Result<()> {
+        self.matrix.begin_set(set_header);
+        Ok(())
+    }
+
+    fn write_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        self.matrix.insert(index, bytes)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.finish_record().map(|_| ()).map_err(into_io_error)
+    }
+}
+
+/// Writes every shard to the writer of its index, such as a `Vec<u8>` to keep the shards in
+/// memory or a socket to send them away.
+impl<W: Write> ShardSink for Vec<W> {
+    fn write_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        let len = self.len();
+        let writer = self.get_mut(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no writer for shard {} among {}", index, len),
+            )
+        })?;
+        writer.write_all(bytes)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.iter_mut().try_for_each(Write::flush)
+    }
+}
+
+/// The error of a sink finished without the header of its set.
+fn no_set_header() -> Error {
+    Error::InvalidParameters("the header of the set was not given to the sink".to_string())
+}
+
+/// Converts an error of the shard format into an `io::Error`, keeping I/O errors as they are.
+fn into_io_error(error: Error) -> io::Error {
+    match error {
+        Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the header of the set of the fake shards, with an x-coordinate of 0.
+    fn set_header() -> Header {
+        Header {
+            threshold: 2,
+            parts: 3,
+            set_id: 42,
+            secret_len: 2,
+            ..Default::default()
+        }
+    }
+
+    /// Encodes a fake shard of x-coordinate `x` for a secret of two bytes.
+    fn shard(x: u8) -> Vec<u8> {
+        let shard_header = Header { x, ..set_header() };
+        header::encode_shard(&shard_header, &[x, x + 1])
+    }
+
+    /// Checks that `bytes` are the three fake shards in the column layout.
+    fn assert_columns(bytes: &[u8]) {
+        let shards = columnar::decode_columns(bytes).unwrap();
+        assert_eq!(shards.len(), 3);
+        for (x, decoded) in (1..).zip(&shards) {
+            let (shard_header, body) = header::decode_shard(decoded).unwrap();
+            assert_eq!((shard_header.x, shard_header.set_id), (x, 42));
+            assert_eq!(body, [x, x + 1]);
+        }
+    }
+
+    #[test]
+    fn test_directory_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = DirectorySink::new(dir.path(), "sss");
+        assert_eq!(sink.shard_path(1), dir.path().join("shards_1.sss"));
+        for index in [1, 0] {
+            sink.clone()
+                .write_shard(index, &shard(index as u8 + 1))
+                .unwrap();
+        }
+        sink.write_shard(2, &shard(3)).unwrap();
+        sink.finish().unwrap();
+        for index in 0..3 {
+            let path = dir.path().join(format!("shards_{}.sss", index));
+            assert_eq!(fs::read(path).unwrap(), shard(index as u8 + 1));
+        }
+
+        let mut sink = DirectorySink::new(&dir.path().join("missing"), "");
+        assert!(sink.write_shard(0, &shard(1)).is_err());
+    }
+
+    #[test]
+    fn test_writer_sink() {
+        let mut sink = vec![Vec::new(); 2];
+        sink.write_shard(1, &shard(2)).unwrap();
+        sink.write_shard(0, &shard(1)).unwrap();
+        let err = sink.write_shard(2, &shard(3)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(sink, vec![shard(1), shard(2)]);
+        sink.finish().unwrap();
+    }
+
+    #[test]
+    fn test_bundle_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_path = dir.path().join("shards.bundle");
+        let mut sink = BundleSink::new(&bundle_path, false, false);
+        sink.begin_set(&set_header()).unwrap();
+        for x in [1, 2, 3] {
+            sink.write_shard(x as usize - 1, &shard(x)).unwrap();
+        }
+        assert_eq!(sink.finish_record().unwrap(), 0);
+
+        let bytes = fs::read(&bundle_path).unwrap();
+        assert_columns(bundle::read_records(&bytes).unwrap()[0]);
+
+        let mut sink = BundleSink::new(&bundle_path, true, false);
+        sink.begin_set(&set_header()).unwrap();
+        sink.write_shard(1, &shard(2)).unwrap();
+        assert!(matches!(sink.finish(), Err(e) if e.kind() == io::ErrorKind::InvalidData));
+        // The header of the set must be given.
+        let mut sink = BundleSink::new(&bundle_path, true, false);
+        sink.write_shard(0, &shard(1)).unwrap();
+        assert!(matches!(sink.finish(), Err(e) if e.kind() == io::ErrorKind::InvalidData));
+        let mut corrupt = shard(1);
+        corrupt[0] ^= 1;
+        let mut sink = BundleSink::new(&bundle_path, true, false);
+        assert!(sink.write_shard(0, &corrupt).is_err());
+    }
+
+    #[test]
+    fn test_column_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let columns_path = dir.path().join("columns.sss");
+        let mut sink = ColumnSink::new(&columns_path, false);
+        sink.begin_set(&set_header()).unwrap();
+        for x in [3, 1, 2] {
+            sink.write_shard(x as usize - 1, &shard(x)).unwrap();
+        }
+        sink.finish().unwrap();
+        assert_columns(&fs::read(&columns_path).unwrap());
+
+        let missing_path = dir.path().join("missing").join("columns.sss");
+        let mut sink = ColumnSink::new(&missing_path, false);
+        sink.begin_set(&set_header()).unwrap();
+        sink.write_shard(0, &shard(1)).unwrap();
+        assert!(sink.finish().is_err());
+    }
+
+    #[test]
+    fn test_headerless_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = HeaderlessSink::new(dir.path(), "sss");
+        sink.begin_set(&set_header()).unwrap();
+        for x in [1, 2] {
+            sink.write_shard(x as usize - 1, &shard(x)).unwrap();
+        }
+        sink.finish().unwrap();
+        assert_eq!(fs::read(dir.path().join("2.sss")).unwrap(), [2, 3]);
+        let manifest = sidecar::read_manifest(&dir.path().join(sidecar::FILE_NAME)).unwrap();
+        assert_eq!(manifest.set_header.set_id, 42);
+        assert_eq!(
+            manifest.shards,
+            [("1.sss".to_string(), 1), ("2.sss".to_string(), 2)]
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    cli::Verbosity,
+    combine::{self, CombineOptions},
+    error::{Error, Result},
+    header::{self, BlockTags, Header},
+    mnemonic, shamir, timing,
+};
+use zeroize::Zeroizing;
+
+/// A shard file read block by block, hashing what is read so that its checksum is checked
+/// once the share data ends.
+struct ShardReader {
+    /// The name of the shard, its path.
+    name: String,
+    header: Header,
+    /// The length in bytes of the header, at which the share data starts.
+    header_len: u64,
+    reader: Box<dyn Read>,
+    hasher: Sha256,
+}
+
+impl ShardReader {
+    /// Opens a shard and reads its header.
+    ///
+    /// A shard written as words by `shard --encoding mnemonic` is decoded whole into memory
+    /// first, since the words of a block cannot be told apart from those of the next.
+    ///
+    /// # Returns
+    ///
+    /// The reader, positioned at the share data, the errors of `header::read_header` and
+    /// `mnemonic::decode`, or `Error::InvalidParameters` if the shard has no block checksums.
+    fn open(path: &Path) -> Result<Self> {
+        let name = path.display().to_string();
+        let mut file = BufReader::new(File::open(path)?);
+        let start = file.fill_buf()?;
+        let mut reader: Box<dyn Read> =
+            if !header::has_magic(start) && mnemonic::as_mnemonic(start).is_some() {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                let decoded = mnemonic::decode(&text).map_err(|e| e.in_shard(&name))?;
+                Box::new(io::Cursor::new(decoded))
+            } else {
+                Box::new(file)
+            };
+        let (header, header_bytes) =
+            header::read_header(&mut reader).map_err(|e| e.in_shard(&name))?;
+        if header.block_tags.is_none() {
+            return Err(Error::InvalidParameters(format!(
+                "{} has no block checksums, combine it without --stream or shard the secret \
+                 again with --block-checksums",
+                name
+            )));
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(&header_bytes);
+        Ok(ShardReader {
+            name,
+            header,
+            header_len: header_bytes.len() as u64,
+            reader,
+            hasher,
+        })
+    }
+
+    /// The block tags of the shard, which every shard opened has.
+    fn block_tags(&self) -> &BlockTags {
+        self.header
+            .block_tags
+            .as_ref()
+            .expect("checked when opened")
+    }
+
+    /// Reads the block of index `index`, `len` bytes long, into `block` and checks it against
+    /// its tag.
+    ///
+    /// # Returns
+    ///
+    /// `Error::BlockChecksumMismatch` naming the shard if the block does not match its tag,
+    /// or `Error::InvalidHeader` if the shard ends before it.
+    fn read_block(&mut self, index: u64, len: usize, block: &mut Vec<u8>) -> Result<()> {
+        block.resize(len, 0);
+        self.reader
+            .read_exact(block)
+            .map_err(|e| truncated(e).in_shard(&self.name))?;
+        self.hasher.update(&block);
+        let block_tags = self.block_tags();
+        if !block_tags.verify(self.header.set_id, self.header.x, index, block) {
+            let error = Error::BlockChecksumMismatch {
+                block: index,
+                offset: self.header_len + index * u64::from(block_tags.block_len),
+            };
+            return Err(error.in_shard(&self.name));
+        }
+        Ok(())
+    }
+
+    /// Reads the checksum which follows the share data and checks the whole shard against it.
+    ///
+    /// # Returns
+    ///
+    /// `Error::ChecksumMismatch` naming the shard if the checksum does not match, or
+    /// `Error::InvalidHeader` if the shard is shorter or longer than its header declares.
+    fn finish(&mut self) -> Result<()> {
+        let mut checksum = [0; header::CHECKSUM_LEN];
+        self.reader
+            .read_exact(&mut checksum)
+            .map_err(|e| truncated(e).in_shard(&self.name))?;
+        if self.reader.read(&mut [0])? != 0 {
+            return Err(length_mismatch().in_shard(&self.name));
+        }
+        if !header::ct_eq(&self.hasher.finalize_reset(), &checksum) {
+            return Err(Error::ChecksumMismatch.in_shard(&self.name));
+        }
+        Ok(())
+    }
+}
+
+/// Builds the error of a shard whose length does not match its header.
+fn length_mismatch() -> Error {
+    Error::InvalidHeader("shard length does not match its header".to_string())
+}
+
+/// Converts an error reading a shard, reporting a shard which ends too early as a length
+/// mismatch.
+fn truncated(error: io::Error) -> Error {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => length_mismatch(),
+        _ => error.into(),
+    }
+}
+
+/// Combines the shards of a directory block by block, checking every block against its
+/// `header::BlockTags` before combining it, and writes the secret as it is reconstructed.
+///
+/// Only one block of every shard and of the secret is held in memory at a time. The secret
+/// is written to a temporary file next to `output_path`, moved into place once every block
+/// and the checksum of every shard were verified, or streamed directly to a named pipe.
+///
+/// # Arguments
+///
+/// * `shards_dir` - The directory containing the shards, written with block checksums.
+/// * `output_path` - The path where the secret is saved.
+/// * `options` - The `CombineOptions` to apply; only `force`, `recursive`, `enforce_expiry`,
+///   the mode, the backend and the verbosity apply.
+/// * `cancel` - A flag which, once set, stops the combination and discards the secret.
+///
+/// # Returns
+///
+/// The path the secret was written to, `Error::BlockChecksumMismatch` naming the shard and
+/// offset of the first corrupted block, `Error::Expired` if the shards expired and
+/// `options.enforce_expiry` is set, `Error::InconsistentShards` or
+/// `Error::InsufficientShares` if the shards cannot be combined together,
+/// `Error::InvalidParameters` if `shards_dir` is not a directory or a shard has no block
+/// checksums, or the errors of the shards and of writing the secret.
+pub fn combine_stream(
+    shards_dir: &Path,
+    output_path: &Path,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    if !shards_dir.is_dir() {
+        return Err(Error::InvalidParameters(
+            "--stream only reads shards from a directory".to_string(),
+        ));
+    }
+    let mut shards = combine::list_shards(shards_dir, options.recursive)?
+        .iter()
+        .map(|path| ShardReader::open(path))
+        .collect::<Result<Vec<_>>>()?;
+    check_set(&shards)?;
+    combine::check_expiry(
+        &shards[0].header,
+        timing::unix_now(),
+        options.enforce_expiry,
+    )?;
+    if options.verbosity == Verbosity::Verbose {
+        for shard in &shards {
+            println!("Read shard {} (x = {})", shard.name, shard.header.x);
+        }
+    }
+
+    if combine::is_fifo(output_path) {
+        let mut fifo = combine::open_fifo(output_path, cancel)?;
+        combine_blocks(&mut shards, &mut fifo, options, cancel)?;
+    } else {
+        let (temp_path, mut temp_file) = combine::create_temp_file(
+            output_path,
+            options.mode.unwrap_or(combine::SECRET_FILE_MODE),
+        )?;
+        let mut write = || -> Result<()> {
+            combine_blocks(&mut shards, &mut temp_file, options, cancel)?;
+            temp_file.sync_all()?;
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Error::Cancelled);
+            }
+            Ok(combine::persist(&temp_path, output_path, options.force)?)
+        };
+        let written = write();
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written?;
+    }
+    Ok(output_path.to_path_buf())
+}
+
+/// Checks that the shards belong to the same set, with the same block length, and reach its
+/// threshold with distinct x-coordinates.
+fn check_set(shards: &[ShardReader]) -> Result<()> {
+    let block_len = |header: &Header| header.block_tags.as_ref().map(|tags| tags.block_len);
+    let first = &shards[0].header;
+    for shard in &shards[1..] {
+        let header = &shard.header;
+        if !header::ct_eq(&first.set_id.to_be_bytes(), &header.set_id.to_be_bytes()) {
+            let error =
+                Error::InconsistentShards("shard belongs to a different share set".to_string());
+            return Err(error.in_shard(&shard.name));
+        }
+        combine::check_same_scheme(&shards[0].name, first, &shard.name, header)?;
+        if block_len(first) != block_len(header) {
+            let error = Error::InconsistentShards(
+                "shard block length differs from the other shards".to_string(),
+            );
+            return Err(error.in_shard(&shard.name));
+        }
+    }
+    let distinct_x: HashSet<u8> = shards.iter().map(|shard| shard.header.x).collect();
+    let need = usize::from(first.threshold).max(2);
+    if distinct_x.len() < need {
+        return Err(Error::InsufficientShares {
+            have: distinct_x.len(),
+            need,
+        });
+    }
+    Ok(())
+}
+
+/// Reads, checks and combines the shards one block at a time, writing every block of the
+/// secret to `writer`, then checks the checksum of every shard and the secret against the
+/// hash stored in the shards.
+fn combine_blocks(
+    shards: &mut [ShardReader],
+    writer: &mut impl Write,
+    options: &CombineOptions,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let set_header = shards[0].header.clone();
+    let block_len = u64::from(shards[0].block_tags().block_len);
+    let mut parts = vec![Vec::new(); shards.len()];
+    let mut secret_block = Zeroizing::new(vec![0; block_len as usize]);
+    let mut secret_hasher = Sha256::new();
+
+    for index in 0..set_header.secret_len.div_ceil(block_len) {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(Error::Cancelled);
+        }
+        let len = block_len.min(set_header.secret_len - index * block_len) as usize;
+        for (shard, part) in shards.iter_mut().zip(&mut parts) {
+            shard.read_block(index, len, part)?;
+            part.push(shard.header.x);
+        }
+        let len = shamir::combine_into(
+            &parts,
+            &mut secret_block,
+            options.gf_backend,
+            Some(cancel),
+            false,
+        )?;
+        writer.write_all(&secret_block[..len])?;
+        secret_hasher.update(&secret_block[..len]);
+    }
+    for shard in shards.iter_mut() {
+        shard.finish()?;
+    }
+
+    if let Some(secret_hash) = &set_header.secret_hash {
+        if !header::ct_eq(&secret_hasher.finalize(), secret_hash) {
+            eprintln!(
+                "{}",
+                "WARNING: the recovered secret does not match the hash stored in the shards, \
+                 at least one shard is corrupted"
+                    .red()
+                    .bold()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sharding::SharingConfig, test_support::shard_fixture};
+
+    /// Default options which only print errors.
+    fn quiet() -> CombineOptions {
+        CombineOptions {
+            verbosity: Verbosity::Quiet,
+            ..Default::default()
+        }
+    }
+
+    /// Shards `secret` with block checksums into `dir/shards` and returns the shard paths.
+    fn write_shards(dir: &Path, secret: &[u8]) -> Vec<PathBuf> {
+        let options = SharingConfig::builder()
+            .embed_hash(true)
+            .block_checksums(true);
+        shard_fixture(&dir.join("shards"), secret, options)
+    }
+
+    #[test]
+    fn test_combine_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret: Vec<u8> = (0..150_000u32).map(|i| (i * 7) as u8).collect();
+        let shard_paths = write_shards(dir.path(), &secret);
+        fs::remove_file(&shard_paths[0]).unwrap();
+
+        let output_path = dir.path().join("recovered");
+        let shards_dir = dir.path().join("shards");
+        combine_stream(&shards_dir, &output_path, &quiet(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), secret);
+        // No temporary file is left next to the shards directory and the secret.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_combine_stream_rejects_corrupted_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = vec![0x5a; 512 * 1024];
+        let shard_paths = write_shards(dir.path(), &secret);
+
+        // Corrupt block 5 of the 8 blocks of 64 KiB of a shard.
+        let mut shard = fs::read(&shard_paths[2]).unwrap();
+        let (header, header_len) = Header::from_bytes(&shard).unwrap();
+        assert_eq!(header.block_tags.unwrap().block_len, header::MIN_BLOCK_LEN);
+        let offset = header_len as u64 + 5 * u64::from(header::MIN_BLOCK_LEN);
+        shard[offset as usize + 1000] ^= 1;
+        fs::write(&shard_paths[2], shard).unwrap();
+
+        let output_path = dir.path().join("recovered");
+        let shards_dir = dir.path().join("shards");
+        let err = combine_stream(&shards_dir, &output_path, &quiet(), &AtomicBool::new(false))
+            .unwrap_err();
+        let Error::Shard { name, source } = err else {
+            panic!("the error does not name the shard: {:?}", err);
+        };
+        assert_eq!(name, shard_paths[2].display().to_string());
+        assert!(
+            matches!(*source, Error::BlockChecksumMismatch { block: 5, offset: o } if o == offset)
+        );
+        // Nothing is left behind, not even the blocks written before the corrupted one.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_combine_stream_requires_block_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards_path = dir.path().join("shards");
+        shard_fixture(&shards_path, b"test", SharingConfig::builder());
+
+        let output_path = dir.path().join("recovered");
+        let err = combine_stream(
+            &shards_path,
+            &output_path,
+            &quiet(),
+            &AtomicBool::new(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidParameters(_)));
+        assert!(!output_path.exists());
+    }
+}
@@ -0,0 +1,27 @@
+//! Fixtures shared by the unit tests of several modules.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
+
+use crate::{
+    cli::Verbosity,
+    sharding::{shard_bytes, SharingConfig, SharingConfigBuilder},
+};
+
+/// Shards `secret` into `shards_dir`, which is created if missing, with `options` but without
+/// any output, and returns the shard paths.
+pub fn shard_fixture(
+    shards_dir: &Path,
+    secret: &[u8],
+    options: SharingConfigBuilder,
+) -> Vec<PathBuf> {
+    let options = options.verbosity(Verbosity::Quiet).build().unwrap();
+    shard_bytes(secret, shards_dir, &options, &AtomicBool::new(false)).unwrap()
+}
+
+/// Starts building the default options with the given scheme.
+pub fn scheme(parts: usize, threshold: usize) -> SharingConfigBuilder {
+    SharingConfig::builder().parts(parts).threshold(threshold)
+}
@@ -0,0 +1,82 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Durations of the phases of a command, such as reading the secret, splitting it and
+/// writing the shards, reported by `--time` to tell CPU-bound from I/O-bound runs.
+#[derive(Debug, Default)]
+pub struct Timings(Mutex<Vec<(&'static str, Duration)>>);
+
+impl Timings {
+    /// Runs `f` and adds its duration to the phase named `phase`.
+    pub fn measure<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        let mut phases = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, duration)) => *duration += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+        result
+    }
+
+    /// Formats the phases in the order they first ran, in seconds, followed by `total`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let timings = Timings::default();
+    /// timings.measure("split", || ());
+    /// print!("{}", timings.report(start.elapsed()));
+    /// // Timings:
+    /// //   split          0.000001 s
+    /// //   total          0.000003 s
+    /// ```
+    pub fn report(&self, total: Duration) -> String {
+        let phases = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report = String::from("Timings:\n");
+        for (phase, duration) in phases.iter().chain([&("total", total)]) {
+            report += &format!("  {:<14} {:.6} s\n", phase, duration.as_secs_f64());
+        }
+        report
+    }
+}
+
+/// Runs `f`, measuring it as `phase` when timings are collected.
+pub fn measure<T>(timings: Option<&Timings>, phase: &'static str, f: impl FnOnce() -> T) -> T {
+    match timings {
+        Some(timings) => timings.measure(phase, f),
+        None => f(),
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch, or 0 if the clock is set
+/// before it.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_report() {
+        let timings = Timings::default();
+        assert_eq!(timings.measure("split", || 42), 42);
+        timings.measure("write shards", || ());
+        timings.measure("split", || ());
+        assert_eq!(measure(None, "ignored", || 7), 7);
+
+        let report = timings.report(Duration::from_millis(1500));
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("  split "));
+        assert!(lines[2].starts_with("  write shards "));
+        assert_eq!(lines[3], "  total          1.500000 s");
+    }
+}
@@ -0,0 +1,142 @@
+//! A minimal S3-compatible store serving path-style requests from memory, standing in for
+//! MinIO: it stores, lists and removes objects, pages listings and refuses unsigned
+//! requests, answering errors with the XML bodies of S3.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Most keys in a page of a listing, few enough that listing a set goes through several.
+pub const PAGE_SIZE: usize = 2;
+
+/// The objects of the store, by bucket and key such as `backups/vault/shards_0.sss`.
+pub type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+/// Starts the store on a free local port, failing the uploads of keys containing
+/// `failing`, and returns its endpoint URL with the objects it holds.
+pub fn start(failing: &'static str) -> (String, Objects) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let objects = Arc::new(Mutex::new(BTreeMap::new()));
+    let store = Arc::clone(&objects);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut len = 0;
+            let mut signed = false;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_ascii_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    len = value.trim().parse().unwrap();
+                }
+                signed |= line.starts_with("authorization: aws4-hmac-sha256 credential=test/");
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut parts = request_line.split_whitespace();
+            let (method, target) = (parts.next().unwrap(), parts.next().unwrap());
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let key = path.trim_start_matches('/').to_string();
+            let mut objects = store.lock().unwrap();
+            let (status, response) = match (method, query) {
+                _ if !signed => error("403 Forbidden", "AccessDenied"),
+                ("PUT", _) if key.contains(failing) => {
+                    error("500 Internal Server Error", "InternalError")
+                }
+                ("PUT", _) => {
+                    objects.insert(key, body);
+                    ("200 OK", Vec::new())
+                }
+                ("DELETE", _) => {
+                    objects.remove(&key);
+                    ("204 No Content", Vec::new())
+                }
+                ("GET", query) if query.contains("list-type=2") => {
+                    let (bucket, _) = key.split_once('/').unwrap_or((&key, ""));
+                    let param = |name: &str| {
+                        query
+                            .split('&')
+                            .find_map(|param| param.strip_prefix(&format!("{}=", name)))
+                            .map(|value| value.replace("%2F", "/"))
+                    };
+                    let prefix = param("prefix").unwrap_or_default();
+                    // The continuation token is the position of the first key of the page.
+                    let start: usize =
+                        param("continuation-token").map_or(0, |t| t.parse().unwrap());
+                    let keys: Vec<&str> = objects
+                        .keys()
+                        .filter_map(|key| key.strip_prefix(&format!("{}/", bucket)))
+                        .filter(|key| key.starts_with(&prefix))
+                        .collect();
+                    let page: String = keys
+                        .iter()
+                        .skip(start)
+                        .take(PAGE_SIZE)
+                        .map(|key| {
+                            let size = objects[&format!("{}/{}", bucket, key)].len();
+                            format!(
+                                "<Contents><Key>{}</Key><LastModified>2024-01-01T00:00:00.000Z\
+                                 </LastModified><Size>{}</Size></Contents>",
+                                key, size
+                            )
+                        })
+                        .collect();
+                    let next = start + PAGE_SIZE;
+                    let truncation = if next < keys.len() {
+                        format!(
+                            "<IsTruncated>true</IsTruncated>\
+                             <NextContinuationToken>{}</NextContinuationToken>",
+                            next
+                        )
+                    } else {
+                        "<IsTruncated>false</IsTruncated>".to_string()
+                    };
+                    let listing = format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                         <ListBucketResult><Name>{}</Name><Prefix>{}</Prefix>\
+                         <MaxKeys>{}</MaxKeys>{}{}</ListBucketResult>",
+                        bucket, prefix, PAGE_SIZE, truncation, page
+                    );
+                    ("200 OK", listing.into_bytes())
+                }
+                ("GET", _) => match objects.get(&key) {
+                    Some(object) => ("200 OK", object.clone()),
+                    None => error("404 Not Found", "NoSuchKey"),
+                },
+                _ => error("405 Method Not Allowed", "MethodNotAllowed"),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                response.len()
+            )
+            .unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    });
+    (endpoint, objects)
+}
+
+/// Returns `status` with the XML error body S3 sends along, of code `code`.
+fn error(status: &'static str, code: &str) -> (&'static str, Vec<u8>) {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <Error><Code>{}</Code><Message>{}</Message></Error>",
+        code, status
+    );
+    (status, body.into_bytes())
+}
@@ -0,0 +1,4 @@
+//! Helpers shared by the integration tests.
+
+#[cfg(feature = "cloud")]
+pub mod mock_s3;